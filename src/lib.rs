@@ -1,3 +1,30 @@
+#![doc = include_str!("../README.md")]
+
 pub mod rsgex;
 
-pub use rsgex::engine::Engine;
+pub use rsgex::analysis::Hazard;
+pub use rsgex::bit_nfa::BitNfa;
+pub use rsgex::builder::EngineBuilder;
+pub use rsgex::bytes::{BytesEngine, BytesMatch};
+pub use rsgex::cache::Cache;
+pub use rsgex::cancel::CancelToken;
+pub use rsgex::compile_cache::{clear_compile_cache, set_compile_cache_capacity};
+pub use rsgex::derivative::DerivativeMatcher;
+pub use rsgex::dfa::Dfa;
+pub use rsgex::engine::{Backend, Engine};
+pub use rsgex::error::{Error, SizeLimitExceeded};
+pub use rsgex::explain::Explanation;
+#[cfg(feature = "memmap2")]
+pub use rsgex::file::{FileMatch, FileMatches};
+pub use rsgex::introspect::{StateView, Stats, TransitionKind};
+pub use rsgex::language::{Examples, Language, Sampler};
+pub use rsgex::lazy_dfa::LazyDfa;
+pub use rsgex::online::OnlineMatcher;
+pub use rsgex::pattern::Pattern;
+pub use rsgex::search::{Match, Matches};
+pub use rsgex::segments::Segments;
+pub use rsgex::shift_or::ShiftOr;
+pub use rsgex::stream::StreamMatches;
+pub use rsgex::tagged_dfa::TaggedDfa;
+pub use rsgex::token_nfa::{TokenNfa, TokenTransition};
+pub use rsgex::trace::{ExecObserver, StepEvent, Trace};
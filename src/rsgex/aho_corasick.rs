@@ -0,0 +1,121 @@
+const UNSET: usize = usize::MAX;
+
+// Finds the leftmost occurrence of any of a set of literals in one pass over
+// the haystack. The trie of the literals is turned into a DFA by resolving
+// every failure link ahead of time, over classes of the bytes the literals
+// contain so rows stay narrow.
+pub struct AhoCorasick {
+    // byte -> class, 0 for bytes no literal contains
+    classes: [usize; 256],
+    class_count: usize,
+    // a row of next states per state, the root being 0
+    table: Vec<usize>,
+    // state -> lengths of the literals ending there
+    outputs: Vec<Vec<usize>>,
+    max_len: usize,
+}
+
+impl AhoCorasick {
+    pub fn new(literals: &[&[u8]]) -> Self {
+        let mut classes = [0; 256];
+        let mut class_count = 1;
+        for &b in literals.iter().flat_map(|literal| literal.iter()) {
+            if classes[b as usize] == 0 {
+                classes[b as usize] = class_count;
+                class_count += 1;
+            }
+        }
+
+        let mut table = vec![UNSET; class_count];
+        let mut outputs = vec![vec![]];
+        for literal in literals.iter() {
+            let mut state = 0;
+            for &b in literal.iter() {
+                let index = state * class_count + classes[b as usize];
+                if table[index] == UNSET {
+                    table[index] = outputs.len();
+                    table.extend(std::iter::repeat_n(UNSET, class_count));
+                    outputs.push(vec![]);
+                }
+                state = table[index];
+            }
+            outputs[state].push(literal.len());
+        }
+
+        // breadth first, so a state's failure is complete before its children
+        let mut failures = vec![0; outputs.len()];
+        let mut queue = vec![0];
+        let mut processed = 0;
+        while processed < queue.len() {
+            let state = queue[processed];
+            processed += 1;
+            for class in 0..class_count {
+                let index = state * class_count + class;
+                let fallback = match state {
+                    0 => 0,
+                    _ => table[failures[state] * class_count + class],
+                };
+                if table[index] == UNSET {
+                    table[index] = fallback;
+                    continue;
+                }
+                let child = table[index];
+                failures[child] = fallback;
+                let inherited = outputs[fallback].clone();
+                outputs[child].extend(inherited);
+                queue.push(child);
+            }
+        }
+
+        Self {
+            classes,
+            class_count,
+            table,
+            outputs,
+            max_len: literals
+                .iter()
+                .map(|literal| literal.len())
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    // The start of the leftmost occurrence at or after `at`.
+    pub fn find(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        let mut state = 0;
+        let mut leftmost: Option<usize> = None;
+
+        for (i, &b) in haystack.iter().enumerate().skip(at) {
+            // later occurrences cannot start before the one found
+            if leftmost.is_some_and(|start| i + 1 >= start + self.max_len) {
+                break;
+            }
+            state = self.table[state * self.class_count + self.classes[b as usize]];
+            for &len in self.outputs[state].iter() {
+                let start = i + 1 - len;
+                leftmost = Some(leftmost.map_or(start, |leftmost| leftmost.min(start)));
+            }
+        }
+
+        leftmost
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AhoCorasick;
+
+    #[test]
+    fn test_find() {
+        let ac = AhoCorasick::new(&[b"he", b"she", b"his", b"hers"]);
+
+        assert_eq!(ac.find(b"ushers", 0), Some(1));
+        assert_eq!(ac.find(b"ushers", 2), Some(2));
+        assert_eq!(ac.find(b"ahishe", 0), Some(1));
+        assert_eq!(ac.find(b"xyz", 0), None);
+
+        // the shorter literal ends first but starts later
+        let ac = AhoCorasick::new(&[b"abcd", b"c"]);
+        assert_eq!(ac.find(b"xabcd", 0), Some(1));
+    }
+}
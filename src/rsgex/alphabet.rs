@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use super::dfa::{Edge, Edges};
+
+// Splits the chars into classes that no transition of an automaton can tell
+// apart. The ends of the transitions' ranges cut the alphabet into
+// intervals, and intervals accepted by the same transitions share a class,
+// so tables indexed by class stay as narrow as the automaton allows.
+#[derive(Clone)]
+pub struct Alphabet {
+    // interval of `c` is the number of boundaries <= `c`
+    boundaries: Vec<char>,
+    // interval -> class
+    intervals: Vec<usize>,
+    // ASCII char -> class, skipping the binary search
+    ascii: [usize; 128],
+    // one char per class
+    representatives: Vec<char>,
+}
+
+impl Alphabet {
+    pub fn new(edges: &[Edges], boundaries: Vec<char>) -> Self {
+        let tests: Vec<_> = edges
+            .iter()
+            .flatten()
+            .filter_map(|(edge, _)| match edge {
+                Edge::Chars(transition) => Some(transition),
+                _ => None,
+            })
+            .collect();
+
+        // the transitions accepting an interval -> its class
+        let mut classes: HashMap<Vec<bool>, usize> = HashMap::new();
+        let mut representatives = vec![];
+        let intervals = std::iter::once('\0')
+            .chain(boundaries.iter().copied())
+            .map(|start| {
+                let accepted = tests
+                    .iter()
+                    .map(|t| t.matches_char(start) == Some(true))
+                    .collect();
+                *classes.entry(accepted).or_insert_with(|| {
+                    representatives.push(start);
+                    representatives.len() - 1
+                })
+            })
+            .collect();
+
+        Self::from_intervals(boundaries, intervals, representatives)
+    }
+
+    // The classes of chars neither alphabet can tell apart, along with the
+    // classes of `self` and `other` each of them falls in.
+    pub fn product(&self, other: &Alphabet) -> (Alphabet, Vec<(usize, usize)>) {
+        let mut boundaries: Vec<char> = self
+            .boundaries
+            .iter()
+            .chain(other.boundaries.iter())
+            .copied()
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let mut classes: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut representatives = vec![];
+        let mut pairs = vec![];
+        let intervals = std::iter::once('\0')
+            .chain(boundaries.iter().copied())
+            .map(|start| {
+                let pair = (self.class(start), other.class(start));
+                *classes.entry(pair).or_insert_with(|| {
+                    representatives.push(start);
+                    pairs.push(pair);
+                    pairs.len() - 1
+                })
+            })
+            .collect();
+
+        let alphabet = Self::from_intervals(boundaries, intervals, representatives);
+        (alphabet, pairs)
+    }
+
+    pub fn class(&self, c: char) -> usize {
+        match self.ascii.get(c as usize) {
+            Some(&class) => class,
+            None => self.lookup(c),
+        }
+    }
+
+    pub fn classes(&self) -> usize {
+        self.representatives.len()
+    }
+
+    // How many chars each class holds.
+    pub fn sizes(&self) -> Vec<u64> {
+        let mut sizes = vec![0; self.classes()];
+        for (start, end, class) in self.ranges() {
+            sizes[class] += end - start - surrogates(start, end);
+        }
+        sizes
+    }
+
+    // The `n`th char of `class`, counting up through its intervals; `n`
+    // is below the class's size.
+    pub fn nth(&self, class: usize, mut n: u64) -> char {
+        for (start, end, _) in self.ranges().filter(|&(_, _, c)| c == class) {
+            let size = end - start - surrogates(start, end);
+            if n < size {
+                let mut c = start + n;
+                if start < 0xD800 && c >= 0xD800 {
+                    c += 0x800;
+                }
+                return char::from_u32(c as u32).unwrap();
+            }
+            n -= size;
+        }
+        unreachable!("char {} is past the class", n)
+    }
+
+    pub fn representative(&self, class: usize) -> char {
+        self.representatives[class]
+    }
+
+    fn from_intervals(
+        boundaries: Vec<char>,
+        intervals: Vec<usize>,
+        representatives: Vec<char>,
+    ) -> Self {
+        let mut alphabet = Self {
+            boundaries,
+            intervals,
+            ascii: [0; 128],
+            representatives,
+        };
+        for b in 0..128u8 {
+            alphabet.ascii[b as usize] = alphabet.lookup(b as char);
+        }
+        alphabet
+    }
+
+    // start, end past the last code point and class of every interval
+    fn ranges(&self) -> impl Iterator<Item = (u64, u64, usize)> + '_ {
+        let starts = std::iter::once(0).chain(self.boundaries.iter().map(|&b| b as u64));
+        let ends = self
+            .boundaries
+            .iter()
+            .map(|&b| b as u64)
+            .chain([char::MAX as u64 + 1]);
+        starts
+            .zip(ends)
+            .zip(self.intervals.iter())
+            .map(|((start, end), &class)| (start, end, class))
+    }
+
+    fn lookup(&self, c: char) -> usize {
+        self.intervals[self.boundaries.partition_point(|&b| b <= c)]
+    }
+}
+
+// how many surrogates, which are not chars, lie in `start..end`
+fn surrogates(start: u64, end: u64) -> u64 {
+    end.min(0xE000).saturating_sub(start.max(0xD800))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rsgex::{dfa, engine::Engine};
+
+    #[test]
+    fn test_classes() {
+        // accepted or not
+        let e = Engine::try_from("([a-c]|[x-z])+").unwrap();
+        let (_, alphabet) = dfa::edges(&e.nfa).unwrap();
+        assert_eq!(alphabet.classes(), 2);
+        assert_eq!(alphabet.class('a'), alphabet.class('y'));
+        assert_eq!(alphabet.class('d'), alphabet.class('中'));
+        assert_ne!(alphabet.class('c'), alphabet.class('d'));
+
+        let e = Engine::try_from("a[ab]").unwrap();
+        let (_, alphabet) = dfa::edges(&e.nfa).unwrap();
+        assert_eq!(alphabet.classes(), 3);
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+
+use regex_syntax::hir::{Class, ClassUnicode, ClassUnicodeRange, Hir, HirKind, Repetition};
+
+// A construct that may make the backtracking backend take far more than
+// linear time, found by `EngineBuilder::analyze`. The analysis is an
+// estimate that looks at the chars each part of the pattern can start
+// with, so it may flag patterns that are fine in practice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hazard {
+    // a repetition whose body can end in another variable repetition of
+    // the text it starts with, like `(a+)+`: exponential
+    NestedQuantifier { pattern: String },
+    // a repetition of branches that can start alike, like `(\w|\d\w)+`:
+    // exponential
+    OverlappingAlternation { pattern: String },
+    // unbounded repetitions of the same chars in a row, like `\w*\w*`:
+    // polynomial
+    AdjacentQuantifiers { pattern: String },
+}
+
+impl Hazard {
+    pub fn is_exponential(&self) -> bool {
+        !matches!(self, Hazard::AdjacentQuantifiers { .. })
+    }
+
+    // the part of the pattern at fault, as printed by regex-syntax
+    pub fn pattern(&self) -> &str {
+        match self {
+            Hazard::NestedQuantifier { pattern }
+            | Hazard::OverlappingAlternation { pattern }
+            | Hazard::AdjacentQuantifiers { pattern } => pattern,
+        }
+    }
+}
+
+impl fmt::Display for Hazard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, time) = match self {
+            Hazard::NestedQuantifier { .. } => ("nested quantifiers", "exponential"),
+            Hazard::OverlappingAlternation { .. } => {
+                ("repeated overlapping branches", "exponential")
+            }
+            Hazard::AdjacentQuantifiers { .. } => ("adjacent quantifiers", "polynomial"),
+        };
+        write!(f, "{} in `{}` may take {} time", what, self.pattern(), time)
+    }
+}
+
+impl std::error::Error for Hazard {}
+
+// The hazards of `hir`, outermost first.
+pub fn hazards(hir: &Hir) -> Vec<Hazard> {
+    let mut hazards = vec![];
+    walk(hir, &mut hazards);
+    hazards
+}
+
+fn walk(hir: &Hir, hazards: &mut Vec<Hazard>) {
+    match hir.kind() {
+        HirKind::Repetition(repetition) => {
+            if repetition.max != Some(1) && repetition.max != Some(0) {
+                check_body(hir, &repetition.sub, hazards);
+            }
+            walk(&repetition.sub, hazards);
+        }
+        HirKind::Capture(capture) => walk(&capture.sub, hazards),
+        HirKind::Concat(hirs) => {
+            check_adjacent(hirs, hazards);
+            hirs.iter().for_each(|hir| walk(hir, hazards));
+        }
+        HirKind::Alternation(hirs) => hirs.iter().for_each(|hir| walk(hir, hazards)),
+        _ => (),
+    }
+}
+
+// Text the body of a repetition matches can be split between its
+// iterations in many ways when a part the body can end with may also take
+// what the next iteration starts with.
+fn check_body(repeated: &Hir, body: &Hir, hazards: &mut Vec<Hazard>) {
+    let first = first_chars(body);
+    let pattern = repeated.to_string();
+    let mut tails = vec![];
+    collect_tails(body, &mut tails);
+
+    for tail in tails {
+        match tail.kind() {
+            HirKind::Repetition(inner)
+                if is_variable(inner) && overlaps(&first_chars(&inner.sub), &first) =>
+            {
+                hazards.push(Hazard::NestedQuantifier { pattern });
+                return;
+            }
+            HirKind::Alternation(branches) => {
+                let firsts: Vec<ClassUnicode> = branches.iter().map(first_chars).collect();
+                let overlapping = firsts
+                    .iter()
+                    .enumerate()
+                    .any(|(i, a)| firsts[i + 1..].iter().any(|b| overlaps(a, b)));
+                if overlapping {
+                    hazards.push(Hazard::OverlappingAlternation { pattern });
+                    return;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// `x*` followed by `y*`, with only parts that may match nothing in between,
+// try every split of a run both can take.
+fn check_adjacent(hirs: &[Hir], hazards: &mut Vec<Hazard>) {
+    for (i, hir) in hirs.iter().enumerate() {
+        let HirKind::Repetition(first) = hir.kind() else {
+            continue;
+        };
+        if first.max.is_some() {
+            continue;
+        }
+        for (j, next) in hirs.iter().enumerate().skip(i + 1) {
+            if let HirKind::Repetition(second) = next.kind()
+                && second.max.is_none()
+                && overlaps(&first_chars(&first.sub), &first_chars(&second.sub))
+            {
+                let pattern = Hir::concat(hirs[i..=j].to_vec()).to_string();
+                hazards.push(Hazard::AdjacentQuantifiers { pattern });
+                return;
+            }
+            if !nullable(next) {
+                break;
+            }
+        }
+    }
+}
+
+// the parts a match of `hir` may end with
+fn collect_tails<'h>(hir: &'h Hir, tails: &mut Vec<&'h Hir>) {
+    match hir.kind() {
+        HirKind::Capture(capture) => collect_tails(&capture.sub, tails),
+        HirKind::Repetition(repetition) => {
+            tails.push(hir);
+            collect_tails(&repetition.sub, tails);
+        }
+        HirKind::Alternation(branches) => {
+            tails.push(hir);
+            branches
+                .iter()
+                .for_each(|branch| collect_tails(branch, tails));
+        }
+        HirKind::Concat(hirs) => {
+            for hir in hirs.iter().rev() {
+                collect_tails(hir, tails);
+                if !nullable(hir) {
+                    break;
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+fn is_variable(repetition: &Repetition) -> bool {
+    repetition.max != Some(repetition.min)
+}
+
+fn nullable(hir: &Hir) -> bool {
+    hir.properties().minimum_len() == Some(0)
+}
+
+fn overlaps(a: &ClassUnicode, b: &ClassUnicode) -> bool {
+    let mut both = a.clone();
+    both.intersect(b);
+    both.iter().next().is_some()
+}
+
+// the chars a match of `hir` may start with
+fn first_chars(hir: &Hir) -> ClassUnicode {
+    match hir.kind() {
+        HirKind::Literal(literal) => {
+            let c = match std::str::from_utf8(&literal.0) {
+                Ok(s) => s.chars().next(),
+                Err(_) => literal.0.first().map(|&b| b as char),
+            };
+            ClassUnicode::new(c.map(|c| ClassUnicodeRange::new(c, c)))
+        }
+        HirKind::Class(Class::Unicode(class)) => class.clone(),
+        HirKind::Class(Class::Bytes(class)) => ClassUnicode::new(
+            class
+                .iter()
+                .map(|r| ClassUnicodeRange::new(r.start() as char, r.end() as char)),
+        ),
+        HirKind::Repetition(repetition) if repetition.max != Some(0) => {
+            first_chars(&repetition.sub)
+        }
+        HirKind::Capture(capture) => first_chars(&capture.sub),
+        HirKind::Concat(hirs) => {
+            let mut first = ClassUnicode::empty();
+            for hir in hirs {
+                first.union(&first_chars(hir));
+                if !nullable(hir) {
+                    break;
+                }
+            }
+            first
+        }
+        HirKind::Alternation(hirs) => {
+            let mut first = ClassUnicode::empty();
+            hirs.iter().for_each(|hir| first.union(&first_chars(hir)));
+            first
+        }
+        _ => ClassUnicode::empty(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use regex_syntax::parse;
+
+    use super::{Hazard, hazards};
+
+    fn kinds(pattern: &str) -> Vec<&'static str> {
+        hazards(&parse(pattern).unwrap())
+            .iter()
+            .map(|hazard| match hazard {
+                Hazard::NestedQuantifier { .. } => "nested",
+                Hazard::OverlappingAlternation { .. } => "alternation",
+                Hazard::AdjacentQuantifiers { .. } => "adjacent",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hazards() {
+        assert_eq!(kinds("(a+)+b"), ["nested"]);
+        assert_eq!(kinds("^(\\w+\\s?)*$"), ["nested"]);
+        assert_eq!(kinds("((ab)*)+"), ["nested"]);
+        assert_eq!(kinds("(\\w|\\d\\w)+x"), ["alternation"]);
+        assert_eq!(kinds("\\w*-?\\w*="), ["adjacent"]);
+        assert_eq!(kinds("x(a+b)+ \\d*(a|b)c*"), Vec::<&str>::new());
+        assert_eq!(kinds("(a{2})+|(ab+)+|\\d+a+"), Vec::<&str>::new());
+
+        let hazard = &hazards(&parse("(a+)+").unwrap())[0];
+        assert!(hazard.is_exponential());
+        assert_eq!(
+            hazard.to_string(),
+            "nested quantifiers in `(a+)+` may take exponential time"
+        );
+    }
+}
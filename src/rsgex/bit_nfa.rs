@@ -0,0 +1,240 @@
+use std::borrow::Cow;
+
+use super::{
+    alphabet::Alphabet,
+    dfa::{self, Edge},
+    engine::Engine,
+    error::{Error, SizeLimitExceeded},
+    normalize,
+};
+
+// states one `u128` can track
+pub const MAX_BIT_NFA_STATES: usize = 128;
+
+// Simulates an automaton of at most 128 states with one bit per state, so
+// a char advances every live state at once: the live set is always closed
+// over epsilon transitions, and each (char class, state) pair has the
+// closed set of states it leads to precomputed. Sits between the
+// backtracker, which follows one path at a time, and a `Dfa`, which has to
+// determinize first. Like `Dfa` it only reports match positions, and
+// rejects counted repetitions and everything else `Dfa` rejects, but
+// supports `^` / `$`.
+pub struct BitNfa {
+    alphabet: Alphabet,
+    states: usize,
+    // class * states + state -> states entered on a char of the class
+    successors: Vec<u128>,
+    // live before the first char, `^` included
+    start: u128,
+    // live when a thread starts after the first char
+    restart: u128,
+    // ending states
+    matching: u128,
+    // states ending once `$` holds
+    eof_matching: u128,
+    // the same on an empty input, where `^` holds too
+    empty_matching: u128,
+    nfc: bool,
+    anchored: bool,
+}
+
+impl BitNfa {
+    pub fn is_match(&self, s: &str) -> bool {
+        self.shortest_match(s).is_some()
+    }
+
+    // The byte offset where the earliest ending match ends.
+    pub fn shortest_match(&self, s: &str) -> Option<usize> {
+        let s = self.prepare(s);
+        let restart = if self.anchored { 0 } else { self.restart };
+
+        let mut active = self.start;
+        if active & self.matching != 0 {
+            return Some(0);
+        }
+        for (i, c) in s.char_indices() {
+            active = self.step(active, c) | restart;
+            if active & self.matching != 0 {
+                return Some(i + c.len_utf8());
+            }
+            if active == 0 {
+                return None;
+            }
+        }
+
+        let eof_matching = match s.is_empty() {
+            true => self.empty_matching,
+            false => self.eof_matching,
+        };
+        (active & eof_matching != 0).then_some(s.len())
+    }
+
+    fn step(&self, mut active: u128, c: char) -> u128 {
+        let row = &self.successors[self.alphabet.class(c) * self.states..];
+        let mut next = 0;
+        while active != 0 {
+            next |= row[active.trailing_zeros() as usize];
+            active &= active - 1;
+        }
+        next
+    }
+
+    fn prepare<'s>(&self, s: &'s str) -> Cow<'s, str> {
+        if self.nfc {
+            normalize::nfc(s)
+        } else {
+            Cow::Borrowed(s)
+        }
+    }
+}
+
+impl TryFrom<&Engine> for BitNfa {
+    type Error = Error;
+
+    fn try_from(engine: &Engine) -> Result<BitNfa, Error> {
+        let nfa = &engine.nfa;
+        if nfa.states.len() > MAX_BIT_NFA_STATES {
+            let limit = MAX_BIT_NFA_STATES;
+            return Err(SizeLimitExceeded::States { limit }.into());
+        }
+        let (edges, alphabet) = dfa::edges(nfa)?;
+        if edges
+            .iter()
+            .flatten()
+            .any(|(edge, _)| matches!(edge, Edge::Counter(_)))
+        {
+            let message = "counted repetitions are not supported by the bit-parallel NFA";
+            return Err(Error::Unsupported(message.to_string()));
+        }
+
+        // states reachable from `states` over the edges `follow` accepts
+        let closure = |states: u128, follow: &dyn Fn(&Edge) -> bool| {
+            let mut closure = states;
+            let mut pending = states;
+            while pending != 0 {
+                let state = pending.trailing_zeros() as usize;
+                pending &= pending - 1;
+                for (edge, to) in edges[state].iter() {
+                    if follow(edge) && closure & (1 << to) == 0 {
+                        closure |= 1 << to;
+                        pending |= 1 << to;
+                    }
+                }
+            }
+            closure
+        };
+        let epsilon = |edge: &Edge| matches!(edge, Edge::Epsilon);
+
+        let states = nfa.states.len();
+        let mut successors = vec![0; alphabet.classes() * states];
+        for class in 0..alphabet.classes() {
+            let c = alphabet.representative(class);
+            for (state, state_edges) in edges.iter().enumerate() {
+                let entered = state_edges
+                    .iter()
+                    .fold(0, |entered, (edge, to)| match edge {
+                        Edge::Chars(t) if t.matches_char(c) == Some(true) => entered | 1 << to,
+                        _ => entered,
+                    });
+                successors[class * states + state] = closure(entered, &epsilon);
+            }
+        }
+
+        let matching = nfa.ending.iter().fold(0, |mask, &state| mask | 1 << state);
+        // the states ending over the edges `follow` accepts
+        let ending_over = |follow: &dyn Fn(&Edge) -> bool| {
+            (0..states)
+                .filter(|&state| closure(1 << state, follow) & matching != 0)
+                .fold(0, |mask, state| mask | 1 << state)
+        };
+        let eof_matching = ending_over(&|edge| matches!(edge, Edge::Epsilon | Edge::End));
+        let empty_matching =
+            ending_over(&|edge| matches!(edge, Edge::Epsilon | Edge::End | Edge::Start));
+
+        Ok(BitNfa {
+            alphabet,
+            states,
+            successors,
+            start: closure(1 << nfa.initial, &|edge| {
+                matches!(edge, Edge::Epsilon | Edge::Start)
+            }),
+            restart: closure(1 << nfa.initial, &epsilon),
+            matching,
+            eof_matching,
+            empty_matching,
+            nfc: engine.flags.nfc,
+            anchored: engine.flags.anchored,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BitNfa;
+    use crate::rsgex::{
+        builder::EngineBuilder,
+        engine::{Backend, Engine},
+        error::{Error, SizeLimitExceeded},
+    };
+
+    #[test]
+    fn test_agrees_with_engine() {
+        let patterns = [
+            "abc",
+            "a|ab",
+            "b+?",
+            "^[^a-c]+",
+            "\\d+-\\w*$",
+            "x*",
+            "^get$|getenv|gets",
+            "é+中",
+            // the empty input is both its start and its end
+            "$^",
+            "(?:$|b)(?:b|^)",
+        ];
+        let inputs = [
+            "", "abc", "xab", "b", "123", "4-x_", "aaaab", "-", "getenv", "ge", "cab", "x\nget",
+            "éé中",
+        ];
+
+        for pattern in patterns {
+            let e = Engine::try_from(pattern).unwrap();
+            let bit_nfa = BitNfa::try_from(&e).unwrap();
+            for input in inputs {
+                assert_eq!(
+                    bit_nfa.is_match(input),
+                    e.find_at(input, 0).is_some(),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+
+        let bit_nfa = BitNfa::try_from(&Engine::try_from("b+|abc").unwrap()).unwrap();
+        assert_eq!(bit_nfa.shortest_match("xabc"), Some(3));
+        assert_eq!(bit_nfa.shortest_match("中abc"), Some(5));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let literal = "x".repeat(128);
+        assert_eq!(
+            BitNfa::try_from(&Engine::try_from(literal.as_str()).unwrap()).err(),
+            Some(Error::SizeLimitExceeded(SizeLimitExceeded::States {
+                limit: 128
+            }))
+        );
+        assert!(matches!(
+            BitNfa::try_from(&Engine::try_from("a{2,3}").unwrap()),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(BitNfa::try_from(&Engine::try_from("\\bx").unwrap()).is_err());
+
+        let e = EngineBuilder::new()
+            .backend(Backend::PikeVm)
+            .build("a{2,3}$")
+            .unwrap();
+        assert!(BitNfa::try_from(&e).unwrap().is_match("xaa"));
+    }
+}
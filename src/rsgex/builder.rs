@@ -0,0 +1,838 @@
+use std::{sync::Arc, time::Duration};
+
+use regex_syntax::{ParserBuilder, ast, hir::Hir};
+
+use super::{
+    analysis::{self, Hazard},
+    bytes::{self, BytesEngine},
+    derivative::DerivativeMatcher,
+    engine::{Backend, Engine, Flags},
+    error::{Error, SizeLimitExceeded},
+    explain::{self, Explanation},
+    instrument,
+    matcher::{CustomMatcher, PredicateMatcher},
+    nfa::DEFAULT_RECURSION_LIMIT,
+    parser,
+    pattern::Pattern,
+};
+
+// see `EngineBuilder::nfa_size_limit`
+pub const DEFAULT_NFA_SIZE_LIMIT: usize = 10 << 20;
+
+// Configures the accepted dialect and compile options of an `Engine`.
+// The parser options are handed straight to `regex_syntax::ParserBuilder`.
+#[derive(Clone, Debug)]
+pub struct EngineBuilder {
+    parser: ParserBuilder,
+    // the options of `parser` that shape the AST, see
+    // `parser::parse_by_regex_syntax`
+    syntax: ast::parse::ParserBuilder,
+    recursion_limit: usize,
+    step_limit: Option<usize>,
+    timeout: Option<Duration>,
+    allow_duplicate_names: bool,
+    // only tracked so byte patterns can default to `(?-u)`
+    unicode: Option<bool>,
+    nfc: bool,
+    graphemes: bool,
+    backend: Backend,
+    anchored: bool,
+    track_captures: bool,
+    // group indices and names to capture, all of them when unset
+    captures: Option<Vec<String>>,
+    thread_local_cache: bool,
+    reject_catastrophic: bool,
+    linear_time: bool,
+    nfa_size_limit: usize,
+    dfa_size_limit: Option<usize>,
+    // matchers patterns may refer to as `\m{name}`
+    named: Vec<(String, CustomMatcher)>,
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self {
+            parser: ParserBuilder::new(),
+            syntax: ast::parse::ParserBuilder::new(),
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            step_limit: None,
+            timeout: None,
+            allow_duplicate_names: true,
+            unicode: None,
+            nfc: false,
+            graphemes: false,
+            backend: Backend::default(),
+            anchored: false,
+            track_captures: true,
+            captures: None,
+            thread_local_cache: false,
+            reject_catastrophic: false,
+            linear_time: false,
+            nfa_size_limit: DEFAULT_NFA_SIZE_LIMIT,
+            dfa_size_limit: None,
+            named: vec![],
+        }
+    }
+
+    pub fn build(&self, pattern: &str) -> Result<Engine, Error> {
+        self.compile(pattern, &self.parser, self.flags())
+    }
+
+    // Compiles a pattern built with `Pattern` rather than parsed from text.
+    // The parser options do not apply.
+    pub fn build_pattern(&self, pattern: &Pattern) -> Result<Engine, Error> {
+        self.build_hir(pattern.hir())
+    }
+
+    // Compiles a HIR built or rewritten by other tools, skipping the parser
+    // and its options. Captures are numbered in order, as the parser would,
+    // and the HIR must only match valid UTF-8 and leave out the chars
+    // patterns may not contain either, see `parser::is_sentinel`.
+    pub fn build_hir(&self, hir: &Hir) -> Result<Engine, Error> {
+        if !hir.properties().is_utf8() {
+            let message = "the HIR may match invalid UTF-8, which needs `build_bytes`";
+            return Err(Error::Unsupported(message.to_string()));
+        }
+        if parser::contains_reserved(hir) {
+            let message = "the HIR contains a char reserved for the pattern extensions";
+            return Err(Error::Unsupported(message.to_string()));
+        }
+        self.compile_parsed(parser::from_hir(hir), &self.parser, self.flags())
+    }
+
+    // Compiles a pattern for `&[u8]` haystacks. Unless `unicode` was set
+    // explicitly the pattern starts out in `(?-u)` mode, and it may match
+    // invalid UTF-8.
+    pub fn build_bytes(&self, pattern: &str) -> Result<BytesEngine, Error> {
+        let mut parser = self.parser.clone();
+        parser.utf8(false).unicode(self.unicode.unwrap_or(false));
+
+        let flags = Flags {
+            bytes: true,
+            anchored: self.anchored,
+            no_captures: !self.track_captures,
+            ..Flags::default()
+        };
+        let engine = self.compile(pattern, &parser, flags)?;
+        Ok(BytesEngine { engine })
+    }
+
+    // An experimental `DerivativeMatcher` for `pattern`, with the parser
+    // options and `anchored`; the other options only apply to engines.
+    pub fn build_derivative(&self, pattern: &str) -> Result<DerivativeMatcher, Error> {
+        let parsed = parser::parse_by_regex_syntax(
+            pattern,
+            &self.parser,
+            &self.syntax,
+            self.allow_duplicate_names,
+        )?;
+        DerivativeMatcher::from_parsed(&parsed, self.anchored)
+    }
+
+    // The constructs of `pattern` that may make the backtracking backend
+    // take more than linear time, see `Hazard`.
+    pub fn analyze(&self, pattern: &str) -> Result<Vec<Hazard>, Error> {
+        let parsed = parser::parse_by_regex_syntax(
+            pattern,
+            &self.parser,
+            &self.syntax,
+            self.allow_duplicate_names,
+        )?;
+        Ok(analysis::hazards(&parsed.hir))
+    }
+
+    // A breakdown of `pattern` into its parts, saying what each matches
+    // and how it is compiled with these options.
+    pub fn explain(&self, pattern: &str) -> Result<Explanation, Error> {
+        let parsed = parser::parse_by_regex_syntax(
+            pattern,
+            &self.parser,
+            &self.syntax,
+            self.allow_duplicate_names,
+        )?;
+        let engine = self.build(pattern)?;
+        Ok(explain::explain(&parsed, &engine))
+    }
+
+    fn flags(&self) -> Flags {
+        Flags {
+            nfc: self.nfc,
+            graphemes: self.graphemes,
+            anchored: self.anchored,
+            no_captures: !self.track_captures,
+            ..Flags::default()
+        }
+    }
+
+    fn compile(
+        &self,
+        pattern: &str,
+        parser: &ParserBuilder,
+        flags: Flags,
+    ) -> Result<Engine, Error> {
+        let _span = instrument::span!(DEBUG, "compile", pattern = pattern);
+        let parsed = parser::parse_by_regex_syntax(
+            pattern,
+            parser,
+            &self.syntax,
+            self.allow_duplicate_names,
+        )?;
+        self.compile_parsed(parsed, parser, flags)
+    }
+
+    // Compiles `pattern` into a branch of an engine with `flags` and
+    // `backend`, see `Engine::or_pattern`. The groups to capture refer to
+    // those of the engine, so they are not checked against the branch.
+    pub(crate) fn build_branch(
+        &self,
+        pattern: &str,
+        flags: Flags,
+        backend: Backend,
+    ) -> Result<Engine, Error> {
+        let builder = EngineBuilder {
+            backend,
+            captures: None,
+            ..self.clone()
+        };
+        builder.compile(pattern, &self.parser, flags)
+    }
+
+    // `parser` is kept with the other options, see `Engine::or_pattern`.
+    fn compile_parsed(
+        &self,
+        parsed: parser::Parsed,
+        parser: &ParserBuilder,
+        flags: Flags,
+    ) -> Result<Engine, Error> {
+        let backend = match self.backend {
+            Backend::Backtrack if self.linear_time => Backend::PikeVm,
+            backend => backend,
+        };
+        if self.linear_time {
+            let unsupported = |message: &str| Err(Error::Unsupported(message.to_string()));
+            if !parsed.calls.is_empty() {
+                return unsupported(
+                    "subroutine calls need backtracking, which linear time mode rules out",
+                );
+            }
+            if flags.graphemes {
+                return unsupported(
+                    "grapheme mode needs backtracking, which linear time mode rules out",
+                );
+            }
+        }
+        let size = Engine::estimated_size(&parsed.hir, backend);
+        if size > self.nfa_size_limit {
+            let limit = self.nfa_size_limit;
+            return Err(SizeLimitExceeded::Nfa { size, limit }.into());
+        }
+        if self.reject_catastrophic
+            && backend == Backend::Backtrack
+            && let Some(hazard) = analysis::hazards(&parsed.hir)
+                .into_iter()
+                .find(Hazard::is_exponential)
+        {
+            return Err(Error::Catastrophic(hazard));
+        }
+        if flags.bytes {
+            bytes::check_byte_pattern(&parsed.hir)?;
+        }
+        let captures = self.captures.as_deref().unwrap_or_default();
+        for group in captures {
+            let found = parser::find_capture(&parsed.hir, |c| {
+                c.index.to_string() == *group || c.name.as_deref() == Some(group)
+            });
+            if found.is_none() && group != "0" {
+                let message = format!("unknown capture group {}", group);
+                return Err(Error::Unsupported(message));
+            }
+        }
+        let named = parsed
+            .named
+            .iter()
+            .zip(&parsed.named_spans)
+            .map(
+                |(name, span)| match self.named.iter().find(|(defined, _)| defined == name) {
+                    Some((_, matcher)) => Ok(matcher.clone()),
+                    None => Err(Error::Parse {
+                        message: format!("unknown matcher {}", name),
+                        span: span.clone(),
+                    }),
+                },
+            )
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut e = Engine::from_parsed(parsed, flags, backend, named)?;
+        if self.captures.is_some() {
+            e.nfa.retain_capture_groups(|index, name| {
+                index == 0
+                    || captures
+                        .iter()
+                        .any(|group| *group == index.to_string() || Some(group.as_str()) == name)
+            });
+        }
+        e.nfa.recursion_limit = self.recursion_limit;
+        e.nfa.step_limit = self.step_limit;
+        e.nfa.timeout = self.timeout;
+        e.thread_local_cache = self.thread_local_cache;
+        e.dfa_size_limit = self.dfa_size_limit;
+        e.nfa_size_limit = self.nfa_size_limit;
+        e.builder = EngineBuilder {
+            parser: parser.clone(),
+            ..self.clone()
+        };
+
+        Ok(e)
+    }
+
+    // Defines a char class patterns can use as `\m{name}`, for classes
+    // the syntax cannot express, like `\m{hex}` below. Defining a name
+    // again replaces it.
+    pub fn define_class(
+        &mut self,
+        name: &str,
+        test: impl Fn(char) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        let matcher = PredicateMatcher {
+            label: name.to_string(),
+            test: Box::new(test),
+        };
+        let matcher = CustomMatcher(Arc::new(matcher));
+        self.named.retain(|(defined, _)| defined != name);
+        self.named.push((name.to_string(), matcher));
+        self
+    }
+
+    // x-mode: whitespace is insignificant and `#` starts a comment
+    pub fn ignore_whitespace(&mut self, yes: bool) -> &mut Self {
+        self.parser.ignore_whitespace(yes);
+        self.syntax.ignore_whitespace(yes);
+        self
+    }
+
+    // `a*` becomes lazy and `a*?` greedy
+    pub fn swap_greed(&mut self, yes: bool) -> &mut Self {
+        self.parser.swap_greed(yes);
+        self
+    }
+
+    // allow `\141` octal escapes, which disables backreference-like syntax
+    pub fn octal(&mut self, yes: bool) -> &mut Self {
+        self.parser.octal(yes);
+        self.syntax.octal(yes);
+        self
+    }
+
+    // maximum nesting depth of groups and nested class set operations
+    pub fn nest_limit(&mut self, limit: u32) -> &mut Self {
+        self.parser.nest_limit(limit);
+        self.syntax.nest_limit(limit);
+        self
+    }
+
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.parser.case_insensitive(yes);
+        self
+    }
+
+    pub fn multi_line(&mut self, yes: bool) -> &mut Self {
+        self.parser.multi_line(yes);
+        self
+    }
+
+    pub fn dot_matches_new_line(&mut self, yes: bool) -> &mut Self {
+        self.parser.dot_matches_new_line(yes);
+        self
+    }
+
+    pub fn crlf(&mut self, yes: bool) -> &mut Self {
+        self.parser.crlf(yes);
+        self
+    }
+
+    pub fn unicode(&mut self, yes: bool) -> &mut Self {
+        self.unicode = Some(yes);
+        self.parser.unicode(yes);
+        self
+    }
+
+    // PCRE-style reuse of a group name, e.g. `(?<y>\d{4})-\d\d|\d\d/(?<y>\d{4})`,
+    // reporting whichever group participated. Off rejects repeated names.
+    pub fn allow_duplicate_names(&mut self, yes: bool) -> &mut Self {
+        self.allow_duplicate_names = yes;
+        self
+    }
+
+    // NFC-normalize pattern literals and haystacks, so `é` and `e\u{301}`
+    // match each other
+    #[cfg(feature = "unicode-normalization")]
+    pub fn nfc(&mut self, yes: bool) -> &mut Self {
+        self.nfc = yes;
+        self
+    }
+
+    // `.` and other classes consume a whole extended grapheme cluster, so
+    // `^.{1,8}$` counts user-visible characters
+    #[cfg(feature = "unicode-segmentation")]
+    pub fn graphemes(&mut self, yes: bool) -> &mut Self {
+        self.graphemes = yes;
+        self
+    }
+
+    // `Backend::PikeVm` and `Backend::BoundedBacktrack` bound the matching
+    // time, but reject subroutine calls and grapheme mode
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = backend;
+        self
+    }
+
+    // Guarantees searches take time linear in the haystack length: the
+    // default `Backend::Backtrack` is replaced by `Backend::PikeVm`, and
+    // patterns needing backtracking, i.e. subroutine calls and grapheme
+    // mode, fail to build.
+    pub fn linear_time(&mut self, yes: bool) -> &mut Self {
+        self.linear_time = yes;
+        self
+    }
+
+    // Matches only ever start where the search starts, as if the pattern
+    // began with `\G`: `find_at` does not look further ahead and
+    // `find_iter` stops at the first gap between matches. No prefilter is
+    // built for such engines.
+    pub fn anchored(&mut self, yes: bool) -> &mut Self {
+        self.anchored = yes;
+        self
+    }
+
+    // Off compiles groups without their capture markers, for callers that
+    // only need spans or booleans. Matches then report the whole match as
+    // group 0 and nothing else.
+    pub fn track_captures(&mut self, yes: bool) -> &mut Self {
+        self.track_captures = yes;
+        self
+    }
+
+    // Captures only the listed groups, given by index or name, e.g.
+    // `captures(&["date", "level"])`. The others match without their
+    // bookkeeping, and the whole match is always group 0.
+    pub fn captures(&mut self, groups: &[&str]) -> &mut Self {
+        self.captures = Some(groups.iter().map(|group| group.to_string()).collect());
+        self
+    }
+
+    // Searches without a `Cache` of their own borrow one kept per thread,
+    // so repeated searches on a thread reuse their buffers like
+    // `search_with` does. The buffers stay allocated, at the size of the
+    // largest search, until the thread exits.
+    pub fn thread_local_cache(&mut self, yes: bool) -> &mut Self {
+        self.thread_local_cache = yes;
+        self
+    }
+
+    // Fails `build` with the `Hazard` as the error when `analyze` finds
+    // one that may take exponential time, unless another backend than
+    // `Backend::Backtrack` bounds the matching time anyway. Meant for
+    // services compiling patterns from their users.
+    pub fn reject_catastrophic(&mut self, yes: bool) -> &mut Self {
+        self.reject_catastrophic = yes;
+        self
+    }
+
+    // Approximate bytes the compiled automaton may take, 10 MiB by default.
+    // The size is estimated from the pattern before compiling it, so
+    // counted repetitions of large groups or huge classes fail to build
+    // with `SizeLimitExceeded::Nfa` instead of exhausting memory.
+    pub fn nfa_size_limit(&mut self, bytes: usize) -> &mut Self {
+        self.nfa_size_limit = bytes;
+        self
+    }
+
+    // Approximate bytes a `Dfa` built from the engine may take before
+    // `Dfa::try_from` gives up with `SizeLimitExceeded::Dfa`. Unlimited by
+    // default, apart from `MAX_DFA_STATES`.
+    pub fn dfa_size_limit(&mut self, bytes: usize) -> &mut Self {
+        self.dfa_size_limit = Some(bytes);
+        self
+    }
+
+    // maximum depth of nested `(?R)` / `(?1)` calls
+    pub fn recursion_limit(&mut self, limit: usize) -> &mut Self {
+        self.recursion_limit = limit;
+        self
+    }
+
+    // maximum backtracking steps of one search, after which `try_exec` and
+    // friends return `Error::StepLimitExceeded`
+    pub fn step_limit(&mut self, limit: usize) -> &mut Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    // wall-clock time one search may take before `try_exec` and friends
+    // return `Error::Timeout`
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::EngineBuilder;
+    use crate::rsgex::{
+        analysis::Hazard,
+        dfa::Dfa,
+        engine::{Backend, Engine},
+        error::{Error, SizeLimitExceeded},
+        tagged_dfa::TaggedDfa,
+    };
+
+    #[test]
+    fn test_ignore_whitespace() {
+        let e = EngineBuilder::new()
+            .ignore_whitespace(true)
+            .build("1 2 3 # digits")
+            .unwrap();
+
+        assert_eq!(e.exec_test("123"), "123");
+    }
+
+    #[test]
+    fn test_swap_greed() {
+        let e = EngineBuilder::new().swap_greed(true).build("01+").unwrap();
+
+        assert_eq!(e.exec_test("0111"), "01");
+    }
+
+    #[test]
+    fn test_octal() {
+        assert!(EngineBuilder::new().build("\\141").is_err());
+
+        let e = EngineBuilder::new().octal(true).build("\\141").unwrap();
+        assert!(e.test("a"));
+    }
+
+    #[test]
+    fn test_nest_limit() {
+        assert!(EngineBuilder::new().nest_limit(1).build("((a))").is_err());
+        assert!(EngineBuilder::new().nest_limit(1).build("(a)").is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let pattern = "(?<y>[0-9]{4})-[0-9]{2}|[0-9]{2}/(?<y>[0-9]{4})";
+        let e = EngineBuilder::new().build(pattern).unwrap();
+
+        assert_eq!(
+            e.exec("2024-05").unwrap().get("y").map(|s| s.as_str()),
+            Some("2024")
+        );
+        assert_eq!(
+            e.exec("05/1999").unwrap().get("y").map(|s| s.as_str()),
+            Some("1999")
+        );
+
+        let err = EngineBuilder::new()
+            .allow_duplicate_names(false)
+            .build(pattern)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("duplicate capture group name"));
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn test_nfc() {
+        let e = EngineBuilder::new()
+            .nfc(true)
+            .build("caf\u{65}\u{301}")
+            .unwrap();
+        assert!(e.test("caf\u{e9}"));
+        assert!(e.test("cafe\u{301}"));
+
+        let e = EngineBuilder::new().nfc(true).build("caf\u{e9}$").unwrap();
+        assert!(e.test("cafe\u{301}"));
+        assert_eq!(e.find_at("xcafe\u{301}", 0).unwrap().as_str(), "caf\u{e9}");
+
+        let e = EngineBuilder::new().build("caf\u{e9}").unwrap();
+        assert!(!e.test("cafe\u{301}"));
+    }
+
+    #[cfg(feature = "unicode-segmentation")]
+    #[test]
+    fn test_graphemes() {
+        let e = EngineBuilder::new()
+            .graphemes(true)
+            .build("^.{1,3}$")
+            .unwrap();
+        assert!(e.test("e\u{301}a\u{308}o"));
+        assert!(e.test("👨\u{200d}👩\u{200d}👧"));
+        assert!(!e.test("abcd"));
+
+        let e = EngineBuilder::new().build("^.{1,3}$").unwrap();
+        assert!(!e.test("e\u{301}a\u{308}o"));
+
+        let e = EngineBuilder::new()
+            .graphemes(true)
+            .build("[a-z]+")
+            .unwrap();
+        assert_eq!(e.exec_test("e\u{301}x!"), "e\u{301}x");
+    }
+
+    #[test]
+    fn test_backend() {
+        for backend in [Backend::PikeVm, Backend::BoundedBacktrack] {
+            let mut builder = EngineBuilder::new();
+            builder.backend(backend);
+
+            let e = builder.build("(?<y>[0-9]{4})-([0-9]{2})?").unwrap();
+            let groups = e.exec("2024-05").unwrap();
+            assert_eq!(groups.get("y").map(|s| s.as_str()), Some("2024"));
+            assert_eq!(groups.get("2").map(|s| s.as_str()), Some("05"));
+            assert_eq!(e.find_at("on 2024-", 0).unwrap().start, 3);
+            assert!(!e.test("24-05"));
+
+            // exponential for the plain backtracker
+            let e = builder.build("(a|aa)*c").unwrap();
+            assert!(e.find_at(&"a".repeat(64), 0).is_none());
+
+            assert!(builder.build("(a(?1)?b)").is_err());
+        }
+    }
+
+    #[test]
+    fn test_backend_captures() {
+        let patterns = [
+            "b(?:([ab])){1,3}",
+            "([ab])+c?",
+            "(?:(a)|(b)){2,4}",
+            "((a)b?){1,2}",
+            "(x){2}(y)?",
+        ];
+        let inputs = ["bbaa`", "abba", "babac", "aaba", "xxy", "xxxy", ""];
+        for pattern in patterns {
+            let expected = Engine::try_from(pattern).unwrap();
+            let e = EngineBuilder::new()
+                .backend(Backend::PikeVm)
+                .build(pattern)
+                .unwrap();
+            let tagged = TaggedDfa::try_from(&e).unwrap();
+            for input in inputs {
+                let m = expected.find_at(input, 0);
+                assert_eq!(tagged.find(input), m, "{} on {:?}", pattern, input);
+                for backend in [Backend::PikeVm, Backend::BoundedBacktrack] {
+                    let e = EngineBuilder::new()
+                        .backend(backend)
+                        .build(pattern)
+                        .unwrap();
+                    assert_eq!(e.find_at(input, 0), m, "{} on {:?}", pattern, input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let e = EngineBuilder::new()
+            .recursion_limit(1)
+            .build("^(\\((?1)?\\))$")
+            .unwrap();
+
+        assert!(e.test("(())"));
+        assert!(!e.test("((()))"));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let e = EngineBuilder::new()
+            .step_limit(10_000)
+            // no required literal the prefilter could rule the haystack out with
+            .build("(a|aa)*[bc]")
+            .unwrap();
+
+        assert_eq!(e.try_test("aac"), Ok(true));
+        assert_eq!(
+            e.try_find_at(&"a".repeat(64), 0),
+            Err(Error::StepLimitExceeded)
+        );
+        assert_eq!(e.try_test(&"a".repeat(64)), Err(Error::StepLimitExceeded));
+        assert!(!e.test(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let e = EngineBuilder::new()
+            .timeout(Duration::from_millis(20))
+            .build("(a|aa)*c")
+            .unwrap();
+
+        assert_eq!(e.try_test("aac"), Ok(true));
+        assert_eq!(e.try_test(&"a".repeat(64)), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn test_anchored() {
+        let e = EngineBuilder::new().anchored(true).build("[a-c]+").unwrap();
+        assert!(e.prefilter.is_none() && e.first_chars.is_none());
+
+        assert!(e.find_at("xab", 0).is_none());
+        assert_eq!(e.find_at("xab", 1).unwrap().as_str(), "ab");
+        let found: Vec<String> = e
+            .find_iter("ab-c")
+            .map(|m| m.as_str().to_string())
+            .collect();
+        assert_eq!(found, vec!["ab"]);
+
+        let dfa = Dfa::try_from(&e).unwrap();
+        assert!(!dfa.is_match("xab"));
+        assert_eq!(dfa.find("abx"), Some((0, 2)));
+    }
+
+    #[test]
+    fn test_track_captures() {
+        let pattern = "(?<key>\\w+)=(\\d+)";
+        let e = EngineBuilder::new()
+            .track_captures(false)
+            .build(pattern)
+            .unwrap();
+
+        let groups = e.exec("ab=12").unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups["0"], "ab=12");
+        let m = e.find_at("-- x=1", 0).unwrap();
+        assert_eq!((m.start, m.end, m.as_str()), (3, 6, "x=1"));
+
+        let tracked = EngineBuilder::new().build(pattern).unwrap();
+        let markers = |e: &Engine| {
+            e.nfa
+                .states
+                .iter()
+                .map(|s| s.start_group.len() + s.end_group.len())
+                .sum::<usize>()
+        };
+        assert_eq!(markers(&e), 2);
+        assert!(markers(&tracked) > 2);
+    }
+
+    #[test]
+    fn test_captures() {
+        let e = EngineBuilder::new()
+            .captures(&["date", "3"])
+            .build("(?<date>\\d+-\\d+) (?<level>[A-Z]+) (\\w+) (\\w+)")
+            .unwrap();
+
+        let groups = e.exec("05-12 INFO boot done").unwrap();
+        let mut keys: Vec<&str> = groups.keys().map(|k| k.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["0", "3", "date"]);
+        assert_eq!(groups["3"], "boot");
+
+        assert!(
+            EngineBuilder::new()
+                .captures(&["nope"])
+                .build("(a)")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_reject_catastrophic() {
+        let mut builder = EngineBuilder::new();
+        builder.reject_catastrophic(true);
+
+        let err = builder.build("^(\\w+\\s?)*$").err().unwrap();
+        assert!(matches!(
+            err,
+            Error::Catastrophic(Hazard::NestedQuantifier { .. })
+        ));
+        // only polynomial
+        assert!(builder.build("\\w*\\w*=").is_ok());
+        assert!(builder.backend(Backend::PikeVm).build("(a+)+").is_ok());
+
+        let hazards = EngineBuilder::new().analyze("\\w*\\w*=").unwrap();
+        assert_eq!(hazards.len(), 1);
+        assert!(!hazards[0].is_exponential());
+    }
+
+    #[test]
+    fn test_linear_time() {
+        let mut builder = EngineBuilder::new();
+        builder.linear_time(true);
+
+        let e = builder.build("(a+)+$").unwrap();
+        assert_eq!(e.backend, Backend::PikeVm);
+        assert!(!e.test(&format!("{}!", "a".repeat(64))));
+        assert!(builder.build("\\((?R)?\\)").is_err());
+
+        builder.backend(Backend::BoundedBacktrack);
+        assert_eq!(
+            builder.build("a+").unwrap().backend,
+            Backend::BoundedBacktrack
+        );
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let pike = || {
+            let mut builder = EngineBuilder::new();
+            builder.backend(Backend::PikeVm);
+            builder
+        };
+        let err = pike().build("(\\w{1000}){1000}").err().unwrap();
+        assert!(matches!(
+            err,
+            Error::SizeLimitExceeded(SizeLimitExceeded::Nfa { .. })
+        ));
+        // counter registers keep it small
+        assert!(EngineBuilder::new().build("(\\w{1000}){1000}").is_ok());
+        assert!(
+            EngineBuilder::new()
+                .nfa_size_limit(1 << 10)
+                .build("\\p{L}")
+                .is_err()
+        );
+
+        let e = pike()
+            .dfa_size_limit(1 << 12)
+            .build("[a-z]{0,20}x")
+            .unwrap();
+        assert_eq!(
+            Dfa::try_from(&e).err(),
+            Some(Error::SizeLimitExceeded(SizeLimitExceeded::Dfa {
+                limit: 1 << 12
+            }))
+        );
+        assert!(Dfa::try_from(&pike().build("[a-z]{0,20}x").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_define_class() {
+        let e = EngineBuilder::new()
+            .define_class("hex", |c| c.is_ascii_hexdigit())
+            .build("#\\m{hex}{6}\\b")
+            .unwrap();
+        assert_eq!(e.find_at("color: #1fA0c3;", 0).unwrap().start, 7);
+        assert!(!e.test("#1fA0g3"));
+        assert_eq!(e.min_match_len(), None);
+
+        let err = EngineBuilder::new().build("\\m{hex}").err().unwrap();
+        assert_eq!(err.to_string(), "unknown matcher hex");
+        assert_eq!(err.span(), Some(0..7));
+        // escaped, it is just text
+        assert!(Engine::try_from("\\\\m").unwrap().test("\\m"));
+        // the char `\m{hex}` is rewritten into cannot be written out
+        let err = EngineBuilder::new()
+            .define_class("hex", |c| c.is_ascii_hexdigit())
+            .build("\\x{100000}")
+            .err()
+            .unwrap();
+        assert_eq!(err.span(), Some(0..10));
+    }
+}
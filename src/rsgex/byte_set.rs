@@ -0,0 +1,128 @@
+// A set of bytes that can be searched for in a haystack. With the `simd`
+// feature on x86_64, 16 bytes are tested at once the way Teddy does: a
+// byte is a candidate when the table entry for its low nibble and the one
+// for its high nibble share a bit. High nibbles 8 apart share that bit, so
+// candidates are checked against the exact set.
+#[cfg_attr(not(all(feature = "simd", target_arch = "x86_64")), allow(dead_code))]
+pub struct ByteSet {
+    members: [u64; 4],
+    // low nibble -> buckets of the members with that low nibble
+    low: [u8; 16],
+    // high nibble -> its bucket, when a member has that high nibble
+    high: [u8; 16],
+}
+
+impl ByteSet {
+    pub fn new(bytes: impl IntoIterator<Item = u8>) -> Self {
+        let mut set = Self {
+            members: [0; 4],
+            low: [0; 16],
+            high: [0; 16],
+        };
+        for b in bytes {
+            let bucket = 1 << (b >> 4 & 7);
+            set.members[b as usize >> 6] |= 1 << (b & 63);
+            set.low[(b & 15) as usize] |= bucket;
+            set.high[(b >> 4) as usize] |= bucket;
+        }
+        set
+    }
+
+    pub fn len(&self) -> usize {
+        self.members
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn contains(&self, b: u8) -> bool {
+        self.members[b as usize >> 6] >> (b & 63) & 1 == 1
+    }
+
+    // The first position at or after `at` holding a member.
+    pub fn find(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        if std::arch::is_x86_feature_detected!("ssse3") {
+            // SAFETY: the CPU supports SSSE3
+            return unsafe { self.find_ssse3(haystack, at) };
+        }
+
+        self.find_scalar(haystack, at)
+    }
+
+    fn find_scalar(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        haystack
+            .get(at..)?
+            .iter()
+            .position(|&b| self.contains(b))
+            .map(|i| at + i)
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    #[target_feature(enable = "ssse3")]
+    unsafe fn find_ssse3(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        use std::arch::x86_64::*;
+
+        let load = |bytes: &[u8]| {
+            // SAFETY: `bytes` holds at least 16 bytes
+            unsafe { _mm_loadu_si128(bytes.as_ptr() as *const __m128i) }
+        };
+        let low = load(&self.low);
+        let high = load(&self.high);
+        let nibble = _mm_set1_epi8(0x0F);
+
+        let mut i = at;
+        while i + 16 <= haystack.len() {
+            let chunk = load(&haystack[i..]);
+            let low_buckets = _mm_shuffle_epi8(low, _mm_and_si128(chunk, nibble));
+            let high_nibbles = _mm_and_si128(_mm_srli_epi16(chunk, 4), nibble);
+            let high_buckets = _mm_shuffle_epi8(high, high_nibbles);
+            let empty = _mm_cmpeq_epi8(
+                _mm_and_si128(low_buckets, high_buckets),
+                _mm_setzero_si128(),
+            );
+
+            let mut candidates = !_mm_movemask_epi8(empty) as u32 & 0xFFFF;
+            while candidates != 0 {
+                let j = i + candidates.trailing_zeros() as usize;
+                if self.contains(haystack[j]) {
+                    return Some(j);
+                }
+                candidates &= candidates - 1;
+            }
+            i += 16;
+        }
+
+        self.find_scalar(haystack, i)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ByteSet;
+
+    #[test]
+    fn test_find() {
+        let haystack: Vec<u8> = (0..200u32).map(|i| (i * 37 % 251) as u8).collect();
+        // 0x21 and 0xA1 share a bucket, 0xA1 is not a member
+        let set = ByteSet::new([b'!', b'z', 0xC3]);
+        assert_eq!(set.len(), 3);
+
+        for at in 0..=haystack.len() + 1 {
+            assert_eq!(
+                set.find(&haystack, at),
+                set.find_scalar(&haystack, at),
+                "at {}",
+                at
+            );
+        }
+        assert_eq!(
+            set.find(
+                b"\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1\xA1!",
+                0
+            ),
+            Some(16)
+        );
+        assert_eq!(ByteSet::new([]).find(b"abc", 0), None);
+    }
+}
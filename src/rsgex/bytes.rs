@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+use super::{builder::EngineBuilder, engine::Engine, error::Error, haystack::Haystack};
+
+// A pattern compiled for `&[u8]` haystacks, e.g. binary protocols or log data
+// that is not valid UTF-8. Every byte is matched as the char of the same
+// value, so the automaton is shared with `Engine`.
+pub struct BytesEngine {
+    pub(crate) engine: Engine,
+}
+
+// start/end are byte offsets into the haystack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BytesMatch {
+    pub start: usize,
+    pub end: usize,
+    pub groups: HashMap<String, Vec<u8>>,
+}
+
+impl BytesMatch {
+    pub fn as_bytes(&self) -> &[u8] {
+        self.groups
+            .get(&0.to_string())
+            .map(|s| s.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+impl BytesEngine {
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn test(&self, haystack: &[u8]) -> bool {
+        self.exec(haystack).is_some()
+    }
+
+    pub fn exec(&self, haystack: &[u8]) -> Option<HashMap<String, Vec<u8>>> {
+        self.engine
+            .nfa
+            .search(&Haystack::Bytes(haystack), 0, 0)
+            .map(|(_, groups)| to_bytes(groups))
+    }
+
+    pub fn find_at(&self, haystack: &[u8], start: usize) -> Option<BytesMatch> {
+        self.find_in(&Haystack::Bytes(haystack), start)
+    }
+
+    pub fn find_iter<'e, 'h>(
+        &'e self,
+        haystack: &'h [u8],
+    ) -> impl Iterator<Item = BytesMatch> + use<'e, 'h> {
+        let haystack = Haystack::Bytes(haystack);
+        let mut next = 0;
+
+        std::iter::from_fn(move || {
+            if next > haystack.len() {
+                return None;
+            }
+            let m = self.find_in(&haystack, next)?;
+            next = if m.end == m.start { m.end + 1 } else { m.end };
+
+            Some(m)
+        })
+    }
+
+    fn find_in(&self, haystack: &Haystack, start: usize) -> Option<BytesMatch> {
+        self.engine.find_in(haystack, start).map(|m| BytesMatch {
+            start: m.start,
+            end: m.end,
+            groups: to_bytes(m.groups),
+        })
+    }
+}
+
+impl TryFrom<&str> for BytesEngine {
+    type Error = Error;
+
+    fn try_from(pattern: &str) -> Result<BytesEngine, Error> {
+        EngineBuilder::new().build_bytes(pattern)
+    }
+}
+
+// Unicode classes can only be matched byte by byte when they are ASCII.
+pub(crate) fn check_byte_pattern(hir: &Hir) -> Result<(), Error> {
+    match hir.kind() {
+        HirKind::Class(Class::Unicode(class)) if !class.is_ascii() => {
+            let message = "non-ASCII Unicode class in a byte pattern, use (?-u)";
+            Err(Error::Unsupported(message.to_string()))
+        }
+        HirKind::Capture(capture) => check_byte_pattern(&capture.sub),
+        HirKind::Repetition(repetition) => check_byte_pattern(&repetition.sub),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => {
+            hirs.iter().try_for_each(check_byte_pattern)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn to_bytes(groups: HashMap<String, String>) -> HashMap<String, Vec<u8>> {
+    groups
+        .into_iter()
+        .map(|(name, text)| (name, text.chars().map(|c| c as u8).collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::BytesEngine;
+    use crate::EngineBuilder;
+
+    #[test]
+    fn test_byte_pattern() {
+        let e = BytesEngine::try_from("\\xFF\\x00(.)").unwrap();
+
+        let res = e.exec(b"\xFF\x00\x80rest").unwrap();
+        assert_eq!(
+            res.get("0").map(|s| s.as_slice()),
+            Some(&b"\xFF\x00\x80"[..])
+        );
+        assert_eq!(res.get("1").map(|s| s.as_slice()), Some(&b"\x80"[..]));
+        assert!(!e.test(b"\xFE\x00a"));
+    }
+
+    #[test]
+    fn test_utf8_literal_in_byte_pattern() {
+        let e = BytesEngine::try_from("é+").unwrap();
+
+        assert_eq!(e.find_at("aéé".as_bytes(), 0).unwrap().start, 1);
+        assert_eq!(
+            e.find_at("aéé".as_bytes(), 0).unwrap().as_bytes(),
+            "éé".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_find_iter_over_bytes() {
+        let e = BytesEngine::try_from("[0-9]+").unwrap();
+
+        let found: Vec<Vec<u8>> = e
+            .find_iter(b"\xC3(12\xFF345")
+            .map(|m| m.as_bytes().to_vec())
+            .collect();
+        assert_eq!(found, vec![b"12".to_vec(), b"345".to_vec()]);
+    }
+
+    #[test]
+    fn test_unicode_class_rejected() {
+        assert!(BytesEngine::try_from("(?u:\\w)").is_err());
+        assert!(
+            EngineBuilder::new()
+                .unicode(true)
+                .build_bytes("\\w")
+                .is_err()
+        );
+        assert!(BytesEngine::try_from("(?u:[a-z])").is_ok());
+    }
+}
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+
+use super::nfa::{CaptureGroupRange, StackFrame, Visited};
+
+// Scratch buffers of the backtracking search, kept between searches so
+// repeated ones reuse their allocations. A cache works with any engine.
+#[derive(Default)]
+pub struct Cache {
+    pub(crate) stack: Vec<StackFrame>,
+    pub(crate) visited: Visited,
+    // capture slots of the last match
+    pub(crate) groups: Vec<Option<CaptureGroupRange>>,
+    // buffers of popped frames, handed to the frames pushed next
+    pub(crate) spare_mem: Vec<Vec<usize>>,
+    pub(crate) spare_groups: Vec<Vec<Option<CaptureGroupRange>>>,
+    // the transitions of the current state that passed their tests
+    pub(crate) passed: Vec<usize>,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+thread_local! {
+    static SCRATCH: RefCell<Cache> = RefCell::new(Cache::new());
+}
+
+// Runs `f` with the cache of the current thread, see
+// `EngineBuilder::thread_local_cache`. A search started from inside another
+// one, e.g. by a custom matcher, gets a fresh cache instead.
+pub(crate) fn with_thread_cache<T>(f: impl FnOnce(&mut Cache) -> T) -> T {
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut cache) => f(&mut cache),
+        Err(_) => f(&mut Cache::new()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    use super::Cache;
+    use crate::rsgex::{
+        builder::EngineBuilder,
+        engine::{Backend, Engine},
+    };
+
+    // Counts the allocations of the current thread, so tests running in
+    // parallel do not disturb each other.
+    struct Counting;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.with(|n| n.set(n.get() + 1));
+            // SAFETY: forwarded as is
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // SAFETY: forwarded as is
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    #[test]
+    fn test_no_allocation() {
+        let inputs = ["12-ab_c", "foo", "barbaz", "9-", "aab", "x"];
+        for backend in [Backend::Backtrack, Backend::BoundedBacktrack] {
+            for pattern in ["\\d+-\\w+", "(?<x>a|b)+c?", "foo|bar(baz)?"] {
+                let e = EngineBuilder::new()
+                    .backend(backend)
+                    .build(pattern)
+                    .unwrap();
+                let mut cache = Cache::new();
+                let expected: Vec<bool> = inputs.iter().map(|s| e.test(s)).collect();
+                // grow the cache; buffers change places in the pools, so it
+                // takes a few rounds until each one has its largest size
+                for _ in 0..3 {
+                    for input in inputs {
+                        e.test_with(&mut cache, input);
+                    }
+                }
+
+                let before = ALLOCATIONS.with(Cell::get);
+                let found: Vec<bool> = inputs.iter().map(|s| e.test_with(&mut cache, s)).collect();
+                // the `Vec` above
+                assert_eq!(ALLOCATIONS.with(Cell::get) - before, 1, "{}", pattern);
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_thread_local_cache() {
+        let e = EngineBuilder::new()
+            .thread_local_cache(true)
+            .build("(?<x>a|b)+c?")
+            .unwrap();
+        for _ in 0..3 {
+            e.test("aab");
+        }
+
+        let before = ALLOCATIONS.with(Cell::get);
+        assert!(e.test("aab") && !e.test("x"));
+        assert_eq!(ALLOCATIONS.with(Cell::get) - before, 0);
+        assert_eq!(e.find_at("xab", 0).unwrap().start, 1);
+    }
+
+    #[test]
+    fn test_search_with() {
+        let mut cache = Cache::new();
+        let inputs = ["", "x ab12 cd3", "ab", "--9", "ab12ab12"];
+        for backend in [Backend::Backtrack, Backend::BoundedBacktrack] {
+            let e = EngineBuilder::new()
+                .backend(backend)
+                .build("(?<w>[a-d]+)(\\d*)")
+                .unwrap();
+            for input in inputs {
+                let expected = e.find_at(input, 0);
+                let found = e.search_with(&mut cache, input);
+                assert_eq!(
+                    found.as_ref().map(|m| m.start),
+                    expected.as_ref().map(|m| m.start)
+                );
+                assert_eq!(found.map(|m| m.groups), expected.map(|m| m.groups));
+            }
+        }
+
+        // the same cache serves another engine
+        let e = Engine::try_from("(a|b)+c").unwrap();
+        assert_eq!(e.search_with(&mut cache, "xababc").unwrap().start, 1);
+        assert!(e.search_with(&mut cache, "abab").is_none());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use super::{builder::EngineBuilder, engine::Engine, error::Error};
+
+// engines kept unless `set_compile_cache_capacity` says otherwise
+pub const DEFAULT_CAPACITY: usize = 256;
+
+// (pattern, builder options) -> engine and when it was last used
+struct CompileCache {
+    engines: HashMap<(String, String), (Arc<Engine>, u64)>,
+    capacity: usize,
+    clock: u64,
+}
+
+static CACHE: Mutex<Option<CompileCache>> = Mutex::new(None);
+
+fn with_cache<T>(f: impl FnOnce(&mut CompileCache) -> T) -> T {
+    // the map is valid between any two statements, so a panic while it was
+    // locked leaves nothing to repair
+    let mut cache = CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(cache.get_or_insert_with(|| CompileCache {
+        engines: HashMap::new(),
+        capacity: DEFAULT_CAPACITY,
+        clock: 0,
+    }))
+}
+
+impl CompileCache {
+    fn get(&mut self, key: &(String, String)) -> Option<Arc<Engine>> {
+        self.clock += 1;
+        let (engine, used) = self.engines.get_mut(key)?;
+        *used = self.clock;
+        Some(engine.clone())
+    }
+
+    fn insert(&mut self, key: (String, String), engine: Arc<Engine>) {
+        self.clock += 1;
+        self.engines.insert(key, (engine, self.clock));
+        self.evict();
+    }
+
+    // drops the least recently used engines past the capacity
+    fn evict(&mut self) {
+        while self.engines.len() > self.capacity {
+            let oldest = self
+                .engines
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest {
+                self.engines.remove(&key);
+            }
+        }
+    }
+}
+
+impl EngineBuilder {
+    // Like `build`, but looks the engine up in a process-wide cache keyed by
+    // the pattern and the builder's options first, and adds it there when
+    // missing. The cache keeps the most recently used engines, see
+    // `set_compile_cache_capacity`. Patterns that fail to compile are not
+    // cached.
+    pub fn build_cached(&self, pattern: &str) -> Result<Arc<Engine>, Error> {
+        let key = (pattern.to_string(), format!("{:?}", self));
+        if let Some(engine) = with_cache(|cache| cache.get(&key)) {
+            return Ok(engine);
+        }
+
+        // compiled without holding the lock; a pattern compiled by two
+        // threads at once is cached twice, the second one winning
+        let engine = Arc::new(self.build(pattern)?);
+        with_cache(|cache| cache.insert(key, engine.clone()));
+        Ok(engine)
+    }
+}
+
+impl Engine {
+    // `build_cached` with the default options.
+    pub fn cached(pattern: &str) -> Result<Arc<Engine>, Error> {
+        EngineBuilder::new().build_cached(pattern)
+    }
+}
+
+// How many engines `build_cached` keeps, evicting the least recently used
+// ones right away when lowered. 0 disables caching.
+pub fn set_compile_cache_capacity(capacity: usize) {
+    with_cache(|cache| {
+        cache.capacity = capacity;
+        cache.evict();
+    });
+}
+
+pub fn clear_compile_cache() {
+    with_cache(|cache| cache.engines.clear());
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::CompileCache;
+    use crate::rsgex::{builder::EngineBuilder, engine::Engine};
+
+    #[test]
+    fn test_build_cached() {
+        let a = Engine::cached("ab+c").unwrap();
+        assert!(Arc::ptr_eq(&a, &Engine::cached("ab+c").unwrap()));
+        assert!(!Arc::ptr_eq(&a, &Engine::cached("ab+").unwrap()));
+
+        // other options make another engine
+        let insensitive = EngineBuilder::new()
+            .case_insensitive(true)
+            .build_cached("ab+c")
+            .unwrap();
+        assert!(!Arc::ptr_eq(&a, &insensitive));
+        assert!(insensitive.test("ABBC"));
+
+        assert!(Engine::cached("(").is_err());
+    }
+
+    #[test]
+    fn test_evict() {
+        // a cache of its own, as tests share the global one
+        let mut cache = CompileCache {
+            engines: Default::default(),
+            capacity: 2,
+            clock: 0,
+        };
+        let key = |pattern: &str| (pattern.to_string(), String::new());
+        let engine = Arc::new(Engine::try_from("x").unwrap());
+        cache.insert(key("a"), engine.clone());
+        cache.insert(key("b"), engine.clone());
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(key("c"), engine);
+        // `b` was used least recently
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("c")).is_some());
+    }
+}
@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+
+use regex_syntax::hir::{Class, Hir, HirKind, Look};
+
+use super::{builder::EngineBuilder, dfa, error::Error, parser};
+
+// A regular expression kept in a canonical form, so the derivatives of a
+// term only ever reach finitely many distinct terms: alternatives and
+// conjuncts are flattened, sorted and deduplicated, and concatenations
+// nest to the right. Children are indices into `DerivativeMatcher::terms`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Term {
+    // matches no text
+    Nothing,
+    // matches the empty text
+    Empty,
+    Start,
+    End,
+    Chars(Vec<(char, char)>),
+    Concat(usize, usize),
+    Or(Vec<usize>),
+    And(Vec<usize>),
+    Not(usize),
+    Star(usize),
+}
+
+const NOTHING: usize = 0;
+const EMPTY: usize = 1;
+// `Not(NOTHING)`, any text
+const ANY: usize = 2;
+
+// An experimental matcher working on the pattern itself rather than an
+// automaton: each char replaces the pattern by its Brzozowski derivative,
+// the pattern matching what may follow that char, and the input matches
+// when the pattern left matches the empty text. Derivatives are memoized
+// per term and class of chars the pattern cannot tell apart, so repeated
+// searches approach the speed of a lazily built DFA. As it needs nothing
+// but the pattern's meaning, it serves as an oracle for the other engines,
+// and supports the intersection and complement of patterns, see `and` and
+// `not`. Captures, laziness and look-arounds other than `^` / `$` are not
+// supported.
+pub struct DerivativeMatcher {
+    terms: Vec<Term>,
+    ids: HashMap<Term, usize>,
+    root: usize,
+    // interval of `c` is the number of boundaries <= `c`
+    boundaries: Vec<char>,
+    // (term, interval, at the input start) -> derivative
+    derivatives: HashMap<(usize, usize, bool), usize>,
+    // (term, at the input start, at the input end) -> whether it matches
+    // the empty text
+    nullable: HashMap<(usize, bool, bool), bool>,
+    anchored: bool,
+}
+
+impl DerivativeMatcher {
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        EngineBuilder::new().build_derivative(pattern)
+    }
+
+    pub(crate) fn from_parsed(parsed: &parser::Parsed, anchored: bool) -> Result<Self, Error> {
+        if !parsed.calls.is_empty() {
+            return Err(unsupported("subroutine calls are"));
+        }
+        let mut matcher = Self::empty(anchored);
+        matcher.root = matcher.lower(&parsed.hir)?;
+        matcher.finish();
+        Ok(matcher)
+    }
+
+    // Matches the texts both patterns match.
+    pub fn and(&self, other: &DerivativeMatcher) -> DerivativeMatcher {
+        self.combine(other, |matcher, a, b| matcher.and_of(vec![a, b]))
+    }
+
+    // Matches the texts either pattern matches.
+    pub fn or(&self, other: &DerivativeMatcher) -> DerivativeMatcher {
+        self.combine(other, |matcher, a, b| matcher.or_of(vec![a, b]))
+    }
+
+    // Matches the texts the pattern does not match, the empty one
+    // included.
+    pub fn not(&self) -> DerivativeMatcher {
+        let mut matcher = Self::empty(self.anchored);
+        let root = matcher.import(self, self.root, &mut HashMap::new());
+        matcher.root = matcher.not_of(root);
+        matcher.finish();
+        matcher
+    }
+
+    pub fn is_match(&mut self, s: &str) -> bool {
+        self.shortest_match(s).is_some()
+    }
+
+    // The byte offset where the earliest ending match ends.
+    pub fn shortest_match(&mut self, s: &str) -> Option<usize> {
+        let restart = if self.anchored { NOTHING } else { self.root };
+        let mut term = self.root;
+
+        for (i, c) in s.char_indices() {
+            if self.is_nullable(term, i == 0, false) {
+                return Some(i);
+            }
+            let next = self.derivative(term, self.interval(c), i == 0);
+            term = self.or_of(vec![next, restart]);
+        }
+
+        self.is_nullable(term, s.is_empty(), true)
+            .then_some(s.len())
+    }
+
+    // Whether the whole of `s` matches, as with `^(?:...)$`.
+    pub fn is_full_match(&mut self, s: &str) -> bool {
+        let mut term = self.root;
+        for (i, c) in s.char_indices() {
+            if term == NOTHING {
+                return false;
+            }
+            term = self.derivative(term, self.interval(c), i == 0);
+        }
+        self.is_nullable(term, s.is_empty(), true)
+    }
+
+    // how many distinct terms the derivatives taken so far went through
+    pub fn term_count(&self) -> usize {
+        self.terms.len()
+    }
+
+    fn empty(anchored: bool) -> Self {
+        let mut matcher = DerivativeMatcher {
+            terms: vec![],
+            ids: HashMap::new(),
+            root: NOTHING,
+            boundaries: vec![],
+            derivatives: HashMap::new(),
+            nullable: HashMap::new(),
+            anchored,
+        };
+        matcher.intern(Term::Nothing);
+        matcher.intern(Term::Empty);
+        matcher.intern(Term::Not(NOTHING));
+        matcher
+    }
+
+    fn combine(
+        &self,
+        other: &DerivativeMatcher,
+        f: impl FnOnce(&mut Self, usize, usize) -> usize,
+    ) -> DerivativeMatcher {
+        let mut matcher = Self::empty(self.anchored);
+        let a = matcher.import(self, self.root, &mut HashMap::new());
+        let b = matcher.import(other, other.root, &mut HashMap::new());
+        matcher.root = f(&mut matcher, a, b);
+        matcher.finish();
+        matcher
+    }
+
+    // Derives the char intervals from the terms.
+    fn finish(&mut self) {
+        let mut boundaries = vec![];
+        for term in self.terms.iter() {
+            if let Term::Chars(ranges) = term {
+                for &(start, end) in ranges {
+                    boundaries.push(start);
+                    boundaries.extend(dfa::successor(end));
+                }
+            }
+        }
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        self.boundaries = boundaries;
+    }
+
+    fn interval(&self, c: char) -> usize {
+        self.boundaries.partition_point(|&boundary| boundary <= c)
+    }
+
+    fn representative(&self, interval: usize) -> char {
+        match interval {
+            0 => '\0',
+            interval => self.boundaries[interval - 1],
+        }
+    }
+
+    fn lower(&mut self, hir: &Hir) -> Result<usize, Error> {
+        Ok(match hir.kind() {
+            HirKind::Empty => EMPTY,
+            HirKind::Literal(literal) => {
+                let Ok(text) = std::str::from_utf8(&literal.0) else {
+                    return Err(unsupported("invalid UTF-8 is"));
+                };
+                if text.chars().any(parser::is_sentinel) {
+                    return Err(unsupported("`\\G` is"));
+                }
+                text.chars().rev().fold(EMPTY, |rest, c| {
+                    let c = self.intern(Term::Chars(vec![(c, c)]));
+                    self.concat(c, rest)
+                })
+            }
+            HirKind::Class(Class::Unicode(class)) => {
+                self.chars(class.iter().map(|r| (r.start(), r.end())).collect())
+            }
+            HirKind::Class(Class::Bytes(class)) => self.chars(
+                class
+                    .iter()
+                    .map(|r| (r.start() as char, r.end() as char))
+                    .collect(),
+            ),
+            HirKind::Look(Look::Start) => self.intern(Term::Start),
+            HirKind::Look(Look::End) => self.intern(Term::End),
+            HirKind::Look(look) => {
+                return Err(unsupported(&format!("{:?} is", look)));
+            }
+            HirKind::Repetition(repetition) => {
+                let sub = self.lower(&repetition.sub)?;
+                let tail = match repetition.max {
+                    None => self.star(sub),
+                    Some(max) => {
+                        let optional = self.or_of(vec![EMPTY, sub]);
+                        (repetition.min..max).fold(EMPTY, |rest, _| self.concat(optional, rest))
+                    }
+                };
+                (0..repetition.min).fold(tail, |rest, _| self.concat(sub, rest))
+            }
+            HirKind::Capture(capture) => self.lower(&capture.sub)?,
+            HirKind::Concat(hirs) => {
+                let mut term = EMPTY;
+                for hir in hirs.iter().rev() {
+                    let first = self.lower(hir)?;
+                    term = self.concat(first, term);
+                }
+                term
+            }
+            HirKind::Alternation(hirs) => {
+                let branches = hirs
+                    .iter()
+                    .map(|hir| self.lower(hir))
+                    .collect::<Result<_, _>>()?;
+                self.or_of(branches)
+            }
+        })
+    }
+
+    // `term` of `from`, rebuilt among the terms of `self`
+    fn import(
+        &mut self,
+        from: &DerivativeMatcher,
+        term: usize,
+        imported: &mut HashMap<usize, usize>,
+    ) -> usize {
+        if let Some(&id) = imported.get(&term) {
+            return id;
+        }
+        let id = self.rebuild(from.terms[term].clone(), |matcher, child| {
+            matcher.import(from, child, imported)
+        });
+        imported.insert(term, id);
+        id
+    }
+
+    // `term` with its children mapped by `f`
+    fn rebuild(&mut self, term: Term, mut f: impl FnMut(&mut Self, usize) -> usize) -> usize {
+        match term {
+            Term::Concat(a, b) => {
+                let (a, b) = (f(self, a), f(self, b));
+                self.concat(a, b)
+            }
+            Term::Or(terms) => {
+                let terms = terms.into_iter().map(|term| f(self, term)).collect();
+                self.or_of(terms)
+            }
+            Term::And(terms) => {
+                let terms = terms.into_iter().map(|term| f(self, term)).collect();
+                self.and_of(terms)
+            }
+            Term::Not(a) => {
+                let a = f(self, a);
+                self.not_of(a)
+            }
+            Term::Star(a) => {
+                let a = f(self, a);
+                self.star(a)
+            }
+            leaf => self.intern(leaf),
+        }
+    }
+
+    // The derivative of `term` by a char of `interval`, where `^` holds
+    // only before the first char of the input.
+    fn derivative(&mut self, term: usize, interval: usize, at_start: bool) -> usize {
+        let key = (term, interval, at_start);
+        if let Some(&derivative) = self.derivatives.get(&key) {
+            return derivative;
+        }
+        let derivative = match self.terms[term].clone() {
+            Term::Nothing | Term::Empty | Term::Start | Term::End => NOTHING,
+            Term::Chars(ranges) => {
+                let c = self.representative(interval);
+                match ranges
+                    .iter()
+                    .any(|&(start, end)| (start..=end).contains(&c))
+                {
+                    true => EMPTY,
+                    false => NOTHING,
+                }
+            }
+            Term::Concat(a, b) => {
+                let da = self.derivative(a, interval, at_start);
+                let first = self.concat(da, b);
+                if self.is_nullable(a, at_start, false) {
+                    let db = self.derivative(b, interval, at_start);
+                    self.or_of(vec![first, db])
+                } else {
+                    first
+                }
+            }
+            Term::Or(terms) => {
+                let terms = terms
+                    .into_iter()
+                    .map(|term| self.derivative(term, interval, at_start))
+                    .collect();
+                self.or_of(terms)
+            }
+            Term::And(terms) => {
+                let terms = terms
+                    .into_iter()
+                    .map(|term| self.derivative(term, interval, at_start))
+                    .collect();
+                self.and_of(terms)
+            }
+            Term::Not(a) => {
+                let da = self.derivative(a, interval, at_start);
+                self.not_of(da)
+            }
+            Term::Star(a) => {
+                let da = self.derivative(a, interval, at_start);
+                self.concat(da, term)
+            }
+        };
+        self.derivatives.insert(key, derivative);
+        derivative
+    }
+
+    // Whether `term` matches the empty text, where `^` holds only at the
+    // input start and `$` only at the input end.
+    fn is_nullable(&mut self, term: usize, at_start: bool, at_end: bool) -> bool {
+        let key = (term, at_start, at_end);
+        if let Some(&nullable) = self.nullable.get(&key) {
+            return nullable;
+        }
+        let nullable = |matcher: &mut Self, t| matcher.is_nullable(t, at_start, at_end);
+        let result = match self.terms[term].clone() {
+            Term::Nothing | Term::Chars(_) => false,
+            Term::Empty | Term::Star(_) => true,
+            Term::Start => at_start,
+            Term::End => at_end,
+            Term::Concat(a, b) => nullable(self, a) && nullable(self, b),
+            Term::Or(terms) => terms.into_iter().any(|t| nullable(self, t)),
+            Term::And(terms) => terms.into_iter().all(|t| nullable(self, t)),
+            Term::Not(a) => !nullable(self, a),
+        };
+        self.nullable.insert(key, result);
+        result
+    }
+
+    fn intern(&mut self, term: Term) -> usize {
+        if let Some(&id) = self.ids.get(&term) {
+            return id;
+        }
+        let id = self.terms.len();
+        self.terms.push(term.clone());
+        self.ids.insert(term, id);
+        id
+    }
+
+    fn chars(&mut self, ranges: Vec<(char, char)>) -> usize {
+        match ranges.is_empty() {
+            true => NOTHING,
+            false => self.intern(Term::Chars(ranges)),
+        }
+    }
+
+    fn concat(&mut self, a: usize, b: usize) -> usize {
+        if a == NOTHING || b == NOTHING {
+            return NOTHING;
+        }
+        match self.terms[a] {
+            Term::Empty => b,
+            _ if b == EMPTY => a,
+            Term::Concat(x, y) => {
+                let rest = self.concat(y, b);
+                self.concat(x, rest)
+            }
+            _ => self.intern(Term::Concat(a, b)),
+        }
+    }
+
+    fn or_of(&mut self, terms: Vec<usize>) -> usize {
+        let mut flat = vec![];
+        for term in terms {
+            match &self.terms[term] {
+                Term::Or(terms) => flat.extend(terms),
+                Term::Nothing => (),
+                _ => flat.push(term),
+            }
+        }
+        flat.sort_unstable();
+        flat.dedup();
+
+        match flat.as_slice() {
+            [] => NOTHING,
+            [term] => *term,
+            _ if flat.contains(&ANY) => ANY,
+            _ => self.intern(Term::Or(flat)),
+        }
+    }
+
+    fn and_of(&mut self, terms: Vec<usize>) -> usize {
+        let mut flat = vec![];
+        for term in terms {
+            match &self.terms[term] {
+                Term::And(terms) => flat.extend(terms),
+                Term::Nothing => return NOTHING,
+                _ if term == ANY => (),
+                _ => flat.push(term),
+            }
+        }
+        flat.sort_unstable();
+        flat.dedup();
+
+        match flat.as_slice() {
+            [] => ANY,
+            [term] => *term,
+            _ => self.intern(Term::And(flat)),
+        }
+    }
+
+    fn not_of(&mut self, term: usize) -> usize {
+        match self.terms[term] {
+            Term::Not(inner) => inner,
+            _ => self.intern(Term::Not(term)),
+        }
+    }
+
+    fn star(&mut self, term: usize) -> usize {
+        match self.terms[term] {
+            Term::Nothing | Term::Empty => EMPTY,
+            Term::Star(_) => term,
+            _ => self.intern(Term::Star(term)),
+        }
+    }
+}
+
+// an `Error::Unsupported` for `what`, such as "`\\G` is"
+fn unsupported(what: &str) -> Error {
+    Error::Unsupported(format!("{} not supported by the derivative matcher", what))
+}
+
+#[cfg(test)]
+mod test {
+    use super::DerivativeMatcher;
+    use crate::rsgex::{builder::EngineBuilder, engine::Engine, error::Error};
+
+    #[test]
+    fn test_agrees_with_engine() {
+        let patterns = [
+            "abc",
+            "a|ab",
+            "^[^a-c]+",
+            "\\d+-\\w*$",
+            "x*",
+            "(a|b)*abb",
+            "^(\\w+\\s?)*$",
+            "é{2,3}中?",
+            "(?i)straße",
+            "^$",
+        ];
+        let inputs = [
+            "", "abc", "xab", "aababb", "123", "4-x_", "aaaab", "-", "ab cd ", "ééé", "STRASSE",
+            "Straße", "x\n",
+        ];
+
+        for pattern in patterns {
+            let e = Engine::try_from(pattern).unwrap();
+            let mut derivative = DerivativeMatcher::new(pattern).unwrap();
+            for input in inputs {
+                assert_eq!(
+                    derivative.is_match(input),
+                    e.find_at(input, 0).is_some(),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+
+        let mut derivative = DerivativeMatcher::new("b+|abc").unwrap();
+        assert_eq!(derivative.shortest_match("xabc"), Some(3));
+        let mut anchored = EngineBuilder::new()
+            .anchored(true)
+            .build_derivative("b+")
+            .unwrap();
+        assert!(!anchored.is_match("ab"));
+    }
+
+    #[test]
+    fn test_start_mid_pattern() {
+        let mut derivative = DerivativeMatcher::new(".^b").unwrap();
+        assert!(!derivative.is_match("cb"));
+        assert!(!derivative.is_full_match("cb"));
+
+        let mut derivative = DerivativeMatcher::new("a^[a-c]").unwrap();
+        assert!(!derivative.is_full_match("ac"));
+        assert!(!derivative.is_match("xac"));
+
+        let mut derivative = DerivativeMatcher::new("(?:^|x)b").unwrap();
+        assert_eq!(derivative.shortest_match("bb"), Some(1));
+        assert_eq!(derivative.shortest_match("abxb"), Some(4));
+        assert!(!derivative.is_match("ab"));
+        assert!(derivative.is_full_match("xb"));
+
+        let mut derivative = DerivativeMatcher::new("a*^b").unwrap();
+        assert!(derivative.is_full_match("b"));
+        assert!(!derivative.is_full_match("ab"));
+    }
+
+    #[test]
+    fn test_and_not() {
+        let word = DerivativeMatcher::new("\\w+").unwrap();
+        let digit = DerivativeMatcher::new("\\w*\\d\\w*").unwrap();
+
+        let mut letters = word.and(&digit.not());
+        assert!(letters.is_full_match("abc"));
+        assert!(!letters.is_full_match("ab1"));
+        assert!(!letters.is_full_match(""));
+
+        let mut either = DerivativeMatcher::new("a+")
+            .unwrap()
+            .or(&DerivativeMatcher::new("b+").unwrap());
+        assert!(either.is_full_match("bb"));
+        assert!(!either.is_full_match("ab"));
+
+        assert!(matches!(
+            DerivativeMatcher::new("\\bx"),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            DerivativeMatcher::new("(a"),
+            Err(Error::Parse { .. })
+        ));
+        assert!(DerivativeMatcher::new("(a)(?1)").is_err());
+    }
+}
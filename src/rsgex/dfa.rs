@@ -0,0 +1,803 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet},
+};
+
+use super::{
+    alphabet::Alphabet,
+    engine::Engine,
+    error::{Error, SizeLimitExceeded},
+    matcher::{CounterOp, DfaEdge, Transition},
+    nfa::NFAutomata,
+    normalize,
+};
+
+pub(crate) const DEAD: usize = 0;
+
+// Determinization is abandoned past this many states.
+pub const MAX_DFA_STATES: usize = 10_000;
+
+// nfa state, consuming transition or `None` for an accepting state, counters.
+// A DFA state is a list of these in the backtracker's priority order.
+type Config = (usize, Option<usize>, Vec<u32>);
+
+// configs, restart, at_start, see `Determinizer::ids`
+type StateKey = (Vec<Config>, bool, bool);
+
+// the classified transitions of one state
+pub(crate) type Edges = Vec<(Edge, usize)>;
+
+pub(crate) enum Edge {
+    Epsilon,
+    Start,
+    End,
+    Counter(CounterOp),
+    Chars(Transition),
+}
+
+// A table-driven DFA built from an engine's automaton by subset
+// construction. It only reports match positions, not captures, and rejects
+// automata with look-arounds other than `^` / `$`, `\G`, subroutine calls
+// or grapheme mode.
+pub struct Dfa {
+    alphabet: Alphabet,
+    // a row of next states per state, one column per char class
+    table: Vec<usize>,
+    matching: Vec<bool>,
+    // matching once `$` is known to hold
+    eof_matching: Vec<bool>,
+    // [anchored, unanchored] x [at the input start, elsewhere]
+    starts: [[usize; 2]; 2],
+    nfc: bool,
+    // matches only start at the input start, see `EngineBuilder::anchored`
+    anchored: bool,
+    // matches the reversed automaton from a match end back to the match
+    // start, when it can be determinized
+    reverse: Option<Box<Dfa>>,
+}
+
+impl Dfa {
+    pub fn is_match(&self, s: &str) -> bool {
+        let s = self.prepare(s);
+        let mut state = self.starts[usize::from(!self.anchored)][0];
+
+        for c in s.chars() {
+            if self.matching[state] {
+                return true;
+            }
+            state = self.next(state, c);
+        }
+
+        self.matching[state] || self.eof_matching[state]
+    }
+
+    // Byte offsets of the leftmost-first match, as `Engine::find_at` would
+    // report it. One unanchored scan finds where the match ends and, given
+    // the reversed automaton, one scan back from there finds its start.
+    pub fn find(&self, s: &str) -> Option<(usize, usize)> {
+        let s = self.prepare(s);
+        if self.anchored {
+            return self.longest_from(&s, 0).map(|end| (0, end));
+        }
+        if let Some(reverse) = &self.reverse {
+            let end = self.leftmost_end(&s)?;
+            return Some((reverse.longest_start(&s, end)?, end));
+        }
+
+        s.char_indices()
+            .map(|(at, _)| at)
+            .chain([s.len()])
+            .find_map(|at| self.longest_from(&s, at).map(|end| (at, end)))
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.matching.len()
+    }
+
+    // Merges equivalent states with Hopcroft's partition refinement. The
+    // minimized DFA reports the same matches with the fewest states.
+    pub fn minimize(&self) -> Dfa {
+        let classes = self.alphabet.classes();
+        let states = self.state_count();
+
+        // class -> target -> sources
+        let mut inverse = vec![vec![vec![]; states]; classes];
+        for state in 0..states {
+            for (class, sources) in inverse.iter_mut().enumerate() {
+                sources[self.table[state * classes + class]].push(state);
+            }
+        }
+
+        let mut blocks: Vec<Vec<usize>> = vec![];
+        let mut block_of = vec![0; states];
+        let mut kinds: HashMap<(bool, bool), usize> = HashMap::new();
+        for (state, block_of_state) in block_of.iter_mut().enumerate() {
+            let kind = (self.matching[state], self.eof_matching[state]);
+            let block = *kinds.entry(kind).or_insert_with(|| {
+                blocks.push(vec![]);
+                blocks.len() - 1
+            });
+            blocks[block].push(state);
+            *block_of_state = block;
+        }
+
+        let mut waiting: Vec<usize> = (0..blocks.len()).collect();
+        let mut is_waiting = vec![true; blocks.len()];
+        while let Some(splitter) = waiting.pop() {
+            is_waiting[splitter] = false;
+            let splitter_states = blocks[splitter].clone();
+
+            for sources in inverse.iter() {
+                // block -> its states leading into the splitter
+                let mut hits: HashMap<usize, Vec<usize>> = HashMap::new();
+                for &target in splitter_states.iter() {
+                    for &source in sources[target].iter() {
+                        hits.entry(block_of[source]).or_default().push(source);
+                    }
+                }
+
+                for (block, mut hit) in hits {
+                    hit.sort_unstable();
+                    hit.dedup();
+                    if hit.len() == blocks[block].len() {
+                        continue;
+                    }
+
+                    let rest: Vec<usize> = blocks[block]
+                        .iter()
+                        .copied()
+                        .filter(|state| hit.binary_search(state).is_err())
+                        .collect();
+                    let split = blocks.len();
+                    for &state in rest.iter() {
+                        block_of[state] = split;
+                    }
+                    blocks[block] = hit;
+                    blocks.push(rest);
+
+                    let smaller = if is_waiting[block] || blocks[split].len() < blocks[block].len()
+                    {
+                        split
+                    } else {
+                        block
+                    };
+                    is_waiting.push(false);
+                    if !is_waiting[smaller] {
+                        is_waiting[smaller] = true;
+                        waiting.push(smaller);
+                    }
+                }
+            }
+        }
+
+        // renumber the blocks in state order, which keeps the dead state at 0
+        let mut ids = vec![usize::MAX; blocks.len()];
+        let mut representatives = vec![];
+        for (state, &block) in block_of.iter().enumerate() {
+            if ids[block] == usize::MAX {
+                ids[block] = representatives.len();
+                representatives.push(state);
+            }
+        }
+
+        let table = representatives
+            .iter()
+            .flat_map(|&state| {
+                self.table[state * classes..(state + 1) * classes]
+                    .iter()
+                    .map(|&target| ids[block_of[target]])
+            })
+            .collect();
+
+        Dfa {
+            alphabet: self.alphabet.clone(),
+            table,
+            matching: representatives.iter().map(|&s| self.matching[s]).collect(),
+            eof_matching: representatives
+                .iter()
+                .map(|&s| self.eof_matching[s])
+                .collect(),
+            starts: self.starts.map(|row| row.map(|start| ids[block_of[start]])),
+            nfc: self.nfc,
+            anchored: self.anchored,
+            reverse: self
+                .reverse
+                .as_ref()
+                .map(|reverse| Box::new(reverse.minimize())),
+        }
+    }
+
+    // Where the leftmost-first match ends. The unanchored states stop
+    // starting new threads once a match is found, so the last match seen
+    // before the automaton dies is the one of the leftmost start.
+    fn leftmost_end(&self, s: &str) -> Option<usize> {
+        let mut state = self.starts[1][0];
+        let mut end = self.matching[state].then_some(0);
+
+        for (i, c) in s.char_indices() {
+            state = self.next(state, c);
+            if state == DEAD {
+                return end;
+            }
+            if self.matching[state] {
+                end = Some(i + c.len_utf8());
+            }
+        }
+        if self.eof_matching[state] {
+            end = Some(s.len());
+        }
+
+        end
+    }
+
+    // On the reversed automaton, the furthest position before `end` that a
+    // match reaches from `end`.
+    fn longest_start(&self, s: &str, end: usize) -> Option<usize> {
+        let mut state = self.starts[0][usize::from(end < s.len())];
+        let mut start = self.matching[state].then_some(end);
+
+        for (i, c) in s[..end].char_indices().rev() {
+            state = self.next(state, c);
+            if state == DEAD {
+                return start;
+            }
+            if self.matching[state] {
+                start = Some(i);
+            }
+        }
+        if self.eof_matching[state] {
+            start = Some(0);
+        }
+
+        start
+    }
+
+    fn longest_from(&self, s: &str, at: usize) -> Option<usize> {
+        let mut state = self.starts[0][usize::from(at > 0)];
+        let mut end = self.matching[state].then_some(at);
+
+        for (i, c) in s[at..].char_indices() {
+            state = self.next(state, c);
+            if state == DEAD {
+                return end;
+            }
+            if self.matching[state] {
+                end = Some(at + i + c.len_utf8());
+            }
+        }
+        if self.eof_matching[state] {
+            end = Some(s.len());
+        }
+
+        end
+    }
+
+    fn next(&self, state: usize, c: char) -> usize {
+        self.table[state * self.alphabet.classes() + self.alphabet.class(c)]
+    }
+
+    fn prepare<'s>(&self, s: &'s str) -> Cow<'s, str> {
+        if self.nfc {
+            normalize::nfc(s)
+        } else {
+            Cow::Borrowed(s)
+        }
+    }
+}
+
+impl TryFrom<&Engine> for Dfa {
+    type Error = Error;
+
+    // Determinizes the automaton with `{n,m}` unrolled, which matches like
+    // the counted one.
+    fn try_from(engine: &Engine) -> Result<Dfa, Error> {
+        let nfa = engine.unrolled_nfa()?;
+        let mut determinizer = Determinizer::new(nfa)?;
+        determinizer.limit = engine.dfa_size_limit;
+        let mut dfa = determinizer.build()?;
+        dfa.nfc = engine.flags.nfc;
+        dfa.anchored = engine.flags.anchored;
+
+        let reversed = reverse(nfa);
+        dfa.reverse = reversed
+            .as_ref()
+            .and_then(|reversed| {
+                let mut determinizer = Determinizer::new(reversed).ok()?;
+                determinizer.leftmost_first = false;
+                determinizer.limit = engine.dfa_size_limit;
+                determinizer.build().ok()
+            })
+            .map(Box::new);
+
+        Ok(dfa)
+    }
+}
+
+pub(crate) struct Determinizer<'a> {
+    nfa: &'a NFAutomata,
+    edges: Vec<Edges>,
+    alphabet: Alphabet,
+    // (configs, restart, at_start) -> DFA state; unanchored states restart
+    // the automaton after every char, and the start states at the input
+    // start are kept apart, as the input may end there too
+    ids: HashMap<StateKey, usize>,
+    queue: Vec<StateKey>,
+    pub(crate) matching: Vec<bool>,
+    pub(crate) eof_matching: Vec<bool>,
+    // approximate bytes held by the states added so far
+    pub(crate) memory: usize,
+    // configs behind an accepting one are dropped, see `cut`; without it
+    // the states track every match, as the reversed automaton needs
+    pub(crate) leftmost_first: bool,
+    // bytes `memory` may reach, see `EngineBuilder::dfa_size_limit`
+    pub(crate) limit: Option<usize>,
+}
+
+impl<'a> Determinizer<'a> {
+    pub(crate) fn new(nfa: &'a NFAutomata) -> Result<Self, Error> {
+        let (edges, alphabet) = edges(nfa)?;
+
+        Ok(Self {
+            nfa,
+            edges,
+            alphabet,
+            ids: HashMap::new(),
+            queue: vec![],
+            matching: vec![],
+            eof_matching: vec![],
+            memory: 0,
+            leftmost_first: true,
+            limit: None,
+        })
+    }
+
+    fn build(&mut self) -> Result<Dfa, Error> {
+        let starts = self.starts();
+
+        let mut table = vec![];
+        let mut processed = 0;
+        while processed < self.queue.len() {
+            for class in 0..self.alphabet.classes() {
+                table.push(self.transition(processed, class));
+            }
+            processed += 1;
+            self.check_limits()?;
+        }
+
+        Ok(Dfa {
+            alphabet: self.alphabet.clone(),
+            table,
+            matching: self.matching.clone(),
+            eof_matching: self.eof_matching.clone(),
+            starts,
+            nfc: false,
+            anchored: false,
+            reverse: None,
+        })
+    }
+
+    // Fails once the states outgrow `MAX_DFA_STATES` or the size limit.
+    pub(crate) fn check_limits(&self) -> Result<(), Error> {
+        if self.queue.len() > MAX_DFA_STATES {
+            let limit = MAX_DFA_STATES;
+            return Err(SizeLimitExceeded::States { limit }.into());
+        }
+        if let Some(limit) = self.limit
+            && self.memory > limit
+        {
+            return Err(SizeLimitExceeded::Dfa { limit }.into());
+        }
+        Ok(())
+    }
+
+    // Adds the dead state and the start states, indexed like `Dfa::starts`.
+    pub(crate) fn starts(&mut self) -> [[usize; 2]; 2] {
+        self.id((vec![], false, false));
+
+        let mut starts = [[DEAD; 2]; 2];
+        for (unanchored, row) in starts.iter_mut().enumerate() {
+            for (elsewhere, start) in row.iter_mut().enumerate() {
+                let configs = self.start(elsewhere == 0);
+                *start = self.id((configs, unanchored == 1, elsewhere == 0));
+            }
+        }
+        starts
+    }
+
+    // The state reached from `state` on a char of `class`, added if new.
+    // Threads starting later than a match cannot win, so unanchored states
+    // stop restarting once they match.
+    pub(crate) fn transition(&mut self, state: usize, class: usize) -> usize {
+        let (configs, restart, _) = &self.queue[state];
+        let restart = *restart && !self.matching[state];
+        let next = self.step(configs, self.alphabet.representative(class), restart);
+        self.id((next, restart, false))
+    }
+
+    pub(crate) fn alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
+    pub(crate) fn class(&self, c: char) -> usize {
+        self.alphabet.class(c)
+    }
+
+    pub(crate) fn classes(&self) -> usize {
+        self.alphabet.classes()
+    }
+
+    fn id(&mut self, key: StateKey) -> usize {
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        let (configs, _, at_start) = &key;
+        let is_match = configs
+            .iter()
+            .any(|(_, transition, _)| transition.is_none());
+        self.matching.push(is_match);
+        self.eof_matching
+            .push(is_match || self.matches_at_end(configs, *at_start));
+        // the key is stored twice, plus a row of transitions
+        self.memory += 2 * configs
+            .iter()
+            .map(|(_, _, counters)| size_of::<Config>() + counters.len() * size_of::<u32>())
+            .sum::<usize>()
+            + self.alphabet.classes() * size_of::<usize>();
+
+        let id = self.queue.len();
+        self.ids.insert(key.clone(), id);
+        self.queue.push(key);
+        id
+    }
+
+    fn start(&self, at_start: bool) -> Vec<Config> {
+        let mut configs = vec![];
+        let counters = vec![0; self.nfa.counters];
+        self.closure(
+            &mut configs,
+            &mut HashSet::new(),
+            self.nfa.initial,
+            counters,
+            at_start,
+        );
+        self.cut(configs)
+    }
+
+    fn step(&self, configs: &[Config], c: char, restart: bool) -> Vec<Config> {
+        let mut next = vec![];
+        let mut seen = HashSet::new();
+
+        for (state, transition, counters) in configs.iter() {
+            let Some(transition) = transition else {
+                continue;
+            };
+            if let (Edge::Chars(matcher), to) = &self.edges[*state][*transition]
+                && matcher.matches_char(c) == Some(true)
+            {
+                self.closure(&mut next, &mut seen, *to, counters.clone(), false);
+            }
+        }
+        if restart {
+            let counters = vec![0; self.nfa.counters];
+            self.closure(&mut next, &mut seen, self.nfa.initial, counters, false);
+        }
+
+        self.cut(next)
+    }
+
+    // configs behind an accepting one can never win
+    fn cut(&self, mut configs: Vec<Config>) -> Vec<Config> {
+        if self.leftmost_first
+            && let Some(accepting) = configs.iter().position(|(_, t, _)| t.is_none())
+        {
+            configs.truncate(accepting + 1);
+        }
+        configs
+    }
+
+    // Appends the configs reachable from `state` without consuming, depth
+    // first so they keep the backtracker's priority order.
+    fn closure(
+        &self,
+        configs: &mut Vec<Config>,
+        seen: &mut HashSet<(usize, Vec<u32>)>,
+        state: usize,
+        counters: Vec<u32>,
+        at_start: bool,
+    ) {
+        let mut stack = vec![(state, None, counters)];
+
+        while let Some((state, emit, counters)) = stack.pop() {
+            if emit.is_some() {
+                configs.push((state, emit, counters));
+                continue;
+            }
+            if !seen.insert((state, counters.clone())) {
+                continue;
+            }
+            if self.nfa.states[state].is_ending {
+                configs.push((state, None, counters));
+                continue;
+            }
+
+            for (transition, (edge, to)) in self.edges[state].iter().enumerate().rev() {
+                match edge {
+                    Edge::Epsilon => stack.push((*to, None, counters.clone())),
+                    Edge::Start if at_start => stack.push((*to, None, counters.clone())),
+                    Edge::Start => (),
+                    Edge::Counter(op) => {
+                        if let Some(next_counters) = op.apply(&counters) {
+                            stack.push((*to, None, next_counters));
+                        }
+                    }
+                    Edge::End | Edge::Chars(_) => {
+                        stack.push((state, Some(transition), counters.clone()))
+                    }
+                }
+            }
+        }
+    }
+
+    // Whether a waiting `$` leads to an accepting state at the end of input.
+    // At the input start, i.e. on an empty input, `^` holds there as well.
+    fn matches_at_end(&self, configs: &[Config], at_start: bool) -> bool {
+        let mut seen = HashSet::new();
+        let mut stack: Vec<(usize, Vec<u32>)> = configs
+            .iter()
+            .filter_map(|(state, transition, counters)| match transition {
+                Some(transition) => match &self.edges[*state][*transition] {
+                    (Edge::End, to) => Some((*to, counters.clone())),
+                    _ => None,
+                },
+                None => None,
+            })
+            .collect();
+
+        while let Some((state, counters)) = stack.pop() {
+            if self.nfa.states[state].is_ending {
+                return true;
+            }
+            if !seen.insert((state, counters.clone())) {
+                continue;
+            }
+            for (edge, to) in self.edges[state].iter() {
+                match edge {
+                    Edge::Epsilon | Edge::End => stack.push((*to, counters.clone())),
+                    Edge::Start if at_start => stack.push((*to, counters.clone())),
+                    Edge::Counter(op) => {
+                        if let Some(next_counters) = op.apply(&counters) {
+                            stack.push((*to, next_counters));
+                        }
+                    }
+                    Edge::Start | Edge::Chars(_) => (),
+                }
+            }
+        }
+
+        false
+    }
+}
+
+// Classifies every transition of `nfa`, along with the classes of chars no
+// transition can tell apart.
+pub(crate) fn edges(nfa: &NFAutomata) -> Result<(Vec<Edges>, Alphabet), Error> {
+    let mut edges = vec![];
+    let mut boundaries = BTreeSet::new();
+
+    for state in nfa.states.iter() {
+        let mut state_edges = vec![];
+        for (matcher, to) in state.matchers.iter() {
+            let edge = if let Some(op) = matcher.counter_op() {
+                Edge::Counter(op)
+            } else {
+                match matcher.dfa_edge() {
+                    Some(DfaEdge::Epsilon) => Edge::Epsilon,
+                    Some(DfaEdge::Start) => Edge::Start,
+                    Some(DfaEdge::End) => Edge::End,
+                    Some(DfaEdge::Chars(ranges)) => {
+                        for (start, end) in ranges {
+                            boundaries.insert(start);
+                            if let Some(after) = successor(end) {
+                                boundaries.insert(after);
+                            }
+                        }
+                        Edge::Chars(matcher.clone())
+                    }
+                    None => {
+                        return Err(Error::Unsupported(format!(
+                            "transition {} is not supported by the DFA",
+                            matcher.label()
+                        )));
+                    }
+                }
+            };
+            state_edges.push((edge, *to));
+        }
+        edges.push(state_edges);
+    }
+
+    let alphabet = Alphabet::new(&edges, boundaries.into_iter().collect());
+    Ok((edges, alphabet))
+}
+
+// The automaton matching the reversed texts of `nfa`'s matches, with `^`
+// and `$` swapped. `None` when it has counters, which do not run backwards.
+fn reverse(nfa: &NFAutomata) -> Option<NFAutomata> {
+    let mut reversed = NFAutomata::new();
+    // a new initial state leading to every ending
+    reversed.fill_state(nfa.states.len() + 1);
+    let initial = nfa.states.len();
+    reversed.set_initial(initial);
+    reversed.add_ending(nfa.initial);
+
+    for (from, state) in nfa.states.iter().enumerate() {
+        if state.is_ending {
+            reversed.add_epsilon_transition(initial, from);
+        }
+        for (transition, to) in state.matchers.iter() {
+            let transition = match transition {
+                Transition::Start => Transition::End,
+                Transition::End => Transition::Start,
+                Transition::Counter(_) => return None,
+                transition => transition.clone(),
+            };
+            reversed.add_transition(*to, from, transition);
+        }
+    }
+
+    Some(reversed)
+}
+
+pub(crate) fn successor(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        c => char::from_u32(c as u32 + 1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Dfa;
+    use crate::rsgex::{engine::Engine, error::Error};
+
+    #[test]
+    fn test_agrees_with_engine() {
+        let patterns = [
+            "abc",
+            "a|ab",
+            "b+?",
+            "^[1-9][0-9]{2,4}$",
+            "(?i)straße",
+            "[^a-c]+",
+            "\\d+-\\w*",
+            "x*",
+            "a$|a.",
+            "(a{2}){1,2}b",
+            // the empty input is both its start and its end
+            "$^",
+            "(?:$|b)(?:b|^)",
+        ];
+        let inputs = [
+            "", "abc", "xab", "b", "123", "12345", "STRASSE", "aab", "a", "ab", "4-x_", "aaaab",
+            "-", "aaaaab",
+        ];
+
+        for pattern in patterns {
+            let e = Engine::try_from(pattern).unwrap();
+            let dfa = Dfa::try_from(&e).unwrap();
+            for input in inputs {
+                let expected = e.find_at(input, 0).map(|m| (m.start, m.end));
+                assert_eq!(dfa.find(input), expected, "{} on {:?}", pattern, input);
+                assert_eq!(dfa.is_match(input), expected.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_counted_repetition() {
+        // empty iterations, as the unrolled repetition takes them
+        let cases = [
+            ("(?:c*|a){2,}", "aca", Some((0, 0))),
+            ("b(?:[ab]*?){2,}", "bacb", Some((0, 1))),
+            ("(?:a*?){2,}", "aacba", Some((0, 0))),
+            ("(?:(bb)){0,2}", "c", Some((0, 0))),
+            ("x(?:a|){2,3}y", "xay", Some((0, 3))),
+        ];
+
+        for (pattern, input, expected) in cases {
+            let e = Engine::try_from(pattern).unwrap();
+            let dfa = Dfa::try_from(&e).unwrap();
+            assert_eq!(dfa.find(input), expected, "{} on {:?}", pattern, input);
+            let expected = e.find_at(input, 0).map(|m| (m.start, m.end));
+            assert_eq!(dfa.find(input), expected, "{} on {:?}", pattern, input);
+        }
+    }
+
+    #[test]
+    fn test_reverse() {
+        let patterns = [
+            "abc|b",
+            "abd|b",
+            "a+b*",
+            "b+?",
+            "x*",
+            "^ab|b$",
+            "[a-c]+\\d|\\d",
+            "$^",
+        ];
+        let inputs = [
+            "", "abc", "xabd", "aab", "bbb", "ab", "cb", "12", "xa1b2", "中ab",
+        ];
+
+        for pattern in patterns {
+            let e = Engine::try_from(pattern).unwrap();
+            let dfa = Dfa::try_from(&e).unwrap();
+            assert!(dfa.reverse.is_some(), "{}", pattern);
+            for input in inputs {
+                let expected = e.find_at(input, 0).map(|m| (m.start, m.end));
+                assert_eq!(dfa.find(input), expected, "{} on {:?}", pattern, input);
+                assert_eq!(dfa.minimize().find(input), expected);
+            }
+        }
+
+        // counters do not run backwards, the unrolled form does
+        let dfa = Dfa::try_from(&Engine::try_from("a{2}").unwrap()).unwrap();
+        assert!(dfa.reverse.is_some());
+        assert_eq!(dfa.find("baab"), Some((1, 3)));
+    }
+
+    #[test]
+    fn test_minimize() {
+        let inputs = [
+            "", "xa", "ya", "yaa", "xb", "abd", "ecd", "abb", "aabb", "babb",
+        ];
+
+        for pattern in [
+            "xa|ya",
+            "a(b|c)d|e(b|c)d",
+            "(a|b)*abb",
+            "a+|b+|ab",
+            "^x?[ab]$",
+        ] {
+            let dfa = Dfa::try_from(&Engine::try_from(pattern).unwrap()).unwrap();
+            let minimized = dfa.minimize();
+
+            assert!(minimized.state_count() <= dfa.state_count());
+            assert_eq!(minimized.minimize().state_count(), minimized.state_count());
+            for input in inputs {
+                assert_eq!(
+                    minimized.find(input),
+                    dfa.find(input),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+                assert_eq!(minimized.is_match(input), dfa.is_match(input));
+            }
+        }
+
+        // the branches only differ before equivalent suffixes
+        let alternation = Dfa::try_from(&Engine::try_from("xa*|y(?:a|aa)*").unwrap()).unwrap();
+        let class = Dfa::try_from(&Engine::try_from("[xy]a*").unwrap()).unwrap();
+        assert!(alternation.state_count() > class.state_count());
+        assert_eq!(
+            alternation.minimize().state_count(),
+            class.minimize().state_count()
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(matches!(
+            Dfa::try_from(&Engine::try_from("a\\b").unwrap()),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(Dfa::try_from(&Engine::try_from("(a(?1)?b)").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_state_count() {
+        let dfa = Dfa::try_from(&Engine::try_from("[a-z]+").unwrap()).unwrap();
+        assert!(dfa.state_count() < 10);
+    }
+}
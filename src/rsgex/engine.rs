@@ -1,29 +1,145 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, OnceLock},
+};
 
 use super::{
-    matcher::{ClassUnicodeMatcher, EndOfInputMatcher, EpsilonMatcher, StartOfInputMatcher},
-    nfa::NFAutomata,
-    parser,
+    builder::EngineBuilder,
+    cache::{self, Cache},
+    cancel::CancelToken,
+    error::{Error, SizeLimitExceeded},
+    explain::Explanation,
+    first_chars::FirstChars,
+    haystack::Haystack,
+    instrument, intern,
+    language::Language,
+    matcher::{
+        ClassMatcher, CounterOp, CustomMatcher, GraphemeMatcher, LookMatcher, PerlClass,
+        PerlClassMatcher, Transition,
+    },
+    nfa::{Budget, NFAutomata, State, captured_groups},
+    normalize,
+    onepass::OnePass,
+    parser::{self, Parsed},
+    pikevm::PikeVm,
+    prefilter::Prefilter,
+    search::{Match, Matches},
+    subtrees::SharedSubtrees,
+};
+use regex_syntax::{
+    hir::{Capture, Class, ClassUnicode, Hir, HirKind, Literal, Look, Repetition},
+    parse,
 };
-use anyhow::Result;
-use regex_syntax::hir::{Capture, Class, Hir, HirKind, Literal, Look, Repetition};
+
+// `ſ`, `s` and `S` are the most case variants a single char folds into
+const MAX_CASE_VARIANTS: usize = 4;
+
+// Options that change how the HIR is lowered, threaded through `ast_to_nfa`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Flags {
+    // literals are matched byte by byte, see `BytesEngine`
+    pub bytes: bool,
+    // literals and haystacks are NFC normalized, see `EngineBuilder::nfc`
+    pub nfc: bool,
+    // classes consume grapheme clusters, see `EngineBuilder::graphemes`
+    pub graphemes: bool,
+    // `{n,m}` is unrolled instead of using counter registers
+    pub unroll: bool,
+    // every search is anchored at its start, see `EngineBuilder::anchored`
+    pub anchored: bool,
+    // groups other than the whole match are not tracked, see
+    // `EngineBuilder::track_captures`
+    pub no_captures: bool,
+}
+
+// How the automaton is executed, see `EngineBuilder::backend`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    // depth-first, supports every extension but may take exponential time
+    #[default]
+    Backtrack,
+    // breadth-first, linear in the haystack length
+    PikeVm,
+    // depth-first, but never explores a (state, position) pair twice; falls
+    // back to the Pike VM when the bitset would exceed `MAX_VISITED_BITS`
+    BoundedBacktrack,
+}
+
+// 2 MiB of visited bits for the bounded backtracker
+pub const MAX_VISITED_BITS: usize = 1 << 24;
 
 #[derive(Default)]
 pub struct Engine {
-    pub nfa: NFAutomata,
+    pub(crate) nfa: NFAutomata,
+    pub flags: Flags,
+    pub(crate) backend: Backend,
+    // set when the pattern is one-pass, speeds up anchored matching
+    pub(crate) onepass: Option<OnePass>,
+    // set when matches can only start at some positions, speeds up
+    // unanchored matching
+    pub(crate) prefilter: Option<Prefilter>,
+    // set when every match starts with a char, used by unanchored matching
+    // when there is no prefilter
+    pub(crate) first_chars: Option<FirstChars>,
+    // the most bytes a match spans, when that is bounded
+    pub(crate) max_len: Option<usize>,
+    // the fewest bytes a match spans, when that is known
+    pub(crate) min_len: Option<usize>,
+    // searches borrow the thread's cache, see
+    // `EngineBuilder::thread_local_cache`
+    pub thread_local_cache: bool,
+    // bytes a `Dfa` built from the engine may take
+    pub dfa_size_limit: Option<usize>,
+    // estimated bytes `unrolled_nfa` may take, see
+    // `EngineBuilder::nfa_size_limit`
+    pub nfa_size_limit: usize,
+    // only used while lowering, see `Engine::lower`
+    shared: SharedSubtrees,
+    // the matchers `\m{name}` refers to, indexed like `Parsed::named`;
+    // only used while lowering
+    named: Arc<[CustomMatcher]>,
+    // built on first use by `is_valid_prefix`, or why it cannot be
+    language: OnceLock<Result<Language, Error>>,
+    // the pattern, kept when its `{n,m}` got counter registers so
+    // `unrolled_nfa` can lower it again
+    counted: Option<Hir>,
+    // built on first use by `unrolled_nfa`, or by `union` and `then` from
+    // the unrolled automata of both parts, or why it cannot be
+    unrolled: OnceLock<Result<NFAutomata, Error>>,
+    // the options the engine was built with, reused by `or_pattern`
+    pub(crate) builder: EngineBuilder,
 }
 
 impl Engine {
     fn alternation(&mut self, ast_vec: &[Hir]) {
+        if let Some(factored) = self.factor_suffix(ast_vec) {
+            return self.concat(&factored);
+        }
+
         let mut nfa = NFAutomata::new();
 
         nfa.fill_state(1);
         nfa.set_initial(0);
 
-        ast_vec.iter().for_each(|ast| {
-            let sub_nfa = Self::ast_to_nfa(ast.kind());
-            nfa.append(&sub_nfa.nfa, 0);
-        });
+        // runs of literal branches share their prefixes, see `literal_trie`
+        let mut rest = ast_vec;
+        while let Some(ast) = rest.first() {
+            let literals: Vec<&Literal> = rest
+                .iter()
+                .map_while(|ast| match ast.kind() {
+                    HirKind::Literal(literal) => Some(literal),
+                    _ => None,
+                })
+                .collect();
+            let sub_nfa = match (literals.len(), ast.kind()) {
+                (0 | 1, _) => self.lower(ast.kind()),
+                _ => self.literal_trie(&literals),
+            };
+            nfa.append(sub_nfa, 0);
+            rest = &rest[literals.len().max(1)..];
+        }
 
         nfa.fill_state(1);
 
@@ -37,6 +153,91 @@ impl Engine {
         self.nfa = nfa;
     }
 
+    // Rewrites `a.*x|b.*x` into `(?:a|b).*x`, so the branches share the
+    // automaton of their common tail. Trying each branch before the tail is
+    // what the original alternation did too, so match priority is unchanged.
+    fn factor_suffix(&self, ast_vec: &[Hir]) -> Option<Vec<Hir>> {
+        let mut branches: Vec<Vec<Hir>> = ast_vec
+            .iter()
+            .map(|ast| match ast.kind() {
+                HirKind::Concat(ast_vec) => ast_vec.clone(),
+                HirKind::Empty => vec![],
+                _ => vec![ast.clone()],
+            })
+            .collect();
+
+        let mut suffix = vec![];
+        while let Some(lasts) = branches
+            .iter()
+            .map(|b| b.last())
+            .collect::<Option<Vec<_>>>()
+        {
+            if lasts.iter().all(|last| *last == lasts[0]) {
+                suffix.push(lasts[0].clone());
+                branches.iter_mut().for_each(|b| _ = b.pop());
+                continue;
+            }
+
+            // literals are normalized as a whole, so they are not split in
+            // NFC mode
+            let literals: Option<Vec<Vec<u8>>> = lasts
+                .iter()
+                .map(|last| match last.kind() {
+                    HirKind::Literal(Literal(bytes)) if !self.flags.nfc => Some(bytes.to_vec()),
+                    _ => None,
+                })
+                .collect();
+            let Some(literals) = literals else { break };
+            let len = Self::common_literal_suffix(&literals);
+            if len > 0 {
+                let tail = &literals[0][literals[0].len() - len..];
+                suffix.push(Hir::literal(tail));
+                for (branch, literal) in branches.iter_mut().zip(literals.iter()) {
+                    branch.pop();
+                    let head = &literal[..literal.len() - len];
+                    if !head.is_empty() {
+                        branch.push(Hir::literal(head));
+                    }
+                }
+            }
+            break;
+        }
+
+        if suffix.is_empty() {
+            return None;
+        }
+        suffix.reverse();
+        let heads = branches.into_iter().map(Hir::concat).collect();
+
+        Some(
+            std::iter::once(Hir::alternation(heads))
+                .chain(suffix)
+                .collect(),
+        )
+    }
+
+    // the length in bytes of the longest common suffix that does not split
+    // a UTF-8 char
+    fn common_literal_suffix(literals: &[Vec<u8>]) -> usize {
+        let mut len = 0;
+        while literals.iter().all(|literal| {
+            literal.len() > len
+                && literal[literal.len() - len - 1] == literals[0][literals[0].len() - len - 1]
+        }) {
+            len += 1;
+        }
+        while !literals
+            .iter()
+            .all(|literal| match std::str::from_utf8(literal) {
+                Ok(s) => s.is_char_boundary(s.len() - len),
+                Err(_) => true,
+            })
+        {
+            len -= 1;
+        }
+        len
+    }
+
     fn concat(&mut self, ast_vec: &[Hir]) {
         let mut nfa = NFAutomata::new();
 
@@ -45,48 +246,119 @@ impl Engine {
         nfa.add_ending(0);
 
         ast_vec.iter().for_each(|ast| {
-            let sub_nfa = Self::ast_to_nfa(ast.kind());
+            let sub_nfa = self.lower(ast.kind());
             let prev_ending = nfa.ending.pop().unwrap();
             nfa.remove_ending(prev_ending);
-            nfa.append(&sub_nfa.nfa, prev_ending);
+            nfa.append(sub_nfa, prev_ending);
         });
 
         self.nfa = nfa;
     }
 
+    // nfa: 0 -ε> 1, so that an empty pattern or group still has states to
+    // mark and to accept in
+    fn empty(&mut self) {
+        let mut nfa = NFAutomata::new();
+        nfa.declare_state(2, 0, 1);
+        nfa.add_epsilon_transition(0, 1);
+
+        self.nfa = nfa;
+    }
+
     fn literal(&mut self, literal: &Literal) {
         let mut nfa = NFAutomata::new();
 
-        let len = literal.0.len();
+        let chars = self.literal_chars(literal);
+        let len = chars.len();
         nfa.declare_state(len + 1, 0, len);
-        literal
-            .0
-            .iter()
-            .map(|&b| b as char)
+        chars
+            .into_iter()
             .enumerate()
-            .for_each(|(from, c)| {
-                nfa.add_char_transition(from, from + 1, c);
-            });
+            .for_each(|(from, c)| nfa.add_transition(from, from + 1, self.char_matcher(c)));
 
         self.nfa = nfa;
     }
 
+    // Lowers alternated literals into a trie, so `foo|fob` tests `fo` once.
+    // A literal only descends into the last child of a node and is otherwise
+    // added after it, which keeps the branches in their original order for
+    // leftmost-first matching.
+    fn literal_trie(&self, literals: &[&Literal]) -> NFAutomata {
+        let mut nfa = NFAutomata::new();
+        let ending = 1;
+        nfa.declare_state(2, 0, ending);
+
+        for literal in literals {
+            let mut node = 0;
+            for c in self.literal_chars(literal) {
+                let matcher = self.char_matcher(c);
+                node = match nfa.states[node].matchers.back() {
+                    Some((last, to)) if *last == matcher => *to,
+                    _ => {
+                        nfa.fill_state(1);
+                        let child = nfa.states.len() - 1;
+                        nfa.add_transition(node, child, matcher);
+                        child
+                    }
+                };
+            }
+            nfa.add_epsilon_transition(node, ending);
+        }
+
+        nfa
+    }
+
+    // literals are UTF-8 unless the pattern was parsed with `utf8(false)`,
+    // in which case each byte stands for itself
+    fn literal_chars(&self, literal: &Literal) -> Vec<char> {
+        match std::str::from_utf8(&literal.0) {
+            Ok(s) if self.flags.nfc => normalize::nfc(s).chars().collect(),
+            Ok(s) if !self.flags.bytes => s.chars().collect(),
+            _ => literal.0.iter().map(|&b| b as char).collect(),
+        }
+    }
+
+    fn char_matcher(&self, c: char) -> Transition {
+        self.sentinel_matcher(c).unwrap_or(Transition::Char(c))
+    }
+
+    // Lowers the noncharacters the parser substitutes for `\G`, named
+    // matchers and subroutine calls.
+    fn sentinel_matcher(&self, c: char) -> Option<Transition> {
+        if c == parser::CONTINUATION_SENTINEL {
+            return Some(Transition::Continuation);
+        }
+        if let Some(index) = (c as u32).checked_sub(parser::NAMED_SENTINEL_BASE as u32) {
+            let matcher = self.named.get(index as usize)?;
+            return Some(Transition::Custom(matcher.clone()));
+        }
+
+        let slot = (c as u32).checked_sub(parser::CALL_SENTINEL_BASE as u32)?;
+        // the private use plane ends at U+FFFFD
+        (slot < 0xFFFE).then_some(Transition::Call(slot as usize))
+    }
+
     // + / * / {min, max}
     fn repetition(&mut self, repetition: &Repetition) {
+        if !self.flags.unroll && (repetition.min > 1 || repetition.max.is_some_and(|max| max > 1)) {
+            self.counted_repetition(repetition);
+            return;
+        }
+
         // nfa: 0
         let mut nfa = NFAutomata::new();
         nfa.fill_state(1);
         nfa.set_initial(0);
         nfa.add_ending(0);
 
-        let sub_nfa = Self::ast_to_nfa(repetition.sub.kind());
+        let sub_nfa = self.lower(repetition.sub.kind());
 
         let mut last_sub_nfa_initial: usize;
         // nfa: 0 -> sub_nfa * min -> end
         for _ in 0..repetition.min {
             last_sub_nfa_initial = nfa.ending.pop().unwrap();
             nfa.remove_ending(last_sub_nfa_initial);
-            nfa.append(&sub_nfa.nfa, last_sub_nfa_initial);
+            nfa.append(sub_nfa.clone(), last_sub_nfa_initial);
         }
 
         // support {min, (max)}
@@ -99,11 +371,27 @@ impl Engine {
             for _ in repetition.min..max {
                 let current_sub_nfa_ending = nfa.ending.pop().unwrap();
                 nfa.remove_ending(current_sub_nfa_ending);
-                nfa.append(&sub_nfa.nfa, current_sub_nfa_ending);
+                nfa.append(sub_nfa.clone(), current_sub_nfa_ending);
                 sub_nfa_ending.push(current_sub_nfa_ending);
             }
+            // a lazy repetition tries to stop before each optional copy.
+            // Skipping must not pass the group ends of the last copy, which
+            // would close the groups the skipped copy opened.
+            let mut end = *nfa.ending.last().unwrap();
+            if !sub_nfa_ending.is_empty() && !nfa.states[end].end_group.is_empty() {
+                nfa.remove_ending(end);
+                nfa.fill_state(1);
+                let past = nfa.states.len() - 1;
+                nfa.add_epsilon_transition(end, past);
+                nfa.add_ending(past);
+                end = past;
+            }
             for ending in sub_nfa_ending.into_iter() {
-                nfa.add_epsilon_transition(ending, *nfa.ending.last().unwrap());
+                if repetition.greedy {
+                    nfa.add_epsilon_transition(ending, end);
+                } else {
+                    nfa.unshift_transition(ending, end, Transition::Epsilon);
+                }
             }
         }
         // support + / *
@@ -115,24 +403,25 @@ impl Engine {
             let mut last_ending = nfa.ending.pop().unwrap();
             nfa.remove_ending(last_ending);
             last_sub_nfa_initial = last_ending;
-            nfa.append(&sub_nfa.nfa, last_sub_nfa_initial);
+            nfa.append(sub_nfa.clone(), last_sub_nfa_initial);
 
             last_ending = nfa.ending.pop().unwrap();
             nfa.remove_ending(last_ending);
 
             nfa.fill_state(1);
             let new_ending = nfa.states.len() - 1;
+            // as above, skipping the loop leaves its group ends out
+            let skip = match nfa.states[last_ending].end_group.is_empty() {
+                true => last_ending,
+                false => new_ending,
+            };
             nfa.add_epsilon_transition(last_ending, last_sub_nfa_initial);
             if repetition.greedy {
-                nfa.add_epsilon_transition(last_sub_nfa_initial, last_ending);
+                nfa.add_epsilon_transition(last_sub_nfa_initial, skip);
                 nfa.add_epsilon_transition(last_ending, new_ending);
             } else {
-                nfa.unshift_transition(
-                    last_sub_nfa_initial,
-                    last_ending,
-                    Rc::new(EpsilonMatcher {}),
-                );
-                nfa.unshift_transition(last_ending, new_ending, Rc::new(EpsilonMatcher {}));
+                nfa.unshift_transition(last_sub_nfa_initial, skip, Transition::Epsilon);
+                nfa.unshift_transition(last_ending, new_ending, Transition::Epsilon);
             }
             nfa.add_ending(new_ending);
         }
@@ -140,38 +429,198 @@ impl Engine {
         self.nfa = nfa;
     }
 
-    fn class(&mut self, class: &Class) {
+    // {min, max} / {min,} with a counter register instead of unrolling
+    // nfa: 0 -(reset)> head -(c < max)> sub_nfa -(c++)> head
+    //                       -(c >= min)> end
+    fn counted_repetition(&mut self, repetition: &Repetition) {
+        let sub_nfa = self.lower(repetition.sub.kind());
+        // nested counted repetitions each get their own register
+        let counter = sub_nfa.counters;
+
         let mut nfa = NFAutomata::new();
-        nfa.fill_state(3);
+        nfa.fill_state(4);
         nfa.set_initial(0);
-        nfa.add_epsilon_transition(0, 1);
-        if let Class::Unicode(unicode_range) = class {
-            unicode_range.iter().for_each(|r| {
-                nfa.add_transition(
-                    1,
-                    2,
-                    Rc::new(ClassUnicodeMatcher {
-                        start: r.start(),
-                        end: r.end(),
-                    }),
-                );
-            });
+        let (head, body, end) = (1, 2, 3);
+
+        nfa.add_transition(0, head, Transition::Counter(CounterOp::Reset(counter)));
+
+        let max = repetition.max.unwrap_or(u32::MAX);
+        let enter = Transition::Counter(CounterOp::Below(counter, max));
+        let leave = Transition::Counter(CounterOp::AtLeast(counter, repetition.min));
+        if repetition.greedy {
+            nfa.add_transition(head, body, enter);
+            nfa.add_transition(head, end, leave);
+        } else {
+            nfa.add_transition(head, end, leave);
+            nfa.add_transition(head, body, enter);
+        }
+
+        nfa.append(sub_nfa, body);
+
+        // the counter saturates at `min` for `{min,}`
+        let bound = repetition.max.unwrap_or(repetition.min);
+        for ending in nfa.ending.clone() {
+            nfa.remove_ending(ending);
+            nfa.add_transition(
+                ending,
+                head,
+                Transition::Counter(CounterOp::Increment(counter, bound)),
+            );
         }
+        nfa.add_ending(end);
+        nfa.counters = counter + 1;
+
+        self.nfa = nfa;
+    }
 
-        nfa.add_ending(2);
+    fn class(&mut self, class: &Class) {
+        let mut nfa = NFAutomata::new();
+        nfa.declare_state(2, 0, 1);
+
+        let transition = match class {
+            Class::Unicode(unicode_range) => {
+                let ranges = |class: &ClassUnicode| -> Vec<(char, char)> {
+                    class.iter().map(|r| (r.start(), r.end())).collect()
+                };
+
+                if let Some((perl_class, negated)) = Self::perl_class(unicode_range) {
+                    let mut table = unicode_range.clone();
+                    if negated {
+                        table.negate();
+                    }
+                    Transition::PerlClass(intern::perl_class(PerlClassMatcher {
+                        class: perl_class,
+                        negated,
+                        table: ClassMatcher {
+                            ranges: ranges(&table),
+                            negated: false,
+                        },
+                    }))
+                } else if unicode_range.is_ascii() {
+                    // POSIX classes like `[[:alpha:]]` are ASCII only
+                    Transition::Ascii {
+                        bitmap: Self::ascii_bitmap(&ranges(unicode_range)),
+                        negated: false,
+                    }
+                } else if Self::is_case_fold(unicode_range) {
+                    // `(?i)é` is translated into `[Éé]`, `(?i)k` into `[Kk\u{212A}]`
+                    Transition::CharSet(unicode_range.iter().map(|r| r.start()).collect())
+                } else if let Some(complement) = Self::complement(unicode_range) {
+                    // `[^a-fx-z]` is stored as `a-f`, `x-z` and matched negated
+                    if complement.is_ascii() {
+                        Transition::Ascii {
+                            bitmap: Self::ascii_bitmap(&ranges(&complement)),
+                            negated: true,
+                        }
+                    } else {
+                        Transition::Class(intern::class(ClassMatcher {
+                            ranges: ranges(&complement),
+                            negated: true,
+                        }))
+                    }
+                } else if let [range] = unicode_range.ranges() {
+                    Transition::Range(range.start(), range.end())
+                } else {
+                    Transition::Class(intern::class(ClassMatcher {
+                        ranges: ranges(unicode_range),
+                        negated: false,
+                    }))
+                }
+            }
+            // `(?-u)` classes
+            Class::Bytes(bytes_range) if bytes_range.is_ascii() => {
+                let ranges: Vec<(char, char)> = bytes_range
+                    .iter()
+                    .map(|r| (r.start() as char, r.end() as char))
+                    .collect();
+                Transition::Ascii {
+                    bitmap: Self::ascii_bitmap(&ranges),
+                    negated: false,
+                }
+            }
+            Class::Bytes(bytes_range) => Transition::Class(intern::class(ClassMatcher {
+                ranges: bytes_range
+                    .iter()
+                    .map(|r| (r.start() as char, r.end() as char))
+                    .collect(),
+                negated: false,
+            })),
+        };
+        if self.flags.graphemes {
+            let grapheme = GraphemeMatcher { inner: transition };
+            nfa.add_transition(0, 1, Transition::Custom(CustomMatcher(Arc::new(grapheme))));
+        } else {
+            nfa.add_transition(0, 1, transition);
+        }
 
         self.nfa = nfa;
     }
 
+    // Case-insensitive literals reach the compiler as tiny classes of
+    // single chars, one per case variant.
+    fn is_case_fold(class: &ClassUnicode) -> bool {
+        class.ranges().len() <= MAX_CASE_VARIANTS && class.iter().all(|r| r.start() == r.end())
+    }
+
+    fn ascii_bitmap(ranges: &[(char, char)]) -> u128 {
+        ranges
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .fold(0u128, |bitmap, c| bitmap | 1 << c as u32)
+    }
+
+    // The complement of a class when it has fewer ranges, which is the case
+    // for negated classes.
+    fn complement(class: &ClassUnicode) -> Option<ClassUnicode> {
+        let mut complement = class.clone();
+        complement.negate();
+
+        (complement.ranges().len() < class.ranges().len()).then_some(complement)
+    }
+
+    // Recognizes the Unicode `\d` / `\w` / `\s` classes and their negations.
+    fn perl_class(class: &ClassUnicode) -> Option<(PerlClass, bool)> {
+        static PERL_CLASSES: OnceLock<Vec<(PerlClass, ClassUnicode)>> = OnceLock::new();
+
+        let perl_classes = PERL_CLASSES.get_or_init(|| {
+            [
+                (PerlClass::Digit, "\\d"),
+                (PerlClass::Word, "\\w"),
+                (PerlClass::Space, "\\s"),
+            ]
+            .into_iter()
+            .filter_map(
+                |(perl_class, pattern)| match parse(pattern).ok()?.into_kind() {
+                    HirKind::Class(Class::Unicode(class)) => Some((perl_class, class)),
+                    _ => None,
+                },
+            )
+            .collect()
+        });
+
+        perl_classes.iter().find_map(|(perl_class, reference)| {
+            if reference == class {
+                return Some((*perl_class, false));
+            }
+            let mut negated = reference.clone();
+            negated.negate();
+            (negated == *class).then_some((*perl_class, true))
+        })
+    }
+
     fn capture(&mut self, capture: &Capture) {
-        let mut e = Self::ast_to_nfa(capture.sub.kind());
+        let mut nfa = self.lower(capture.sub.kind());
+        if self.flags.no_captures {
+            self.nfa = nfa;
+            return;
+        }
 
-        e.nfa.mark_capture_group(
+        nfa.mark_capture_group(
             capture.index,
-            capture.name.as_ref().map(|n| Rc::from(n.clone())),
+            capture.name.as_ref().map(|n| Arc::from(n.clone())),
         );
 
-        self.nfa = e.nfa;
+        self.nfa = nfa;
     }
 
     fn look(&mut self, look: &Look) {
@@ -182,22 +631,46 @@ impl Engine {
 
         match look {
             Look::Start => {
-                nfa.add_transition(0, 1, Rc::new(StartOfInputMatcher {}));
+                nfa.add_transition(0, 1, Transition::Start);
             }
             Look::End => {
-                nfa.add_transition(0, 1, Rc::new(EndOfInputMatcher {}));
+                nfa.add_transition(0, 1, Transition::End);
             }
-            _ => {
-                nfa.add_epsilon_transition(0, 1);
+            look => {
+                nfa.add_transition(0, 1, Transition::Look(LookMatcher { look: *look }));
             }
         }
 
         self.nfa = nfa;
     }
 
-    fn ast_to_nfa(ast: &HirKind) -> Self {
-        let mut builder = Self::default();
+    pub(crate) fn ast_to_nfa(ast: &HirKind, flags: Flags, named: &Arc<[CustomMatcher]>) -> Self {
+        let mut builder = Self {
+            flags,
+            shared: SharedSubtrees::new(ast),
+            named: named.clone(),
+            ..Self::default()
+        };
+        builder.nfa = builder.lower(ast);
+        builder.shared = SharedSubtrees::default();
+        builder
+    }
+
+    // Lowers `ast` into an automaton of its own, copying the one of an
+    // identical subtree lowered before, see `SharedSubtrees`.
+    fn lower(&mut self, ast: &HirKind) -> NFAutomata {
+        if let Some(nfa) = self.shared.get(ast) {
+            return nfa.clone();
+        }
+
+        let mut builder = Self {
+            flags: self.flags,
+            shared: std::mem::take(&mut self.shared),
+            named: self.named.clone(),
+            ..Self::default()
+        };
         match ast {
+            HirKind::Empty => builder.empty(),
             HirKind::Alternation(ast_vec) => builder.alternation(ast_vec.as_slice()),
             HirKind::Concat(ast_vec) => builder.concat(ast_vec.as_slice()),
             HirKind::Literal(literal) => builder.literal(literal),
@@ -205,44 +678,636 @@ impl Engine {
             HirKind::Class(class) => builder.class(class),
             HirKind::Capture(capture) => builder.capture(capture),
             HirKind::Look(look) => builder.look(look),
-            _ => (),
         }
 
-        println!("ast_to_nfa, {:?}", ast);
+        instrument::event!(trace, "lowered {:?}", ast);
 
-        builder
+        self.shared = std::mem::take(&mut builder.shared);
+        self.shared.insert(ast, &builder.nfa);
+        builder.nfa
     }
 
+    // A search that runs out of its step budget counts as no match, see
+    // `try_exec` to tell them apart.
     pub fn exec(&self, s: &str) -> Option<HashMap<String, String>> {
-        self.nfa.compute(s)
+        self.try_exec(s).ok().flatten()
+    }
+
+    pub fn try_exec(&self, s: &str) -> Result<Option<HashMap<String, String>>, Error> {
+        let s = self.prepare(s);
+
+        Ok(self
+            .run(&Haystack::Str(&s), 0, true, None)?
+            .map(|m| m.groups))
+    }
+
+    // Like `try_exec`, but gives up with `Error::Cancelled` once `cancel` is
+    // cancelled from another thread.
+    pub fn try_exec_cancellable(
+        &self,
+        s: &str,
+        cancel: &CancelToken,
+    ) -> Result<Option<HashMap<String, String>>, Error> {
+        let s = self.prepare(s);
+
+        Ok(self
+            .run(&Haystack::Str(&s), 0, true, Some(cancel))?
+            .map(|m| m.groups))
     }
 
+    #[cfg(test)]
     pub(crate) fn exec_test(&self, s: &str) -> String {
         self.exec(s).unwrap().get(&0.to_string()).unwrap().clone()
     }
 
     pub fn test(&self, s: &str) -> bool {
-        self.nfa.compute(s).is_some()
+        self.try_test(s).unwrap_or(false)
+    }
+
+    pub fn try_test(&self, s: &str) -> Result<bool, Error> {
+        self.with_scratch(|cache| self.try_test_with(cache, s))
+    }
+
+    // Like `test`, reusing the buffers in `cache`. Captures are not read, so
+    // with the backtracking backends a search allocates nothing once the
+    // cache has grown, unless NFC normalization copies the haystack.
+    pub fn test_with(&self, cache: &mut Cache, s: &str) -> bool {
+        self.try_test_with(cache, s).unwrap_or(false)
+    }
+
+    pub fn try_test_with(&self, cache: &mut Cache, s: &str) -> Result<bool, Error> {
+        let s = self.prepare(s);
+
+        Ok(self
+            .run_with(cache, &Haystack::Str(&s), 0, true, false, None)?
+            .is_some())
+    }
+
+    // Finds the leftmost match starting at or after byte offset `start`,
+    // which finds nothing unless it is a char boundary. `\G` anchors on
+    // `start`.
+    pub fn find_at(&self, s: &str, start: usize) -> Option<Match> {
+        self.try_find_at(s, start).ok().flatten()
+    }
+
+    pub fn try_find_at(&self, s: &str, start: usize) -> Result<Option<Match>, Error> {
+        let s = self.prepare(s);
+
+        self.run(&Haystack::Str(&s), start, false, None)
+    }
+
+    pub fn try_find_at_cancellable(
+        &self,
+        s: &str,
+        start: usize,
+        cancel: &CancelToken,
+    ) -> Result<Option<Match>, Error> {
+        let s = self.prepare(s);
+
+        self.run(&Haystack::Str(&s), start, false, Some(cancel))
+    }
+
+    // Like `find_at` from the start of `s`, reusing the buffers in `cache`
+    // instead of allocating them for this search.
+    pub fn search_with(&self, cache: &mut Cache, s: &str) -> Option<Match> {
+        self.try_search_with(cache, s).ok().flatten()
+    }
+
+    pub fn try_search_with(&self, cache: &mut Cache, s: &str) -> Result<Option<Match>, Error> {
+        let s = self.prepare(s);
+
+        self.run_with(cache, &Haystack::Str(&s), 0, false, true, None)
+    }
+
+    pub fn find_iter<'e, 's>(&'e self, s: &'s str) -> Matches<'e, 's> {
+        Matches::new(self, self.prepare(s))
+    }
+
+    // Every distinct way the pattern matches all of `s`, as the groups each
+    // one captures, for looking into ambiguous patterns: `(a*)(a*)` matches
+    // "aa" in three ways. `exec` reports the first of them. Enumerating may
+    // take exponential time; `step_limit` and `timeout` bound it.
+    pub fn derivations(&self, s: &str) -> Vec<HashMap<String, String>> {
+        self.try_derivations(s).unwrap_or_default()
+    }
+
+    pub fn try_derivations(&self, s: &str) -> Result<Vec<HashMap<String, String>>, Error> {
+        let s = self.prepare(s);
+        let mut budget = Budget::new(self.nfa.step_limit, self.nfa.timeout, None);
+        self.nfa.derivations(&Haystack::Str(&s), &mut budget)
+    }
+
+    // The haystack as the automaton sees it. With NFC normalization enabled,
+    // match offsets and captures refer to the normalized haystack.
+    pub fn prepare<'s>(&self, s: &'s str) -> Cow<'s, str> {
+        if self.flags.nfc {
+            normalize::nfc(s)
+        } else {
+            Cow::Borrowed(s)
+        }
+    }
+
+    // The compiled automaton, e.g. to print or export it. Counted
+    // repetitions keep their counter registers here when the backend has
+    // them.
+    pub fn nfa(&self) -> &NFAutomata {
+        &self.nfa
+    }
+
+    // The backend the engine was built for. It is fixed at build time, as
+    // the automaton is lowered for it.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    // The fewest bytes a match spans, as measured on the pattern after
+    // normalization. `None` when the pattern matches nothing, or when its
+    // subroutine calls, named matchers or grapheme clusters leave the
+    // length unknown.
+    pub fn min_match_len(&self) -> Option<usize> {
+        self.min_len
+    }
+
+    // The most bytes a match spans, `None` when that is unbounded or, as
+    // for `min_match_len`, unknown.
+    pub fn max_match_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    // Equal for engines compiled into the same automaton, whatever pattern
+    // text they came from, such as `a|b` and `[ab]`; see
+    // `NFAutomata::canonical_hash`. Meant as the key of a cache of compiled
+    // engines.
+    pub fn structure_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.nfa.canonical_hash().hash(&mut hasher);
+        (self.flags.bytes, self.flags.nfc, self.flags.anchored).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub(crate) fn find_in(&self, haystack: &Haystack, start: usize) -> Option<Match> {
+        self.run(haystack, start, false, None).ok().flatten()
+    }
+
+    // Matches at `at`, or at the leftmost position after it when not
+    // `anchored`, with the configured backend. `\G` asserts against `at`.
+    pub(crate) fn run(
+        &self,
+        haystack: &Haystack,
+        at: usize,
+        anchored: bool,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Option<Match>, Error> {
+        self.with_scratch(|cache| self.run_with(cache, haystack, at, anchored, true, cancel))
+    }
+
+    // runs `f` with the thread's cache or with a new one
+    fn with_scratch<T>(&self, f: impl FnOnce(&mut Cache) -> T) -> T {
+        if self.thread_local_cache {
+            cache::with_thread_cache(f)
+        } else {
+            f(&mut Cache::new())
+        }
+    }
+
+    // Without `captures` the match comes without groups.
+    fn run_with(
+        &self,
+        cache: &mut Cache,
+        haystack: &Haystack,
+        at: usize,
+        anchored: bool,
+        captures: bool,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Option<Match>, Error> {
+        let _span = instrument::span!(TRACE, "search", at = at, anchored = anchored);
+        let search_start = at;
+        let boundary = match haystack {
+            Haystack::Str(s) => s.is_char_boundary(at),
+            Haystack::Bytes(_) => true,
+            Haystack::Segments(segments) => segments.is_char_boundary(at),
+        };
+        if !boundary {
+            return Ok(None);
+        }
+        if at > haystack.len() {
+            return Ok(None);
+        }
+        let anchored = anchored || self.flags.anchored;
+        // the one-pass DFA always records captures
+        if anchored
+            && captures
+            && let Some(onepass) = &self.onepass
+        {
+            instrument::event!(trace, "searching with the one-pass DFA");
+            return Ok(onepass
+                .search(&self.nfa, haystack, at)
+                .map(|(end, groups)| Match {
+                    start: at,
+                    end,
+                    groups,
+                }));
+        }
+
+        let mut at = at;
+        if !anchored {
+            match self.candidate(haystack, at) {
+                Some(candidate) => at = candidate,
+                None => return Ok(None),
+            }
+        }
+
+        let mut budget = Budget::new(self.nfa.step_limit, self.nfa.timeout, cancel.cloned());
+        let bounded = self.backend == Backend::BoundedBacktrack;
+        let fits = self.nfa.states.len() * (haystack.len() + 1) <= MAX_VISITED_BITS;
+        if self.backend == Backend::PikeVm || (bounded && !fits) {
+            instrument::event!(trace, "searching from byte {} with the Pike VM", at);
+            return PikeVm::new(&self.nfa, *haystack, search_start)
+                .search(at, anchored, &mut budget)
+                .inspect_err(|error| instrument::event!(debug, "search abandoned: {}", error));
+        }
+        instrument::event!(trace, "backtracking from byte {}, bounded: {}", at, bounded);
+
+        if bounded {
+            cache
+                .visited
+                .reset(self.nfa.states.len(), haystack.len() + 1);
+        }
+        loop {
+            let found = self
+                .nfa
+                .try_search(haystack, at, search_start, cache, bounded, &mut budget)
+                .inspect_err(|error| instrument::event!(debug, "search abandoned: {}", error))?;
+            if let Some(end) = found {
+                let groups = if captures {
+                    captured_groups(haystack, &cache.groups)
+                } else {
+                    HashMap::new()
+                };
+                return Ok(Some(Match {
+                    start: at,
+                    end,
+                    groups,
+                }));
+            }
+            if anchored || at == haystack.len() {
+                return Ok(None);
+            }
+            at = haystack.next(at);
+            match self.candidate(haystack, at) {
+                Some(candidate) => at = candidate,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    // The first position at or after `at` an unanchored match can start at,
+    // `None` when there is none.
+    fn candidate(&self, haystack: &Haystack, at: usize) -> Option<usize> {
+        match (&self.prefilter, &self.first_chars) {
+            (Some(prefilter), _) => prefilter.candidate(haystack, at),
+            (None, Some(first_chars)) => first_chars.find(haystack, at),
+            (None, None) => Some(at),
+        }
+    }
+}
+
+// whether `backend` needs `{n,m}` unrolled, as it has no counter registers
+fn unrolls(backend: Backend) -> bool {
+    backend != Backend::Backtrack
+}
+
+// bytes of a state with a couple of transitions
+const STATE_BYTES: usize = size_of::<State>() + 2 * size_of::<(Transition, usize)>();
+
+impl Engine {
+    // The bytes the automaton compiled from `hir` will roughly take,
+    // estimated without compiling it, so a pattern that blows up is turned
+    // down before it does. Repetition copies multiply the states; class
+    // tables count once since the copies share them.
+    pub(crate) fn estimated_size(hir: &Hir, backend: Backend) -> usize {
+        let (states, tables) = Self::estimate(hir, unrolls(backend));
+        states.saturating_mul(STATE_BYTES).saturating_add(tables)
+    }
+
+    // (states, bytes of class tables)
+    fn estimate(hir: &Hir, unroll: bool) -> (usize, usize) {
+        let ranges = |len: usize| len * size_of::<(char, char)>();
+        match hir.kind() {
+            HirKind::Empty => (1, 0),
+            HirKind::Literal(literal) => (literal.0.len() + 1, 0),
+            HirKind::Class(Class::Unicode(class)) => (2, ranges(class.ranges().len())),
+            HirKind::Class(Class::Bytes(class)) => (2, ranges(class.ranges().len())),
+            HirKind::Look(_) => (2, 0),
+            HirKind::Capture(capture) => Self::estimate(&capture.sub, unroll),
+            HirKind::Concat(hirs) | HirKind::Alternation(hirs) => {
+                hirs.iter().fold((hirs.len(), 0), |(states, tables), hir| {
+                    let (sub_states, sub_tables) = Self::estimate(hir, unroll);
+                    (
+                        states.saturating_add(sub_states),
+                        tables.saturating_add(sub_tables),
+                    )
+                })
+            }
+            HirKind::Repetition(repetition) => {
+                let (states, tables) = Self::estimate(&repetition.sub, unroll);
+                let max = repetition.max.unwrap_or(repetition.min);
+                let copies = if !unroll && max > 1 {
+                    1
+                } else {
+                    max.max(repetition.min).max(1) as usize
+                };
+                (
+                    states.saturating_mul(copies).saturating_add(copies + 2),
+                    tables,
+                )
+            }
+        }
     }
 }
 
 impl TryFrom<&str> for Engine {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     fn try_from(pattern: &str) -> Result<Engine, Self::Error> {
-        let ast = parser::parse_by_regex_syntax(pattern);
-        let mut e = Engine::ast_to_nfa(ast?.kind());
+        EngineBuilder::new().build(pattern)
+    }
+}
+
+impl Engine {
+    // An engine for a HIR that is already built, such as by an optimizer
+    // or a translator from another syntax; see `EngineBuilder::build_hir`.
+    pub fn from_hir(hir: &Hir) -> Result<Engine, Error> {
+        EngineBuilder::new().build_hir(hir)
+    }
+
+    // `named` holds the matchers of `parsed.named`, in the same order.
+    pub(crate) fn from_parsed(
+        parsed: Parsed,
+        flags: Flags,
+        backend: Backend,
+        named: Vec<CustomMatcher>,
+    ) -> Result<Engine, Error> {
+        if backend != Backend::Backtrack {
+            if !parsed.calls.is_empty() {
+                let message = "subroutine calls are only supported by the backtracking backend";
+                return Err(Error::Unsupported(message.to_string()));
+            }
+            if flags.graphemes {
+                let message = "grapheme mode is only supported by the backtracking backend";
+                return Err(Error::Unsupported(message.to_string()));
+            }
+        }
+        let flags = Flags {
+            unroll: unrolls(backend),
+            ..flags
+        };
+
+        let named: Arc<[CustomMatcher]> = named.into();
+        let mut e = Engine::ast_to_nfa(parsed.hir.kind(), flags, &named);
+        e.backend = backend;
+        let pattern_nfa = e.nfa.clone();
 
         e.nfa.mark_capture_group(0, None);
-        e.nfa.debug();
+
+        // every called group is compiled once more as a detached component
+        // that call transitions jump into
+        let mut compiled: HashMap<u32, (usize, Vec<usize>)> = HashMap::new();
+        for (slot, &group) in parsed.calls.iter().enumerate() {
+            let subroutine = match compiled.get(&group) {
+                Some(subroutine) => subroutine.clone(),
+                None => {
+                    let sub_nfa = if group == 0 {
+                        pattern_nfa.clone()
+                    } else {
+                        // the parser checked that every called group exists
+                        let capture = parser::find_capture(&parsed.hir, |c| c.index == group);
+                        Engine::ast_to_nfa(capture.unwrap().sub.kind(), flags, &named).nfa
+                    };
+                    let subroutine = e.nfa.embed(&sub_nfa);
+                    compiled.insert(group, subroutine.clone());
+                    subroutine
+                }
+            };
+            e.nfa.subroutines.insert(slot, subroutine);
+        }
+        e.nfa.compact();
+        e.nfa.dedup();
+        e.nfa.build_ascii_tables();
+        e.nfa.build_literal_runs();
+        e.onepass = OnePass::new(&e.nfa).ok();
+        if e.nfa.counters > 0 {
+            e.counted = Some(parsed.hir.clone());
+        }
+        if !flags.anchored {
+            e.first_chars = FirstChars::new(&e.nfa, flags.bytes);
+        }
+        // calls, named matchers and grapheme clusters are not measured by
+        // the HIR
+        let exact_lengths = parsed.calls.is_empty() && parsed.named.is_empty() && !flags.graphemes;
+        if exact_lengths {
+            e.max_len = parsed.hir.properties().maximum_len();
+            e.min_len = parsed.hir.properties().minimum_len();
+        }
+        // literals are compared after normalization
+        if !flags.nfc && !flags.anchored {
+            e.prefilter = Prefilter::new(&parsed.hir, exact_lengths);
+        }
+        instrument::event!(
+            debug,
+            "compiled {} states for the {:?} backend, one-pass: {}, prefilter: {}, first chars: {}",
+            e.nfa.states.len(),
+            e.backend,
+            e.onepass.is_some(),
+            e.prefilter.is_some(),
+            e.first_chars.is_some()
+        );
 
         Ok(e)
     }
+
+    // What each part of `pattern` matches and what it compiles into, with
+    // the default options; see `Explanation` and `EngineBuilder::explain`.
+    pub fn explain(pattern: &str) -> Result<Explanation, Error> {
+        EngineBuilder::new().explain(pattern)
+    }
+
+    // An engine running the finite automaton of a JFLAP `.jff` file, with
+    // the default options, which can then be combined with engines compiled
+    // from patterns. See `NFAutomata::from_jflap`.
+    pub fn from_jflap(jff: &str) -> Result<Engine, Error> {
+        Ok(Engine::from_nfa(NFAutomata::from_jflap(jff)?))
+    }
+
+    // Likewise for an FST in the OpenFST text format, its numeric labels
+    // named by `symbols`; see `NFAutomata::from_openfst`.
+    pub fn from_openfst(fst: &str, symbols: Option<&str>) -> Result<Engine, Error> {
+        Ok(Engine::from_nfa(NFAutomata::from_openfst(fst, symbols)?))
+    }
+
+    // The automaton with `{n,m}` unrolled, for the matchers that have no
+    // counter registers, like `OnlineMatcher`. Only differs from `nfa` when
+    // the backtracking backend gave the pattern, or a part combined by
+    // `union` or `then`, counters; the groups left out by
+    // `EngineBuilder::captures` stay out.
+    pub(crate) fn unrolled_nfa(&self) -> Result<&NFAutomata, Error> {
+        let unrolled = match (&self.counted, self.unrolled.get()) {
+            (Some(hir), _) => self.unrolled.get_or_init(|| self.unroll(hir)),
+            (None, Some(unrolled)) => unrolled,
+            (None, None) => return Ok(&self.nfa),
+        };
+        unrolled.as_ref().map_err(Clone::clone)
+    }
+
+    fn unroll(&self, hir: &Hir) -> Result<NFAutomata, Error> {
+        let size = Engine::estimated_size(hir, Backend::PikeVm);
+        if size > self.nfa_size_limit {
+            let limit = self.nfa_size_limit;
+            return Err(SizeLimitExceeded::Nfa { size, limit }.into());
+        }
+
+        let flags = Flags {
+            unroll: true,
+            ..self.flags
+        };
+        let mut nfa = Engine::ast_to_nfa(hir.kind(), flags, &self.named).nfa;
+        nfa.mark_capture_group(0, None);
+        let kept: Vec<u32> = self
+            .nfa
+            .states
+            .iter()
+            .flat_map(|state| state.start_group.iter().map(|(index, _)| *index))
+            .collect();
+        nfa.retain_capture_groups(|index, _| kept.contains(&index));
+        nfa.compact();
+        nfa.dedup();
+        nfa.build_ascii_tables();
+        nfa.build_literal_runs();
+        Ok(nfa)
+    }
+
+    fn from_nfa(mut nfa: NFAutomata) -> Engine {
+        nfa.mark_capture_group(0, None);
+        nfa.build_ascii_tables();
+        nfa.build_literal_runs();
+        Engine {
+            onepass: OnePass::new(&nfa).ok(),
+            first_chars: FirstChars::new(&nfa, false),
+            nfa,
+            ..Engine::default()
+        }
+    }
+
+    // An engine matching what either engine matches, preferring `self`'s
+    // matches like `a|b` prefers `a`, without parsing either pattern again.
+    // The groups of `other` are numbered after those of `self`, while named
+    // groups keep their names. The flags and options of `self` apply, and
+    // engines with different backends combine into a backtracking one, the
+    // backend that runs every automaton. No prefilter is built.
+    pub fn union(&self, other: &Engine) -> Engine {
+        let mut e = self.combined(other, NFAutomata::union);
+        e.max_len = self.max_len.zip(other.max_len).map(|(a, b)| a.max(b));
+        e.min_len = self.min_len.zip(other.min_len).map(|(a, b)| a.min(b));
+        e
+    }
+
+    // Adds `pattern` as one more alternation branch, tried after those of
+    // `self`, compiling only the new pattern. The builder options `self`
+    // was compiled with apply to it, such as the parser flags and the
+    // classes of `define_class`, along with the flags and backend of
+    // `self`. Groups are numbered as for `union`.
+    pub fn or_pattern(&self, pattern: &str) -> Result<Engine, Error> {
+        let branch = self
+            .builder
+            .build_branch(pattern, self.flags, self.backend)?;
+        Ok(self.union(&branch))
+    }
+
+    // An engine matching what `self` matches immediately followed by what
+    // `other` matches, without parsing either pattern again. Groups are
+    // numbered as for `union`, the whole match spanning both parts, and
+    // the flags, options and backend are chosen the same way.
+    pub fn then(&self, other: &Engine) -> Engine {
+        let mut e = self.combined(other, NFAutomata::concat);
+        e.max_len = self.max_len.zip(other.max_len).map(|(a, b)| a + b);
+        e.min_len = self.min_len.zip(other.min_len).map(|(a, b)| a + b);
+        e
+    }
+
+    // An engine running the automaton `combine` makes of those of `self`
+    // and `other`, with the flags and options of `self`. When either has
+    // counters, their unrolled automata are combined the same way, as the
+    // patterns are not lowered again.
+    fn combined(
+        &self,
+        other: &Engine,
+        combine: impl Fn(&NFAutomata, &NFAutomata) -> NFAutomata,
+    ) -> Engine {
+        let backend = match self.backend == other.backend {
+            true => self.backend,
+            false => Backend::Backtrack,
+        };
+        let nfa = combine(&self.nfa, &other.nfa);
+        let unrolled = OnceLock::new();
+        if nfa.counters > 0 {
+            let parts = self
+                .unrolled_nfa()
+                .and_then(|a| Ok((a, other.unrolled_nfa()?)));
+            let _ = unrolled.set(parts.map(|(a, b)| combine(a, b)));
+        }
+
+        Engine {
+            onepass: OnePass::new(&nfa).ok(),
+            first_chars: match self.flags.anchored {
+                true => None,
+                false => FirstChars::new(&nfa, self.flags.bytes),
+            },
+            nfa,
+            flags: self.flags,
+            backend,
+            thread_local_cache: self.thread_local_cache,
+            dfa_size_limit: self.dfa_size_limit,
+            nfa_size_limit: self.nfa_size_limit,
+            unrolled,
+            builder: self.builder.clone(),
+            ..Engine::default()
+        }
+    }
+
+    // Whether both engines fully match the same texts, such as `(a|b)*` and
+    // `(a*b*)*`, compared as languages; see `Language::is_equivalent`.
+    // Fails for the patterns a `Language` cannot be built from.
+    pub fn is_equivalent(&self, other: &Engine) -> Result<bool, Error> {
+        let (a, b) = (Language::try_from(self)?, Language::try_from(other)?);
+        Ok(a.is_equivalent(&b))
+    }
+
+    // Whether `s` can still be completed into a text the engine fully
+    // matches, as when validating a form field while it is typed; see
+    // `Language::is_valid_prefix`. Fails for the patterns a `Language`
+    // cannot be built from, such as those with look-arounds.
+    pub fn is_valid_prefix(&self, s: &str) -> Result<bool, Error> {
+        match self.language.get_or_init(|| Language::try_from(self)) {
+            Ok(language) => Ok(language.is_valid_prefix(s)),
+            Err(err) => Err(err.clone()),
+        }
+    }
+
+    // Whether every text `self` fully matches is fully matched by `other`,
+    // such as `/users/\d+` by `/users/\w+`; see `Language::is_subset_of`.
+    // Fails like `is_equivalent`.
+    pub fn is_subset_of(&self, other: &Engine) -> Result<bool, Error> {
+        let (a, b) = (Language::try_from(self)?, Language::try_from(other)?);
+        Ok(a.is_subset_of(&b))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Engine;
+    use super::{Backend, Engine, MAX_VISITED_BITS};
+    use crate::rsgex::{
+        builder::EngineBuilder, cancel::CancelToken, dfa::Dfa, error::Error, matcher::Transition,
+    };
 
     #[test]
     fn test_literal() {
@@ -251,6 +1316,32 @@ mod test {
         assert!(e.nfa.compute("124").is_none());
     }
 
+    #[test]
+    fn test_unicode_literal() {
+        let e = Engine::try_from("é中(文)").unwrap();
+
+        assert_eq!(e.exec_test("é中文!"), "é中文");
+        assert_eq!(
+            e.exec("é中文").unwrap().get("1").map(|s| s.as_str()),
+            Some("文")
+        );
+        assert!(!e.test("e中文"));
+        assert_eq!(e.nfa.states.len(), 4);
+    }
+
+    #[test]
+    fn test_empty() {
+        let e = Engine::try_from("").unwrap();
+        assert_eq!(e.find_at("abc", 1).map(|m| (m.start, m.end)), Some((1, 1)));
+        let e = Engine::try_from("()").unwrap();
+        assert_eq!(e.exec("x").unwrap()["1"], "");
+        let e = Engine::try_from("a{0}").unwrap();
+        assert_eq!(e.exec_test("a"), "");
+        let e = Engine::try_from("a{0}b|()c").unwrap();
+        assert_eq!(e.find_at("ab", 0).unwrap().as_str(), "b");
+        assert_eq!(e.find_at("c", 0).unwrap().groups["1"], "");
+    }
+
     #[test]
     fn test_alternation() {
         let e = Engine::try_from("123|456").unwrap();
@@ -259,6 +1350,70 @@ mod test {
         assert!(e.nfa.compute("345").is_none());
     }
 
+    #[test]
+    fn test_alternation_of_loops() {
+        // branches whose first state is looped back to cannot share the
+        // state the alternation starts from
+        let e = Engine::try_from("b|b*c").unwrap();
+        assert_eq!(e.find_at("a", 0), None);
+        assert_eq!(e.find_at("bbc", 0).unwrap().as_str(), "b");
+        let e = Engine::try_from("x|b*c").unwrap();
+        assert_eq!(e.find_at("bbx", 0).unwrap().as_str(), "x");
+        let e = Engine::try_from("^(?:a*|b)$").unwrap();
+        assert!(!e.test("ab"));
+        assert!(e.test("aa") && e.test("b") && e.test(""));
+        let e = Engine::try_from("(?:b*c)*").unwrap();
+        assert_eq!(e.find_at("bbcbb", 0).unwrap().as_str(), "bbc");
+
+        let dfa = Dfa::try_from(&Engine::try_from("x|b*c").unwrap()).unwrap();
+        assert_eq!(dfa.find("bbx"), Some((2, 3)));
+        let e = Engine::try_from("b|b*c").unwrap();
+        assert_eq!(
+            e.is_equivalent(&Engine::try_from("b|c|b+c").unwrap()),
+            Ok(true)
+        );
+        assert_eq!(
+            Engine::try_from("b|bb").unwrap().is_subset_of(&e),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_suffix_sharing() {
+        let e = Engine::try_from(".*\\.jpeg|.*\\.mpeg").unwrap();
+        assert!(e.nfa.states.len() < 12);
+        assert_eq!(e.exec("a.jpeg").unwrap()["0"], "a.jpeg");
+        assert!(e.test("b.mpeg"));
+        assert!(!e.test("c.png"));
+
+        // a branch may be left empty, or split inside a literal
+        let e = Engine::try_from("x(abc|bc|c)").unwrap();
+        for (s, group) in [("xabc", "abc"), ("xbc", "bc"), ("xc", "c")] {
+            assert_eq!(e.exec(s).unwrap()["1"], group);
+        }
+        assert!(!e.test("xac"));
+        let e = Engine::try_from("(é|è)").unwrap();
+        assert!(e.test("è") && !e.test("e"));
+    }
+
+    #[test]
+    fn test_literal_trie() {
+        let e = Engine::try_from("foo|fob|bar|fo").unwrap();
+        // `fo` is tested once for the first two branches
+        assert_eq!(e.nfa.states.len(), 9);
+        for s in ["foo", "fob", "bar", "fo"] {
+            assert!(e.test(s));
+        }
+        assert!(!e.test("fbo"));
+
+        // earlier branches keep their priority
+        let e = Engine::try_from("(ab|a|abc)(c*)").unwrap();
+        let groups = e.exec("abc").unwrap();
+        assert_eq!((groups["1"].as_str(), groups["2"].as_str()), ("ab", "c"));
+        let e = Engine::try_from("(a|ab)(c*)").unwrap();
+        assert_eq!(e.exec("abc").unwrap()["1"], "a");
+    }
+
     #[test]
     fn test_repetition() {
         let e = Engine::try_from("1+").unwrap();
@@ -298,6 +1453,118 @@ mod test {
         assert!(e2.nfa.compute("123444455").is_some());
     }
 
+    #[test]
+    fn test_counted_repetition() {
+        let e = Engine::try_from("^(foo){1,1000}$").unwrap();
+        assert!(e.nfa.states.len() < 20);
+        assert!(e.test(&"foo".repeat(1000)));
+        assert!(!e.test(&"foo".repeat(1001)));
+        assert!(!e.test(""));
+
+        let e = Engine::try_from("^(?:a{2}b){3}$").unwrap();
+        assert!(e.test("aabaabaab"));
+        assert!(!e.test("aabaabab"));
+
+        let e = Engine::try_from("^(?:a?){3}b{2,}$").unwrap();
+        assert!(e.test("bb"));
+        assert!(e.test("aabbbb"));
+        assert!(!e.test("aaaab"));
+
+        let e = Engine::try_from("a{2,4}?").unwrap();
+        assert_eq!(e.exec_test("aaaa"), "aa");
+        let e = Engine::try_from("a{2,4}").unwrap();
+        assert_eq!(e.exec_test("aaaaa"), "aaaa");
+
+        // empty iterations inside a loop end once the counters stop moving
+        let e = Engine::try_from("(?:(?:a?){2})*c").unwrap();
+        assert!(e.test("c"));
+        assert_eq!(e.exec_test("aaac"), "aaac");
+        let e = Engine::try_from("(?:(?:a|){1,2})+").unwrap();
+        assert!(e.test("x"));
+        assert_eq!(e.exec_test("aaa"), "aaa");
+
+        // past the minimum an empty iteration still leaves, as when unrolled
+        let e = Engine::try_from("(?:c*|a){2,}").unwrap();
+        assert_eq!(e.find_at("aca", 0).map(|m| (m.start, m.end)), Some((0, 0)));
+        let e = Engine::try_from("b(?:[ab]*?){2,}").unwrap();
+        assert_eq!(e.exec_test("bacb"), "b");
+        // a skipped copy leaves its group unset
+        let e = Engine::try_from("(?:(bb)){0,2}").unwrap();
+        assert_eq!(e.find_at("c", 0).unwrap().groups.get("1"), None);
+        assert_eq!(
+            Engine::try_from("(bb)?")
+                .unwrap()
+                .exec("c")
+                .unwrap()
+                .get("1"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_counted_repetition_agrees_with_unrolling() {
+        // xorshift
+        let mut seed = 0x9e3779b97f4a7c15_u64;
+        let mut rng = move |n: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % n
+        };
+        // small patterns over `abc`, with bodies that may match empty
+        fn pattern(rng: &mut impl FnMut(u64) -> u64, depth: u32) -> String {
+            let atom = match rng(if depth == 0 { 3 } else { 7 }) {
+                0 => ["a", "b", "c"][rng(3) as usize].to_string(),
+                1 => "[ab]".to_string(),
+                2 => String::new(),
+                3 => format!("({})", pattern(rng, depth - 1)),
+                4 => format!("(?:{})", pattern(rng, depth - 1)),
+                5 => format!("{}|{}", pattern(rng, depth - 1), pattern(rng, depth - 1)),
+                _ => format!("{}{}", pattern(rng, depth - 1), pattern(rng, depth - 1)),
+            };
+            let min = rng(3);
+            let quantifier = match rng(6) {
+                0 => format!("{{{}}}", min + 1),
+                1 => format!("{{{},{}}}", min, min + 1 + rng(2)),
+                2 => format!("{{{},}}", min + 1),
+                3 => ["*", "+", "?"][rng(3) as usize].to_string(),
+                _ => return atom,
+            };
+            let lazy = if rng(2) == 0 { "?" } else { "" };
+            format!("(?:{}){}{}", atom, quantifier, lazy)
+        }
+
+        for _ in 0..1000 {
+            let pattern = pattern(&mut rng, 3);
+            // nested nullable bodies backtrack exponentially either way,
+            // such inputs are skipped
+            let counted = EngineBuilder::new()
+                .backend(Backend::Backtrack)
+                .step_limit(20_000)
+                .build(&pattern)
+                .unwrap();
+            let unrolled = EngineBuilder::new()
+                .backend(Backend::PikeVm)
+                .build(&pattern)
+                .unwrap();
+            for _ in 0..8 {
+                let input: String = (0..rng(6))
+                    .map(|_| ['a', 'b', 'c'][rng(3) as usize])
+                    .collect();
+                let Ok(found) = counted.try_find_at(&input, 0) else {
+                    continue;
+                };
+                assert_eq!(
+                    found,
+                    unrolled.find_at(&input, 0),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_accepter() {
         let e = Engine::try_from("123+|456+|7{3}|888").unwrap();
@@ -335,6 +1602,130 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_many_capture_groups() {
+        let pattern = "(a)".repeat(70);
+        let e = Engine::try_from(pattern.as_str()).unwrap();
+
+        let res = e.exec(&"a".repeat(70)).unwrap();
+        assert_eq!(res.len(), 71);
+        assert_eq!(res.get(&70.to_string()).map(|s| s.as_str()), Some("a"));
+    }
+
+    #[test]
+    fn test_capture_after_backtracking() {
+        let e = Engine::try_from("(a+)(b)|(a)c").unwrap();
+
+        let res = e.exec("ac").unwrap();
+        assert_eq!(res.get(&1.to_string()), None);
+        assert_eq!(res.get(&3.to_string()).map(|s| s.as_str()), Some("a"));
+
+        let e = Engine::try_from("(?:(a)|b)+").unwrap();
+        assert_eq!(
+            e.exec("ab")
+                .unwrap()
+                .get(&1.to_string())
+                .map(|s| s.as_str()),
+            Some("a")
+        );
+
+        // the loop back into `x*` must not reopen the group
+        let e = Engine::try_from("(?<x>x*)y?").unwrap();
+        let res = e.nfa.compute("x@").unwrap();
+        assert_eq!(res.get("x").map(|s| s.as_str()), Some("x"));
+        assert_eq!(res.get("0").map(|s| s.as_str()), Some("x"));
+
+        // the skip edge of the loop is no empty last iteration
+        let e = Engine::try_from("c(b)*|c").unwrap();
+        assert_eq!(e.exec("cb").unwrap()["1"], "b");
+        let e = Engine::try_from("(b)*b?").unwrap();
+        assert_eq!(e.exec("b").unwrap()["1"], "b");
+        let expected = Engine::try_from("c(b)*").unwrap().find_at("cb", 0).unwrap();
+        assert_eq!(expected.groups["1"], "b");
+        for backend in [Backend::PikeVm, Backend::BoundedBacktrack] {
+            let e = EngineBuilder::new()
+                .backend(backend)
+                .build("c(b)*")
+                .unwrap();
+            assert_eq!(e.find_at("cb", 0).as_ref(), Some(&expected));
+        }
+    }
+
+    #[test]
+    fn test_branch_reset() {
+        let e = Engine::try_from("(?|a([0-9])|b([0-9]))-([0-9])").unwrap();
+
+        let res = e.exec("b7-8").unwrap();
+        assert_eq!(res.get(&1.to_string()).map(|s| s.as_str()), Some("7"));
+        assert_eq!(res.get(&2.to_string()).map(|s| s.as_str()), Some("8"));
+
+        // no internal name reaches the groups, nor is a user name taken
+        // for one
+        for state in e.states() {
+            assert!(state.groups_started().all(|(_, name)| name.is_none()));
+        }
+        let e = Engine::try_from("(?<__rsgex_branch_reset_0>a)(b)").unwrap();
+        let res = e.exec("ab").unwrap();
+        assert_eq!(
+            res.get("__rsgex_branch_reset_0").map(|s| s.as_str()),
+            Some("a")
+        );
+        assert_eq!(res.get(&2.to_string()).map(|s| s.as_str()), Some("b"));
+
+        // parens in classes and comments open no group
+        let e = Engine::try_from("[[:digit:](](?|(a)|(b))(c)").unwrap();
+        let res = e.exec("(bc").unwrap();
+        assert_eq!(res.get("1").map(|s| s.as_str()), Some("b"));
+        assert_eq!(res.get("2").map(|s| s.as_str()), Some("c"));
+        let e = EngineBuilder::new()
+            .ignore_whitespace(true)
+            .build("a#(\n(?|(a)|(b))(c)")
+            .unwrap();
+        let res = e.exec("abc").unwrap();
+        assert_eq!(res.get("1").map(|s| s.as_str()), Some("b"));
+        assert_eq!(res.get("2").map(|s| s.as_str()), Some("c"));
+    }
+
+    #[test]
+    fn test_recursion() {
+        let e = Engine::try_from("\\((?:[^()]|(?R))*\\)").unwrap();
+
+        assert_eq!(e.exec_test("(a(b)(c(d)))"), "(a(b)(c(d)))");
+        assert_eq!(e.exec_test("(a(b))c)"), "(a(b))");
+        assert!(!e.test("(a(b)"));
+    }
+
+    #[test]
+    fn test_subroutine_call() {
+        let e = Engine::try_from("^(?<pair>\\[(?&pair)*\\])=(?1)$").unwrap();
+
+        assert!(e.test("[[][]]=[]"));
+        assert!(!e.test("[[]=[]"));
+        assert_eq!(
+            e.exec("[[]]=[]").unwrap().get("pair").map(|s| s.as_str()),
+            Some("[[]]")
+        );
+    }
+
+    #[test]
+    fn test_recursion_with_counted_repetition() {
+        // the call reuses the registers, the caller gets its counts back
+        let e = Engine::try_from("^((?:a(?1)?b){2})$").unwrap();
+        assert!(e.test("aababbab"));
+        assert!(e.test("abab"));
+        assert!(!e.test("aababb"));
+        assert!(!e.test("ab"));
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let mut e = Engine::try_from("^(\\((?1)?\\))$").unwrap();
+        e.nfa.recursion_limit = 2;
+
+        assert!(e.test("((()))"));
+        assert!(!e.test("(((())))"));
+    }
+
     #[test]
     fn test_class() {
         let e = Engine::try_from("[1-9]+").unwrap();
@@ -352,6 +1743,251 @@ mod test {
         assert!(e.nfa.compute("1").is_none());
     }
 
+    #[test]
+    fn test_continuation_anchor() {
+        let e = Engine::try_from("\\G[0-9]").unwrap();
+
+        let digits: Vec<String> = e
+            .find_iter("123a4")
+            .map(|m| m.as_str().to_string())
+            .collect();
+        assert_eq!(digits, vec!["1", "2", "3"]);
+
+        assert!(e.find_at("a1", 0).is_none());
+        assert_eq!(e.find_at("a1", 1).unwrap().as_str(), "1");
+    }
+
+    #[test]
+    fn test_find_iter() {
+        let e = Engine::try_from("[0-9]+").unwrap();
+
+        let spans: Vec<(usize, usize)> = e.find_iter("a12b345").map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, vec![(1, 3), (4, 7)]);
+    }
+
+    #[test]
+    fn test_prefix_prefilter() {
+        let e = Engine::try_from("ERROR: (\\d+)").unwrap();
+        assert!(e.prefilter.is_some());
+
+        let log = "INFO: 1\nERROR: x\nERROR: 42\nERROR: 7";
+        let codes: Vec<String> = e.find_iter(log).map(|m| m.groups["1"].clone()).collect();
+        assert_eq!(codes, vec!["42", "7"]);
+        assert!(e.find_at(log, 30).is_none());
+
+        let e = crate::BytesEngine::try_from("\\xFF(.)").unwrap();
+        assert_eq!(e.find_at(b"a\xFFb\xFFc", 2).unwrap().start, 3);
+    }
+
+    #[test]
+    fn test_inner_prefilter() {
+        let e = Engine::try_from("(\\w+)@example\\.com").unwrap();
+        assert!(e.prefilter.is_some());
+
+        let text = "mail bob@example.org or amy@example.com";
+        assert_eq!(e.find_at(text, 0).unwrap().groups["1"], "amy");
+        assert!(e.find_at(text, 28).is_none());
+
+        let e = Engine::try_from("[0-9]{2}:[0-9]{2} ok").unwrap();
+        let found: Vec<(usize, usize)> = e
+            .find_iter("12:34 no, 56:78 ok")
+            .map(|m| (m.start, m.end))
+            .collect();
+        assert_eq!(found, vec![(10, 18)]);
+    }
+
+    #[test]
+    fn test_literals_prefilter() {
+        let e = Engine::try_from("foo|bar|baz").unwrap();
+        assert!(e.prefilter.is_some());
+
+        let found: Vec<String> = e
+            .find_iter("a bar, a foo and a baz")
+            .map(|m| m.groups["0"].to_string())
+            .collect();
+        assert_eq!(found, vec!["bar", "foo", "baz"]);
+        assert!(e.find_at("ba fo", 0).is_none());
+    }
+
+    #[test]
+    fn test_byte_offsets() {
+        let s = "héllo wörld";
+        let e = Engine::try_from("w\\w+").unwrap();
+
+        let m = e.find_at(s, 0).unwrap();
+        assert_eq!((m.start, m.end), (7, 13));
+        assert_eq!(&s[m.start..m.end], "wörld");
+        // inside `é`
+        assert!(e.find_at(s, 2).is_none());
+
+        let e = Engine::try_from("x*").unwrap();
+        let starts: Vec<usize> = e.find_iter("éé").map(|m| m.start).collect();
+        assert_eq!(starts, vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_inline_flags() {
+        let e = Engine::try_from("(?i:a)b").unwrap();
+        assert!(e.test("Ab"));
+        assert!(!e.test("AB"));
+
+        let e = Engine::try_from("a(?s:.).").unwrap();
+        assert!(e.test("a\nb"));
+        assert!(!e.test("ab\n"));
+
+        let e = Engine::try_from("(?m:^b$)").unwrap();
+        assert_eq!(e.find_at("a\nb\nc", 0).map(|m| m.start), Some(2));
+
+        let e = Engine::try_from("(?Rm:c$)").unwrap();
+        assert!(e.find_at("abc\r\n", 0).is_some());
+
+        let e = Engine::try_from("\\bfoo\\b").unwrap();
+        assert!(e.find_at("a foo b", 0).is_some());
+        assert!(e.find_at("afoo", 0).is_none());
+
+        let e = Engine::try_from("(?-u:[a-c])+").unwrap();
+        assert_eq!(e.exec_test("abcd"), "abc");
+    }
+
+    #[test]
+    fn test_unicode_property_class() {
+        let e = Engine::try_from("\\p{Greek}+").unwrap();
+        assert_eq!(e.exec_test("αβγabc"), "αβγ");
+        assert!(!e.test("abc"));
+
+        let e = Engine::try_from("\\p{Greek}").unwrap();
+        let consuming = e
+            .nfa
+            .states
+            .iter()
+            .flat_map(|s| s.matchers.iter())
+            .filter(|(m, _)| !m.is_epsilon())
+            .count();
+        assert_eq!(consuming, 1);
+
+        let e = Engine::try_from("\\p{L}\\P{L}").unwrap();
+        assert!(e.test("中1"));
+        assert!(e.test("a "));
+        assert!(!e.test("ab"));
+    }
+
+    #[test]
+    fn test_negated_class() {
+        let e = Engine::try_from("[^a-fx-z]").unwrap();
+
+        assert!(e.test("g"));
+        assert!(e.test("0"));
+        assert!(e.test("中"));
+        assert!(!e.test("a"));
+        assert!(!e.test("f"));
+        assert!(!e.test("y"));
+        assert_eq!(
+            e.nfa.states.iter().map(|s| s.matchers.len()).sum::<usize>(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_class_alternates_ranges() {
+        let e = Engine::try_from("[a-zA-Z0-9_]+").unwrap();
+
+        assert_eq!(e.exec_test("aZ0_-"), "aZ0_");
+
+        let e = Engine::try_from("[a-cx]").unwrap();
+        assert_eq!(e.nfa.states.len(), 2);
+        assert!(e.test("b"));
+        assert!(e.test("x"));
+        assert!(!e.test("d"));
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Engine>();
+        assert_send_sync::<crate::BytesEngine>();
+        assert_send_sync::<crate::Dfa>();
+
+        let e = Engine::try_from("(?<word>\\w+) \\p{Greek}").unwrap();
+        std::thread::scope(|scope| {
+            for input in ["ab α", "cd β"] {
+                let e = &e;
+                scope.spawn(move || assert!(e.test(input)));
+            }
+        });
+    }
+
+    #[test]
+    fn test_single_range_class() {
+        let e = Engine::try_from("[α-ω]+").unwrap();
+
+        assert_eq!(e.exec_test("αβγabc"), "αβγ");
+        assert!(
+            e.nfa
+                .states
+                .iter()
+                .flat_map(|s| s.matchers.iter())
+                .any(|(t, _)| *t == Transition::Range('α', 'ω'))
+        );
+    }
+
+    #[test]
+    fn test_case_folded_literals() {
+        let e = Engine::try_from("(?i)kéß").unwrap();
+
+        assert!(e.test("KÉß"));
+        assert!(e.test("\u{212A}éẞ"));
+        assert!(!e.test("kes"));
+        let labels: Vec<String> = e
+            .nfa
+            .states
+            .iter()
+            .flat_map(|s| s.matchers.iter())
+            .map(|(m, _)| m.label())
+            .collect();
+        assert!(labels.contains(&"{K,k,\u{212A}}".to_string()));
+        assert!(labels.contains(&"{É,é}".to_string()));
+    }
+
+    #[test]
+    fn test_posix_classes() {
+        let e = Engine::try_from("[[:alpha:]]+[[:digit:]][[:^space:]]").unwrap();
+
+        assert_eq!(e.exec_test("abC1-"), "abC1-");
+        assert!(!e.test("ab1 "));
+        assert!(!e.test("éb1-"));
+        assert!(
+            e.nfa
+                .states
+                .iter()
+                .flat_map(|s| s.matchers.iter())
+                .any(|(m, _)| m.label() == "ascii(^0x100003e00)")
+        );
+
+        let e = Engine::try_from("[[:xdigit:]]{2}").unwrap();
+        assert!(e.test("fA"));
+        assert!(!e.test("fG"));
+    }
+
+    #[test]
+    fn test_perl_classes() {
+        let e = Engine::try_from("\\d+").unwrap();
+        assert_eq!(e.exec_test("12٣a"), "12٣");
+        assert!(
+            e.nfa
+                .states
+                .iter()
+                .flat_map(|s| s.matchers.iter())
+                .any(|(m, _)| m.label() == "\\d")
+        );
+
+        let e = Engine::try_from("\\w\\W\\s\\S\\D").unwrap();
+        assert!(e.test("é-\u{3000}xy"));
+        assert!(!e.test("é-\u{3000}x1"));
+
+        let e = Engine::try_from("(?-u:\\w)+").unwrap();
+        assert_eq!(e.exec_test("ab_1é"), "ab_1");
+    }
+
     #[test]
     fn test_look() {
         let e = Engine::try_from("123$").unwrap();
@@ -359,4 +1995,345 @@ mod test {
         assert_eq!(e.exec_test("123"), "123");
         assert!(e.nfa.compute("1234").is_none());
     }
+
+    #[test]
+    fn test_bounded_backtrack() {
+        let bounded = |pattern: &str| {
+            EngineBuilder::new()
+                .backend(Backend::BoundedBacktrack)
+                .build(pattern)
+                .unwrap()
+        };
+        let patterns = [
+            "(?<y>\\d{4})-(\\d\\d)?",
+            "(a|ab)(c|bcd)(d*)",
+            "(a+?)(b*)",
+            "(?:(x)|(y))+z",
+            "\\b(\\w+)\\s*$",
+            // lazy counted repetitions, unrolled for this backend
+            "(\\d{2,3}?)(\\d*)",
+            "(?:(x)|y){1,3}?z",
+            "([a-d]{0,2}?)(b*)",
+        ];
+        let inputs = ["", "2024-05 2023-", "abcd", "aabbb", "xyxz", "one two  "];
+        for pattern in patterns {
+            let (e, expected) = (bounded(pattern), Engine::try_from(pattern).unwrap());
+            for input in inputs {
+                for (at, _) in input.char_indices().chain([(input.len(), ' ')]) {
+                    assert_eq!(
+                        e.find_at(input, at),
+                        expected.find_at(input, at),
+                        "{} on {:?} at {}",
+                        pattern,
+                        input,
+                        at
+                    );
+                }
+            }
+        }
+
+        // exponential for the plain backtracker, each (state, position) pair
+        // is explored once here
+        let e = bounded("(a|a)*[bc]");
+        assert!(e.find_at(&"a".repeat(64), 0).is_none());
+        assert_eq!(
+            e.find_at(&format!("{}c", "a".repeat(64)), 0).unwrap().end,
+            65
+        );
+    }
+
+    #[test]
+    fn test_bounded_backtrack_fallback() {
+        // too many (state, position) pairs for the visited set, the Pike VM
+        // takes over
+        let pattern = "(\\d{1,100})-\\b(\\w)";
+        let e = EngineBuilder::new()
+            .backend(Backend::BoundedBacktrack)
+            .build(pattern)
+            .unwrap();
+        let input = format!("{} 12-x", "a".repeat(1_000_000));
+        assert!(e.nfa.states.len() * (input.len() + 1) > MAX_VISITED_BITS);
+
+        let m = e.find_at(&input, 0).unwrap();
+        assert_eq!((m.start, m.end), (1_000_001, 1_000_005));
+        assert_eq!(
+            (m.groups["1"].as_str(), m.groups["2"].as_str()),
+            ("12", "x")
+        );
+        assert_eq!(
+            Some(m),
+            Engine::try_from(pattern).unwrap().find_at(&input, 0)
+        );
+        assert!(e.find_at(&input[..1_000_003], 0).is_none());
+
+        // lazy, the Pike VM stops where the backtracker does
+        let pattern = "(\\d{1,100}?)(\\d?)-";
+        let e = EngineBuilder::new()
+            .backend(Backend::BoundedBacktrack)
+            .build(pattern)
+            .unwrap();
+        let m = e.find_at(&input, 0).unwrap();
+        assert_eq!((m.groups["1"].as_str(), m.groups["2"].as_str()), ("1", "2"));
+        assert_eq!(
+            Some(m),
+            Engine::try_from(pattern).unwrap().find_at(&input, 0)
+        );
+    }
+
+    #[test]
+    fn test_cancel() {
+        let e = Engine::try_from("(a|aa)*[bc]").unwrap();
+        let cancel = CancelToken::new();
+        assert_eq!(
+            e.try_exec_cancellable("aac", &cancel).map(|m| m.is_some()),
+            Ok(true)
+        );
+
+        let handle = std::thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                cancel.cancel();
+            }
+        });
+        assert_eq!(
+            e.try_find_at_cancellable(&"a".repeat(64), 0, &cancel),
+            Err(Error::Cancelled)
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_structure_hash() {
+        let hash = |pattern: &str| Engine::try_from(pattern).unwrap().structure_hash();
+        assert_eq!(hash("[ab]x"), hash("[ba]x"));
+        assert_eq!(hash("a|b"), hash("[ab]"));
+        assert_eq!(hash("a+"), hash("aa*"));
+        assert_ne!(hash("foo|bar"), hash("bar|foo"));
+        assert_ne!(hash("(a)b"), hash("ab"));
+
+        // equal classes share their table
+        let class = |pattern: &str| {
+            let e = Engine::try_from(pattern).unwrap();
+            e.nfa
+                .states
+                .iter()
+                .flat_map(|state| state.matchers.iter())
+                .find_map(|(t, _)| match t {
+                    Transition::Class(class) => Some(class.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert!(std::sync::Arc::ptr_eq(
+            &class("[α-ωа-я]x"),
+            &class("y*[а-яα-ω]")
+        ));
+    }
+
+    #[test]
+    fn test_union() {
+        let date = Engine::try_from("(?<y>\\d{4})-(\\d\\d)").unwrap();
+        let word = Engine::try_from("(\\w+)@(?<host>\\w+)|(b)(?3)").unwrap();
+        let e = date.union(&word);
+
+        let m = e.find_at("on 2024-05", 0).unwrap();
+        assert_eq!(
+            (m.groups["y"].as_str(), m.groups["2"].as_str()),
+            ("2024", "05")
+        );
+        // the groups of `word` follow those of `date`
+        let m = e.find_at("mail x@y", 0).unwrap();
+        assert_eq!((m.start, m.groups["3"].as_str()), (5, "x"));
+        assert_eq!(m.groups["host"], "y");
+        assert_eq!(e.find_at("-bb", 0).unwrap().groups["5"], "b");
+        assert!(!e.test("-"));
+
+        // `self` wins where both match at the same position
+        let e = Engine::try_from("a")
+            .unwrap()
+            .union(&Engine::try_from("ab").unwrap());
+        assert_eq!(e.exec_test("ab"), "a");
+    }
+
+    #[test]
+    fn test_or_pattern() {
+        let e = Engine::try_from("ERROR (\\d+)")
+            .unwrap()
+            .or_pattern("WARN (?<w>.*)")
+            .unwrap()
+            .or_pattern("INFO")
+            .unwrap();
+        assert_eq!(e.find_at("[ERROR 42]", 0).unwrap().groups["1"], "42");
+        assert_eq!(e.find_at("WARN disk", 0).unwrap().groups["w"], "disk");
+        assert!(e.test("INFO"));
+        assert!(!e.test("DEBUG"));
+        assert!(e.or_pattern("(").is_err());
+
+        // the branch is parsed and compiled with the options of `self`
+        let e = EngineBuilder::new()
+            .case_insensitive(true)
+            .multi_line(true)
+            .define_class("hex", |c| c.is_ascii_hexdigit())
+            .build("x")
+            .unwrap()
+            .or_pattern("^\\m{hex}+$")
+            .unwrap();
+        assert!(e.test("X"));
+        let span = e.find_at("-\nfF0", 0).map(|m| (m.start, m.end));
+        assert_eq!(span, Some((2, 5)));
+        assert!(e.find_at("-\nfG", 0).is_none());
+        let e = EngineBuilder::new()
+            .allow_duplicate_names(false)
+            .build("a")
+            .unwrap();
+        assert!(e.or_pattern("(?<n>b)|(?<n>c)").is_err());
+        assert!(
+            Engine::try_from("a")
+                .unwrap()
+                .or_pattern("(?<n>b)|(?<n>c)")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_is_equivalent() {
+        let e = |pattern| Engine::try_from(pattern).unwrap();
+        assert_eq!(e("(a|b)*c").is_equivalent(&e("(a*b*)*c")), Ok(true));
+        assert_eq!(e("[0-9]{2}").is_equivalent(&e("[0-9][0-9]")), Ok(true));
+        // `\d` takes in the digits of every script
+        assert_eq!(e("\\d").is_equivalent(&e("[0-9]")), Ok(false));
+        assert_eq!(e("ab|a").is_equivalent(&e("ab?c")), Ok(false));
+        // look-arounds the DFA rejects, even on the same pattern
+        assert!(matches!(
+            e("a\\b").is_equivalent(&e("a\\b")),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(e("a").is_equivalent(&e("a\\B")).is_err());
+    }
+
+    #[test]
+    fn test_match_len() {
+        let e = Engine::try_from("ab{2,3}|中").unwrap();
+        assert_eq!((e.min_match_len(), e.max_match_len()), (Some(3), Some(4)));
+        let e = Engine::try_from("a\\d+").unwrap();
+        assert_eq!((e.min_match_len(), e.max_match_len()), (Some(2), None));
+        // subroutine calls are not measured
+        let e = Engine::try_from("(a)(?1)").unwrap();
+        assert_eq!((e.min_match_len(), e.max_match_len()), (None, None));
+
+        let e = Engine::try_from("abc")
+            .unwrap()
+            .union(&Engine::try_from("x{5}").unwrap());
+        assert_eq!((e.min_match_len(), e.max_match_len()), (Some(3), Some(5)));
+    }
+
+    #[test]
+    fn test_is_subset_of() {
+        let e = |pattern| Engine::try_from(pattern).unwrap();
+        assert_eq!(e("/users/\\d+").is_subset_of(&e("/users/\\w+")), Ok(true));
+        assert_eq!(e("/users/\\w+").is_subset_of(&e("/users/\\d+")), Ok(false));
+        assert_eq!(e("a{2}").is_subset_of(&e("a+")), Ok(true));
+        // look-arounds the DFA rejects, on either side
+        assert!(matches!(
+            e("a\\b").is_subset_of(&e("a\\b")),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(e("a").is_subset_of(&e("a\\b")).is_err());
+    }
+
+    #[test]
+    fn test_is_valid_prefix() {
+        let e = Engine::try_from("\\d{3}-\\d{4}").unwrap();
+        assert_eq!(e.is_valid_prefix(""), Ok(true));
+        assert_eq!(e.is_valid_prefix("555-"), Ok(true));
+        assert_eq!(e.is_valid_prefix("555-1234"), Ok(true));
+        assert_eq!(e.is_valid_prefix("555-12345"), Ok(false));
+        assert_eq!(e.is_valid_prefix("55a"), Ok(false));
+
+        // look-arounds the DFA rejects, every time
+        let e = Engine::try_from("a\\b").unwrap();
+        assert!(matches!(e.is_valid_prefix("b"), Err(Error::Unsupported(_))));
+        assert!(matches!(e.is_valid_prefix("a"), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_then() {
+        let key = Engine::try_from("(?<key>\\w+)=").unwrap();
+        let value = Engine::try_from("(\\d+)(?:,(\\d+))?").unwrap();
+        let e = key.then(&value);
+
+        let m = e.find_at("set x=1,2;", 0).unwrap();
+        assert_eq!((m.start, m.end), (4, 9));
+        assert_eq!(m.groups["key"], "x");
+        assert_eq!((m.groups["2"].as_str(), m.groups["3"].as_str()), ("1", "2"));
+        assert!(!e.test("x=y"));
+        assert_eq!((e.min_match_len(), e.max_match_len()), (Some(3), None));
+
+        // counted repetitions in both parts
+        let e = Engine::try_from("a{2}")
+            .unwrap()
+            .then(&Engine::try_from("b{1,2}").unwrap());
+        assert_eq!(e.exec_test("aabbb"), "aabb");
+        assert!(!e.test("abb"));
+    }
+
+    #[test]
+    fn test_from_hir() {
+        let hir = regex_syntax::parse("(?<year>\\d{4})-(\\d\\d)").unwrap();
+        let e = Engine::from_hir(&hir).unwrap();
+        let m = e.find_at("on 2024-05", 0).unwrap();
+        assert_eq!(
+            (m.groups["year"].as_str(), m.groups["2"].as_str()),
+            ("2024", "05")
+        );
+
+        let bytes = regex_syntax::ParserBuilder::new()
+            .utf8(false)
+            .build()
+            .parse("(?-u:\\xFF)")
+            .unwrap();
+        assert!(Engine::from_hir(&bytes).is_err());
+
+        // would be taken for a subroutine call
+        let reserved = regex_syntax::hir::Hir::literal("\u{F0000}".as_bytes());
+        assert!(Engine::from_hir(&reserved).is_err());
+        assert!(Engine::try_from("\u{F0000}").is_err());
+    }
+
+    #[test]
+    fn test_derivations() {
+        let e = Engine::try_from("(a*)(a*)").unwrap();
+        let derivations = e.derivations("aa");
+        let splits: Vec<(&str, &str)> = derivations
+            .iter()
+            .map(|groups| (groups["1"].as_str(), groups["2"].as_str()))
+            .collect();
+        assert_eq!(splits, vec![("aa", ""), ("a", "a"), ("", "aa")]);
+        assert_eq!(derivations[0], e.exec("aa").unwrap());
+
+        // paths capturing the same text are one derivation
+        assert_eq!(Engine::try_from("a|a").unwrap().derivations("a").len(), 1);
+        assert!(
+            Engine::try_from("ab")
+                .unwrap()
+                .derivations("abc")
+                .is_empty()
+        );
+        let e = Engine::try_from("(?<x>a|ab)(?<y>c|bc)").unwrap();
+        assert_eq!(e.derivations("abc").len(), 2);
+    }
+
+    #[test]
+    fn test_from_openfst() {
+        // the digits 1 to 3 then `!`, as `fstprint` writes it
+        let fst = "0\t1\t49\n0\t1\t50\n0\t1\t51\n1\t2\t33\n2\n";
+        let e = Engine::from_openfst(fst, None).unwrap();
+        assert_eq!(e.find_at("x 2!", 0).unwrap().as_str(), "2!");
+        assert!(!e.test("4!"));
+
+        let e = e.then(&Engine::try_from("[a-z]+").unwrap());
+        assert_eq!(e.find_at("3!abc", 0).unwrap().end, 5);
+        assert!(Engine::from_jflap("not jflap").is_err());
+    }
 }
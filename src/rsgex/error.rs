@@ -0,0 +1,114 @@
+use std::{fmt, ops::Range};
+
+use super::analysis::Hazard;
+
+// Why a pattern did not compile, or why a search was abandoned before it
+// could tell whether the pattern matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // the pattern is malformed; `span` is the byte range of the pattern at
+    // fault, see `Error::diagnostic`
+    Parse { message: String, span: Range<usize> },
+    // the pattern or the options need what the engine cannot do, like
+    // subroutine calls on the Pike VM
+    Unsupported(String),
+    SizeLimitExceeded(SizeLimitExceeded),
+    // found by `EngineBuilder::reject_catastrophic`
+    Catastrophic(Hazard),
+    // an automaton or a trace that could not be read, see
+    // `Engine::from_jflap` and `Trace::from_json`
+    Import(String),
+    // more frames were popped than `EngineBuilder::step_limit` allows
+    StepLimitExceeded,
+    // the search ran longer than `EngineBuilder::timeout`
+    Timeout,
+    // the `CancelToken` passed to the search was cancelled
+    Cancelled,
+}
+
+impl Error {
+    // The bytes of the pattern the error is about, when it is about some.
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            Error::Parse { span, .. } => Some(span.clone()),
+            _ => None,
+        }
+    }
+
+    // The error and, when it has a span, `pattern` with carets under the
+    // part at fault:
+    //
+    //     unclosed group
+    //     a(b
+    //      ^
+    pub fn diagnostic(&self, pattern: &str) -> String {
+        let Some(span) = self.span() else {
+            return self.to_string();
+        };
+        let column = |offset: usize| pattern.get(..offset).map_or(0, |s| s.chars().count());
+        let (start, end) = (column(span.start), column(span.end));
+        format!(
+            "{}\n{}\n{}{}",
+            self,
+            pattern,
+            " ".repeat(start),
+            "^".repeat(end.saturating_sub(start).max(1))
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse { message, .. } => write!(f, "{}", message),
+            Error::Unsupported(message) => write!(f, "{}", message),
+            Error::SizeLimitExceeded(limit) => write!(f, "{}", limit),
+            Error::Catastrophic(hazard) => write!(f, "{}", hazard),
+            Error::Import(message) => write!(f, "cannot import: {}", message),
+            Error::StepLimitExceeded => write!(f, "step limit exceeded"),
+            Error::Timeout => write!(f, "search timed out"),
+            Error::Cancelled => write!(f, "search cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<SizeLimitExceeded> for Error {
+    fn from(limit: SizeLimitExceeded) -> Self {
+        Error::SizeLimitExceeded(limit)
+    }
+}
+
+// Why an automaton was not built: it outgrew a size limit set on the
+// builder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeLimitExceeded {
+    // estimated bytes of the compiled NFA, see `EngineBuilder::nfa_size_limit`
+    Nfa { size: usize, limit: usize },
+    // see `EngineBuilder::dfa_size_limit`
+    Dfa { limit: usize },
+    // more states than the automaton can hold, such as a DFA past
+    // `MAX_DFA_STATES` or a bit-parallel NFA past the bits of its word
+    States { limit: usize },
+}
+
+impl fmt::Display for SizeLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SizeLimitExceeded::Nfa { size, limit } => write!(
+                f,
+                "the NFA would take about {} bytes, over the limit of {}",
+                size, limit
+            ),
+            SizeLimitExceeded::Dfa { limit } => {
+                write!(f, "the DFA exceeds the limit of {} bytes", limit)
+            }
+            SizeLimitExceeded::States { limit } => {
+                write!(f, "the automaton needs more than {} states", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SizeLimitExceeded {}
@@ -0,0 +1,347 @@
+use std::{fmt, sync::Arc};
+
+use regex_syntax::hir::{Capture, Hir, HirKind, Literal, Look, Repetition};
+
+use super::{engine::Engine, matcher::Transition, parser, parser::Parsed};
+
+// A breakdown of a parsed pattern for people learning how it works, see
+// `Engine::explain`. Every node of the pattern says what it matches and
+// what it compiled into, and prints as an indented tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explanation {
+    // the part of the pattern, as the parser normalized it
+    pub pattern: String,
+    pub description: String,
+    pub lowering: String,
+    pub children: Vec<Explanation>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl Explanation {
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        writeln!(f, "{}{}: {}", indent, self.pattern, self.description)?;
+        writeln!(f, "{}  -> {}", indent, self.lowering)?;
+        self.children
+            .iter()
+            .try_for_each(|child| child.write(f, depth + 1))
+    }
+}
+
+// Explains `parsed`, which `engine` was compiled from.
+pub(crate) fn explain(parsed: &Parsed, engine: &Engine) -> Explanation {
+    let explainer = Explainer { parsed, engine };
+    let mut root = explainer.node(&parsed.hir);
+    let stats = engine.stats();
+    root.lowering = format!(
+        "{}; the whole automaton has {} states and {} transitions",
+        root.lowering, stats.states, stats.transitions
+    );
+    root
+}
+
+struct Explainer<'p> {
+    parsed: &'p Parsed,
+    engine: &'p Engine,
+}
+
+impl Explainer<'_> {
+    fn node(&self, hir: &Hir) -> Explanation {
+        let (description, lowering, children) = match hir.kind() {
+            HirKind::Empty => (
+                "matches the empty text".to_string(),
+                "an epsilon transition".to_string(),
+                vec![],
+            ),
+            HirKind::Literal(literal) => {
+                let pieces = self.pieces(literal);
+                let descriptions: Vec<&str> = pieces.iter().map(|(_, d)| d.as_str()).collect();
+                return Explanation {
+                    pattern: pieces.iter().map(|(pattern, _)| pattern.as_str()).collect(),
+                    description: descriptions.join(", then "),
+                    lowering: "one transition per char or extension, in a chain".to_string(),
+                    children: vec![],
+                };
+            }
+            HirKind::Class(_) => {
+                let (pattern, lowering) = self.class(hir);
+                return Explanation {
+                    pattern,
+                    description: "matches any one char of the class".to_string(),
+                    lowering,
+                    children: vec![],
+                };
+            }
+            HirKind::Look(look) => (
+                describe_look(look),
+                "an assertion, taken without consuming a char".to_string(),
+                vec![],
+            ),
+            HirKind::Repetition(repetition) => (
+                describe_repetition(repetition),
+                self.repetition_lowering(repetition),
+                vec![self.node(&repetition.sub)],
+            ),
+            HirKind::Capture(capture) => (
+                self.describe_capture(capture),
+                match self.engine.flags.no_captures {
+                    true => "its states only, the group not being tracked".to_string(),
+                    false => "its states, marked to open the group on entry and close it on exit"
+                        .to_string(),
+                },
+                vec![self.node(&capture.sub)],
+            ),
+            HirKind::Concat(parts) => (
+                "matches each part in turn".to_string(),
+                "the parts' automata, each ending joined to the next one's start".to_string(),
+                parts.iter().map(|part| self.node(part)).collect(),
+            ),
+            HirKind::Alternation(branches) => {
+                let literals = branches
+                    .windows(2)
+                    .any(|pair| pair.iter().all(|b| matches!(b.kind(), HirKind::Literal(_))));
+                let lowering = match literals {
+                    true => {
+                        "a start state with an epsilon transition into each branch, \
+                             literal branches next to each other sharing their prefixes in a trie"
+                    }
+                    false => "a start state with an epsilon transition into each branch",
+                };
+                (
+                    "tries each branch in order, the first one that leads to a match wins"
+                        .to_string(),
+                    lowering.to_string(),
+                    branches.iter().map(|branch| self.node(branch)).collect(),
+                )
+            }
+        };
+
+        Explanation {
+            pattern: pattern(hir, &children),
+            description,
+            lowering,
+            children,
+        }
+    }
+
+    // The text and extensions of a literal: runs of chars, and the
+    // sentinels the parser rewrote `\G`, calls and named matchers into.
+    fn pieces(&self, literal: &Literal) -> Vec<(String, String)> {
+        let text = String::from_utf8_lossy(&literal.0);
+        let mut pieces: Vec<(String, String)> = vec![];
+        let mut run = String::new();
+        let flush = |run: &mut String, pieces: &mut Vec<(String, String)>| {
+            if !run.is_empty() {
+                let description = format!("matches the text {:?}", run);
+                pieces.push((regex_syntax::escape(run), description));
+                run.clear();
+            }
+        };
+        for c in text.chars() {
+            if !parser::is_sentinel(c) {
+                run.push(c);
+                continue;
+            }
+            flush(&mut run, &mut pieces);
+            pieces.push(self.sentinel(c));
+        }
+        flush(&mut run, &mut pieces);
+        pieces
+    }
+
+    // The syntax and description of a sentinel char.
+    fn sentinel(&self, c: char) -> (String, String) {
+        if c == parser::CONTINUATION_SENTINEL {
+            let description = "asserts the position the search started from";
+            return ("\\G".to_string(), description.to_string());
+        }
+        let code = c as u32;
+        if let Some(index) = code.checked_sub(parser::NAMED_SENTINEL_BASE as u32) {
+            let name = &self.parsed.named[index as usize];
+            let description = format!("matches a char of the class defined as {:?}", name);
+            return (format!("\\m{{{}}}", name), description);
+        }
+        let slot = (code - parser::CALL_SENTINEL_BASE as u32) as usize;
+        let group = self.parsed.calls[slot];
+        let description = match group {
+            0 => "matches the whole pattern again, recursively".to_string(),
+            group => format!("matches the pattern of group {} again", group),
+        };
+        (format!("(?{})", group), description)
+    }
+
+    // How a class is tested, and how to write it: Perl classes are
+    // written as `\d` and so on rather than as their many ranges.
+    fn class(&self, hir: &Hir) -> (String, String) {
+        let named: Arc<[_]> = Arc::new([]);
+        let nfa = Engine::ast_to_nfa(hir.kind(), self.engine.flags, &named).nfa;
+        let transition = nfa.states.first().and_then(|state| state.matchers.front());
+        let Some((transition, _)) = transition else {
+            return (hir.to_string(), "a class test".to_string());
+        };
+        let test = match transition {
+            Transition::Range(..) => "testing a single range".to_string(),
+            Transition::CharSet(chars) => {
+                format!("comparing with each of its {} chars", chars.len())
+            }
+            Transition::PerlClass(_) => {
+                let lowering =
+                    format!("looking the char up in the table of {}", transition.label());
+                return (transition.label(), format!("one transition {}", lowering));
+            }
+            Transition::Ascii { negated: false, .. } => {
+                "testing a 128-bit ASCII bitmap".to_string()
+            }
+            Transition::Ascii { negated: true, .. } => {
+                "testing a 128-bit ASCII bitmap, negated".to_string()
+            }
+            Transition::Class(class) => match class.negated {
+                true => format!(
+                    "binary searching the {} ranges it excludes",
+                    class.ranges.len()
+                ),
+                false => format!("binary searching its {} ranges", class.ranges.len()),
+            },
+            Transition::Custom(_) => {
+                "testing the first char of the next grapheme cluster, consuming the cluster"
+                    .to_string()
+            }
+            _ => "testing the class".to_string(),
+        };
+        (hir.to_string(), format!("one transition {}", test))
+    }
+
+    fn repetition_lowering(&self, repetition: &Repetition) -> String {
+        let counted = repetition.min > 1 || repetition.max.is_some_and(|max| max > 1);
+        if counted && !self.engine.flags.unroll {
+            return "one copy of its automaton in a loop, a counter register checking the bounds"
+                .to_string();
+        }
+        match (repetition.min, repetition.max) {
+            (0 | 1, None) => {
+                "its automaton, looping back through an epsilon transition".to_string()
+            }
+            (min, None) => format!(
+                "{} copies of its automaton, the last looping back through an epsilon transition",
+                min
+            ),
+            (0, Some(1)) => "its automaton, with an epsilon transition skipping it".to_string(),
+            (min, Some(max)) => format!(
+                "{} copies of its automaton, those past the first {} skippable",
+                max, min
+            ),
+        }
+    }
+
+    fn describe_capture(&self, capture: &Capture) -> String {
+        match &capture.name {
+            Some(name) => format!("captures group {} named {:?}", capture.index, name),
+            None => format!("captures group {}", capture.index),
+        }
+    }
+}
+
+// Writes `hir` back as a pattern, its children as explained.
+fn pattern(hir: &Hir, children: &[Explanation]) -> String {
+    let children: Vec<&str> = children
+        .iter()
+        .map(|child| child.pattern.as_str())
+        .collect();
+    match hir.kind() {
+        HirKind::Repetition(repetition) => {
+            let quantifier = match (repetition.min, repetition.max) {
+                (0, Some(1)) => "?".to_string(),
+                (0, None) => "*".to_string(),
+                (1, None) => "+".to_string(),
+                (min, None) => format!("{{{},}}", min),
+                (min, Some(max)) if min == max => format!("{{{}}}", min),
+                (min, Some(max)) => format!("{{{},{}}}", min, max),
+            };
+            let lazy = if repetition.greedy { "" } else { "?" };
+            let sub = match repetition.sub.kind() {
+                HirKind::Class(_) | HirKind::Capture(_) => children[0].to_string(),
+                HirKind::Literal(Literal(bytes)) if bytes.len() == 1 => children[0].to_string(),
+                _ => format!("(?:{})", children[0]),
+            };
+            format!("{}{}{}", sub, quantifier, lazy)
+        }
+        HirKind::Capture(capture) => match &capture.name {
+            Some(name) => format!("(?<{}>{})", name, children[0]),
+            None => format!("({})", children[0]),
+        },
+        HirKind::Concat(_) => children.concat(),
+        HirKind::Alternation(_) => format!("(?:{})", children.join("|")),
+        _ => hir.to_string(),
+    }
+}
+
+fn describe_repetition(repetition: &Repetition) -> String {
+    let times = match (repetition.min, repetition.max) {
+        (0, Some(1)) => "optionally".to_string(),
+        (0, None) => "any number of times".to_string(),
+        (1, None) => "at least once".to_string(),
+        (min, None) => format!("at least {} times", min),
+        (min, Some(max)) if min == max => format!("exactly {} times", min),
+        (min, Some(max)) => format!("between {} and {} times", min, max),
+    };
+    let greed = match repetition.greedy {
+        true => "as many times as possible",
+        false => "as few times as possible",
+    };
+    format!("matches its part {}, {}", times, greed)
+}
+
+fn describe_look(look: &Look) -> String {
+    let position = match look {
+        Look::Start => "the start of the text",
+        Look::End => "the end of the text",
+        Look::StartLF | Look::StartCRLF => "the start of a line",
+        Look::EndLF | Look::EndCRLF => "the end of a line",
+        Look::WordAscii | Look::WordUnicode => "a word boundary",
+        Look::WordAsciiNegate | Look::WordUnicodeNegate => "a position that is no word boundary",
+        Look::WordStartAscii | Look::WordStartUnicode => "the start of a word",
+        Look::WordEndAscii | Look::WordEndUnicode => "the end of a word",
+        _ => "a word boundary on one side",
+    };
+    format!("asserts {}", position)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rsgex::{builder::EngineBuilder, engine::Engine};
+
+    #[test]
+    fn test_explain() {
+        let explanation = Engine::explain("(?<y>\\d{4})-[a-f]+?").unwrap();
+        assert_eq!(explanation.description, "matches each part in turn");
+        assert!(explanation.lowering.contains("the whole automaton has"));
+
+        let [year, dash, letters] = &explanation.children[..] else {
+            panic!("{}", explanation);
+        };
+        assert_eq!(year.description, "captures group 1 named \"y\"");
+        assert_eq!(
+            year.children[0].description,
+            "matches its part exactly 4 times, as many times as possible"
+        );
+        assert!(year.children[0].lowering.contains("counter register"));
+        assert!(year.children[0].children[0].lowering.contains("\\d"));
+        assert_eq!(dash.description, "matches the text \"-\"");
+        assert!(letters.description.contains("as few times"));
+        assert_eq!(letters.pattern, "[a-f]+?");
+        assert!(letters.children[0].lowering.contains("ASCII bitmap"));
+        assert!(explanation.pattern.starts_with("(?<y>\\d{4})\\-"));
+
+        let explanation = EngineBuilder::new()
+            .define_class("hex", |c| c.is_ascii_hexdigit())
+            .explain("\\G\\m{hex}")
+            .unwrap();
+        assert_eq!(explanation.pattern, "\\G\\m{hex}");
+        assert!(explanation.to_string().starts_with("\\G\\m{hex}: asserts"));
+    }
+}
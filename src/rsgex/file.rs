@@ -0,0 +1,142 @@
+use std::{fs::File, io, path::Path};
+
+use memmap2::Mmap;
+
+use super::{engine::Engine, haystack::Haystack, search::Match};
+
+// A match in a file. Offsets are bytes from the start of the file, or of
+// its normalized text with NFC normalization enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMatch {
+    pub m: Match,
+    // 1-based line of the match start, see `FileMatches::line_numbers`
+    pub line: Option<usize>,
+}
+
+enum Text {
+    Mapped(Mmap),
+    Normalized(String),
+}
+
+// The matches of a memory-mapped file, found lazily like `find_iter`'s.
+pub struct FileMatches<'e> {
+    engine: &'e Engine,
+    text: Text,
+    next: usize,
+    // position up to which newlines were counted, and the line there
+    lines: Option<(usize, usize)>,
+}
+
+impl Engine {
+    // Maps the file at `path` and searches it without reading it into
+    // memory first. The file has to be valid UTF-8, and must not change
+    // while it is searched.
+    pub fn search_file(&self, path: impl AsRef<Path>) -> io::Result<FileMatches<'_>> {
+        let file = File::open(path)?;
+        // SAFETY: the map is only read, and the caller keeps the file as is
+        let mmap = unsafe { Mmap::map(&file)? };
+        let s = std::str::from_utf8(&mmap)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let text = if self.flags.nfc {
+            Text::Normalized(self.prepare(s).into_owned())
+        } else {
+            Text::Mapped(mmap)
+        };
+        Ok(FileMatches {
+            engine: self,
+            text,
+            next: 0,
+            lines: None,
+        })
+    }
+}
+
+impl FileMatches<'_> {
+    // Reports the line each match starts on, counting the newlines between
+    // one match and the next.
+    pub fn line_numbers(mut self) -> Self {
+        self.lines = Some((0, 1));
+        self
+    }
+
+    fn text(&self) -> &str {
+        match &self.text {
+            // SAFETY: validated in `search_file`
+            Text::Mapped(mmap) => unsafe { std::str::from_utf8_unchecked(mmap) },
+            Text::Normalized(s) => s,
+        }
+    }
+}
+
+impl Iterator for FileMatches<'_> {
+    type Item = FileMatch;
+
+    fn next(&mut self) -> Option<FileMatch> {
+        let text = self.text();
+        if self.next > text.len() {
+            return None;
+        }
+
+        let haystack = Haystack::Str(text);
+        let m = self.engine.find_in(&haystack, self.next)?;
+        let next = if m.end == m.start {
+            haystack.next(m.end)
+        } else {
+            m.end
+        };
+        let line = self.lines.map(|(counted, line)| {
+            line + text.as_bytes()[counted..m.start]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count()
+        });
+
+        self.next = next;
+        if let Some(line) = line {
+            self.lines = Some((m.start, line));
+        }
+        Some(FileMatch { m, line })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use crate::rsgex::engine::Engine;
+
+    #[test]
+    fn test_search_file() {
+        let dir = env::temp_dir().join(format!("rsgex-file-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log.txt");
+        fs::write(&path, "ok\nERROR 1\nok\n\nERROR 22 中 ERROR 3\n").unwrap();
+
+        let e = Engine::try_from("ERROR \\d+").unwrap();
+        let found: Vec<(usize, Option<usize>)> = e
+            .search_file(&path)
+            .unwrap()
+            .line_numbers()
+            .map(|found| (found.m.start, found.line))
+            .collect();
+        assert_eq!(found, vec![(3, Some(2)), (15, Some(5)), (28, Some(5))]);
+        assert_eq!(e.search_file(&path).unwrap().next().unwrap().line, None);
+
+        let empty = dir.join("empty.txt");
+        fs::write(&empty, "").unwrap();
+        assert_eq!(
+            Engine::try_from("x*")
+                .unwrap()
+                .search_file(&empty)
+                .unwrap()
+                .count(),
+            1
+        );
+
+        fs::write(&path, b"\xFF").unwrap();
+        assert!(e.search_file(&path).is_err());
+        assert!(e.search_file(dir.join("missing.txt")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use super::{
+    byte_set::ByteSet,
+    haystack::Haystack,
+    matcher::{DfaEdge, Transition},
+    nfa::NFAutomata,
+};
+
+// The chars a match can begin with, taken from the char tests reachable
+// from the initial state without consuming anything, and kept as the bytes
+// they start with so they can be scanned for.
+pub struct FirstChars {
+    set: ByteSet,
+}
+
+impl FirstChars {
+    // `None` when a match can be empty, or starts in a subroutine call or
+    // with a test that is not known char by char. `bytes` is set when
+    // haystacks are byte strings, whose bytes are the chars themselves.
+    pub fn new(nfa: &NFAutomata, bytes: bool) -> Option<FirstChars> {
+        let mut tests: Vec<&Transition> = vec![];
+        let mut closure = vec![nfa.initial];
+        let mut i = 0;
+        while i < closure.len() {
+            let state = &nfa.states[closure[i]];
+            if state.is_ending {
+                return None;
+            }
+            for (transition, to) in state.matchers.iter() {
+                if transition.call_slot().is_some() {
+                    return None;
+                }
+                // look-arounds only narrow down where matches start
+                if transition.is_epsilon() {
+                    if !closure.contains(to) {
+                        closure.push(*to);
+                    }
+                    continue;
+                }
+                transition.matches_char('\0')?;
+                tests.push(transition);
+            }
+            i += 1;
+        }
+
+        let mut starts = vec![];
+        for transition in tests {
+            let Some(DfaEdge::Chars(ranges)) = transition.dfa_edge() else {
+                return None;
+            };
+            // acceptance only changes at these, see `dfa::edges`
+            let mut boundaries: Vec<u32> = ranges
+                .iter()
+                .flat_map(|&(start, end)| [start as u32, end as u32 + 1])
+                .chain([0])
+                .collect();
+            boundaries.sort_unstable();
+            boundaries.dedup();
+
+            for (j, &start) in boundaries.iter().enumerate() {
+                let end = boundaries
+                    .get(j + 1)
+                    .map_or(char::MAX as u32, |next| next - 1);
+                let accepted = (start..=end)
+                    .find_map(char::from_u32)
+                    .is_some_and(|c| transition.matches_char(c) == Some(true));
+                if !accepted {
+                    continue;
+                }
+                if bytes {
+                    starts.extend((start..=end.min(0xFF)).map(|b| b as u8));
+                } else {
+                    // bytes between the lead bytes of a range only continue chars
+                    starts.extend(
+                        (lead_byte(start)..=lead_byte(end)).filter(|b| !(0x80..0xC2).contains(b)),
+                    );
+                }
+            }
+        }
+
+        Some(FirstChars {
+            set: ByteSet::new(starts),
+        })
+    }
+
+    // The first position at or after `at` holding one of the chars.
+    pub fn find(&self, haystack: &Haystack, at: usize) -> Option<usize> {
+        match haystack {
+            Haystack::Str(s) => self.set.find(s.as_bytes(), at),
+            Haystack::Bytes(b) => self.set.find(b, at),
+            Haystack::Segments(segments) => segments.find_byte(at, |b, at| self.set.find(b, at)),
+        }
+    }
+}
+
+// the first byte of the UTF-8 encoding of `c`
+fn lead_byte(c: u32) -> u8 {
+    match c {
+        0..0x80 => c as u8,
+        0x80..0x800 => 0xC0 | (c >> 6) as u8,
+        0x800..0x10000 => 0xE0 | (c >> 12) as u8,
+        _ => 0xF0 | (c >> 18) as u8,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rsgex::{engine::Engine, haystack::Haystack};
+
+    #[test]
+    fn test_find() {
+        let e = Engine::try_from("\\bé?\\w+").unwrap();
+        let first_chars = e.first_chars.as_ref().unwrap();
+        let haystack = Haystack::Str("  -- été");
+        assert_eq!(first_chars.find(&haystack, 0), Some(5));
+        assert_eq!(e.find_at("  -- été", 0).unwrap().start, 5);
+
+        // every char but `a`
+        let e = Engine::try_from("[^a]b").unwrap();
+        let haystack = Haystack::Str("aaa中b");
+        assert_eq!(e.first_chars.as_ref().unwrap().find(&haystack, 0), Some(3));
+
+        assert!(Engine::try_from("a?").unwrap().first_chars.is_none());
+        assert!(Engine::try_from("(a)(?1)").unwrap().first_chars.is_some());
+        assert!(Engine::try_from("(?1)(a)").unwrap().first_chars.is_none());
+    }
+}
@@ -0,0 +1,20 @@
+use super::haystack::Haystack;
+
+// Length in bytes of the extended grapheme cluster starting at `i`.
+#[cfg(feature = "unicode-segmentation")]
+pub fn cluster_len(s: &Haystack, i: usize) -> usize {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    match s {
+        Haystack::Str(s) => s[i..].graphemes(true).next().map_or(1, str::len),
+        Haystack::Bytes(_) => 1,
+        // joined before the search, see `find_segments`
+        Haystack::Segments(_) => s.next(i) - i,
+    }
+}
+
+// without the feature the mode cannot be enabled
+#[cfg(not(feature = "unicode-segmentation"))]
+pub fn cluster_len(s: &Haystack, i: usize) -> usize {
+    s.next(i) - i
+}
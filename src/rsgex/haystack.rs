@@ -0,0 +1,77 @@
+use super::segments::Segments;
+
+// The text a search walks over. Positions are byte offsets, which for `Str`
+// always fall on a char boundary. A `Bytes` haystack matches every byte as
+// the char of the same value, see `BytesEngine`. `Segments` are pieces of
+// text laid end to end.
+#[derive(Clone, Copy, Debug)]
+pub enum Haystack<'h> {
+    Str(&'h str),
+    Bytes(&'h [u8]),
+    Segments(&'h Segments<'h>),
+}
+
+impl<'h> Haystack<'h> {
+    pub fn len(&self) -> usize {
+        match self {
+            Haystack::Str(s) => s.len(),
+            Haystack::Bytes(b) => b.len(),
+            Haystack::Segments(segments) => segments.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn char_at(&self, i: usize) -> Option<char> {
+        match self {
+            Haystack::Str(s) => s.get(i..)?.chars().next(),
+            Haystack::Bytes(b) => b.get(i).map(|&b| b as char),
+            Haystack::Segments(segments) => segments.char_at(i),
+        }
+    }
+
+    pub fn char_before(&self, i: usize) -> Option<char> {
+        match self {
+            Haystack::Str(s) => s.get(..i)?.chars().next_back(),
+            Haystack::Bytes(b) => b.get(i.checked_sub(1)?).map(|&b| b as char),
+            Haystack::Segments(segments) => segments.char_before(i),
+        }
+    }
+
+    // The position after the char at `i`, or past the end when `i` is the
+    // end of the haystack.
+    pub fn next(&self, i: usize) -> usize {
+        match (self, self.char_at(i)) {
+            (Haystack::Str(_) | Haystack::Segments(_), Some(c)) => i + c.len_utf8(),
+            _ => i + 1,
+        }
+    }
+
+    // the chars from `at` on, with their positions
+    pub fn chars_from(&self, at: usize) -> impl Iterator<Item = (usize, char)> + 'h {
+        let (s, b, segments): (&'h str, &'h [u8], _) = match *self {
+            Haystack::Str(s) => (s.get(at..).unwrap_or_default(), &[], None),
+            Haystack::Bytes(b) => ("", b.get(at..).unwrap_or_default(), None),
+            Haystack::Segments(segments) => ("", &[], Some(segments)),
+        };
+
+        s.char_indices()
+            .chain(b.iter().enumerate().map(|(i, &b)| (i, b as char)))
+            .map(move |(i, c)| (at + i, c))
+            .chain(
+                segments
+                    .into_iter()
+                    .flat_map(move |segments| segments.chars_from(at)),
+            )
+    }
+
+    pub fn text(&self, start: usize, end: usize) -> String {
+        match self {
+            Haystack::Str(s) => s[start..end].to_string(),
+            Haystack::Bytes(b) => b[start..end].iter().map(|&b| b as char).collect(),
+            Haystack::Segments(segments) => segments.text(start, end),
+        }
+    }
+}
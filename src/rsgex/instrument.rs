@@ -0,0 +1,82 @@
+// Diagnostics of compiling and searching, sent to `tracing` or, failing
+// that, to `log` when one of those features is enabled, and compiled out
+// otherwise. Verbosity is set the usual way for each: a subscriber's or
+// logger's filter, or the crates' `max_level_*` features.
+//
+// `debug` reports each compiled engine and each abandoned search, `trace`
+// each node lowered and each search run.
+
+// An event at `$level`, one of `error`, `warn`, `info`, `debug` and
+// `trace`, with a message formatted as by `format!`.
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+        #[cfg(all(feature = "log", not(feature = "tracing")))]
+        log::$level!($($arg)+);
+        // keeps the arguments used without either feature
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        if false {
+            let _ = format!($($arg)+);
+        }
+    }};
+}
+
+// A span at `$level`, one of `ERROR` to `TRACE`, entered until the guard
+// it returns is dropped. Fields are recorded with their `Debug` format.
+// Only `tracing` has spans, with `log` the fields are dropped.
+macro_rules! span {
+    ($level:ident, $name:expr $(, $field:ident = $value:expr)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::span!(tracing::Level::$level, $name $(, $field = ?$value)*).entered();
+        #[cfg(not(feature = "tracing"))]
+        let span = {
+            $(let _ = &$value;)*
+            $crate::rsgex::instrument::NoSpan
+        };
+        span
+    }};
+}
+
+pub(crate) use {event, span};
+
+// The guard of a span without `tracing`.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+#[cfg(all(test, feature = "log", not(feature = "tracing")))]
+mod test {
+    use std::sync::Mutex;
+
+    use crate::rsgex::engine::Engine;
+
+    static RECORDS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+    struct Recorder;
+
+    impl log::Log for Recorder {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let message = format!("{} {}", record.level(), record.args());
+            RECORDS.lock().unwrap().push(message);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_log() {
+        log::set_logger(&Recorder).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        let e = Engine::try_from("a+b").unwrap();
+        assert!(e.find_at("xaab", 0).is_some());
+
+        let records = RECORDS.lock().unwrap();
+        assert!(records.iter().any(|r| r.starts_with("DEBUG compiled")));
+        assert!(records.iter().any(|r| r.starts_with("TRACE lowered")));
+        assert!(records.iter().any(|r| r.starts_with("TRACE backtracking")));
+    }
+}
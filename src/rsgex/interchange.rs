@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+
+use super::{error::Error, matcher::Transition, nfa::NFAutomata};
+
+// states per row when laying out a JFLAP automaton
+const JFLAP_ROW: usize = 8;
+
+// Exporters to the formats of other automata toolchains. Only char and
+// epsilon transitions keep their meaning there; the others are written as
+// their label, which those tools read as one more symbol. Capture groups
+// are dropped.
+//
+// The importers read a symbol as the text it spells, so a symbol of
+// several chars becomes a run of char transitions.
+impl NFAutomata {
+    // A JFLAP `.jff` finite automaton, with the states laid out on a grid.
+    pub fn to_jflap(&self) -> String {
+        let mut jff = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n\
+             <structure>\n\t<type>fa</type>\n\t<automaton>\n",
+        );
+        for (index, state) in self.states.iter().enumerate() {
+            let x = 100 + 120 * (index % JFLAP_ROW);
+            let y = 100 + 120 * (index / JFLAP_ROW);
+            jff.push_str(&format!(
+                "\t\t<state id=\"{}\" name=\"q{}\">\n\t\t\t<x>{}.0</x>\n\t\t\t<y>{}.0</y>\n",
+                index, index, x, y
+            ));
+            if index == self.initial {
+                jff.push_str("\t\t\t<initial/>\n");
+            }
+            if state.is_ending {
+                jff.push_str("\t\t\t<final/>\n");
+            }
+            jff.push_str("\t\t</state>\n");
+        }
+        for (index, state) in self.states.iter().enumerate() {
+            for (matcher, to) in state.matchers.iter() {
+                let read = match matcher {
+                    Transition::Epsilon => "<read/>".to_string(),
+                    matcher => format!("<read>{}</read>", xml_text(&matcher.label())),
+                };
+                jff.push_str(&format!(
+                    "\t\t<transition>\n\t\t\t<from>{}</from>\n\t\t\t<to>{}</to>\n\t\t\t{}\n\t\t</transition>\n",
+                    index, to, read
+                ));
+            }
+        }
+        jff.push_str("\t</automaton>\n</structure>\n");
+        jff
+    }
+
+    // An acceptor in the OpenFST (AT&T) text format, labelled with the
+    // symbols of `openfst_symbols`, to be compiled with
+    // `fstcompile --acceptor --isymbols=...`. The arcs of the initial
+    // state come first, as the format takes the first state for the start.
+    pub fn to_openfst(&self) -> String {
+        let Some(initial) = self.states.get(self.initial) else {
+            return String::new();
+        };
+        let mut fst = String::new();
+        // a start without arcs can only come first as a final state line,
+        // and otherwise accepts nothing, like the empty FST
+        if initial.matchers.is_empty() {
+            if !initial.is_ending {
+                return fst;
+            }
+            fst.push_str(&format!("{}\n", self.initial));
+        }
+
+        let mut order: Vec<usize> = (0..self.states.len()).collect();
+        order.sort_by_key(|&state| state != self.initial);
+        for &index in order.iter() {
+            for (matcher, to) in self.states[index].matchers.iter() {
+                fst.push_str(&format!("{}\t{}\t{}\n", index, to, fst_symbol(matcher)));
+            }
+        }
+        for &index in order.iter() {
+            let written = index == self.initial && initial.matchers.is_empty();
+            if self.states[index].is_ending && !written {
+                fst.push_str(&format!("{}\n", index));
+            }
+        }
+        fst
+    }
+
+    // The symbol table of `to_openfst`, with `<eps>` as symbol 0 and the
+    // others numbered in order of appearance.
+    pub fn openfst_symbols(&self) -> String {
+        let mut symbols = vec!["<eps>".to_string()];
+        for state in self.states.iter() {
+            for (matcher, _) in state.matchers.iter() {
+                let symbol = fst_symbol(matcher);
+                if !symbols.contains(&symbol) {
+                    symbols.push(symbol);
+                }
+            }
+        }
+        symbols
+            .iter()
+            .enumerate()
+            .map(|(index, symbol)| format!("{}\t{}\n", symbol, index))
+            .collect()
+    }
+}
+
+impl NFAutomata {
+    // The finite automaton of a JFLAP `.jff` file, states keeping their
+    // order; an empty `<read/>` is an epsilon transition.
+    pub fn from_jflap(jff: &str) -> Result<NFAutomata, Error> {
+        match xml_elements(jff, "type").first() {
+            Some((_, Some("fa"))) => {}
+            _ => {
+                return Err(invalid(
+                    "only JFLAP finite automata (type fa) can be imported",
+                ));
+            }
+        }
+
+        let mut nfa = NFAutomata::new();
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        let mut initial = None;
+        for (attributes, content) in xml_elements(jff, "state") {
+            let id = xml_attribute(attributes, "id")
+                .ok_or_else(|| invalid("a JFLAP state has no id"))?;
+            let index = nfa.states.len();
+            ids.insert(id, index);
+            nfa.fill_state(1);
+            let content = content.unwrap_or_default();
+            if !xml_elements(content, "initial").is_empty() {
+                initial = Some(index);
+            }
+            if !xml_elements(content, "final").is_empty() {
+                nfa.add_ending(index);
+            }
+        }
+        nfa.set_initial(
+            initial.ok_or_else(|| invalid("the JFLAP automaton has no initial state"))?,
+        );
+
+        for (_, content) in xml_elements(jff, "transition") {
+            let content = content.unwrap_or_default();
+            let state = |tag| {
+                let (_, id) = *xml_elements(content, tag).first()?;
+                ids.get(id?.trim()).copied()
+            };
+            let (Some(from), Some(to)) = (state("from"), state("to")) else {
+                return Err(invalid("a JFLAP transition joins unknown states"));
+            };
+            let read = xml_elements(content, "read")
+                .first()
+                .and_then(|(_, read)| *read)
+                .unwrap_or_default();
+            nfa.add_text_transition(from, to, &xml_unescape(read));
+        }
+        Ok(nfa)
+    }
+
+    // The automaton of an FST in the OpenFST (AT&T) text format, as
+    // written by `fstprint`, reading the input labels and ignoring weights
+    // and output labels. Numeric labels are looked up in the `symbols`
+    // table when given, and are otherwise code points; `<eps>` and label 0
+    // are epsilon transitions.
+    pub fn from_openfst(fst: &str, symbols: Option<&str>) -> Result<NFAutomata, Error> {
+        let symbols: Option<HashMap<&str, &str>> = symbols.map(|symbols| {
+            symbols
+                .lines()
+                .filter_map(|line| {
+                    let mut fields = line.split_whitespace();
+                    let symbol = fields.next()?;
+                    Some((fields.next()?, symbol))
+                })
+                .collect()
+        });
+        let state = |field: &str| {
+            field
+                .parse::<usize>()
+                .map_err(|_| invalid(&format!("invalid OpenFST state {}", field)))
+        };
+
+        let mut nfa = NFAutomata::new();
+        let mut initial = None;
+        for line in fst.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let from = state(fields[0])?;
+            let to = match fields.len() {
+                1 | 2 => None,
+                _ => Some(state(fields[1])?),
+            };
+            let last = from.max(to.unwrap_or_default());
+            if nfa.states.len() <= last {
+                nfa.fill_state(last + 1 - nfa.states.len());
+            }
+            initial.get_or_insert(from);
+
+            let Some(to) = to else {
+                nfa.add_ending(from);
+                continue;
+            };
+            let label = fields[2];
+            let text = match (label.parse::<u32>(), &symbols) {
+                (Ok(0), _) => String::new(),
+                (Ok(id), Some(symbols)) => symbols
+                    .get(label)
+                    .map(|symbol| fst_text(symbol))
+                    .ok_or_else(|| {
+                        invalid(&format!("OpenFST label {} is not in the symbols", id))
+                    })?,
+                (Ok(id), None) => char::from_u32(id)
+                    .ok_or_else(|| invalid(&format!("OpenFST label {} is not a char", id)))?
+                    .to_string(),
+                (Err(_), _) => fst_text(label),
+            };
+            nfa.add_text_transition(from, to, &text);
+        }
+        nfa.set_initial(initial.ok_or_else(|| invalid("the OpenFST text has no states"))?);
+        Ok(nfa)
+    }
+
+    // A path from `from` to `to` reading `text`, an epsilon transition
+    // when it is empty.
+    fn add_text_transition(&mut self, from: usize, to: usize, text: &str) {
+        let mut chars = text.chars().peekable();
+        let mut at = from;
+        if chars.peek().is_none() {
+            self.add_epsilon_transition(from, to);
+        }
+        while let Some(c) = chars.next() {
+            let next = match chars.peek() {
+                Some(_) => {
+                    self.fill_state(1);
+                    self.states.len() - 1
+                }
+                None => to,
+            };
+            self.add_char_transition(at, next, c);
+            at = next;
+        }
+    }
+}
+
+// The attributes and content of each `<tag ...>...</tag>` in `xml`, the
+// content being `None` for `<tag/>`. Elements do not nest in themselves in
+// the files read here.
+fn xml_elements<'x>(xml: &'x str, tag: &str) -> Vec<(&'x str, Option<&'x str>)> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut elements = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        // `<to` must not match `<type>`
+        if !rest.starts_with(['>', '/', ' ', '\t', '\n', '\r']) {
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let attributes = &rest[..end];
+        rest = &rest[end + 1..];
+        if let Some(attributes) = attributes.strip_suffix('/') {
+            elements.push((attributes, None));
+            continue;
+        }
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        elements.push((attributes, Some(&rest[..end])));
+        rest = &rest[end + close.len()..];
+    }
+    elements
+}
+
+fn xml_attribute<'x>(attributes: &'x str, name: &str) -> Option<&'x str> {
+    let start = attributes.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = attributes[start..].find('"')?;
+    Some(&attributes[start..start + end])
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// The text of an OpenFST symbol, reading back the `U+XXXX` escapes of
+// `fst_symbol`.
+fn fst_text(symbol: &str) -> String {
+    if symbol == "<eps>" {
+        return String::new();
+    }
+    let mut text = String::new();
+    let mut rest = symbol;
+    while let Some(start) = rest.find("U+") {
+        let digits = rest[start + 2..]
+            .chars()
+            .take_while(char::is_ascii_hexdigit)
+            .count()
+            .min(6);
+        let hex = &rest[start + 2..start + 2 + digits];
+        match u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+            Some(c) if digits >= 4 => {
+                text.push_str(&rest[..start]);
+                text.push(c);
+            }
+            _ => text.push_str(&rest[..start + 2 + digits]),
+        }
+        rest = &rest[start + 2 + digits..];
+    }
+    text.push_str(rest);
+    text
+}
+
+fn xml_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// OpenFST symbols are split on whitespace, so whitespace and control chars
+// are written as `U+XXXX`.
+fn fst_symbol(matcher: &Transition) -> String {
+    if let Transition::Epsilon = matcher {
+        return "<eps>".to_string();
+    }
+    matcher
+        .label()
+        .chars()
+        .map(|c| match c.is_whitespace() || c.is_control() {
+            true => format!("U+{:04X}", c as u32),
+            false => c.to_string(),
+        })
+        .collect()
+}
+
+// an `Error::Import` with `message`
+fn invalid(message: &str) -> Error {
+    Error::Import(message.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rsgex::{error::Error, nfa::NFAutomata};
+
+    fn sample() -> NFAutomata {
+        let mut nfa = NFAutomata::default();
+        nfa.declare_state(3, 0, 2);
+        nfa.add_char_transition(0, 1, '<');
+        nfa.add_epsilon_transition(1, 2);
+        nfa.add_char_transition(1, 2, ' ');
+        nfa
+    }
+
+    #[test]
+    fn test_to_jflap() {
+        let jff = sample().to_jflap();
+        assert!(jff.contains("<type>fa</type>"));
+        assert!(jff.contains("<state id=\"0\" name=\"q0\">\n\t\t\t<x>100.0</x>"));
+        assert!(jff.contains("<y>100.0</y>\n\t\t\t<initial/>"));
+        assert!(jff.contains("<y>100.0</y>\n\t\t\t<final/>"));
+        assert!(jff.contains("<read>&lt;</read>"));
+        assert!(jff.contains("<to>2</to>\n\t\t\t<read/>"));
+    }
+
+    #[test]
+    fn test_to_openfst() {
+        let nfa = sample();
+        assert_eq!(nfa.to_openfst(), "0\t1\t<\n1\t2\t<eps>\n1\t2\tU+0020\n2\n");
+        assert_eq!(nfa.openfst_symbols(), "<eps>\t0\n<\t1\nU+0020\t2\n");
+
+        let mut nfa = NFAutomata::default();
+        nfa.declare_state(2, 1, 1);
+        nfa.add_char_transition(0, 1, 'a');
+        assert_eq!(nfa.to_openfst(), "1\n0\t1\ta\n");
+    }
+
+    #[test]
+    fn test_from_jflap() {
+        let nfa = NFAutomata::from_jflap(&sample().to_jflap()).unwrap();
+        assert!(nfa.compute("<").is_some());
+        assert!(nfa.compute("< ").is_some());
+        assert!(nfa.compute(" ").is_none());
+
+        let jff = "<structure><type>fa</type><automaton>\
+                   <state id=\"7\"><initial/></state><state id=\"3\"><final/></state>\
+                   <transition><from>7</from><to>3</to><read>ab</read></transition>\
+                   </automaton></structure>";
+        let nfa = NFAutomata::from_jflap(jff).unwrap();
+        assert!(nfa.compute("ab").is_some());
+        assert!(nfa.compute("a").is_none());
+        assert!(matches!(
+            NFAutomata::from_jflap("<structure><type>pda</type></structure>"),
+            Err(Error::Import(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_openfst() {
+        let nfa = sample();
+        let symbols = nfa.openfst_symbols();
+        let imported = NFAutomata::from_openfst(&nfa.to_openfst(), None).unwrap();
+        assert!(imported.compute("< ").is_some());
+        assert!(imported.compute("<").is_some());
+
+        // numeric labels, with and without a symbol table
+        let imported = NFAutomata::from_openfst("0 1 1 1 0.5\n1 2 0\n2 0.0\n", Some(&symbols));
+        assert!(imported.unwrap().compute("<").is_some());
+        let imported = NFAutomata::from_openfst("0 1 97\n1\n", None).unwrap();
+        assert!(imported.compute("a").is_some());
+        assert!(NFAutomata::from_openfst("0 1 9\n", Some(&symbols)).is_err());
+    }
+}
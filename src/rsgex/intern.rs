@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
+
+use super::matcher::{ClassMatcher, PerlClassMatcher};
+
+// Tables compiled from equal classes, shared by every automaton of the
+// process that contains one, however many patterns or compilations they
+// come from. Entries are weak, so a table goes away with its last engine;
+// dead entries are swept whenever the table count doubled since the last
+// sweep.
+pub(crate) struct Interner<T> {
+    hasher: RandomState,
+    // hash -> tables with that hash
+    entries: HashMap<u64, Vec<Weak<T>>>,
+    len: usize,
+    sweep_at: usize,
+}
+
+impl<T: Hash + Eq> Interner<T> {
+    fn new() -> Self {
+        Interner {
+            hasher: RandomState::new(),
+            entries: HashMap::new(),
+            len: 0,
+            sweep_at: 64,
+        }
+    }
+
+    // The shared table equal to `value`, which becomes the shared one if
+    // there is none yet.
+    fn intern(&mut self, value: Arc<T>) -> Arc<T> {
+        let hash = self.hasher.hash_one(&*value);
+        let bucket = self.entries.entry(hash).or_default();
+        if let Some(shared) = bucket
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|shared| *shared == value)
+        {
+            return shared;
+        }
+
+        bucket.push(Arc::downgrade(&value));
+        self.len += 1;
+        if self.len >= self.sweep_at {
+            self.sweep();
+        }
+        value
+    }
+
+    fn sweep(&mut self) {
+        self.entries.retain(|_, bucket| {
+            bucket.retain(|weak| weak.strong_count() > 0);
+            !bucket.is_empty()
+        });
+        self.len = self.entries.values().map(Vec::len).sum();
+        self.sweep_at = (self.len * 2).max(64);
+    }
+}
+
+static CLASSES: OnceLock<Mutex<Interner<ClassMatcher>>> = OnceLock::new();
+static PERL_CLASSES: OnceLock<Mutex<Interner<PerlClassMatcher>>> = OnceLock::new();
+
+pub(crate) fn class(class: ClassMatcher) -> Arc<ClassMatcher> {
+    let interner = CLASSES.get_or_init(|| Mutex::new(Interner::new()));
+    // a panic elsewhere cannot leave the map half updated
+    let mut interner = interner
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    interner.intern(Arc::new(class))
+}
+
+pub(crate) fn perl_class(class: PerlClassMatcher) -> Arc<PerlClassMatcher> {
+    let interner = PERL_CLASSES.get_or_init(|| Mutex::new(Interner::new()));
+    let mut interner = interner
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    interner.intern(Arc::new(class))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::Interner;
+
+    #[test]
+    fn test_intern() {
+        let mut interner = Interner::new();
+        let a = interner.intern(Arc::new(vec![1, 2]));
+        let b = interner.intern(Arc::new(vec![1, 2]));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &interner.intern(Arc::new(vec![3]))));
+
+        drop((a, b));
+        for i in 0..100 {
+            interner.intern(Arc::new(vec![i]));
+        }
+        // only dropped tables were interned
+        assert!(interner.len < 64);
+    }
+}
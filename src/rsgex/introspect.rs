@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+
+use super::{
+    engine::Engine,
+    matcher::Transition,
+    nfa::{NFAutomata, State},
+};
+
+// What taking a transition does, see `StateView::transitions`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransitionKind {
+    Epsilon,
+    // consumes one given char
+    Char,
+    // consumes one char of a class or range
+    Class,
+    // consumes nothing but checks the position, like `^` or `\b`
+    Assertion,
+    // enters a subroutine, see `(?R)`
+    Call,
+    // updates or checks the counter of a `{n,m}` repetition
+    Counter,
+    Custom,
+}
+
+// A read-only view of a state of the compiled automaton, for tools that
+// analyze it without depending on how it is stored; see `Engine::states`.
+#[derive(Clone, Copy)]
+pub struct StateView<'e> {
+    index: usize,
+    state: &'e State,
+}
+
+impl<'e> StateView<'e> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn is_initial(&self) -> bool {
+        self.state.is_initial
+    }
+
+    pub fn is_ending(&self) -> bool {
+        self.state.is_ending
+    }
+
+    // The index and name of the capture groups that open at this state.
+    pub fn groups_started(self) -> impl Iterator<Item = (u32, Option<&'e str>)> + 'e {
+        let groups = self.state.start_group.iter();
+        groups.map(|(index, name)| (*index, name.as_deref()))
+    }
+
+    // The same for the groups that close here.
+    pub fn groups_ended(self) -> impl Iterator<Item = (u32, Option<&'e str>)> + 'e {
+        let groups = self.state.end_group.iter();
+        groups.map(|(index, name)| (*index, name.as_deref()))
+    }
+
+    // The label, target state and kind of each transition, in the order
+    // they are tried.
+    pub fn transitions(self) -> impl Iterator<Item = (String, usize, TransitionKind)> + 'e {
+        let transitions = self.state.matchers.iter();
+        transitions.map(|(matcher, to)| (matcher.label(), *to, kind(matcher)))
+    }
+}
+
+// The size of a compiled automaton, see `Engine::stats`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub states: usize,
+    pub transitions: usize,
+    // transitions taken without consuming a char, a subset of `transitions`
+    pub epsilons: usize,
+    // the whole match included
+    pub capture_groups: usize,
+    // the most transitions leaving one state
+    pub max_fan_out: usize,
+    // roughly the bytes the states, their transitions and tables take
+    pub memory: usize,
+}
+
+impl Engine {
+    // The states of the compiled automaton, in index order.
+    pub fn states(&self) -> impl Iterator<Item = StateView<'_>> {
+        self.nfa.state_views()
+    }
+
+    // How big the pattern compiled, for keeping an eye on patterns from
+    // users. `EngineBuilder::nfa_size_limit` caps an estimate of `memory`
+    // made before compiling.
+    pub fn stats(&self) -> Stats {
+        let states = &self.nfa.states;
+        let transitions = states.iter().map(|state| state.matchers.len());
+        let epsilons = states.iter().flat_map(|state| state.matchers.iter());
+        let groups: HashSet<u32> = states
+            .iter()
+            .flat_map(|state| state.start_group.iter())
+            .map(|(index, _)| *index)
+            .collect();
+        let memory = states.iter().map(|state| {
+            let tables = state.ascii_table.as_ref().map_or(0, |table| {
+                size_of_val(&**table)
+                    + table.iter().map(Vec::len).sum::<usize>() * size_of::<usize>()
+            });
+            size_of::<State>() + state.matchers.len() * size_of::<(Transition, usize)>() + tables
+        });
+
+        Stats {
+            states: states.len(),
+            transitions: transitions.clone().sum(),
+            epsilons: epsilons.filter(|(matcher, _)| matcher.is_epsilon()).count(),
+            capture_groups: groups.len(),
+            max_fan_out: transitions.max().unwrap_or_default(),
+            memory: memory.sum(),
+        }
+    }
+}
+
+impl NFAutomata {
+    pub fn state_views(&self) -> impl Iterator<Item = StateView<'_>> {
+        let states = self.states.iter().enumerate();
+        states.map(|(index, state)| StateView { index, state })
+    }
+}
+
+fn kind(matcher: &Transition) -> TransitionKind {
+    match matcher {
+        Transition::Epsilon => TransitionKind::Epsilon,
+        Transition::Char(_) => TransitionKind::Char,
+        Transition::Range(..)
+        | Transition::CharSet(_)
+        | Transition::Class(_)
+        | Transition::PerlClass(_)
+        | Transition::Ascii { .. } => TransitionKind::Class,
+        Transition::Start | Transition::End | Transition::Continuation | Transition::Look(_) => {
+            TransitionKind::Assertion
+        }
+        Transition::Call(_) => TransitionKind::Call,
+        Transition::Counter(_) => TransitionKind::Counter,
+        Transition::Custom(_) => TransitionKind::Custom,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Stats, TransitionKind};
+    use crate::rsgex::engine::Engine;
+
+    #[test]
+    fn test_states() {
+        let e = Engine::try_from("(?<d>[0-9])x{2}").unwrap();
+        let states: Vec<_> = e.states().collect();
+        assert_eq!(states.len(), e.nfa.to_string().lines().count());
+        assert!(states[0].is_initial());
+        assert!(states.iter().any(|state| state.is_ending()));
+        assert!(
+            states
+                .iter()
+                .any(|state| state.groups_started().any(|group| group == (1, Some("d"))))
+        );
+
+        let kinds: Vec<TransitionKind> = states
+            .iter()
+            .flat_map(|state| state.transitions())
+            .map(|(_, _, kind)| kind)
+            .collect();
+        for kind in [
+            TransitionKind::Class,
+            TransitionKind::Char,
+            TransitionKind::Counter,
+        ] {
+            assert!(kinds.contains(&kind));
+        }
+        let mut transitions = states.iter().flat_map(|state| state.transitions());
+        assert!(transitions.all(|(_, to, _)| to < states.len()));
+    }
+
+    #[test]
+    fn test_stats() {
+        let e = Engine::try_from("(a)|(b)|(c)").unwrap();
+        let Stats {
+            states,
+            transitions,
+            epsilons,
+            capture_groups,
+            max_fan_out,
+            memory,
+        } = e.stats();
+        assert_eq!(states, e.states().count());
+        assert_eq!(
+            transitions,
+            e.states().flat_map(|s| s.transitions()).count()
+        );
+        assert!(epsilons < transitions);
+        assert_eq!(capture_groups, 4);
+        assert!(max_fan_out >= 3);
+        assert!(memory > 0);
+    }
+}
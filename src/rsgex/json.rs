@@ -0,0 +1,260 @@
+use std::fmt::Write;
+
+use super::error::Error;
+
+// A JSON document as far as traces need it: numbers are unsigned integers,
+// and objects keep their keys in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(usize),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub(crate) fn get(&self, key: &str) -> Result<&Value, Error> {
+        let Value::Object(members) = self else {
+            return Err(invalid(&format!("expected an object with {:?}", key)));
+        };
+        let member = members.iter().find(|(name, _)| name == key);
+        member
+            .map(|(_, value)| value)
+            .ok_or_else(|| invalid(&format!("missing {:?}", key)))
+    }
+
+    pub(crate) fn as_usize(&self) -> Result<usize, Error> {
+        match self {
+            Value::Number(n) => Ok(*n),
+            _ => Err(invalid(&format!("expected a number, found {:?}", self))),
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Result<bool, Error> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(invalid(&format!("expected a boolean, found {:?}", self))),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Result<&str, Error> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(invalid(&format!("expected a string, found {:?}", self))),
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Result<&[Value], Error> {
+        match self {
+            Value::Array(values) => Ok(values),
+            _ => Err(invalid(&format!("expected an array, found {:?}", self))),
+        }
+    }
+}
+
+// `text` as a quoted JSON string.
+pub(crate) fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            // `<` too, so the text can sit in an HTML script element
+            c if c.is_control() || c == '<' => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    let _ = write!(quoted, "\\u{:04x}", unit);
+                }
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub(crate) fn parse(text: &str) -> Result<Value, Error> {
+    let mut parser = Parser { text, at: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.at < text.len() {
+        return Err(invalid(&format!(
+            "unexpected text after the JSON value at byte {}",
+            parser.at
+        )));
+    }
+    Ok(value)
+}
+
+struct Parser<'t> {
+    text: &'t str,
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.at..];
+        self.at += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.as_bytes().get(self.at).copied()
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), Error> {
+        self.skip_whitespace();
+        if !self.text[self.at..].starts_with(token) {
+            return Err(invalid(&format!(
+                "expected {:?} at byte {}",
+                token, self.at
+            )));
+        }
+        self.at += token.len();
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|_| Value::Null),
+            Some(b't') => self.expect("true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => {
+                self.expect("[")?;
+                let mut values = vec![];
+                while self.peek() != Some(b']') {
+                    if !values.is_empty() {
+                        self.expect(",")?;
+                    }
+                    values.push(self.value()?);
+                }
+                self.expect("]")?;
+                Ok(Value::Array(values))
+            }
+            Some(b'{') => {
+                self.expect("{")?;
+                let mut members = vec![];
+                while self.peek() != Some(b'}') {
+                    if !members.is_empty() {
+                        self.expect(",")?;
+                    }
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(":")?;
+                    members.push((key, self.value()?));
+                }
+                self.expect("}")?;
+                Ok(Value::Object(members))
+            }
+            Some(b'0'..=b'9') => {
+                let rest = &self.text[self.at..];
+                let digits =
+                    rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                self.at += digits;
+                let number = rest[..digits]
+                    .parse()
+                    .map_err(|_| invalid("number out of range"))?;
+                Ok(Value::Number(number))
+            }
+            _ => Err(invalid(&format!(
+                "expected a JSON value at byte {}",
+                self.at
+            ))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect("\"")?;
+        let mut text = String::new();
+        let mut chars = self.text[self.at..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.at += offset + 1;
+                    return Ok(text);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let first = hex_unit(&mut chars).ok_or_else(bad_unicode)?;
+                            let mut units = vec![first];
+                            // a high surrogate is followed by `\uXXXX` with the low one
+                            if (0xD800..0xDC00).contains(&first) {
+                                chars.nth(1);
+                                units.extend(hex_unit(&mut chars));
+                            }
+                            let mut decoded = char::decode_utf16(units);
+                            decoded
+                                .next()
+                                .and_then(|c| c.ok())
+                                .ok_or_else(bad_unicode)?
+                        }
+                        Some(c @ ('"' | '\\' | '/')) => c,
+                        _ => {
+                            return Err(invalid(&format!(
+                                "bad escape in a string at byte {}",
+                                self.at + offset
+                            )));
+                        }
+                    };
+                    text.push(escaped);
+                }
+                c => text.push(c),
+            }
+        }
+        Err(invalid(&format!("unterminated string at byte {}", self.at)))
+    }
+}
+
+fn hex_unit(chars: &mut std::str::CharIndices) -> Option<u16> {
+    let hex: String = chars.take(4).map(|(_, c)| c).collect();
+    u16::from_str_radix(&hex, 16).ok()
+}
+
+// an `Error::Import` for a document that is not JSON or not a trace
+pub(crate) fn invalid(message: &str) -> Error {
+    Error::Import(message.to_string())
+}
+
+fn bad_unicode() -> Error {
+    invalid("bad \\u escape")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Value, parse, string};
+
+    #[test]
+    fn test_round_trip() {
+        let text = "a \"quoted\" \\ line\n<tab>\t\u{1}\u{1F600}";
+        assert_eq!(
+            parse(&string(text)).unwrap(),
+            Value::String(text.to_string())
+        );
+        assert!(!string(text).contains('<'));
+
+        let value = parse(r#" {"a": [1, true, null], "b": "😀é"} "#).unwrap();
+        assert_eq!(
+            value.get("a").unwrap().as_array().unwrap()[0],
+            Value::Number(1)
+        );
+        assert_eq!(value.get("b").unwrap().as_str().unwrap(), "\u{1F600}é");
+        assert!(value.get("c").is_err());
+        let escaped = parse("\"\\ud83d\\ude00\\u00e9\"").unwrap();
+        assert_eq!(escaped.as_str().unwrap(), "\u{1F600}é");
+        assert!(parse("[1,]").is_err());
+        assert!(parse("\"open").is_err());
+    }
+}
@@ -0,0 +1,671 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    sync::OnceLock,
+};
+
+use super::{alphabet::Alphabet, dfa::Determinizer, engine::Engine, error::Error, normalize};
+
+// The set of texts a pattern matches in full, as a complete DFA: every
+// state has a transition on every class of chars, so languages combine by
+// running their DFAs side by side. Supports the same patterns as `Dfa`;
+// `^` / `$` hold at the ends of the text.
+#[derive(Clone)]
+pub struct Language {
+    alphabet: Alphabet,
+    // a row of next states per state, one column per char class
+    table: Vec<usize>,
+    accepting: Vec<bool>,
+    start: usize,
+    nfc: bool,
+    // see `live`, found on first use by `is_valid_prefix`
+    live_states: OnceLock<Vec<bool>>,
+}
+
+impl Language {
+    // Whether the whole of `s` is in the language.
+    pub fn is_full_match(&self, s: &str) -> bool {
+        let s = self.prepare(s);
+        let mut state = self.start;
+        for c in s.chars() {
+            state = self.table[state * self.alphabet.classes() + self.alphabet.class(c)];
+        }
+        self.accepting[state]
+    }
+
+    // Whether some text of the language starts with `s`. The states after
+    // `s` are looked up among those that can still reach an accepting one,
+    // which are found once per language.
+    pub fn is_valid_prefix(&self, s: &str) -> bool {
+        let live = self.live_states.get_or_init(|| self.live());
+        let s = self.prepare(s);
+        let mut state = self.start;
+        for c in s.chars() {
+            if !live[state] {
+                return false;
+            }
+            state = self.table[state * self.alphabet.classes() + self.alphabet.class(c)];
+        }
+        live[state]
+    }
+
+    // The texts in both languages, by product construction. The result
+    // normalizes texts like `self`.
+    pub fn intersection(&self, other: &Language) -> Language {
+        self.product(other, |a, b| a && b)
+    }
+
+    // The texts in `self` but not in `other`, the intersection with the
+    // complement of `other` built in one pass.
+    pub fn difference(&self, other: &Language) -> Language {
+        self.product(other, |a, b| a && !b)
+    }
+
+    // The texts not in the language, the empty one included. As the DFA is
+    // complete, this only swaps its accepting states.
+    pub fn complement(&self) -> Language {
+        let mut complement = self.clone();
+        complement
+            .accepting
+            .iter_mut()
+            .for_each(|accepting| *accepting = !*accepting);
+        complement.live_states = OnceLock::new();
+        complement
+    }
+
+    // Whether no text is in the language. Only states reachable from the
+    // start are ever added, so any accepting one is reached by some text.
+    pub fn is_empty(&self) -> bool {
+        !self.accepting.contains(&true)
+    }
+
+    // Whether every text is in the language.
+    pub fn is_universal(&self) -> bool {
+        !self.accepting.contains(&false)
+    }
+
+    // Whether the language holds finitely many texts, that is whether no
+    // cycle of the DFA lies on the way to an accepting state.
+    pub fn is_finite(&self) -> bool {
+        let live = self.live();
+        // 0 unvisited, 1 on the current path, 2 done
+        let mut marks = vec![0u8; self.state_count()];
+        let mut stack = vec![(self.start, 0)];
+        marks[self.start] = 1;
+
+        while let Some((state, class)) = stack.pop() {
+            if class == self.alphabet.classes() {
+                marks[state] = 2;
+                continue;
+            }
+            stack.push((state, class + 1));
+            let next = self.table[state * self.alphabet.classes() + class];
+            if !live[next] {
+                continue;
+            }
+            match marks[next] {
+                0 => {
+                    marks[next] = 1;
+                    stack.push((next, 0));
+                }
+                1 => return false,
+                _ => (),
+            }
+        }
+        true
+    }
+
+    // A shortest text in the language, found breadth first. Each char
+    // stands for its class, taking a letter or digit where the class has
+    // one, so `[A-Z]{3}-\d{3}` gives `AAA-000`.
+    pub fn shortest_example(&self) -> Option<String> {
+        let chars = self.readable_chars();
+        // state -> (previous state, char taken), once reached
+        let mut reached: Vec<Option<(usize, char)>> = vec![None; self.state_count()];
+        let mut queue = VecDeque::from([self.start]);
+        reached[self.start] = Some((self.start, '\0'));
+
+        while let Some(state) = queue.pop_front() {
+            if self.accepting[state] {
+                let mut text = vec![];
+                let mut at = state;
+                while at != self.start {
+                    let (previous, c) = reached[at].unwrap();
+                    text.push(c);
+                    at = previous;
+                }
+                return Some(text.into_iter().rev().collect());
+            }
+            for (class, &c) in chars.iter().enumerate() {
+                let next = self.table[state * self.alphabet.classes() + class];
+                if reached[next].is_none() {
+                    reached[next] = Some((state, c));
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    // The texts of at most `max_len` chars, in lexicographic order of their
+    // classes, with each class standing for one char as in
+    // `shortest_example`.
+    pub fn examples(&self, max_len: usize) -> Examples<'_> {
+        let chars = self.readable_chars().into_iter().map(|c| vec![c]).collect();
+        Examples::new(self, chars, max_len)
+    }
+
+    // Every text of at most `max_len` chars, each class expanded into all
+    // of its chars. Sorting the chars scans the whole of Unicode once.
+    pub fn texts(&self, max_len: usize) -> Examples<'_> {
+        let mut chars = vec![vec![]; self.alphabet.classes()];
+        for c in (0..=char::MAX as u32).filter_map(char::from_u32) {
+            chars[self.alphabet.class(c)].push(c);
+        }
+        Examples::new(self, chars, max_len)
+    }
+
+    // How many texts of each length up to `max_len` the language holds,
+    // counting the chars of every class. Counts past `u128::MAX` saturate.
+    pub fn counts(&self, max_len: usize) -> Vec<u128> {
+        let sizes = self.alphabet.sizes();
+        let classes = sizes.len();
+        // state -> texts of the current length leading to it
+        let mut ways = vec![0u128; self.state_count()];
+        ways[self.start] = 1;
+
+        let mut counts = vec![];
+        for len in 0..=max_len {
+            let count = ways
+                .iter()
+                .zip(self.accepting.iter())
+                .filter(|(_, accepting)| **accepting)
+                .fold(0u128, |count, (ways, _)| count.saturating_add(*ways));
+            counts.push(count);
+            if len == max_len {
+                break;
+            }
+
+            let mut next = vec![0u128; self.state_count()];
+            for (state, &ways) in ways.iter().enumerate().filter(|(_, ways)| **ways > 0) {
+                for (class, &size) in sizes.iter().enumerate() {
+                    let to = self.table[state * classes + class];
+                    next[to] = next[to].saturating_add(ways.saturating_mul(size as u128));
+                }
+            }
+            ways = next;
+        }
+        counts
+    }
+
+    // Draws random texts of the language, see `Sampler`.
+    pub fn sampler(&self) -> Sampler<'_> {
+        Sampler::new(self)
+    }
+
+    // Whether every text of `self` is in `other` as well.
+    pub fn is_subset_of(&self, other: &Language) -> bool {
+        self.difference(other).is_empty()
+    }
+
+    // Whether both languages hold the same texts, that is whether no text
+    // is in exactly one of them.
+    pub fn is_equivalent(&self, other: &Language) -> bool {
+        self.product(other, |a, b| a != b).is_empty()
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.accepting.len()
+    }
+
+    // Runs both DFAs side by side from their starts, only adding the pairs
+    // of states reachable together. A pair accepts when `accept` holds for
+    // its states.
+    fn product(&self, other: &Language, accept: impl Fn(bool, bool) -> bool) -> Language {
+        let (alphabet, pairs) = self.alphabet.product(&other.alphabet);
+        let mut ids = HashMap::new();
+        let mut queue = vec![];
+        let mut id = |pair: (usize, usize), queue: &mut Vec<(usize, usize)>| {
+            *ids.entry(pair).or_insert_with(|| {
+                queue.push(pair);
+                queue.len() - 1
+            })
+        };
+        let start = id((self.start, other.start), &mut queue);
+
+        let mut table = vec![];
+        let mut accepting = vec![];
+        let mut processed = 0;
+        while processed < queue.len() {
+            let (a, b) = queue[processed];
+            accepting.push(accept(self.accepting[a], other.accepting[b]));
+            for &(class_a, class_b) in pairs.iter() {
+                let next = (
+                    self.table[a * self.alphabet.classes() + class_a],
+                    other.table[b * other.alphabet.classes() + class_b],
+                );
+                table.push(id(next, &mut queue));
+            }
+            processed += 1;
+        }
+
+        Language {
+            alphabet,
+            table,
+            accepting,
+            start,
+            nfc: self.nfc,
+            live_states: OnceLock::new(),
+        }
+    }
+
+    // A char per class, preferring letters, digits and then other printable
+    // ASCII over the first char of the class.
+    fn readable_chars(&self) -> Vec<char> {
+        let mut chars: Vec<Option<char>> = vec![None; self.alphabet.classes()];
+        let readable = ('a'..='z')
+            .chain('A'..='Z')
+            .chain('0'..='9')
+            .chain(' '..='~');
+        for c in readable {
+            chars[self.alphabet.class(c)].get_or_insert(c);
+        }
+        chars
+            .into_iter()
+            .enumerate()
+            .map(|(class, c)| c.unwrap_or(self.alphabet.representative(class)))
+            .collect()
+    }
+
+    // The states from which an accepting state can be reached.
+    fn live(&self) -> Vec<bool> {
+        self.distances()
+            .into_iter()
+            .map(|distance| distance != usize::MAX)
+            .collect()
+    }
+
+    // The fewest chars leading from each state to an accepting one,
+    // `usize::MAX` where none can be reached.
+    fn distances(&self) -> Vec<usize> {
+        let classes = self.alphabet.classes();
+        let mut sources = vec![vec![]; self.state_count()];
+        for (i, &next) in self.table.iter().enumerate() {
+            sources[next].push(i / classes);
+        }
+
+        let mut distances = vec![usize::MAX; self.state_count()];
+        let mut queue = VecDeque::new();
+        for state in (0..self.state_count()).filter(|&state| self.accepting[state]) {
+            distances[state] = 0;
+            queue.push_back(state);
+        }
+        while let Some(state) = queue.pop_front() {
+            for &source in sources[state].iter() {
+                if distances[source] == usize::MAX {
+                    distances[source] = distances[state] + 1;
+                    queue.push_back(source);
+                }
+            }
+        }
+        distances
+    }
+
+    fn prepare<'s>(&self, s: &'s str) -> Cow<'s, str> {
+        if self.nfc {
+            normalize::nfc(s)
+        } else {
+            Cow::Borrowed(s)
+        }
+    }
+}
+
+// Texts of a language up to a length, see `Language::examples`. Only
+// states from which an accepting one can be reached are entered.
+pub struct Examples<'l> {
+    language: &'l Language,
+    // class -> the chars standing for it
+    chars: Vec<Vec<char>>,
+    live: Vec<bool>,
+    max_len: usize,
+    // state, text leading to it, its length in chars
+    stack: Vec<(usize, String, usize)>,
+}
+
+impl<'l> Examples<'l> {
+    fn new(language: &'l Language, chars: Vec<Vec<char>>, max_len: usize) -> Self {
+        let live = language.live();
+        let stack = match live[language.start] {
+            true => vec![(language.start, String::new(), 0)],
+            false => vec![],
+        };
+        Self {
+            language,
+            chars,
+            live,
+            max_len,
+            stack,
+        }
+    }
+}
+
+impl Iterator for Examples<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let language = self.language;
+        let classes = language.alphabet.classes();
+
+        while let Some((state, text, len)) = self.stack.pop() {
+            if len < self.max_len {
+                // pushed in reverse, so the first class comes out first
+                for class in (0..classes).rev() {
+                    let next = language.table[state * classes + class];
+                    if !self.live[next] {
+                        continue;
+                    }
+                    for &c in self.chars[class].iter().rev() {
+                        let mut longer = text.clone();
+                        longer.push(c);
+                        self.stack.push((next, longer, len + 1));
+                    }
+                }
+            }
+            if language.accepting[state] {
+                return Some(text);
+            }
+        }
+        None
+    }
+}
+
+// Draws random texts of a language by walking its DFA with a random
+// number generator the caller provides as a closure returning `u64`s, so
+// any generator fits. Each step takes a class of chars that can still
+// reach an accepting state within `max_len` chars, then a char of it, and
+// at an accepting state the walk goes on with the `repeat` probability.
+pub struct Sampler<'l> {
+    language: &'l Language,
+    distances: Vec<usize>,
+    max_len: usize,
+    repeat: f64,
+}
+
+impl<'l> Sampler<'l> {
+    fn new(language: &'l Language) -> Self {
+        Self {
+            language,
+            distances: language.distances(),
+            max_len: 32,
+            repeat: 0.5,
+        }
+    }
+
+    // The most chars a text may have, 32 by default.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    // The probability of going on past an accepting state, 0.5 by default.
+    // Higher values favor more repetitions of `*` and `+`.
+    pub fn repeat(mut self, repeat: f64) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    // `None` when the language has no text of at most `max_len` chars.
+    pub fn sample(&self, rng: &mut impl FnMut() -> u64) -> Option<String> {
+        let language = self.language;
+        let sizes = language.alphabet.sizes();
+        let mut state = language.start;
+        let mut text = String::new();
+        let mut len = 0;
+        if self.distances[state] > self.max_len {
+            return None;
+        }
+
+        loop {
+            let classes: Vec<usize> = (0..sizes.len())
+                .filter(|&class| {
+                    let next = language.table[state * sizes.len() + class];
+                    self.distances[next] < self.max_len - len
+                })
+                .collect();
+            if language.accepting[state] && (classes.is_empty() || unit(rng()) >= self.repeat) {
+                return Some(text);
+            }
+
+            let class = classes[(rng() % classes.len() as u64) as usize];
+            text.push(language.alphabet.nth(class, rng() % sizes[class]));
+            state = language.table[state * sizes.len() + class];
+            len += 1;
+        }
+    }
+}
+
+// a random `u64` as a float in [0, 1)
+fn unit(random: u64) -> f64 {
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl TryFrom<&Engine> for Language {
+    type Error = Error;
+
+    // Determinizes from the anchored start, keeping every thread so that
+    // longer matches are not cut off by shorter ones. Only the states
+    // reachable from that start are kept, renumbered in the order reached.
+    fn try_from(engine: &Engine) -> Result<Language, Error> {
+        let mut determinizer = Determinizer::new(&engine.nfa)?;
+        determinizer.leftmost_first = false;
+        determinizer.limit = engine.dfa_size_limit;
+        let start = determinizer.starts()[0][0];
+
+        // determinizer state -> language state
+        let mut ids = HashMap::from([(start, 0)]);
+        let mut states = vec![start];
+        let mut table = vec![];
+        let mut processed = 0;
+        while processed < states.len() {
+            for class in 0..determinizer.classes() {
+                let next = determinizer.transition(states[processed], class);
+                table.push(*ids.entry(next).or_insert_with(|| {
+                    states.push(next);
+                    states.len() - 1
+                }));
+            }
+            processed += 1;
+            determinizer.check_limits()?;
+        }
+
+        Ok(Language {
+            alphabet: determinizer.alphabet().clone(),
+            table,
+            accepting: states
+                .iter()
+                .map(|&state| determinizer.eof_matching[state])
+                .collect(),
+            start: 0,
+            nfc: engine.flags.nfc,
+            live_states: OnceLock::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Language;
+    use crate::rsgex::engine::Engine;
+
+    fn language(pattern: &str) -> Language {
+        Language::try_from(&Engine::try_from(pattern).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_full_match() {
+        let l = language("a|ab|(cd)+");
+        assert!(l.is_full_match("a"));
+        assert!(l.is_full_match("ab"));
+        assert!(l.is_full_match("cdcd"));
+        assert!(!l.is_full_match("abc"));
+        assert!(!l.is_full_match(""));
+
+        let l = language("^a*$|b");
+        assert!(l.is_full_match(""));
+        assert!(l.is_full_match("aa"));
+        assert!(!l.is_full_match("ab"));
+        let l = language("$^");
+        assert!(l.is_full_match(""));
+        assert!(!l.is_full_match("a"));
+    }
+
+    #[test]
+    fn test_intersection() {
+        let identifier = language("[a-z_]\\w*");
+        let short = language(".{0,8}");
+        let l = identifier.intersection(&short);
+
+        assert!(l.is_full_match("snake_8"));
+        assert!(!l.is_full_match("8snake"));
+        assert!(!l.is_full_match("snake_case"));
+        assert!(
+            !language("a+")
+                .intersection(&language("b+"))
+                .is_full_match("ab")
+        );
+    }
+
+    #[test]
+    fn test_complement() {
+        let l = language("admin|root").complement();
+        assert!(l.is_full_match("alice"));
+        assert!(l.is_full_match(""));
+        assert!(l.is_full_match("admins"));
+        assert!(!l.is_full_match("root"));
+
+        let word = language("\\w+");
+        let letters = word.intersection(&language("\\w*\\d\\w*").complement());
+        assert!(letters.is_full_match("abc"));
+        assert!(!letters.is_full_match("ab1"));
+    }
+
+    #[test]
+    fn test_difference() {
+        let email = language("\\w+@\\w+\\.com");
+        let internal = language("\\w+@corp\\.com");
+        let external = email.difference(&internal);
+
+        assert!(external.is_full_match("a@b.com"));
+        assert!(!external.is_full_match("a@corp.com"));
+        assert!(!external.is_full_match("a@corp"));
+        assert!(!internal.difference(&email).is_full_match("a@corp.com"));
+    }
+
+    #[test]
+    fn test_equivalent() {
+        assert!(language("a|b").is_equivalent(&language("[ab]")));
+        assert!(language("(a|b)*").is_equivalent(&language("(a*b*)*")));
+        assert!(language("x{2,3}").is_equivalent(&language("xxx?")));
+        assert!(!language("a+").is_equivalent(&language("a*")));
+        assert!(language("a+").difference(&language("a*")).is_empty());
+    }
+
+    #[test]
+    fn test_emptiness_universality_finiteness() {
+        assert!(language("a^b").is_empty());
+        assert!(!language("a^b|c").is_empty());
+        assert!(language("(?s:.*)").is_universal());
+        assert!(!language(".*").is_universal());
+        assert!(
+            language("a+")
+                .complement()
+                .complement()
+                .is_equivalent(&language("a+"))
+        );
+
+        assert!(language("(a|bc){0,3}d?").is_finite());
+        assert!(
+            language("a+b")
+                .intersection(&language(".{0,4}"))
+                .is_finite()
+        );
+        assert!(!language("ab*").is_finite());
+        // the cycle of the dead state leads nowhere
+        assert!(language("ab").is_finite());
+    }
+
+    #[test]
+    fn test_shortest_example() {
+        let example = |pattern| language(pattern).shortest_example();
+        assert_eq!(example("[A-Z]{3}-\\d{3}").as_deref(), Some("AAA-000"));
+        assert_eq!(example("abc|x+y|z*").as_deref(), Some(""));
+        assert_eq!(example("abc|x+y").as_deref(), Some("xy"));
+        assert_eq!(example("[^a-z]").as_deref(), Some("A"));
+        assert_eq!(example("a^b"), None);
+    }
+
+    #[test]
+    fn test_examples() {
+        let l = language("(a|bc)d?|[0-9]");
+        let examples: Vec<_> = l.examples(5).collect();
+        assert_eq!(examples, ["0", "a", "ad", "bc", "bcd"]);
+        // `a` and `b` share a class, which `texts` expands
+        let l = language("(a|b)c?|[0-9]");
+        assert_eq!(l.examples(2).count(), 3);
+        assert_eq!(l.texts(2).count(), 14);
+
+        let l = language("x*");
+        assert_eq!(l.examples(3).collect::<Vec<_>>(), ["", "x", "xx", "xxx"]);
+        assert_eq!(language("a^b").examples(3).count(), 0);
+    }
+
+    #[test]
+    fn test_counts() {
+        assert_eq!(language("[a-c]{2}|x?").counts(3), [1, 1, 9, 0]);
+        // 10 digits then 26 letters, or 36 of either
+        let l = language("[0-9][a-z]*|[0-9a-z]");
+        assert_eq!(l.counts(2), [0, 36, 260]);
+
+        let any = language("(?s:.*)").counts(2);
+        assert_eq!(any[1], char::MAX as u128 + 1 - 2048);
+        assert_eq!(any[2], any[1] * any[1]);
+        assert_eq!(language("(?s:.*)").counts(8)[8], u128::MAX);
+    }
+
+    #[test]
+    fn test_sampler() {
+        // xorshift
+        let mut seed = 0x2545f4914f6cdd1d_u64;
+        let mut rng = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let l = language("[A-Z]{3}-\\d+|[\\u{4e00}-\\u{9fff}]");
+        let sampler = l.sampler().max_len(6);
+        for _ in 0..100 {
+            let text = sampler.sample(&mut rng).unwrap();
+            assert!(l.is_full_match(&text), "{text}");
+            assert!(text.chars().count() <= 6);
+        }
+        assert_eq!(l.sampler().max_len(0).sample(&mut rng), None);
+
+        let l = language("a*");
+        let long = l.sampler().repeat(1.0).max_len(10).sample(&mut rng);
+        assert_eq!(long.as_deref(), Some("aaaaaaaaaa"));
+        let empty = l.sampler().repeat(0.0).sample(&mut rng);
+        assert_eq!(empty.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_valid_prefix() {
+        let l = language("(ab)+c");
+        assert!(l.is_valid_prefix("abab"));
+        assert!(l.is_valid_prefix("ababc"));
+        assert!(!l.is_valid_prefix("aba c"));
+        // `ababcx` is not in the language
+        assert!(l.complement().is_valid_prefix("ababc"));
+    }
+}
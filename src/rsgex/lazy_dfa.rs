@@ -0,0 +1,185 @@
+use super::{
+    dfa::{DEAD, Determinizer},
+    engine::Engine,
+    error::Error,
+    haystack::Haystack,
+};
+
+pub const DEFAULT_CACHE_CAPACITY: usize = 2 * (1 << 20);
+
+const UNKNOWN: usize = usize::MAX;
+
+// A DFA whose states are determinized on demand while searching and cached.
+// Once the cache outgrows its capacity no more states are added and the
+// search is finished by the engine's NFA instead. Supports the same
+// patterns as `Dfa`.
+pub struct LazyDfa<'e> {
+    engine: &'e Engine,
+    determinizer: Determinizer<'e>,
+    // a row of next states per state, `UNKNOWN` until first taken
+    table: Vec<usize>,
+    starts: [[usize; 2]; 2],
+    capacity: usize,
+}
+
+impl<'e> LazyDfa<'e> {
+    pub fn new(engine: &'e Engine) -> Result<Self, Error> {
+        Self::with_capacity(engine, DEFAULT_CACHE_CAPACITY)
+    }
+
+    // `capacity` is the approximate number of bytes the cached states may
+    // take up.
+    pub fn with_capacity(engine: &'e Engine, capacity: usize) -> Result<Self, Error> {
+        // counters are unrolled, see `Dfa::try_from`
+        let mut determinizer = Determinizer::new(engine.unrolled_nfa()?)?;
+        let starts = determinizer.starts();
+        let table = vec![UNKNOWN; determinizer.matching.len() * determinizer.classes()];
+
+        Ok(Self {
+            engine,
+            determinizer,
+            table,
+            starts,
+            capacity,
+        })
+    }
+
+    pub fn is_match(&mut self, s: &str) -> bool {
+        let s = self.engine.prepare(s);
+        let mut state = self.starts[usize::from(!self.engine.flags.anchored)][0];
+
+        for c in s.chars() {
+            if self.determinizer.matching[state] {
+                return true;
+            }
+            match self.next(state, c) {
+                Some(next) => state = next,
+                None => return self.engine.find_in(&Haystack::Str(&s), 0).is_some(),
+            }
+        }
+
+        self.determinizer.matching[state] || self.determinizer.eof_matching[state]
+    }
+
+    // Byte offsets of the leftmost-first match, like `Dfa::find`.
+    pub fn find(&mut self, s: &str) -> Option<(usize, usize)> {
+        let s = self.engine.prepare(s);
+        let positions = s.char_indices().map(|(at, _)| at).chain([s.len()]);
+        let positions = positions.take(match self.engine.flags.anchored {
+            true => 1,
+            false => usize::MAX,
+        });
+
+        for at in positions {
+            match self.longest_from(&s, at) {
+                Some(Some(end)) => return Some((at, end)),
+                Some(None) => (),
+                None => {
+                    return self
+                        .engine
+                        .find_in(&Haystack::Str(&s), at)
+                        .map(|m| (m.start, m.end));
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.determinizer.memory
+    }
+
+    // `None` when the cache is full.
+    fn longest_from(&mut self, s: &str, at: usize) -> Option<Option<usize>> {
+        let mut state = self.starts[0][usize::from(at > 0)];
+        let mut end = self.determinizer.matching[state].then_some(at);
+
+        for (i, c) in s[at..].char_indices() {
+            state = self.next(state, c)?;
+            if state == DEAD {
+                return Some(end);
+            }
+            if self.determinizer.matching[state] {
+                end = Some(at + i + c.len_utf8());
+            }
+        }
+        if self.determinizer.eof_matching[state] {
+            end = Some(s.len());
+        }
+
+        Some(end)
+    }
+
+    fn next(&mut self, state: usize, c: char) -> Option<usize> {
+        let classes = self.determinizer.classes();
+        let class = self.determinizer.class(c);
+        let index = state * classes + class;
+
+        if self.table[index] == UNKNOWN {
+            if self.determinizer.memory > self.capacity {
+                return None;
+            }
+            self.table[index] = self.determinizer.transition(state, class);
+            self.table
+                .resize(self.determinizer.matching.len() * classes, UNKNOWN);
+        }
+
+        Some(self.table[index])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LazyDfa;
+    use crate::rsgex::engine::Engine;
+
+    #[test]
+    fn test_agrees_with_engine() {
+        let inputs = ["", "abc", "xab", "123-45", "a1b2c3", "zzz", "éa1"];
+
+        for pattern in ["a|ab", "^\\d+-\\d{2}$", "[a-c][0-9]", "z*?$", "$^"] {
+            let e = Engine::try_from(pattern).unwrap();
+            let mut lazy = LazyDfa::new(&e).unwrap();
+            // too small to cache anything past the start states
+            let mut full = LazyDfa::with_capacity(&e, 0).unwrap();
+
+            for input in inputs {
+                let expected = e.find_at(input, 0).map(|m| (m.start, m.end));
+                assert_eq!(lazy.find(input), expected, "{} on {:?}", pattern, input);
+                assert_eq!(full.find(input), expected, "{} on {:?}", pattern, input);
+                assert_eq!(lazy.is_match(input), expected.is_some());
+                assert_eq!(full.is_match(input), expected.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_counted_repetition() {
+        let cases = [
+            ("(?:c*|a){2,}", "aca", Some((0, 0))),
+            ("b(?:[ab]*?){2,}", "bacb", Some((0, 1))),
+            ("(?:a*?){2,}", "aacba", Some((0, 0))),
+            ("x(?:a|){2,3}y", "xay", Some((0, 3))),
+        ];
+
+        for (pattern, input, expected) in cases {
+            let e = Engine::try_from(pattern).unwrap();
+            let mut lazy = LazyDfa::new(&e).unwrap();
+            assert_eq!(lazy.find(input), expected, "{} on {:?}", pattern, input);
+            let expected = e.find_at(input, 0).map(|m| (m.start, m.end));
+            assert_eq!(lazy.find(input), expected, "{} on {:?}", pattern, input);
+        }
+    }
+
+    #[test]
+    fn test_states_are_cached() {
+        let e = Engine::try_from("[a-z]+[0-9]").unwrap();
+        let mut lazy = LazyDfa::new(&e).unwrap();
+
+        assert!(lazy.is_match("abc1"));
+        let memory = lazy.memory_usage();
+        assert!(lazy.is_match("cba2"));
+        assert_eq!(lazy.memory_usage(), memory);
+    }
+}
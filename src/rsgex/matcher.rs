@@ -1,86 +1,463 @@
-pub trait Matcher {
-    fn matches(&self, s: &[char], i: usize) -> bool;
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use regex_syntax::hir::Look;
+
+use super::{grapheme, haystack::Haystack};
+
+// The escape hatch for transitions the `Transition` enum has no variant for,
+// see `Transition::Custom`.
+pub trait Matcher: Send + Sync {
+    fn matches(&self, s: &Haystack, i: usize) -> bool;
+    // number of bytes consumed when the transition is taken
+    fn width(&self, s: &Haystack, i: usize) -> usize {
+        s.next(i) - i
+    }
     fn is_epsilon(&self) -> bool;
     fn label(&self) -> String;
 }
 
-pub struct ClassUnicodeMatcher {
-    pub start: char,
-    pub end: char,
+// A transition of the automaton, stored inline in its state. Built-in
+// transitions are dispatched with a `match` instead of a virtual call.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Transition {
+    Epsilon,
+    Char(char),
+    Range(char, char),
+    // a handful of chars compared directly, used for case-folded literals
+    CharSet(Arc<[char]>),
+    Class(Arc<ClassMatcher>),
+    PerlClass(Arc<PerlClassMatcher>),
+    // classes that only contain ASCII, such as `(?-u)` or POSIX
+    // `[[:alpha:]]` classes, and their negations
+    Ascii { bitmap: u128, negated: bool },
+    Start,
+    End,
+    // `\G`
+    Continuation,
+    Look(LookMatcher),
+    // subroutine call slot for `(?R)` / `(?1)` / `(?&name)`
+    Call(usize),
+    // counter register operation for `{n,m}`
+    Counter(CounterOp),
+    Custom(CustomMatcher),
 }
 
-impl Matcher for ClassUnicodeMatcher {
-    fn matches(&self, s: &[char], i: usize) -> bool {
-        let c = s[i];
-        c >= self.start && c <= self.end
+impl Transition {
+    pub fn matches(&self, s: &Haystack, i: usize) -> bool {
+        match self {
+            Transition::Epsilon | Transition::Call(_) | Transition::Counter(_) => true,
+            Transition::Start | Transition::Continuation => i == 0,
+            Transition::End => i == s.len(),
+            Transition::Look(look) => look.matches(s, i),
+            Transition::Custom(custom) => custom.0.matches(s, i),
+            _ => s.char_at(i).and_then(|c| self.matches_char(c)) == Some(true),
+        }
     }
 
-    fn is_epsilon(&self) -> bool {
-        false
+    // whether `matches_char` decides the transition
+    pub fn tests_char(&self) -> bool {
+        matches!(
+            self,
+            Transition::Char(_)
+                | Transition::Range(..)
+                | Transition::CharSet(_)
+                | Transition::Class(_)
+                | Transition::PerlClass(_)
+                | Transition::Ascii { .. }
+        )
     }
 
-    fn label(&self) -> String {
-        format!("{}-{}", self.start, self.end)
+    // Whether the transition accepts `c`, `None` unless that only depends
+    // on the char itself.
+    pub fn matches_char(&self, c: char) -> Option<bool> {
+        let matched = match self {
+            Transition::Char(expected) => c == *expected,
+            Transition::Range(start, end) => (*start..=*end).contains(&c),
+            Transition::CharSet(chars) => chars.contains(&c),
+            Transition::Class(class) => class.contains(c),
+            Transition::PerlClass(class) => class.contains(c),
+            Transition::Ascii { bitmap, negated } => {
+                let found = c.is_ascii() && bitmap & (1 << c as u32) != 0;
+                found != *negated
+            }
+            _ => return None,
+        };
+
+        Some(matched)
+    }
+
+    // `search_start` is where the current search began, used by `\G`
+    pub fn matches_at(&self, s: &Haystack, i: usize, search_start: usize) -> bool {
+        match self {
+            Transition::Continuation => i == search_start,
+            _ => self.matches(s, i),
+        }
+    }
+
+    pub fn call_slot(&self) -> Option<usize> {
+        match self {
+            Transition::Call(slot) => Some(*slot),
+            _ => None,
+        }
+    }
+
+    pub fn counter_op(&self) -> Option<CounterOp> {
+        match self {
+            Transition::Counter(op) => Some(*op),
+            _ => None,
+        }
+    }
+
+    // number of bytes consumed when the transition is taken
+    pub fn width(&self, s: &Haystack, i: usize) -> usize {
+        match self {
+            Transition::Custom(custom) => custom.0.width(s, i),
+            _ => s.next(i) - i,
+        }
+    }
+
+    // how the transition is determinized, `None` when the DFA cannot
+    // represent it
+    pub fn dfa_edge(&self) -> Option<DfaEdge> {
+        match self {
+            Transition::Epsilon => Some(DfaEdge::Epsilon),
+            Transition::Start => Some(DfaEdge::Start),
+            Transition::End => Some(DfaEdge::End),
+            Transition::Char(c) => Some(DfaEdge::Chars(vec![(*c, *c)])),
+            Transition::Range(start, end) => Some(DfaEdge::Chars(vec![(*start, *end)])),
+            Transition::CharSet(chars) => {
+                Some(DfaEdge::Chars(chars.iter().map(|&c| (c, c)).collect()))
+            }
+            Transition::Class(class) => Some(DfaEdge::Chars(class.ranges.clone())),
+            Transition::PerlClass(class) => Some(DfaEdge::Chars(class.table.ranges.clone())),
+            Transition::Ascii { bitmap, .. } => {
+                let ranges = (0..128u8)
+                    .filter(|&b| bitmap & (1 << b) != 0)
+                    .map(|b| (b as char, b as char))
+                    .collect();
+                Some(DfaEdge::Chars(ranges))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_epsilon(&self) -> bool {
+        match self {
+            Transition::Char(_)
+            | Transition::Range(..)
+            | Transition::CharSet(_)
+            | Transition::Class(_)
+            | Transition::PerlClass(_)
+            | Transition::Ascii { .. } => false,
+            Transition::Custom(custom) => custom.0.is_epsilon(),
+            _ => true,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Transition::Epsilon => "ε".to_string(),
+            Transition::Char(c) => c.to_string(),
+            Transition::Range(start, end) => {
+                format!("[{}-{}]", start.escape_debug(), end.escape_debug())
+            }
+            Transition::CharSet(chars) => {
+                let chars: Vec<String> = chars.iter().map(|c| c.to_string()).collect();
+                format!("{{{}}}", chars.join(","))
+            }
+            Transition::Class(class) => class.label(),
+            Transition::PerlClass(class) => class.label(),
+            Transition::Ascii { bitmap, negated } => {
+                let negation = if *negated { "^" } else { "" };
+                format!("ascii({}{:#x})", negation, bitmap)
+            }
+            Transition::Start => "^".to_string(),
+            Transition::End => "$".to_string(),
+            Transition::Continuation => "\\G".to_string(),
+            Transition::Look(look) => format!("{:?}", look.look),
+            Transition::Call(slot) => format!("call({})", slot),
+            Transition::Counter(op) => match op {
+                CounterOp::Reset(counter) => format!("c{}:=0", counter),
+                CounterOp::Below(counter, bound) => format!("c{}<{}", counter, bound),
+                CounterOp::AtLeast(counter, bound) => format!("c{}>={}", counter, bound),
+                CounterOp::Increment(counter, _) => format!("c{}++", counter),
+            },
+            Transition::Custom(custom) => custom.0.label(),
+        }
     }
 }
 
-pub struct CharacterMatcher {
-    pub c: char,
+// Custom matchers only equal themselves.
+#[derive(Clone)]
+pub struct CustomMatcher(pub Arc<dyn Matcher>);
+
+impl PartialEq for CustomMatcher {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
-impl Matcher for CharacterMatcher {
-    fn matches(&self, s: &[char], i: usize) -> bool {
-        let c = s[i];
-        self.c == c
+
+impl Eq for CustomMatcher {}
+
+impl fmt::Debug for CustomMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CustomMatcher({})", self.0.label())
     }
+}
 
-    fn is_epsilon(&self) -> bool {
-        false
+impl Hash for CustomMatcher {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const ()).hash(state);
+    }
+}
+
+pub enum DfaEdge {
+    Epsilon,
+    Start,
+    End,
+    // consumes one char; the ends of these ranges split the alphabet into
+    // chars the matcher cannot tell apart
+    Chars(Vec<(char, char)>),
+}
+
+// Matches a sorted, non-overlapping interval set with a binary search, so a
+// class such as `\p{L}` compiles into a single transition. Negated classes
+// keep the complement of their ranges when that is the smaller set.
+#[derive(PartialEq, Eq, Hash)]
+pub struct ClassMatcher {
+    pub ranges: Vec<(char, char)>,
+    pub negated: bool,
+}
+
+impl ClassMatcher {
+    pub fn contains(&self, c: char) -> bool {
+        let found = self
+            .ranges
+            .binary_search_by(|&(start, end)| {
+                if end < c {
+                    Ordering::Less
+                } else if start > c {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .is_ok();
+
+        found != self.negated
     }
 
     fn label(&self) -> String {
-        self.c.to_string()
+        let negation = if self.negated { "^" } else { "" };
+        match (self.ranges.first(), self.ranges.last()) {
+            (Some(first), Some(last)) => format!(
+                "[{}{}-{}..{} ranges]",
+                negation,
+                first.0.escape_debug(),
+                last.1.escape_debug(),
+                self.ranges.len()
+            ),
+            _ => format!("[{}]", negation),
+        }
     }
 }
 
-pub struct EpsilonMatcher {}
-impl Matcher for EpsilonMatcher {
-    fn matches(&self, _s: &[char], _i: usize) -> bool {
-        true
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PerlClass {
+    Digit,
+    Word,
+    Space,
+}
 
-    fn is_epsilon(&self) -> bool {
-        true
+// `\d` / `\w` / `\s` and their negations: ASCII is answered directly and only
+// other chars fall back to the Unicode table.
+#[derive(PartialEq, Eq, Hash)]
+pub struct PerlClassMatcher {
+    pub class: PerlClass,
+    pub negated: bool,
+    pub table: ClassMatcher,
+}
+
+impl PerlClassMatcher {
+    pub fn contains(&self, c: char) -> bool {
+        let is_member = if c.is_ascii() {
+            match self.class {
+                PerlClass::Digit => c.is_ascii_digit(),
+                PerlClass::Word => c.is_ascii_alphanumeric() || c == '_',
+                PerlClass::Space => c.is_ascii_whitespace() || c == '\x0B',
+            }
+        } else {
+            self.table.contains(c)
+        };
+
+        is_member != self.negated
     }
 
     fn label(&self) -> String {
-        "ε".to_string()
+        let label = match self.class {
+            PerlClass::Digit => "\\d",
+            PerlClass::Word => "\\w",
+            PerlClass::Space => "\\s",
+        };
+        if self.negated {
+            label.to_uppercase()
+        } else {
+            label.to_string()
+        }
     }
 }
 
-pub struct StartOfInputMatcher {}
-impl Matcher for StartOfInputMatcher {
-    fn matches(&self, _s: &[char], i: usize) -> bool {
-        i == 0
+// Multi-line, CRLF and word boundary assertions, as resolved by regex-syntax
+// from the `m`, `R` and `u` flags in effect where they appear.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LookMatcher {
+    pub look: Look,
+}
+impl LookMatcher {
+    fn is_word(&self, c: char) -> bool {
+        match self.look {
+            Look::WordAscii
+            | Look::WordAsciiNegate
+            | Look::WordStartAscii
+            | Look::WordEndAscii
+            | Look::WordStartHalfAscii
+            | Look::WordEndHalfAscii => c.is_ascii() && regex_syntax::is_word_byte(c as u8),
+            _ => regex_syntax::is_word_character(c),
+        }
     }
 
-    fn is_epsilon(&self) -> bool {
-        true
+    fn matches(&self, s: &Haystack, i: usize) -> bool {
+        let before = s.char_before(i);
+        let after = s.char_at(i);
+        let word_before = before.is_some_and(|c| self.is_word(c));
+        let word_after = after.is_some_and(|c| self.is_word(c));
+
+        match self.look {
+            Look::Start => before.is_none(),
+            Look::End => after.is_none(),
+            Look::StartLF => before.is_none_or(|c| c == '\n'),
+            Look::EndLF => after.is_none_or(|c| c == '\n'),
+            Look::StartCRLF => {
+                before.is_none_or(|c| c == '\n' || (c == '\r' && after != Some('\n')))
+            }
+            Look::EndCRLF => after.is_none_or(|c| c == '\r' || (c == '\n' && before != Some('\r'))),
+            Look::WordAscii | Look::WordUnicode => word_before != word_after,
+            Look::WordAsciiNegate | Look::WordUnicodeNegate => word_before == word_after,
+            Look::WordStartAscii | Look::WordStartUnicode => !word_before && word_after,
+            Look::WordEndAscii | Look::WordEndUnicode => word_before && !word_after,
+            Look::WordStartHalfAscii | Look::WordStartHalfUnicode => !word_before,
+            Look::WordEndHalfAscii | Look::WordEndHalfUnicode => !word_after,
+        }
+    }
+}
+
+impl Hash for LookMatcher {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.look.as_repr().hash(state);
+    }
+}
+
+// A chain of literal char transitions leading to `to`, compared against the
+// haystack at once, see `NFAutomata::build_literal_runs`.
+#[derive(Clone)]
+pub struct SeqMatcher {
+    pub chars: Arc<str>,
+    pub to: usize,
+}
+
+impl SeqMatcher {
+    // the position after the chars when they occur at `i`
+    pub fn matches(&self, s: &Haystack, i: usize) -> Option<usize> {
+        match s {
+            Haystack::Str(s) => s
+                .get(i..)?
+                .starts_with(&*self.chars)
+                .then(|| i + self.chars.len()),
+            Haystack::Bytes(_) | Haystack::Segments(_) => {
+                let mut end = i;
+                for c in self.chars.chars() {
+                    if s.char_at(end) != Some(c) {
+                        return None;
+                    }
+                    end = s.next(end);
+                }
+                Some(end)
+            }
+        }
     }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CounterOp {
+    Reset(usize),
+    // passes while the counter is below the bound
+    Below(usize, u32),
+    // passes once the counter reached the bound, clearing it as the
+    // repetition is left, so states past it compare equal however many
+    // iterations it took
+    AtLeast(usize, u32),
+    // increments the counter, saturating at the bound
+    Increment(usize, u32),
+}
+
+impl CounterOp {
+    // the counters after taking the transition, if it can be taken
+    pub fn apply(&self, counters: &[u32]) -> Option<Vec<u32>> {
+        let mut counters = counters.to_vec();
+        match *self {
+            CounterOp::Reset(counter) => counters[counter] = 0,
+            CounterOp::Below(counter, bound) if counters[counter] >= bound => return None,
+            CounterOp::AtLeast(counter, bound) if counters[counter] < bound => return None,
+            CounterOp::AtLeast(counter, _) => counters[counter] = 0,
+            CounterOp::Increment(counter, bound) => {
+                counters[counter] = (counters[counter] + 1).min(bound)
+            }
+            _ => (),
+        }
+
+        Some(counters)
+    }
+}
 
+// Consumes a char the closure accepts, see
+// `NFAutomata::add_predicate_transition`.
+pub struct PredicateMatcher {
+    pub label: String,
+    pub test: Box<dyn Fn(char) -> bool + Send + Sync>,
+}
+impl Matcher for PredicateMatcher {
+    fn matches(&self, s: &Haystack, i: usize) -> bool {
+        s.char_at(i).is_some_and(|c| (self.test)(c))
+    }
+    fn is_epsilon(&self) -> bool {
+        false
+    }
     fn label(&self) -> String {
-        "^".to_string()
+        self.label.clone()
     }
 }
 
-pub struct EndOfInputMatcher {}
-impl Matcher for EndOfInputMatcher {
-    fn matches(&self, s: &[char], i: usize) -> bool {
-        i == s.len()
+// Grapheme mode: a class step consumes a whole extended grapheme cluster,
+// matching on the cluster's first char.
+pub struct GraphemeMatcher {
+    pub inner: Transition,
+}
+impl Matcher for GraphemeMatcher {
+    fn matches(&self, s: &Haystack, i: usize) -> bool {
+        self.inner.matches(s, i)
+    }
+    fn width(&self, s: &Haystack, i: usize) -> usize {
+        grapheme::cluster_len(s, i)
     }
     fn is_epsilon(&self) -> bool {
-        true
+        false
     }
     fn label(&self) -> String {
-        "$".to_string()
+        format!("grapheme({})", self.inner.label())
     }
 }
@@ -1,4 +1,47 @@
+mod aho_corasick;
+mod alphabet;
+pub mod analysis;
+pub mod bit_nfa;
+pub mod builder;
+mod byte_set;
+pub mod bytes;
+pub mod cache;
+pub mod cancel;
+pub mod compile_cache;
+pub mod derivative;
+pub mod dfa;
 pub mod engine;
+pub mod error;
+pub mod explain;
+#[cfg(feature = "memmap2")]
+pub mod file;
+mod first_chars;
+mod grapheme;
+mod haystack;
+mod instrument;
+mod interchange;
+mod intern;
+pub mod introspect;
+mod json;
+pub mod language;
+pub mod lazy_dfa;
 mod matcher;
 mod nfa;
+mod normalize;
+pub mod onepass;
+pub mod online;
+#[cfg(feature = "rayon")]
+mod parallel;
 mod parser;
+pub mod pattern;
+mod pikevm;
+mod prefilter;
+pub mod search;
+pub mod segments;
+pub mod shift_or;
+pub mod stream;
+mod subtrees;
+pub mod tagged_dfa;
+pub mod token_nfa;
+pub mod trace;
+mod visualize;
@@ -1,23 +1,69 @@
 use std::{
     collections::{HashMap, VecDeque},
-    rc::Rc,
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use super::matcher::{CharacterMatcher, EpsilonMatcher, Matcher};
+use super::{
+    cache::Cache,
+    cancel::CancelToken,
+    dfa,
+    error::Error,
+    haystack::Haystack,
+    matcher::{CounterOp, CustomMatcher, DfaEdge, PredicateMatcher, SeqMatcher, Transition},
+    trace::{ExecObserver, StepEvent},
+};
+
+pub const DEFAULT_RECURSION_LIMIT: usize = 64;
+
+const CLOCK_INTERVAL: usize = 1024;
+
+// char tests a state needs before it gets an ASCII table
+const ASCII_TABLE_MIN_TRANSITIONS: usize = 4;
+
+// char tests a state needs before it gets a table for other chars too
+const CHAR_TABLE_MIN_TRANSITIONS: usize = 16;
+
+// end offset and captured groups of a match
+pub type Found = (usize, HashMap<String, String>);
 
 #[derive(Clone)]
 pub struct NFAutomata {
-    pub states: Vec<State>,
-    pub initial: usize,
-    pub ending: Vec<usize>,
+    pub(crate) states: Vec<State>,
+    pub(crate) initial: usize,
+    pub(crate) ending: Vec<usize>,
+    // call slot -> (entry state, exit states) of the called sub-automaton
+    pub(crate) subroutines: HashMap<usize, (usize, Vec<usize>)>,
+    pub recursion_limit: usize,
+    // maximum frames popped by one search, see `Budget`
+    pub step_limit: Option<usize>,
+    // wall-clock time one search may take, see `Budget`
+    pub timeout: Option<Duration>,
+    // number of counter registers used by `{n,m}` repetitions
+    pub(crate) counters: usize,
 }
 
-// left: usize, right: Option<usize>, group_name: Rc<str>
-#[derive(Debug)]
-pub struct CaptureGroupRange(usize, Option<usize>, Option<Rc<str>>);
+// open_left: Option<usize>, last_span: Option<(usize, usize)>, group_name: Arc<str>
+// A group start only opens an attempt; the span is committed when the group
+// ends, so a start marker on a state shared with other branches never
+// discards an earlier completed span.
+#[derive(Debug, Clone)]
+pub struct CaptureGroupRange(Option<usize>, Option<(usize, usize)>, Option<Arc<str>>);
 
-// char_index: usize, current_state_index: usize, epsilon_mem: Vec<usize>, group_flag: u64
-struct StackFrame(usize, usize, Vec<usize>, u64);
+// char_index: usize, current_state_index: usize, epsilon_mem: Vec<usize>
+// (see `entered`), groups: Vec<Option<CaptureGroupRange>> indexed by group,
+// call_stack: Vec<(call_slot, return_state, caller_counters)>,
+// counters: Vec<u32>
+pub(crate) struct StackFrame(
+    usize,
+    usize,
+    Vec<usize>,
+    Vec<Option<CaptureGroupRange>>,
+    Vec<(usize, usize, Vec<u32>)>,
+    Vec<u32>,
+);
 
 impl NFAutomata {
     pub fn new() -> Self {
@@ -25,88 +71,395 @@ impl NFAutomata {
             states: vec![],
             initial: 0,
             ending: vec![],
+            subroutines: HashMap::new(),
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            step_limit: None,
+            timeout: None,
+            counters: 0,
         }
     }
 
     pub fn compute(&self, input: &str) -> Option<HashMap<String, String>> {
-        let mut stack: Vec<StackFrame> = vec![StackFrame(0, self.initial, vec![], 0)];
-        let input_chars: Vec<char> = input.chars().collect();
-        let mut group_map: HashMap<usize, CaptureGroupRange> = HashMap::new();
-        let mut captured_group_flag: u64 = 0;
+        self.search(&Haystack::Str(input), 0, 0)
+            .map(|(_, captured)| captured)
+    }
+
+    // Runs the automaton from byte offset `at`, returning the offset where the
+    // accepting state was reached along with the captured groups.
+    // `search_start` is the position the caller began searching from, which
+    // `\G` asserts against.
+    pub fn search(&self, haystack: &Haystack, at: usize, search_start: usize) -> Option<Found> {
+        // an unlimited budget never runs out
+        let mut cache = Cache::new();
+        let end = self
+            .try_search(
+                haystack,
+                at,
+                search_start,
+                &mut cache,
+                false,
+                &mut Budget::default(),
+            )
+            .ok()
+            .flatten()?;
+
+        Some((end, captured_groups(haystack, &cache.groups)))
+    }
+
+    // When `bounded`, every (state, position) pair is explored at most once,
+    // bounding the search by states × positions. A pair that failed once
+    // fails again, so the cache's visited set may be shared by searches from
+    // several `at` with the same `search_start`; the caller sizes it. Only
+    // sound without calls and counters. Every popped frame is charged to
+    // `budget`. Returns where the match ends and leaves its capture slots in
+    // `cache.groups`, so that a search only after a match allocates nothing
+    // once the cache has grown.
+    pub fn try_search(
+        &self,
+        haystack: &Haystack,
+        at: usize,
+        search_start: usize,
+        cache: &mut Cache,
+        bounded: bool,
+        budget: &mut Budget,
+    ) -> Result<Option<usize>, Error> {
+        self.backtrack(
+            haystack,
+            at,
+            search_start,
+            cache,
+            bounded,
+            budget,
+            |_, _| true,
+            &mut |_| {},
+        )
+    }
 
-        while let Some(StackFrame(i, current_state_index, epsilon_mem, mut group_flag)) =
-            stack.pop()
+    // `try_search` unbounded, reporting each step to `observer`.
+    pub fn try_search_observed(
+        &self,
+        haystack: &Haystack,
+        at: usize,
+        search_start: usize,
+        cache: &mut Cache,
+        budget: &mut Budget,
+        observer: &mut impl ExecObserver,
+    ) -> Result<Option<usize>, Error> {
+        let accept = |_, _: &[_]| true;
+        self.backtrack(
+            haystack,
+            at,
+            search_start,
+            cache,
+            false,
+            budget,
+            accept,
+            observer,
+        )
+    }
+
+    // Every distinct way the automaton matches all of `haystack`, as the
+    // groups each path captures, in the order the backtracker finds them.
+    // Paths that capture the same text are reported once.
+    pub fn derivations(
+        &self,
+        haystack: &Haystack,
+        budget: &mut Budget,
+    ) -> Result<Vec<HashMap<String, String>>, Error> {
+        let mut found: Vec<HashMap<String, String>> = vec![];
+        self.backtrack(
+            haystack,
+            0,
+            0,
+            &mut Cache::new(),
+            false,
+            budget,
+            |end, groups| {
+                if end == haystack.len() {
+                    let captured = captured_groups(haystack, groups);
+                    if !found.contains(&captured) {
+                        found.push(captured);
+                    }
+                }
+                false
+            },
+            &mut |_| {},
+        )?;
+        Ok(found)
+    }
+
+    // The search behind `try_search`. Each time a path reaches an ending
+    // state, `accept` is given where it ends and what it captured, and the
+    // search stops there if it returns true, or backtracks for more paths.
+    // `observer` is told of every step, see `StepEvent`.
+    #[allow(clippy::too_many_arguments)]
+    fn backtrack(
+        &self,
+        haystack: &Haystack,
+        at: usize,
+        search_start: usize,
+        cache: &mut Cache,
+        bounded: bool,
+        budget: &mut Budget,
+        mut accept: impl FnMut(usize, &[Option<CaptureGroupRange>]) -> bool,
+        observer: &mut impl ExecObserver,
+    ) -> Result<Option<usize>, Error> {
+        let Cache {
+            stack,
+            visited,
+            groups: found,
+            spare_mem,
+            spare_groups,
+            passed,
+        } = cache;
+        // frames left over by a search that returned early
+        for StackFrame(_, _, mem, groups, ..) in stack.drain(..) {
+            spare_mem.push(mem);
+            spare_groups.push(groups);
+        }
+        let counters = vec![0; self.counters];
+        stack.push(StackFrame(
+            at,
+            self.initial,
+            entering(spare_mem, self.initial, &counters),
+            reuse(spare_groups, &[]),
+            vec![],
+            counters,
+        ));
+
+        while let Some(StackFrame(
+            i,
+            current_state_index,
+            epsilon_mem,
+            mut groups,
+            calls,
+            counters,
+        )) = stack.pop()
         {
+            budget.step()?;
+            let state = current_state_index;
+            observer.on_step(StepEvent::Enter { state, at: i });
+            if bounded && !visited.insert(current_state_index, i) {
+                observer.on_step(StepEvent::Backtrack { state, at: i });
+                spare_mem.push(epsilon_mem);
+                spare_groups.push(groups);
+                continue;
+            }
             let current_state = self.states.get(current_state_index).unwrap();
+            // groups matched inside a subroutine call are not captured
+            let in_call = !calls.is_empty();
 
-            current_state
-                .start_group
-                .iter()
-                .for_each(|(group_index, name)| {
-                    group_flag |= 1 << group_index;
-                    let key = *group_index as usize;
-                    group_map
-                        .entry(key)
-                        .or_insert(CaptureGroupRange(i, None, name.clone()));
-                });
+            // each frame carries its own capture slots, so a group reopened
+            // by a later iteration or abandoned by backtracking never leaks
+            // into another path
+            if !in_call {
+                current_state.mark_groups(&mut groups, i);
+                for (group, _) in current_state.start_group.iter() {
+                    observer.on_step(StepEvent::GroupOpen {
+                        group: *group,
+                        at: i,
+                    });
+                }
+                for (group, _) in current_state.end_group.iter() {
+                    observer.on_step(StepEvent::GroupClose {
+                        group: *group,
+                        at: i,
+                    });
+                }
+            }
+            let pushed = stack.len();
+
+            // the caller gets its counters back, whatever the subroutine
+            // did with the registers
+            if let Some((slot, return_state, caller_counters)) = calls.last()
+                && let Some((_, exits)) = self.subroutines.get(slot)
+                && exits.contains(&current_state_index)
+                && !entered(&epsilon_mem, *return_state, caller_counters)
+            {
+                let mut mem = reuse(spare_mem, &epsilon_mem);
+                enter(&mut mem, *return_state, caller_counters);
+                let mut returned = calls.clone();
+                returned.pop();
+                stack.push(StackFrame(
+                    i,
+                    *return_state,
+                    mem,
+                    reuse(spare_groups, &groups),
+                    returned,
+                    caller_counters.clone(),
+                ));
+            }
 
-            current_state.end_group.iter().for_each(|(group_index, _)| {
-                if group_flag & 1 << group_index != 0 {
-                    let key = *group_index as usize;
-                    let capture_result = group_map.get_mut(&key).unwrap();
-                    capture_result.1 = Some(i);
+            if current_state.is_ending && !in_call && accept(i, &groups) {
+                observer.on_step(StepEvent::Match { at: i });
+                spare_mem.push(epsilon_mem);
+                spare_groups.push(std::mem::replace(found, groups));
+                return Ok(Some(i));
+            }
 
-                    group_flag &= !(1 << group_index);
-                    captured_group_flag &= 1 << group_index;
+            // the state's only transition starts the run, so nothing else
+            // is worth trying
+            if let Some(seq) = &current_state.seq {
+                let end = seq.matches(haystack, i);
+                observer.on_step(StepEvent::Attempt {
+                    state,
+                    transition: 0,
+                    to: seq.to,
+                    at: i,
+                    passed: end.is_some(),
+                });
+                if let Some(end) = end {
+                    stack.push(StackFrame(
+                        end,
+                        seq.to,
+                        entering(spare_mem, seq.to, &counters),
+                        reuse(spare_groups, &groups),
+                        calls.clone(),
+                        counters.clone(),
+                    ));
                 }
-            });
+                if stack.len() == pushed {
+                    observer.on_step(StepEvent::Backtrack { state, at: i });
+                }
+                spare_mem.push(epsilon_mem);
+                spare_groups.push(groups);
+                continue;
+            }
 
-            if current_state.is_ending {
-                // 创建一个新的HashMap来存储捕获组的字符串结果
-                let mut group_captured: HashMap<String, String> = HashMap::new();
-
-                // 遍历所有捕获组，提取对应的字符串
-                for (group_index, CaptureGroupRange(left, right_opt, name_opt)) in &group_map {
-                    if let Some(right) = right_opt {
-                        // 只处理有完整范围的捕获组
-                        let captured_text: String =
-                            input.chars().skip(*left).take(right - left).collect();
-                        if let Some(name) = name_opt {
-                            group_captured.insert(name.to_string(), captured_text);
-                        } else {
-                            group_captured.insert(group_index.to_string(), captured_text);
-                        }
-                    }
+            // with a table only the transitions listed for the char are
+            // tried, and the char tests among them already passed
+            let listed = haystack.char_at(i).and_then(|c| current_state.listed(c));
+            let mut all = current_state.matchers.iter().enumerate();
+            let mut from_table;
+            let candidates: &mut dyn Iterator<Item = (usize, &(Transition, usize))> = match listed {
+                Some(listed) => {
+                    from_table = listed.iter().map(|&t| (t, &current_state.matchers[t]));
+                    &mut from_table
                 }
+                None => &mut all,
+            };
 
-                return Some(group_captured);
+            // tested in order, then stacked so that the first is tried first
+            passed.clear();
+            for (transition, (m, to)) in candidates {
+                let matches = if listed.is_some() && m.tests_char() {
+                    true
+                } else if i < haystack.len() {
+                    m.matches_at(haystack, i, search_start)
+                } else {
+                    m.is_epsilon() && m.matches_at(haystack, i, search_start)
+                };
+                observer.on_step(StepEvent::Attempt {
+                    state,
+                    transition,
+                    to: *to,
+                    at: i,
+                    passed: matches,
+                });
+                if matches {
+                    passed.push(transition);
+                }
             }
 
-            current_state
-                .matchers
+            passed
                 .iter()
-                .filter(|(m, _)| {
-                    if i < input_chars.len() {
-                        m.matches(&input_chars, i)
-                    } else {
-                        m.is_epsilon()
-                    }
-                })
                 .rev()
+                .map(|&transition| &current_state.matchers[transition])
                 .for_each(|(matcher, to_state_name)| {
-                    if matcher.is_epsilon() {
-                        if epsilon_mem.iter().all(|name| *name != *to_state_name) {
-                            let mut mem = epsilon_mem.clone();
-                            mem.push(*to_state_name);
-                            stack.push(StackFrame(i, *to_state_name, mem, group_flag));
+                    if let Some(slot) = matcher.call_slot() {
+                        // a call into nothing never matches
+                        let Some(&(entry, _)) = self.subroutines.get(&slot) else {
+                            return;
+                        };
+                        if calls.len() < self.recursion_limit
+                            && !entered(&epsilon_mem, entry, &counters)
+                        {
+                            let mut mem = reuse(spare_mem, &epsilon_mem);
+                            enter(&mut mem, entry, &counters);
+                            let mut called = calls.clone();
+                            called.push((slot, *to_state_name, counters.clone()));
+                            stack.push(StackFrame(
+                                i,
+                                entry,
+                                mem,
+                                reuse(spare_groups, &groups),
+                                called,
+                                counters.clone(),
+                            ));
+                        }
+                    } else if let Some(op) = matcher.counter_op() {
+                        let Some(mut next_counters) = op.apply(&counters) else {
+                            return;
+                        };
+                        // an empty iteration moving a counter comes back to
+                        // the repetition head with other counters, which
+                        // is not yet an epsilon cycle
+                        let mut to = *to_state_name;
+                        if entered(&epsilon_mem, to, &next_counters) {
+                            // past the minimum of `{n,}` the counter stays
+                            // put, and like the loop of the unrolled form an
+                            // empty iteration may still leave the head
+                            let CounterOp::Increment(counter, _) = op else {
+                                return;
+                            };
+                            let leave =
+                                self.states[to].matchers.iter().find_map(|(m, end)| {
+                                    match m.counter_op() {
+                                        Some(op @ CounterOp::AtLeast(c, _)) if c == counter => {
+                                            Some((*end, op.apply(&next_counters)?))
+                                        }
+                                        _ => None,
+                                    }
+                                });
+                            match leave {
+                                Some((end, left)) if !entered(&epsilon_mem, end, &left) => {
+                                    (to, next_counters) = (end, left)
+                                }
+                                _ => return,
+                            }
+                        }
+                        let mut mem = reuse(spare_mem, &epsilon_mem);
+                        enter(&mut mem, to, &next_counters);
+                        stack.push(StackFrame(
+                            i,
+                            to,
+                            mem,
+                            reuse(spare_groups, &groups),
+                            calls.clone(),
+                            next_counters,
+                        ));
+                    } else if matcher.is_epsilon() {
+                        if !entered(&epsilon_mem, *to_state_name, &counters) {
+                            let mut mem = reuse(spare_mem, &epsilon_mem);
+                            enter(&mut mem, *to_state_name, &counters);
+                            stack.push(StackFrame(
+                                i,
+                                *to_state_name,
+                                mem,
+                                reuse(spare_groups, &groups),
+                                calls.clone(),
+                                counters.clone(),
+                            ));
                         }
                     } else {
-                        stack.push(StackFrame(i + 1, *to_state_name, vec![], group_flag));
+                        stack.push(StackFrame(
+                            i + matcher.width(haystack, i),
+                            *to_state_name,
+                            entering(spare_mem, *to_state_name, &counters),
+                            reuse(spare_groups, &groups),
+                            calls.clone(),
+                            counters.clone(),
+                        ));
                     }
                 });
+            if stack.len() == pushed {
+                observer.on_step(StepEvent::Backtrack { state, at: i });
+            }
+            spare_mem.push(epsilon_mem);
+            spare_groups.push(groups);
         }
 
-        None
+        Ok(None)
     }
 
     pub fn set_initial(&mut self, initial: usize) {
@@ -135,12 +488,7 @@ impl NFAutomata {
         }
     }
 
-    pub fn add_transition(
-        &mut self,
-        from_state: usize,
-        to_state: usize,
-        transition: Rc<dyn Matcher>,
-    ) {
+    pub fn add_transition(&mut self, from_state: usize, to_state: usize, transition: Transition) {
         let state_value = self.states.get_mut(from_state);
 
         if let Some(from) = state_value {
@@ -152,7 +500,7 @@ impl NFAutomata {
         &mut self,
         from_state: usize,
         to_state: usize,
-        transition: Rc<dyn Matcher>,
+        transition: Transition,
     ) {
         let state_value = self.states.get_mut(from_state);
 
@@ -178,62 +526,511 @@ impl NFAutomata {
     }
 
     pub fn add_char_transition(&mut self, from: usize, to: usize, c: char) {
-        self.add_transition(from, to, Rc::new(CharacterMatcher { c }))
+        self.add_transition(from, to, Transition::Char(c))
     }
 
     pub fn add_epsilon_transition(&mut self, from: usize, to: usize) {
-        self.add_transition(from, to, Rc::new(EpsilonMatcher {}))
+        self.add_transition(from, to, Transition::Epsilon)
+    }
+
+    // Consumes a char `test` accepts, such as `char::is_alphabetic`, without
+    // writing a `Matcher`. `label` names the transition in `debug` output.
+    // The DFA based engines reject it, as they cannot look inside.
+    pub fn add_predicate_transition(
+        &mut self,
+        from: usize,
+        to: usize,
+        label: &str,
+        test: impl Fn(char) -> bool + Send + Sync + 'static,
+    ) {
+        let matcher = PredicateMatcher {
+            label: label.to_string(),
+            test: Box::new(test),
+        };
+        self.add_transition(
+            from,
+            to,
+            Transition::Custom(CustomMatcher(Arc::new(matcher))),
+        )
     }
 
-    pub fn append(&mut self, other_nfa: &NFAutomata, union_state: usize) {
+    // Splices `other_nfa` in with its initial state merged into
+    // `union_state`. The initial state has to come first, so the others move
+    // over in bulk and only their transition targets are shifted. An initial
+    // state the sub-automaton loops back to would take on the transitions of
+    // `union_state` too, and one opening groups would open them where
+    // `union_state` closes them, as between the copies of `(a)+`. Such a
+    // state moves over as well, entered through ε.
+    pub fn append(&mut self, other_nfa: NFAutomata, union_state: usize) {
         if other_nfa.states.len() < 2 {
             return;
         }
+        debug_assert_eq!(other_nfa.initial, 0);
 
-        let origin_len = self.states.len();
-
-        self.fill_state(other_nfa.states.len() - 1);
+        self.counters = self.counters.max(other_nfa.counters);
         self.remove_ending(union_state);
+        let looped = other_nfa
+            .states
+            .iter()
+            .any(|state| state.matchers.iter().any(|(_, to)| *to == 0));
+        let reopened = !self.states[union_state].end_group.is_empty()
+            && !other_nfa.states[0].start_group.is_empty();
+        if looped || reopened {
+            let offset = self.states.len();
+            self.states
+                .extend(other_nfa.states.into_iter().map(|mut state| {
+                    state.matchers.iter_mut().for_each(|(_, to)| *to += offset);
+                    state.is_initial = false;
+                    state.is_ending = false;
+                    state
+                }));
+            self.add_epsilon_transition(union_state, offset);
+            other_nfa.ending.iter().for_each(|i| {
+                self.add_ending(i + offset);
+            });
+            return;
+        }
+
+        let offset = self.states.len() - 1;
+        let map = |i: usize| if i == 0 { union_state } else { i + offset };
+        let shift = |state: &mut State| {
+            state.matchers.iter_mut().for_each(|(_, to)| *to = map(*to));
+        };
+
+        let mut states = other_nfa.states.into_iter();
+        let mut initial = states.next().unwrap();
+        shift(&mut initial);
+        let union = &mut self.states[union_state];
+        union.start_group.append(&mut initial.start_group);
+        union.end_group.append(&mut initial.end_group);
+        union.matchers.append(&mut initial.matchers);
+
+        self.states.extend(states.map(|mut state| {
+            shift(&mut state);
+            state.is_ending = false;
+            state
+        }));
         other_nfa.ending.iter().for_each(|i| {
-            self.add_ending(i + origin_len - 1);
+            self.add_ending(map(*i));
         });
+    }
 
-        other_nfa
-            .states
+    // Copies `other_nfa` in as a detached component (no initial or ending
+    // marks), returning the indices of its initial and ending states.
+    pub fn embed(&mut self, other_nfa: &NFAutomata) -> (usize, Vec<usize>) {
+        let offset = self.states.len();
+
+        self.counters = self.counters.max(other_nfa.counters);
+        other_nfa.states.iter().for_each(|state| {
+            let mut state = state.clone();
+            state.is_initial = false;
+            state.is_ending = false;
+            state.matchers.iter_mut().for_each(|(_, to)| *to += offset);
+            self.states.push(state);
+        });
+
+        (
+            other_nfa.initial + offset,
+            other_nfa.ending.iter().map(|i| i + offset).collect(),
+        )
+    }
+
+    // An automaton matching what either automaton matches, trying `self`
+    // first like an alternation would. The groups of `other` other than the
+    // whole match are renumbered to follow those of `self`, and so are its
+    // subroutine slots. The limits of `self` apply.
+    pub fn union(&self, other: &NFAutomata) -> NFAutomata {
+        let mut nfa = self.combined();
+        nfa.fill_state(1);
+        nfa.set_initial(0);
+
+        let (groups, slots) = self.shifts();
+        for (part, group_shift, slot_shift) in [(self, 0, 0), (other, groups, slots)] {
+            let (initial, endings) = nfa.embed_shifted(part, group_shift, slot_shift);
+            nfa.add_epsilon_transition(0, initial);
+            endings
+                .into_iter()
+                .for_each(|ending| nfa.add_ending(ending));
+        }
+
+        nfa.build_ascii_tables();
+        nfa.build_literal_runs();
+        nfa
+    }
+
+    // An automaton matching what `self` matches followed by what `other`
+    // matches. The whole match spans both, and the other groups and the
+    // subroutine slots of `other` are renumbered as for `union`.
+    pub fn concat(&self, other: &NFAutomata) -> NFAutomata {
+        let mut nfa = self.combined();
+
+        let (groups, slots) = self.shifts();
+        let (initial, first_endings) = nfa.embed_shifted(self, 0, 0);
+        let first_states = nfa.states.len();
+        let (second_initial, endings) = nfa.embed_shifted(other, groups, slots);
+        nfa.set_initial(initial);
+        first_endings
+            .into_iter()
+            .for_each(|ending| nfa.add_epsilon_transition(ending, second_initial));
+        endings
+            .into_iter()
+            .for_each(|ending| nfa.add_ending(ending));
+
+        // the whole match opens in `self` and closes in `other`
+        for state in nfa.states[..first_states].iter_mut() {
+            state.end_group.retain(|(index, _)| *index > 0);
+        }
+        for state in nfa.states[first_states..].iter_mut() {
+            state.start_group.retain(|(index, _)| *index > 0);
+        }
+
+        nfa.build_ascii_tables();
+        nfa.build_literal_runs();
+        nfa
+    }
+
+    // an empty automaton with the limits of `self`
+    fn combined(&self) -> NFAutomata {
+        let mut nfa = NFAutomata::new();
+        nfa.recursion_limit = self.recursion_limit;
+        nfa.step_limit = self.step_limit;
+        nfa.timeout = self.timeout;
+        nfa
+    }
+
+    // how much the groups and subroutine slots of an automaton combined
+    // after `self` are shifted
+    fn shifts(&self) -> (u32, usize) {
+        let slots = self.subroutines.keys().map(|slot| slot + 1).max();
+        (self.max_group(), slots.unwrap_or(0))
+    }
+
+    // Embeds `part` with its groups but the whole match and its subroutine
+    // slots shifted, see `embed`.
+    fn embed_shifted(
+        &mut self,
+        part: &NFAutomata,
+        group_shift: u32,
+        slot_shift: usize,
+    ) -> (usize, Vec<usize>) {
+        let offset = self.states.len();
+        let embedded = self.embed(part);
+        for (slot, (entry, exits)) in part.subroutines.iter() {
+            let exits = exits.iter().map(|exit| exit + offset).collect();
+            self.subroutines
+                .insert(slot + slot_shift, (entry + offset, exits));
+        }
+
+        for state in self.states[offset..].iter_mut() {
+            for (index, _) in state.start_group.iter_mut().chain(&mut state.end_group) {
+                if *index > 0 {
+                    *index += group_shift;
+                }
+            }
+            for (matcher, _) in state.matchers.iter_mut() {
+                if let Transition::Call(slot) = matcher {
+                    *slot += slot_shift;
+                }
+            }
+            // refers to states by index, rebuilt by the caller
+            state.seq = None;
+        }
+        embedded
+    }
+
+    // the highest group index marked, 0 being the whole match
+    fn max_group(&self) -> u32 {
+        self.states
             .iter()
+            .flat_map(|state| state.start_group.iter())
+            .map(|(index, _)| *index)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Drops states that are unreachable from the initial state or cannot
+    // reach an ending, renumbering the rest in their original order.
+    // Subroutine entries are always kept.
+    pub fn compact(&mut self) {
+        if self.states.is_empty() {
+            return;
+        }
+        let mut forward: Vec<Vec<usize>> = vec![vec![]; self.states.len()];
+        for (from, state) in self.states.iter().enumerate() {
+            for (matcher, to) in state.matchers.iter() {
+                forward[from].push(*to);
+                if let Some((entry, _)) = matcher.call_slot().and_then(|s| self.subroutines.get(&s))
+                {
+                    forward[from].push(*entry);
+                }
+            }
+        }
+        let mut backward: Vec<Vec<usize>> = vec![vec![]; self.states.len()];
+        for (from, targets) in forward.iter().enumerate() {
+            for &to in targets.iter() {
+                backward[to].push(from);
+            }
+        }
+
+        let reachable = mark_reachable(&forward, [self.initial]);
+        let exits = self
+            .subroutines
+            .values()
+            .flat_map(|(_, exits)| exits.iter().copied());
+        let live = mark_reachable(&backward, self.ending.iter().copied().chain(exits));
+
+        let mut renumbered = vec![None; self.states.len()];
+        let mut kept = 0;
+        for (index, new_index) in renumbered.iter_mut().enumerate() {
+            let is_entry = self.subroutines.values().any(|(entry, _)| *entry == index);
+            if index == self.initial || is_entry || (reachable[index] && live[index]) {
+                *new_index = Some(kept);
+                kept += 1;
+            }
+        }
+
+        let states = std::mem::take(&mut self.states);
+        self.states = states
+            .into_iter()
             .enumerate()
-            .for_each(|(from, state)| {
-                let from_state = if state.is_initial {
-                    union_state
-                } else {
-                    from + origin_len - 1
-                };
+            .filter(|(index, _)| renumbered[*index].is_some())
+            .map(|(_, mut state)| {
+                state.matchers = state
+                    .matchers
+                    .into_iter()
+                    .filter_map(|(matcher, to)| renumbered[to].map(|to| (matcher, to)))
+                    .collect();
+                state
+            })
+            .collect();
+        self.initial = renumbered[self.initial].unwrap();
+        self.ending = self.ending.iter().filter_map(|i| renumbered[*i]).collect();
+        for (entry, exits) in self.subroutines.values_mut() {
+            *entry = renumbered[*entry].unwrap();
+            *exits = exits.iter().filter_map(|i| renumbered[*i]).collect();
+        }
+    }
 
-                if !state.start_group.is_empty() {
-                    state.start_group.iter().for_each(|group| {
-                        self.mark_start_capture_group(from_state, group.0, group.1.clone());
-                    });
+    // Gives states with at least `ASCII_TABLE_MIN_TRANSITIONS` char tests a
+    // table from ASCII chars to the transitions worth trying on them, and
+    // those with `CHAR_TABLE_MIN_TRANSITIONS` a `CharTable` for the others.
+    // Transitions are referred to by index, so this runs last.
+    pub fn build_ascii_tables(&mut self) {
+        for state in self.states.iter_mut() {
+            let tests = state
+                .matchers
+                .iter()
+                .filter(|(t, _)| t.tests_char())
+                .count();
+            state.ascii_table = (tests >= ASCII_TABLE_MIN_TRANSITIONS).then(|| {
+                (0..128u8)
+                    .map(|b| {
+                        (0..state.matchers.len())
+                            .filter(|&t| state.matchers[t].0.matches_char(b as char) != Some(false))
+                            .collect()
+                    })
+                    .collect()
+            });
+            state.char_table = (tests >= CHAR_TABLE_MIN_TRANSITIONS)
+                .then(|| Arc::new(CharTable::new(&state.matchers)));
+        }
+    }
+
+    // Gives the states starting a chain of at least two literal chars the
+    // whole chain as a `SeqMatcher`, which the backtracking search walks with
+    // one comparison instead of a frame per char. Each state inside the chain
+    // must do nothing but lead on to the next char, so it has no other
+    // transition, accepts nothing, opens or closes no group and is no
+    // subroutine exit. The chain refers to states by index, so this runs
+    // last.
+    pub fn build_literal_runs(&mut self) {
+        let exits: Vec<usize> = self
+            .subroutines
+            .values()
+            .flat_map(|(_, exits)| exits.iter().copied())
+            .collect();
+        let only_char = |state: &State| match state.matchers.front() {
+            Some((Transition::Char(c), to)) if state.matchers.len() == 1 => Some((*c, *to)),
+            _ => None,
+        };
+
+        for start in 0..self.states.len() {
+            let Some((first, mut to)) = only_char(&self.states[start]) else {
+                continue;
+            };
+            let mut chars = first.to_string();
+            let mut walked = vec![start];
+            while let Some((c, next)) = only_char(&self.states[to]) {
+                let state = &self.states[to];
+                if state.is_ending
+                    || !state.start_group.is_empty()
+                    || !state.end_group.is_empty()
+                    || exits.contains(&to)
+                    || walked.contains(&to)
+                {
+                    break;
                 }
+                walked.push(to);
+                chars.push(c);
+                to = next;
+            }
 
-                if !state.end_group.is_empty() {
-                    state.end_group.iter().for_each(|group| {
-                        self.mark_end_capture_group(from_state, group.0, group.1.clone());
-                    });
+            if walked.len() >= 2 {
+                self.states[start].seq = Some(SeqMatcher {
+                    chars: Arc::from(chars),
+                    to,
+                });
+            }
+        }
+    }
+
+    // Merges states with the same markers and the same transitions, i.e.
+    // equal matchers leading to the same states, until no two states are
+    // alike. Alternation branches and repeated sub-automata collapse from
+    // their common tails. A second visit to a state without consuming is
+    // dropped, so states that branch over ε transitions stay apart: the
+    // first visit may not have reached every branch yet, which would change
+    // the path that wins.
+    pub fn dedup(&mut self) {
+        loop {
+            let mut protected: Vec<usize> = vec![self.initial];
+            for (entry, exits) in self.subroutines.values() {
+                protected.push(*entry);
+                protected.extend(exits.iter().copied());
+            }
+
+            let mut representatives: HashMap<Signature, usize> = HashMap::new();
+            let mut merged: Vec<Option<usize>> = vec![None; self.states.len()];
+            for (index, state) in self.states.iter().enumerate() {
+                let branches_over_epsilon =
+                    state.matchers.len() > 1 && state.matchers.iter().any(|(m, _)| m.is_epsilon());
+                if protected.contains(&index) || branches_over_epsilon {
+                    continue;
                 }
+                let signature = (
+                    state.is_ending,
+                    state.start_group.clone(),
+                    state.end_group.clone(),
+                    state.matchers.iter().cloned().collect(),
+                );
+                match representatives.get(&signature) {
+                    Some(&representative) => merged[index] = Some(representative),
+                    None => {
+                        representatives.insert(signature, index);
+                    }
+                }
+            }
+            if merged.iter().all(Option::is_none) {
+                return;
+            }
 
-                state.matchers.iter().for_each(|(matcher, to)| {
-                    let to_state = (*to) + origin_len - 1;
+            for state in self.states.iter_mut() {
+                for (_, to) in state.matchers.iter_mut() {
+                    *to = merged[*to].unwrap_or(*to);
+                }
+            }
+            self.ending.retain(|i| merged[*i].is_none());
+            // the merged states are now unreachable
+            self.compact();
+        }
+    }
 
-                    self.add_transition(from_state, to_state, matcher.clone())
-                });
-            });
+    // A hash of the automaton's structure that does not depend on how its
+    // states are numbered, for keying caches of compiled automata. States
+    // are renumbered in the order a breadth-first walk from the initial
+    // state, then from each subroutine entry, reaches them, and unreachable
+    // states do not count, nor do those that only pass on to another state
+    // through ε. Custom transitions only equal themselves. The hash is
+    // stable for a given build of the crate.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut slots: Vec<usize> = self.subroutines.keys().copied().collect();
+        slots.sort_unstable();
+
+        let mut kept: Vec<usize> = vec![self.initial];
+        for (entry, exits) in self.subroutines.values() {
+            kept.push(*entry);
+            kept.extend(exits.iter().copied());
+        }
+        let passes_on = |index: usize| {
+            let state = &self.states[index];
+            match state.matchers.front() {
+                Some((Transition::Epsilon, to))
+                    if state.matchers.len() == 1
+                        && !state.is_ending
+                        && state.start_group.is_empty()
+                        && state.end_group.is_empty()
+                        && !kept.contains(&index) =>
+                {
+                    Some(*to)
+                }
+                _ => None,
+            }
+        };
+        // the first state after a chain of passing states, cycles aside
+        let skip = |mut index: usize| {
+            for _ in 0..self.states.len() {
+                match passes_on(index) {
+                    Some(to) => index = to,
+                    None => break,
+                }
+            }
+            index
+        };
+
+        let mut number: Vec<Option<usize>> = vec![None; self.states.len()];
+        let mut order = vec![];
+        let roots =
+            std::iter::once(self.initial).chain(slots.iter().map(|slot| self.subroutines[slot].0));
+        for root in roots {
+            if number[root].is_some() {
+                continue;
+            }
+            number[root] = Some(order.len());
+            order.push(root);
+            let mut i = order.len() - 1;
+            while i < order.len() {
+                for (_, to) in self.states[order[i]].matchers.iter() {
+                    let to = skip(*to);
+                    if number[to].is_none() {
+                        number[to] = Some(order.len());
+                        order.push(to);
+                    }
+                }
+                i += 1;
+            }
+        }
+
+        let mut hasher = DefaultHasher::new();
+        order.len().hash(&mut hasher);
+        for &index in order.iter() {
+            let state = &self.states[index];
+            state.is_ending.hash(&mut hasher);
+            state.start_group.hash(&mut hasher);
+            state.end_group.hash(&mut hasher);
+            state.matchers.len().hash(&mut hasher);
+            for (matcher, to) in state.matchers.iter() {
+                matcher.hash(&mut hasher);
+                number[skip(*to)].hash(&mut hasher);
+            }
+        }
+        for slot in slots {
+            let (entry, exits) = &self.subroutines[&slot];
+            slot.hash(&mut hasher);
+            number[*entry].hash(&mut hasher);
+            for exit in exits {
+                number[*exit].hash(&mut hasher);
+            }
+        }
+        self.counters.hash(&mut hasher);
+
+        hasher.finish()
     }
 
     pub fn mark_start_capture_group(
         &mut self,
         state_index: usize,
         capture_index: u32,
-        name: Option<Rc<str>>,
+        name: Option<Arc<str>>,
     ) {
         if let Some(state) = self.states.get_mut(state_index) {
             state.start_group.push((capture_index, name.clone()));
@@ -244,14 +1041,24 @@ impl NFAutomata {
         &mut self,
         state_index: usize,
         capture_index: u32,
-        name: Option<Rc<str>>,
+        name: Option<Arc<str>>,
     ) {
         if let Some(state) = self.states.get_mut(state_index) {
             state.end_group.push((capture_index, name.clone()));
         }
     }
 
-    pub fn mark_capture_group(&mut self, index: u32, name: Option<Rc<str>>) {
+    pub fn mark_capture_group(&mut self, index: u32, name: Option<Arc<str>>) {
+        // a loop back into the initial state would reopen the group, so the
+        // group gets an entry state of its own
+        let initial = self.initial;
+        if self
+            .states
+            .iter()
+            .any(|state| state.matchers.iter().any(|(_, to)| *to == initial))
+        {
+            self.prepend_entry();
+        }
         self.mark_start_capture_group(self.initial, index, name.clone());
 
         self.ending.clone().into_iter().for_each(|i| {
@@ -259,78 +1066,134 @@ impl NFAutomata {
         });
     }
 
-    pub fn debug(&self) {
-        println!("NFA Debug Information:");
-        println!("======================");
+    // Drops the markers of the groups `keep` rejects, given their index and
+    // name.
+    pub fn retain_capture_groups(&mut self, keep: impl Fn(u32, Option<&str>) -> bool) {
+        for state in self.states.iter_mut() {
+            for markers in [&mut state.start_group, &mut state.end_group] {
+                markers.retain(|(index, name)| keep(*index, name.as_deref()));
+            }
+        }
+    }
+
+    // Inserts a new initial state 0 with an epsilon transition to the old
+    // one, as `append` expects the initial state to come first.
+    fn prepend_entry(&mut self) {
+        self.states.insert(0, create_state());
+        self.states.iter_mut().for_each(|state| {
+            state.matchers.iter_mut().for_each(|(_, to)| *to += 1);
+        });
+        self.ending.iter_mut().for_each(|i| *i += 1);
+        self.subroutines.values_mut().for_each(|(entry, exits)| {
+            *entry += 1;
+            exits.iter_mut().for_each(|i| *i += 1);
+        });
 
+        let initial = self.initial + 1;
+        self.states[initial].is_initial = false;
+        self.add_epsilon_transition(0, initial);
+        self.set_initial(0);
+    }
+
+    // The automaton as a Graphviz digraph, rendered with e.g.
+    // `dot -Tsvg`. Ending states are double circles, an arrow from a point
+    // marks the initial state, and capture group marks are listed under
+    // the state number.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph nfa {\n    rankdir=LR;\n    node [shape=circle];\n");
+        dot.push_str(&format!(
+            "    start [shape=point];\n    start -> {};\n",
+            self.initial
+        ));
+        for (index, state) in self.states.iter().enumerate() {
+            let mut label = index.to_string();
+            for mark in state.group_marks() {
+                label.push_str(&format!("\n{}", mark));
+            }
+            let shape = match state.is_ending {
+                true => "doublecircle",
+                false => "circle",
+            };
+            dot.push_str(&format!(
+                "    {} [shape={}, label={}];\n",
+                index,
+                shape,
+                dot_string(&label)
+            ));
+        }
         for (index, state) in self.states.iter().enumerate() {
-            let mut state_info = format!("State({})", index);
+            for (matcher, to) in state.matchers.iter() {
+                dot.push_str(&format!(
+                    "    {} -> {} [label={}];\n",
+                    index,
+                    to,
+                    dot_string(&matcher.label())
+                ));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
 
-            // 添加标记
-            let mut markers = Vec::new();
-            if state.is_initial {
-                markers.push("INITIAL");
+    // The automaton as a Mermaid `stateDiagram-v2`, which renders in
+    // Markdown on e.g. GitHub. States are named `s0`, `s1`, ... and
+    // described by their number and capture group marks.
+    pub fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("stateDiagram-v2\n");
+        mermaid.push_str(&format!("    [*] --> s{}\n", self.initial));
+        for (index, state) in self.states.iter().enumerate() {
+            let mut description = vec![index.to_string()];
+            description.extend(state.group_marks());
+            let description = mermaid_text(&description.join(" "));
+            mermaid.push_str(&format!("    s{}: {}\n", index, description));
+        }
+        for (index, state) in self.states.iter().enumerate() {
+            for (matcher, to) in state.matchers.iter() {
+                let label = mermaid_text(&matcher.label());
+                mermaid.push_str(&format!("    s{} --> s{}: {}\n", index, to, label));
             }
             if state.is_ending {
-                markers.push("ENDING");
+                mermaid.push_str(&format!("    s{} --> [*]\n", index));
             }
+        }
+        mermaid
+    }
+}
 
+// One line per state with its markers, capture group marks and
+// transitions in the order they are tried, then the subroutines by call
+// slot, e.g. `State(0) [INITIAL] {START(0)}: --a-> 1`.
+impl fmt::Display for NFAutomata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, state) in self.states.iter().enumerate() {
+            write!(f, "State({})", index)?;
+            let markers: Vec<&str> = [(state.is_initial, "INITIAL"), (state.is_ending, "ENDING")]
+                .into_iter()
+                .filter_map(|(marked, marker)| marked.then_some(marker))
+                .collect();
             if !markers.is_empty() {
-                state_info.push_str(&format!(" [{}]", markers.join(", ")));
-            }
-
-            // 添加capture group信息
-            let mut capture_info = Vec::new();
-
-            if !state.start_group.is_empty() {
-                let start_groups: Vec<String> = state
-                    .start_group
-                    .iter()
-                    .map(|(index, name)| {
-                        if let Some(name) = name {
-                            format!("START({}:{})", index, name)
-                        } else {
-                            format!("START({})", index)
-                        }
-                    })
-                    .collect();
-                capture_info.extend(start_groups);
-            }
-
-            if !state.end_group.is_empty() {
-                let end_groups: Vec<String> = state
-                    .end_group
-                    .iter()
-                    .map(|(index, name)| {
-                        if let Some(name) = name {
-                            format!("END({}:{})", index, name)
-                        } else {
-                            format!("END({})", index)
-                        }
-                    })
-                    .collect();
-                capture_info.extend(end_groups);
+                write!(f, " [{}]", markers.join(", "))?;
             }
-
-            if !capture_info.is_empty() {
-                state_info.push_str(&format!(" {{{}}}", capture_info.join(", ")));
+            let groups = state.group_marks();
+            if !groups.is_empty() {
+                write!(f, " {{{}}}", groups.join(", "))?;
             }
-
-            // 添加转换关系
+            write!(f, ":")?;
             if state.matchers.is_empty() {
-                println!("{}: (no transitions)", state_info);
-            } else {
-                let transitions: Vec<String> = state
-                    .matchers
-                    .iter()
-                    .map(|(matcher, to_state)| format!("--{}-> {}", matcher.label(), to_state))
-                    .collect();
-
-                println!("{}: {}", state_info, transitions.join(" "));
+                write!(f, " (no transitions)")?;
+            }
+            for (matcher, to) in state.matchers.iter() {
+                write!(f, " --{}-> {}", matcher.label(), to)?;
             }
+            writeln!(f)?;
         }
 
-        println!("======================");
+        let mut slots: Vec<_> = self.subroutines.iter().collect();
+        slots.sort_by_key(|(slot, _)| **slot);
+        for (slot, (entry, exits)) in slots {
+            writeln!(f, "Subroutine({}): {} -> {:?}", slot, entry, exits)?;
+        }
+        Ok(())
     }
 }
 
@@ -340,6 +1203,197 @@ impl Default for NFAutomata {
     }
 }
 
+fn mark_reachable(graph: &[Vec<usize>], roots: impl IntoIterator<Item = usize>) -> Vec<bool> {
+    let mut marked = vec![false; graph.len()];
+    let mut stack: Vec<usize> = roots.into_iter().collect();
+
+    while let Some(state) = stack.pop() {
+        if !marked[state] {
+            marked[state] = true;
+            stack.extend(graph[state].iter().copied());
+        }
+    }
+
+    marked
+}
+
+// is_ending, start markers, end markers, (transition, target) pairs
+type Signature = (
+    bool,
+    Vec<(u32, Option<Arc<str>>)>,
+    Vec<(u32, Option<Arc<str>>)>,
+    Vec<(Transition, usize)>,
+);
+
+// Limits shared by the attempts of one search at every start position.
+#[derive(Default)]
+pub struct Budget {
+    steps: Option<usize>,
+    deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
+    ticks: usize,
+}
+
+impl Budget {
+    pub fn new(
+        step_limit: Option<usize>,
+        timeout: Option<Duration>,
+        cancel: Option<CancelToken>,
+    ) -> Self {
+        Self {
+            steps: step_limit,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            cancel,
+            ticks: 0,
+        }
+    }
+
+    // charges one backtracking step
+    pub fn step(&mut self) -> Result<(), Error> {
+        match &mut self.steps {
+            Some(0) => return Err(Error::StepLimitExceeded),
+            Some(steps) => *steps -= 1,
+            None => (),
+        }
+        self.tick()
+    }
+
+    // reading the clock or the cancel flag is comparatively slow, so only
+    // every `CLOCK_INTERVAL` ticks look at them
+    pub fn tick(&mut self) -> Result<(), Error> {
+        if self.deadline.is_none() && self.cancel.is_none() {
+            return Ok(());
+        }
+        self.ticks += 1;
+        if !self.ticks.is_multiple_of(CLOCK_INTERVAL) {
+            return Ok(());
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Error::Timeout);
+        }
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+// One bit per (state, position) pair.
+#[derive(Default)]
+pub struct Visited {
+    bits: Vec<u64>,
+    positions: usize,
+}
+
+impl Visited {
+    // clears the set and sizes it for a search, keeping the allocation
+    pub fn reset(&mut self, states: usize, positions: usize) {
+        self.bits.clear();
+        self.bits.resize((states * positions).div_ceil(64), 0);
+        self.positions = positions;
+    }
+
+    // false when the pair was already visited
+    pub fn insert(&mut self, state: usize, i: usize) -> bool {
+        let bit = state * self.positions + i;
+        let (word, mask) = (bit / 64, 1 << (bit % 64));
+        let fresh = self.bits[word] & mask == 0;
+        self.bits[word] |= mask;
+        fresh
+    }
+}
+
+// Whether the path entered `state` with `counters` since it last consumed
+// a char. Each entry of `mem` is a state followed by the counters it was
+// entered with, so that counted repetitions of an empty body still reach
+// their bounds, while coming back with the same counters is a cycle.
+fn entered(mem: &[usize], state: usize, counters: &[u32]) -> bool {
+    mem.chunks(1 + counters.len()).any(|entry| {
+        entry[0] == state
+            && entry[1..]
+                .iter()
+                .zip(counters)
+                .all(|(&a, &b)| a == b as usize)
+    })
+}
+
+fn enter(mem: &mut Vec<usize>, state: usize, counters: &[u32]) {
+    mem.push(state);
+    mem.extend(counters.iter().map(|&c| c as usize));
+}
+
+// The memory of a path that just consumed a char into `state`, which the
+// path may not enter again through ε at this position: a loop going round
+// with an empty iteration would move the spans of the groups inside it off
+// the last real one, as the skip edge of `(b)*` does.
+fn entering(spare: &mut Vec<Vec<usize>>, state: usize, counters: &[u32]) -> Vec<usize> {
+    let mut mem = reuse(spare, &[]);
+    enter(&mut mem, state, counters);
+    mem
+}
+
+// A buffer of `spare` holding a copy of `from`, or a new one when there is
+// none left.
+fn reuse<T: Clone>(spare: &mut Vec<Vec<T>>, from: &[T]) -> Vec<T> {
+    let mut buffer = spare.pop().unwrap_or_default();
+    buffer.clear();
+    buffer.extend_from_slice(from);
+    buffer
+}
+
+// Reads the committed spans out of the capture slots.
+pub fn captured_groups(
+    haystack: &Haystack,
+    groups: &[Option<CaptureGroupRange>],
+) -> HashMap<String, String> {
+    // 创建一个新的HashMap来存储捕获组的字符串结果
+    let mut group_captured: HashMap<String, String> = HashMap::new();
+
+    // 遍历所有捕获组，提取对应的字符串
+    for (group_index, group) in groups.iter().enumerate() {
+        let Some(CaptureGroupRange(_, span, name_opt)) = group else {
+            continue;
+        };
+        if let Some((left, right)) = span {
+            // 只处理有完整范围的捕获组
+            let captured_text = haystack.text(*left, *right);
+            if let Some(name) = name_opt {
+                // the first participating group wins a shared name
+                group_captured
+                    .entry(name.to_string())
+                    .or_insert(captured_text);
+            } else {
+                group_captured.insert(group_index.to_string(), captured_text);
+            }
+        }
+    }
+
+    group_captured
+}
+
+// A double-quoted DOT string, with line breaks escaped as `\n`.
+fn dot_string(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+// Mermaid text with the chars that end or break a statement written as
+// entity codes.
+fn mermaid_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '#' | ';' | ':' | '\n' | '<' | '>' | '"' | '{' | '}' => format!("#{};", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
 fn create_state() -> State {
     State {
         matchers: VecDeque::new(),
@@ -347,16 +1401,140 @@ fn create_state() -> State {
         is_ending: false,
         start_group: Vec::new(),
         end_group: Vec::new(),
+        ascii_table: None,
+        char_table: None,
+        seq: None,
     }
 }
 
 #[derive(Clone)]
 pub struct State {
-    pub matchers: VecDeque<(Rc<dyn Matcher>, usize)>,
-    pub is_initial: bool,
-    pub is_ending: bool,
-    pub start_group: Vec<(u32, Option<Rc<str>>)>,
-    pub end_group: Vec<(u32, Option<Rc<str>>)>,
+    pub(crate) matchers: VecDeque<(Transition, usize)>,
+    pub(crate) is_initial: bool,
+    pub(crate) is_ending: bool,
+    pub(crate) start_group: Vec<(u32, Option<Arc<str>>)>,
+    pub(crate) end_group: Vec<(u32, Option<Arc<str>>)>,
+    // ASCII char -> indices of the transitions that may be taken on it, see
+    // `build_ascii_tables`
+    pub(crate) ascii_table: Option<Arc<[Vec<usize>]>>,
+    // the same for the other chars
+    pub(crate) char_table: Option<Arc<CharTable>>,
+    // the literal run starting here, see `build_literal_runs`
+    pub(crate) seq: Option<SeqMatcher>,
+}
+
+impl State {
+    // the transitions that may be taken on `c`, when the state has a table
+    // for it
+    pub fn listed(&self, c: char) -> Option<&[usize]> {
+        if c.is_ascii() {
+            self.ascii_table.as_ref().map(|table| &*table[c as usize])
+        } else {
+            self.char_table.as_ref().map(|table| table.get(c))
+        }
+    }
+
+    // `START(1)`, `END(2:name)` and so on, for the groups this state
+    // opens and then closes, by group index
+    pub fn group_marks(&self) -> Vec<String> {
+        let mut starts: Vec<_> = self
+            .start_group
+            .iter()
+            .map(|group| ("START", group))
+            .collect();
+        let mut ends: Vec<_> = self.end_group.iter().map(|group| ("END", group)).collect();
+        starts.sort_by_key(|(_, (index, _))| *index);
+        ends.sort_by_key(|(_, (index, _))| *index);
+        starts
+            .into_iter()
+            .chain(ends)
+            .map(|(mark, (index, name))| match name {
+                Some(name) => format!("{}({}:{})", mark, index, name),
+                None => format!("{}({})", mark, index),
+            })
+            .collect()
+    }
+
+    // Opens the groups starting at this state and commits the spans of the
+    // groups ending here.
+    pub fn mark_groups(&self, groups: &mut Vec<Option<CaptureGroupRange>>, i: usize) {
+        for (group_index, name) in self.start_group.iter() {
+            let key = *group_index as usize;
+            if groups.len() <= key {
+                groups.resize(key + 1, None);
+            }
+            match &mut groups[key] {
+                Some(capture_result) => capture_result.0 = Some(i),
+                None => groups[key] = Some(CaptureGroupRange(Some(i), None, name.clone())),
+            }
+        }
+
+        for (group_index, _) in self.end_group.iter() {
+            let key = *group_index as usize;
+            if let Some(Some(capture_result)) = groups.get_mut(key)
+                && let Some(left) = capture_result.0.take()
+            {
+                capture_result.1 = Some((left, i));
+            }
+        }
+    }
+}
+
+// Non-ASCII char -> indices of the transitions that may be taken on it.
+// The chars that single char tests look for are hashed, every other char
+// falls into an interval of chars the remaining tests cannot tell apart.
+pub struct CharTable {
+    chars: HashMap<char, Vec<usize>>,
+    // interval start -> transitions, sorted by start
+    intervals: Vec<(char, Vec<usize>)>,
+}
+
+impl CharTable {
+    fn new(matchers: &VecDeque<(Transition, usize)>) -> Self {
+        let single = |t: &Transition| matches!(t, Transition::Char(_) | Transition::CharSet(_));
+        let accepting = |c: char, skip_single: bool| -> Vec<usize> {
+            (0..matchers.len())
+                .filter(|&t| !(skip_single && single(&matchers[t].0)))
+                .filter(|&t| matchers[t].0.matches_char(c) != Some(false))
+                .collect()
+        };
+
+        let mut chars = HashMap::new();
+        let mut boundaries = vec!['\u{80}'];
+        for (transition, _) in matchers.iter() {
+            match transition.dfa_edge() {
+                Some(DfaEdge::Chars(ranges)) if single(transition) => {
+                    for (c, _) in ranges.into_iter().filter(|(c, _)| !c.is_ascii()) {
+                        chars.entry(c).or_insert_with(|| accepting(c, false));
+                    }
+                }
+                Some(DfaEdge::Chars(ranges)) => {
+                    for (start, end) in ranges {
+                        boundaries.push(start);
+                        boundaries.extend(dfa::successor(end));
+                    }
+                }
+                _ => (),
+            }
+        }
+        boundaries.retain(|c| !c.is_ascii());
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let intervals = boundaries
+            .into_iter()
+            .map(|start| (start, accepting(start, true)))
+            .collect();
+        Self { chars, intervals }
+    }
+
+    fn get(&self, c: char) -> &[usize] {
+        if let Some(listed) = self.chars.get(&c) {
+            return listed;
+        }
+        let interval = self.intervals.partition_point(|(start, _)| *start <= c);
+        &self.intervals[interval - 1].1
+    }
 }
 
 #[cfg(test)]
@@ -379,6 +1557,66 @@ mod tests {
         assert!(nfa.compute("a").is_none());
     }
 
+    #[test]
+    fn test_predicate_transition() {
+        let mut nfa = NFAutomata::default();
+
+        nfa.declare_state(3, 0, 2);
+        nfa.add_predicate_transition(0, 1, "cjk", |c| ('\u{4e00}'..='\u{9fff}').contains(&c));
+        nfa.add_predicate_transition(1, 1, "cjk", |c| ('\u{4e00}'..='\u{9fff}').contains(&c));
+        nfa.add_char_transition(1, 2, '!');
+
+        assert!(nfa.compute("中文!").is_some());
+        assert!(nfa.compute("!").is_none());
+        assert!(nfa.compute("ab!").is_none());
+        assert!(dfa::edges(&nfa).is_err());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut nfa = NFAutomata::default();
+        nfa.declare_state(2, 0, 1);
+        nfa.add_char_transition(0, 1, '"');
+        nfa.mark_capture_group(1, Some("q".into()));
+
+        let dot = nfa.to_dot();
+        assert!(dot.starts_with("digraph nfa {"));
+        assert!(dot.contains("start -> 0;"));
+        assert!(dot.contains("0 -> 1 [label=\"\\\"\"];"));
+        assert!(dot.contains("1 [shape=doublecircle"));
+        assert!(dot.contains("\\nEND(1:q)"));
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let mut nfa = NFAutomata::default();
+        nfa.declare_state(2, 0, 1);
+        nfa.add_char_transition(0, 1, ':');
+        nfa.mark_capture_group(1, None);
+
+        let mermaid = nfa.to_mermaid();
+        assert!(mermaid.starts_with("stateDiagram-v2\n    [*] --> s0\n"));
+        assert!(mermaid.contains("s0: 0 START(1)\n"));
+        assert!(mermaid.contains("s0 --> s1: #58;\n"));
+        assert!(mermaid.contains("s1 --> [*]\n"));
+    }
+
+    #[test]
+    fn test_display() {
+        let mut nfa = NFAutomata::default();
+        nfa.declare_state(2, 0, 1);
+        nfa.add_char_transition(0, 1, 'a');
+        nfa.add_epsilon_transition(0, 1);
+        nfa.mark_capture_group(2, None);
+        nfa.mark_capture_group(1, Some("x".into()));
+
+        assert_eq!(
+            nfa.to_string(),
+            "State(0) [INITIAL] {START(1:x), START(2)}: --a-> 1 --ε-> 1\n\
+             State(1) [ENDING] {END(1:x), END(2)}: (no transitions)\n"
+        );
+    }
+
     #[test]
     fn test_nfa_epsilon_loop() {
         let mut nfa = NFAutomata::default();
@@ -390,4 +1628,162 @@ mod tests {
 
         assert!(nfa.compute("ab").is_some());
     }
+
+    #[test]
+    fn test_compact() {
+        let mut nfa = NFAutomata::default();
+
+        // 3 is unreachable and 4 is a dead end
+        nfa.declare_state(5, 0, 2);
+        nfa.add_char_transition(0, 1, 'a');
+        nfa.add_char_transition(1, 2, 'b');
+        nfa.add_char_transition(3, 2, 'c');
+        nfa.add_char_transition(1, 4, 'd');
+        nfa.compact();
+
+        assert_eq!(nfa.states.len(), 3);
+        assert_eq!(nfa.ending, vec![2]);
+        assert!(nfa.compute("ab").is_some());
+        assert!(nfa.compute("ad").is_none());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut nfa = NFAutomata::default();
+
+        // 1 and 2 both go to 3 on `b`
+        nfa.declare_state(4, 0, 3);
+        nfa.add_char_transition(0, 1, 'a');
+        nfa.add_char_transition(0, 2, 'c');
+        nfa.add_char_transition(1, 3, 'b');
+        nfa.add_char_transition(2, 3, 'b');
+        nfa.dedup();
+
+        assert_eq!(nfa.states.len(), 3);
+        assert!(nfa.compute("ab").is_some());
+        assert!(nfa.compute("cb").is_some());
+        assert!(nfa.compute("bb").is_none());
+
+        // 1 and 3 both branch to 2 and 4 over ε, but merging them would
+        // let the first visit hide the second's branch to the end
+        let mut nfa = NFAutomata::default();
+        nfa.declare_state(5, 0, 4);
+        nfa.add_epsilon_transition(0, 1);
+        nfa.add_epsilon_transition(1, 2);
+        nfa.add_epsilon_transition(1, 4);
+        nfa.add_epsilon_transition(2, 3);
+        nfa.add_char_transition(2, 3, 'a');
+        nfa.add_epsilon_transition(3, 2);
+        nfa.add_epsilon_transition(3, 4);
+        nfa.dedup();
+        assert_eq!(nfa.states.len(), 5);
+    }
+
+    #[test]
+    fn test_ascii_table() {
+        let mut nfa = NFAutomata::default();
+
+        nfa.declare_state(3, 0, 2);
+        for c in ['a', 'b', 'c', 'é'] {
+            nfa.add_char_transition(0, 1, c);
+        }
+        nfa.add_transition(0, 2, Transition::Start);
+        nfa.add_char_transition(1, 2, '!');
+        nfa.build_ascii_tables();
+
+        let table = nfa.states[0].ascii_table.as_ref().unwrap();
+        assert_eq!(table['b' as usize], vec![1, 4]);
+        assert!(nfa.states[1].ascii_table.is_none());
+        assert!(nfa.compute("b!").is_some());
+        assert!(nfa.compute("é!").is_some());
+        assert!(nfa.compute("x").is_some());
+        assert!(nfa.search(&Haystack::Str("xd!"), 1, 1).is_none());
+    }
+
+    #[test]
+    fn test_append() {
+        let mut nfa = NFAutomata::default();
+        nfa.declare_state(2, 0, 1);
+        nfa.add_char_transition(0, 1, 'a');
+
+        let mut other = NFAutomata::default();
+        other.declare_state(3, 0, 2);
+        other.add_char_transition(0, 1, 'b');
+        other.add_char_transition(1, 2, 'c');
+        other.add_epsilon_transition(2, 1);
+        other.mark_start_capture_group(0, 1, None);
+
+        nfa.append(other, 1);
+        assert_eq!(nfa.states.len(), 4);
+        assert_eq!(nfa.ending, vec![3]);
+        assert_eq!(nfa.states[1].start_group.len(), 1);
+        assert_eq!(nfa.states[3].matchers[0].1, 2);
+        assert!(nfa.compute("abcc").is_some());
+        assert!(nfa.compute("ab").is_none());
+    }
+
+    #[test]
+    fn test_literal_runs() {
+        let mut nfa = NFAutomata::default();
+
+        nfa.declare_state(5, 0, 4);
+        for (from, c) in ['a', 'b', 'c'].into_iter().enumerate() {
+            nfa.add_char_transition(from, from + 1, c);
+        }
+        nfa.add_char_transition(3, 4, 'd');
+        nfa.add_char_transition(3, 4, 'é');
+        nfa.build_literal_runs();
+
+        let seq = nfa.states[0].seq.as_ref().unwrap();
+        assert_eq!((&*seq.chars, seq.to), ("abc", 3));
+        assert!(nfa.states[2].seq.is_none());
+        assert!(nfa.compute("abcé").is_some());
+        assert!(nfa.compute("abxd").is_none());
+        assert!(nfa.search(&Haystack::Bytes(b"abcd"), 0, 0).is_some());
+        assert!(nfa.search(&Haystack::Bytes(b"ab"), 0, 0).is_none());
+    }
+
+    #[test]
+    fn test_char_table() {
+        let mut nfa = NFAutomata::default();
+
+        nfa.declare_state(2, 0, 1);
+        for c in ('à'..='ÿ').take(CHAR_TABLE_MIN_TRANSITIONS) {
+            nfa.add_char_transition(0, 1, c);
+        }
+        nfa.add_transition(0, 1, Transition::Range('ä', '中'));
+        nfa.add_transition(0, 1, Transition::Start);
+        nfa.build_ascii_tables();
+
+        let state = &nfa.states[0];
+        let range = CHAR_TABLE_MIN_TRANSITIONS;
+        assert_eq!(state.listed('á'), Some(&[1, range + 1][..]));
+        assert_eq!(state.listed('ä'), Some(&[4, range, range + 1][..]));
+        assert_eq!(state.listed('一'), Some(&[range, range + 1][..]));
+        assert_eq!(state.listed('中'), Some(&[range, range + 1][..]));
+        assert_eq!(state.listed('丮'), Some(&[range + 1][..]));
+        assert_eq!(state.listed('ß'), Some(&[range + 1][..]));
+        assert_eq!(state.listed('a'), Some(&[range + 1][..]));
+        // the range comes before `^`
+        assert_eq!(nfa.search(&Haystack::Str("中"), 0, 0).unwrap().0, 3);
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let mut nfa = NFAutomata::default();
+        nfa.declare_state(3, 0, 2);
+        nfa.add_char_transition(0, 1, 'a');
+        nfa.add_char_transition(1, 2, 'b');
+
+        // numbered differently, with an unreachable state
+        let mut renumbered = NFAutomata::default();
+        renumbered.declare_state(4, 0, 1);
+        renumbered.add_char_transition(0, 2, 'a');
+        renumbered.add_char_transition(2, 1, 'b');
+        renumbered.add_char_transition(3, 1, 'c');
+        assert_eq!(nfa.canonical_hash(), renumbered.canonical_hash());
+
+        renumbered.add_char_transition(2, 3, 'c');
+        assert_ne!(nfa.canonical_hash(), renumbered.canonical_hash());
+    }
 }
@@ -0,0 +1,17 @@
+use std::borrow::Cow;
+
+#[cfg(feature = "unicode-normalization")]
+pub fn nfc(s: &str) -> Cow<'_, str> {
+    use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfc_quick};
+
+    match is_nfc_quick(s.chars()) {
+        IsNormalized::Yes => Cow::Borrowed(s),
+        _ => Cow::Owned(s.nfc().collect()),
+    }
+}
+
+// without the feature the flag cannot be set
+#[cfg(not(feature = "unicode-normalization"))]
+pub fn nfc(s: &str) -> Cow<'_, str> {
+    Cow::Borrowed(s)
+}
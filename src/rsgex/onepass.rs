@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    alphabet::Alphabet,
+    dfa::{self, Edge},
+    error::Error,
+    haystack::Haystack,
+    nfa::{CaptureGroupRange, NFAutomata, captured_groups},
+};
+
+enum Step {
+    // consumes a char, continuing in another one-pass state
+    Consume(usize),
+    Accept,
+    // accepts only at the end of input
+    AcceptAtEnd,
+}
+
+// the states whose capture markers apply on the way to the step
+struct Item {
+    path: Vec<usize>,
+    step: Step,
+}
+
+struct OnePassState {
+    // in the backtracker's priority order, cut after an unconditional accept
+    items: Vec<Item>,
+    // class -> the only item consuming it
+    next: Vec<Option<usize>>,
+}
+
+// A DFA over the NFA states entered right after consuming a char, for
+// patterns where at most one transition can consume any char at any point.
+// Captures are then recorded in a single forward scan, with a saved accept to
+// fall back to when a greedy continuation dies.
+pub struct OnePass {
+    alphabet: Alphabet,
+    states: Vec<OnePassState>,
+    // [at the input start, elsewhere]
+    starts: [usize; 2],
+}
+
+impl OnePass {
+    // Fails when the automaton is not one-pass or uses counters, calls or
+    // look-arounds other than `^` / `$`.
+    pub fn new(nfa: &NFAutomata) -> Result<OnePass, Error> {
+        let (edges, alphabet) = dfa::edges(nfa)?;
+
+        // (nfa state, at the input start) -> one-pass state
+        let mut ids: HashMap<(usize, bool), usize> = HashMap::new();
+        let mut queue: Vec<(usize, bool)> = vec![];
+        let mut id = |key: (usize, bool), queue: &mut Vec<(usize, bool)>| {
+            *ids.entry(key).or_insert_with(|| {
+                queue.push(key);
+                queue.len() - 1
+            })
+        };
+        let starts = [
+            id((nfa.initial, true), &mut queue),
+            id((nfa.initial, false), &mut queue),
+        ];
+
+        let mut states = vec![];
+        while states.len() < queue.len() {
+            let (root, at_start) = queue[states.len()];
+
+            let mut items = vec![];
+            let mut consuming = vec![];
+            let mut seen = HashSet::new();
+            // (state, path, behind a `$`, consuming transition to emit)
+            let mut stack = vec![(root, vec![root], false, None)];
+            while let Some((state, path, after_end, emit)) = stack.pop() {
+                if let Some((transition, next)) = emit {
+                    consuming.push((items.len(), state, transition));
+                    items.push(Item {
+                        path,
+                        step: Step::Consume(next),
+                    });
+                    continue;
+                }
+                if !seen.insert((state, after_end)) {
+                    continue;
+                }
+                if nfa.states[state].is_ending {
+                    if after_end {
+                        items.push(Item {
+                            path,
+                            step: Step::AcceptAtEnd,
+                        });
+                        continue;
+                    }
+                    items.push(Item {
+                        path,
+                        step: Step::Accept,
+                    });
+                    // lower priority items can never win
+                    break;
+                }
+
+                for (transition, (edge, to)) in edges[state].iter().enumerate().rev() {
+                    let mut next_path = path.clone();
+                    next_path.push(*to);
+                    match edge {
+                        Edge::Epsilon => stack.push((*to, next_path, after_end, None)),
+                        Edge::Start if at_start => stack.push((*to, next_path, after_end, None)),
+                        Edge::Start => (),
+                        Edge::End => stack.push((*to, next_path, true, None)),
+                        Edge::Counter(_) => {
+                            let message = "counted repetitions are not one-pass";
+                            return Err(Error::Unsupported(message.to_string()));
+                        }
+                        // nothing can be consumed behind a `$`
+                        Edge::Chars(_) if after_end => (),
+                        Edge::Chars(_) => {
+                            let next = id((*to, false), &mut queue);
+                            stack.push((state, path.clone(), false, Some((transition, next))));
+                        }
+                    }
+                }
+            }
+
+            let mut next = vec![None; alphabet.classes()];
+            for (class, next_item) in next.iter_mut().enumerate() {
+                let c = alphabet.representative(class);
+                for &(item, state, transition) in consuming.iter() {
+                    let Edge::Chars(matcher) = &edges[state][transition].0 else {
+                        continue;
+                    };
+                    if matcher.matches_char(c) != Some(true) {
+                        continue;
+                    }
+                    if next_item.is_some() {
+                        let message = "the pattern is not one-pass";
+                        return Err(Error::Unsupported(message.to_string()));
+                    }
+                    *next_item = Some(item);
+                }
+            }
+
+            states.push(OnePassState { items, next });
+        }
+
+        Ok(OnePass {
+            alphabet,
+            states,
+            starts,
+        })
+    }
+
+    // Anchored at `at`, like `NFAutomata::search`.
+    pub fn search(
+        &self,
+        nfa: &NFAutomata,
+        haystack: &Haystack,
+        at: usize,
+    ) -> Option<(usize, HashMap<String, String>)> {
+        let mut groups: Vec<Option<CaptureGroupRange>> = vec![];
+        let mut accepted = None;
+        let mut state = &self.states[self.starts[usize::from(at > 0)]];
+
+        let mut i = at;
+        loop {
+            let consume = haystack
+                .char_at(i)
+                .and_then(|c| state.next[self.alphabet.class(c)]);
+            let accept = state.items.iter().position(|item| match item.step {
+                Step::Accept => true,
+                Step::AcceptAtEnd => i == haystack.len(),
+                Step::Consume(_) => false,
+            });
+
+            if let Some(accept) = accept {
+                let mut accept_groups = groups.clone();
+                mark(nfa, &state.items[accept].path, &mut accept_groups, i);
+                if consume.is_none_or(|consume| accept < consume) {
+                    return Some((i, captured_groups(haystack, &accept_groups)));
+                }
+                accepted = Some((i, accept_groups));
+            }
+
+            let Some(consume) = consume else {
+                break;
+            };
+            let item = &state.items[consume];
+            mark(nfa, &item.path, &mut groups, i);
+            let Step::Consume(next) = item.step else {
+                unreachable!()
+            };
+            state = &self.states[next];
+            i = haystack.next(i);
+        }
+
+        accepted.map(|(end, groups)| (end, captured_groups(haystack, &groups)))
+    }
+}
+
+fn mark(nfa: &NFAutomata, path: &[usize], groups: &mut Vec<Option<CaptureGroupRange>>, i: usize) {
+    for &state in path.iter() {
+        nfa.states[state].mark_groups(groups, i);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OnePass;
+    use crate::rsgex::{engine::Engine, haystack::Haystack};
+
+    #[test]
+    fn test_detection() {
+        for pattern in ["^(\\w+)@(\\w+)$", "a(b|c)d", "01+?", "x*y", "(ab|ac)"] {
+            assert!(
+                Engine::try_from(pattern).unwrap().onepass.is_some(),
+                "{}",
+                pattern
+            );
+        }
+        for pattern in ["a*a", "(ab|\\wc)", "(a+)(a+)", "a\\b"] {
+            assert!(
+                Engine::try_from(pattern).unwrap().onepass.is_none(),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn test_agrees_with_backtracker() {
+        let inputs = ["", "ab", "abcd", "a_1@b", "x@y!", "aaa", "xxxy", "-12"];
+
+        for pattern in [
+            "(\\w+)@(\\w+)",
+            "^(a)?(b)?$",
+            "(?<x>x*)y?",
+            "(a|-)(\\d)+",
+            "ab?|-|",
+        ] {
+            let e = Engine::try_from(pattern).unwrap();
+            let onepass = OnePass::new(&e.nfa).expect(pattern);
+            for input in inputs {
+                let haystack = Haystack::Str(input);
+                for (at, _) in input.char_indices().chain([(input.len(), ' ')]) {
+                    assert_eq!(
+                        onepass.search(&e.nfa, &haystack, at),
+                        e.nfa.search(&haystack, at, at),
+                        "{} on {:?} at {}",
+                        pattern,
+                        input,
+                        at
+                    );
+                }
+            }
+        }
+    }
+}
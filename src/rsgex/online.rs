@@ -0,0 +1,264 @@
+use super::{
+    dfa::{self, Edge},
+    engine::Engine,
+    error::Error,
+    matcher::Transition,
+};
+
+// Matches input fed to it a char at a time, like the text of a form field
+// as it is typed. The input so far is matched as a whole, as if the pattern
+// were wrapped in `^(?:...)$`. Epsilon closures are computed up front, so a
+// char only follows the char tests of the active states. Rejects whatever
+// `Dfa` rejects; `{n,m}` is unrolled, as there are no counter registers.
+// NFC normalization is not applied.
+pub struct OnlineMatcher {
+    // state -> char tests leaving its epsilon closure
+    steps: Vec<Vec<(Transition, usize)>>,
+    // state -> whether its closure reaches an ending state at the input end
+    accepts: Vec<bool>,
+    // state -> whether any input leads it to an ending state
+    live: Vec<bool>,
+    // states active before any char, entered over `^` as well
+    start: Vec<usize>,
+    // whether the empty input matches
+    accepts_empty: bool,
+    active: Vec<usize>,
+    next: Vec<usize>,
+    seen: Vec<bool>,
+    pushed: usize,
+}
+
+impl OnlineMatcher {
+    // Feeds the next char of the input.
+    pub fn push(&mut self, c: char) {
+        self.next.clear();
+        for &state in self.active.iter() {
+            for (transition, to) in self.steps[state].iter() {
+                if transition.matches_char(c) == Some(true) && !self.seen[*to] {
+                    self.seen[*to] = true;
+                    self.next.push(*to);
+                }
+            }
+        }
+        for &state in self.next.iter() {
+            self.seen[state] = false;
+        }
+
+        std::mem::swap(&mut self.active, &mut self.next);
+        self.pushed += 1;
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        s.chars().for_each(|c| self.push(c));
+    }
+
+    // Whether the input pushed so far matches.
+    pub fn is_accepting(&self) -> bool {
+        if self.pushed == 0 {
+            return self.accepts_empty;
+        }
+        self.active.iter().any(|&state| self.accepts[state])
+    }
+
+    // Whether some continuation of the input pushed so far matches. Once it
+    // is false, no further char changes that.
+    pub fn can_still_match(&self) -> bool {
+        self.active.iter().any(|&state| self.live[state])
+    }
+
+    // Forgets the input pushed so far.
+    pub fn reset(&mut self) {
+        self.active.clone_from(&self.start);
+        self.pushed = 0;
+    }
+}
+
+impl TryFrom<&Engine> for OnlineMatcher {
+    type Error = Error;
+
+    fn try_from(engine: &Engine) -> Result<OnlineMatcher, Error> {
+        let nfa = engine.unrolled_nfa()?;
+        let (edges, _) = dfa::edges(nfa)?;
+        // only left in automata combined by `Engine::union` and the like
+        if edges
+            .iter()
+            .flatten()
+            .any(|(edge, _)| matches!(edge, Edge::Counter(_)))
+        {
+            let message = "counted repetitions are not supported by the online matcher";
+            return Err(Error::Unsupported(message.to_string()));
+        }
+
+        // states reachable from `state` over the edges `follow` accepts
+        let closure = |state: usize, follow: &dyn Fn(&Edge) -> bool| {
+            let mut closure = vec![state];
+            let mut i = 0;
+            while i < closure.len() {
+                for (edge, to) in edges[closure[i]].iter() {
+                    if follow(edge) && !closure.contains(to) {
+                        closure.push(*to);
+                    }
+                }
+                i += 1;
+            }
+            closure
+        };
+        let ends = |closure: Vec<usize>| closure.iter().any(|&s| nfa.states[s].is_ending);
+
+        let mut steps = vec![];
+        let mut accepts = vec![];
+        for state in 0..nfa.states.len() {
+            let step: Vec<(Transition, usize)> =
+                closure(state, &|edge| matches!(edge, Edge::Epsilon))
+                    .into_iter()
+                    .flat_map(|s| edges[s].iter())
+                    .filter_map(|(edge, to)| match edge {
+                        Edge::Chars(transition) => Some((transition.clone(), *to)),
+                        _ => None,
+                    })
+                    .collect();
+            steps.push(step);
+            accepts.push(ends(closure(state, &|edge| {
+                matches!(edge, Edge::Epsilon | Edge::End)
+            })));
+        }
+
+        // walk the char tests backwards from the states accepting at the
+        // input end; `^` only holds before the first char
+        let mut into = vec![vec![]; nfa.states.len()];
+        for (from, step) in steps.iter().enumerate() {
+            for (_, to) in step.iter() {
+                into[*to].push(from);
+            }
+        }
+        let mut live = vec![false; nfa.states.len()];
+        let mut stack: Vec<usize> = (0..nfa.states.len()).filter(|&s| accepts[s]).collect();
+        while let Some(state) = stack.pop() {
+            if !live[state] {
+                live[state] = true;
+                stack.extend(into[state].iter().copied());
+            }
+        }
+
+        let start = closure(nfa.initial, &|edge| {
+            matches!(edge, Edge::Epsilon | Edge::Start)
+        });
+        let accepts_empty = ends(closure(nfa.initial, &|edge| {
+            matches!(edge, Edge::Epsilon | Edge::Start | Edge::End)
+        }));
+
+        Ok(OnlineMatcher {
+            steps,
+            accepts,
+            live,
+            active: start.clone(),
+            start,
+            accepts_empty,
+            next: vec![],
+            seen: vec![false; nfa.states.len()],
+            pushed: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OnlineMatcher;
+    use crate::rsgex::{
+        builder::EngineBuilder,
+        engine::{Backend, Engine},
+    };
+
+    #[test]
+    fn test_agrees_with_engine() {
+        let patterns = [
+            "\\d{3}-\\d{4}",
+            "[a-z]+@[a-z]+\\.com",
+            "a|ab*",
+            "x*",
+            "^(ab)+$",
+            "(a{2}|b){1,3}c?",
+            "\\d{4}-\\d{2}",
+        ];
+        let inputs = [
+            "", "555-0100", "555-01", "a@b.com", "a@b", "abbb", "ba", "xx", "aab", "baac", "aaa",
+            "2024-01", "2024-1",
+        ];
+        // the backtracking backend counts `{n,m}`, which is unrolled here
+        for (pattern, backend) in patterns
+            .into_iter()
+            .flat_map(|pattern| [(pattern, Backend::PikeVm), (pattern, Backend::Backtrack)])
+        {
+            let e = EngineBuilder::new()
+                .backend(backend)
+                .build(pattern)
+                .unwrap();
+            let full = Engine::try_from(format!("^(?:{})$", pattern).as_str()).unwrap();
+            let mut online = OnlineMatcher::try_from(&e).unwrap();
+            for input in inputs {
+                online.reset();
+                online.push_str(input);
+                assert_eq!(
+                    online.is_accepting(),
+                    full.test(input),
+                    "{} {:?} {}",
+                    pattern,
+                    backend,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_can_still_match() {
+        let e = Engine::try_from("\\d{3}-\\d{4}").unwrap();
+        let mut online = OnlineMatcher::try_from(&e).unwrap();
+        assert!(online.can_still_match() && !online.is_accepting());
+        online.push_str("555-01");
+        assert!(online.can_still_match() && !online.is_accepting());
+        online.push_str("00");
+        assert!(online.can_still_match() && online.is_accepting());
+        online.push('0');
+        assert!(!online.can_still_match() && !online.is_accepting());
+
+        // `$` can only hold at the end, `^` only at the start
+        let e = Engine::try_from("a$b|a^").unwrap();
+        let mut online = OnlineMatcher::try_from(&e).unwrap();
+        online.push('a');
+        assert!(!online.can_still_match());
+
+        assert!(OnlineMatcher::try_from(&Engine::try_from("a\\b").unwrap()).is_err());
+        let e = EngineBuilder::new()
+            .nfa_size_limit(1 << 12)
+            .build("a{2,500}")
+            .unwrap();
+        assert!(OnlineMatcher::try_from(&e).is_err());
+    }
+
+    #[test]
+    fn test_combined_engines() {
+        let (a, b) = (
+            Engine::try_from("a{2,3}").unwrap(),
+            Engine::try_from("b{2}").unwrap(),
+        );
+        let mut online = OnlineMatcher::try_from(&a.union(&b)).unwrap();
+        for (input, accepting) in [("aa", true), ("aaa", true), ("bb", true), ("ab", false)] {
+            online.reset();
+            online.push_str(input);
+            assert_eq!(online.is_accepting(), accepting, "{}", input);
+        }
+        let mut online = OnlineMatcher::try_from(&a.then(&b)).unwrap();
+        online.push_str("aaabb");
+        assert!(online.is_accepting());
+
+        // the size limit of `self` applies to the unrolled parts
+        let e = EngineBuilder::new()
+            .nfa_size_limit(1 << 12)
+            .build("a")
+            .unwrap()
+            .or_pattern("a{2,500}")
+            .unwrap();
+        assert!(OnlineMatcher::try_from(&e).is_err());
+    }
+}
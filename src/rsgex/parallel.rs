@@ -0,0 +1,159 @@
+use rayon::prelude::*;
+
+use super::{engine::Engine, haystack::Haystack, matcher::Transition, search::Match};
+
+// haystacks shorter than this per thread are not worth splitting
+const MIN_CHUNK_LEN: usize = 1 << 16;
+
+// A match and the position its search started from.
+struct Found {
+    from: usize,
+    m: Match,
+}
+
+impl Engine {
+    // The matches `find_iter` yields, found by searching chunks of `s` on
+    // the rayon thread pool. Each chunk searches the whole haystack but only
+    // keeps matches starting inside it, so a match may run past its chunk.
+    // The chunks are then joined in order: a chunk whose searches started
+    // somewhere the sequential iteration would not is rescanned from where
+    // the previous match ended until both line up again.
+    pub fn par_find_iter(&self, s: &str) -> Vec<Match> {
+        let chunks = (s.len() / MIN_CHUNK_LEN).clamp(1, rayon::current_num_threads());
+        self.find_in_chunks(s, chunks)
+    }
+
+    fn find_in_chunks(&self, s: &str, chunks: usize) -> Vec<Match> {
+        let s = self.prepare(s);
+        let haystack = Haystack::Str(&s);
+        // `\G` matches depend on where the search started
+        let continuation = self.nfa.states.iter().any(|state| {
+            state
+                .matchers
+                .iter()
+                .any(|(t, _)| matches!(t, Transition::Continuation))
+        });
+        if chunks == 1 || continuation {
+            return self.find_iter(&s).collect();
+        }
+
+        let mut bounds: Vec<usize> = (0..chunks)
+            .map(|chunk| {
+                let mut at = s.len() * chunk / chunks;
+                while !s.is_char_boundary(at) {
+                    at += 1;
+                }
+                at
+            })
+            .collect();
+        // the last chunk keeps an empty match at the very end
+        bounds.push(s.len() + 1);
+
+        let found: Vec<Vec<Found>> = bounds
+            .par_windows(2)
+            .map(|window| self.search_chunk(&haystack, window[0], window[1]))
+            .collect();
+
+        let mut matches = vec![];
+        let mut next = 0;
+        // whether the chunk's search went on from where the last match ended
+        let mut synced = true;
+        for found in found.into_iter().flatten() {
+            if found.m.start < next {
+                synced = false;
+                continue;
+            }
+            // a search from `next` lands on the leftmost match at or after
+            // it, so it reaches this one unless another one comes first
+            while found.from != next {
+                let Some(m) = self.find_in(&haystack, next) else {
+                    return matches;
+                };
+                if m == found.m {
+                    break;
+                }
+                next = after(&haystack, &m);
+                matches.push(m);
+                if found.m.start < next {
+                    break;
+                }
+            }
+            if found.m.start < next {
+                synced = false;
+                continue;
+            }
+            next = after(&haystack, &found.m);
+            matches.push(found.m);
+            synced = true;
+        }
+        // the searches after the last match were skipped over
+        while !synced && next <= haystack.len() {
+            let Some(m) = self.find_in(&haystack, next) else {
+                break;
+            };
+            next = after(&haystack, &m);
+            matches.push(m);
+        }
+
+        matches
+    }
+
+    fn search_chunk(&self, haystack: &Haystack, start: usize, end: usize) -> Vec<Found> {
+        let mut found = vec![];
+        let mut from = start;
+        while from < end {
+            let Some(m) = self.find_in(haystack, from) else {
+                break;
+            };
+            if m.start >= end {
+                break;
+            }
+            let next = after(haystack, &m);
+            found.push(Found { from, m });
+            from = next;
+        }
+
+        found
+    }
+}
+
+// where the search for the match after `m` starts, see `Matches`
+fn after(haystack: &Haystack, m: &Match) -> usize {
+    if m.end == m.start {
+        haystack.next(m.end)
+    } else {
+        m.end
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rsgex::{engine::Engine, search::Match};
+
+    #[test]
+    fn test_par_find_iter() {
+        let haystack = "key=value 中文 a=b c==d\n".repeat(20);
+
+        for pattern in [
+            "\\w+=\\w*",
+            "=*",
+            "[^\\n]{0,40}\\n",
+            "中文 a|a=b c|c",
+            "\\Gk",
+        ] {
+            let e = Engine::try_from(pattern).unwrap();
+            let expected: Vec<Match> = e.find_iter(&haystack).collect();
+            assert_eq!(e.par_find_iter(&haystack), expected, "{}", pattern);
+            // chunk bounds fall inside matches and chars
+            for chunks in [2, 7, 50] {
+                assert_eq!(
+                    e.find_in_chunks(&haystack, chunks),
+                    expected,
+                    "{} in {} chunks",
+                    pattern,
+                    chunks
+                );
+            }
+        }
+    }
+}
@@ -1,10 +1,458 @@
-use anyhow::Result;
-use regex_syntax::{hir::Hir, parse};
+use std::ops::Range;
 
-pub fn parse_by_regex_syntax(pattern: &str) -> Result<Hir> {
-    let ast = parse(pattern)?;
+use regex_syntax::{
+    ParserBuilder,
+    ast::{self, Ast, GroupKind},
+    hir::{Capture, Hir, HirKind, Repetition},
+};
 
-    Ok(ast)
+use super::error::Error;
+
+// `\G` is not understood by regex-syntax, so it is rewritten into this
+// noncharacter before parsing and lowered back into a matcher by the engine.
+pub const CONTINUATION_SENTINEL: char = '\u{FDD0}';
+
+// Subroutine calls like `(?1)` are rewritten into `CALL_SENTINEL_BASE + n`,
+// where `n` indexes `Parsed::calls`.
+pub const CALL_SENTINEL_BASE: char = '\u{F0000}';
+
+// Named matchers like `\m{hex}` are rewritten into `NAMED_SENTINEL_BASE + n`,
+// where `n` indexes `Parsed::named`.
+pub const NAMED_SENTINEL_BASE: char = '\u{100000}';
+
+// The chars a pattern may not contain, raw or escaped, since the engine
+// would take them for an extension.
+pub fn is_sentinel(c: char) -> bool {
+    c == CONTINUATION_SENTINEL
+        || (CALL_SENTINEL_BASE..='\u{FFFFD}').contains(&c)
+        || (NAMED_SENTINEL_BASE..='\u{10FFFD}').contains(&c)
+}
+
+// Whether a literal of `hir` contains a reserved char, which a HIR built
+// without parsing has no way to escape.
+pub fn contains_reserved(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Literal(literal) => String::from_utf8_lossy(&literal.0).chars().any(is_sentinel),
+        HirKind::Capture(capture) => contains_reserved(&capture.sub),
+        HirKind::Repetition(repetition) => contains_reserved(&repetition.sub),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => hirs.iter().any(contains_reserved),
+        _ => false,
+    }
+}
+
+// Repeated group names get this suffix and a counter so regex-syntax accepts
+// them; the suffix is stripped again when the captures are renumbered.
+const DUPLICATE_NAME_SUFFIX: &str = "__rsgex_duplicate_";
+
+#[derive(Debug)]
+pub struct Parsed {
+    pub hir: Hir,
+    // call slot -> called capture group index, 0 being the whole pattern
+    pub calls: Vec<u32>,
+    // the names of the matchers referenced by `\m{name}`, see
+    // `EngineBuilder::define_class`
+    pub named: Vec<String>,
+    // where each of `named` first appears in the pattern
+    pub named_spans: Vec<Range<usize>>,
+}
+
+// What `rewrite_extensions` made of a pattern.
+struct Rewritten {
+    pattern: String,
+    // the offset in the original pattern of each byte of `pattern`, and of
+    // its end
+    origins: Vec<usize>,
+    calls: Vec<(CallTarget, Range<usize>)>,
+    named: Vec<String>,
+    named_spans: Vec<Range<usize>>,
+    // the offsets in `pattern` of the plain groups `(?|...)` was rewritten
+    // into, dropped again when the captures are renumbered
+    branch_resets: Vec<usize>,
+}
+
+enum CallTarget {
+    Index(u32),
+    Name(String),
+}
+
+// With `allow_duplicate_names` off, a repeated group name is left for
+// regex-syntax to reject. `syntax` holds the options of `parser` that shape
+// the AST, which is parsed again when there are branch reset groups.
+pub fn parse_by_regex_syntax(
+    pattern: &str,
+    parser: &ParserBuilder,
+    syntax: &ast::parse::ParserBuilder,
+    allow_duplicate_names: bool,
+) -> Result<Parsed, Error> {
+    let rewritten = rewrite_extensions(pattern, allow_duplicate_names)?;
+    let ast = parser
+        .build()
+        .parse(&rewritten.pattern)
+        .map_err(|err| syntax_error(err, &rewritten.origins))?;
+    // the AST tells which of the rewritten parens open groups, rather than
+    // sit in classes or comments
+    let mut branch_resets = vec![];
+    if !rewritten.branch_resets.is_empty() {
+        let tree = syntax
+            .build()
+            .parse(&rewritten.pattern)
+            .map_err(|err| syntax_error(err.into(), &rewritten.origins))?;
+        captures_at(&tree, &rewritten.branch_resets, &mut branch_resets);
+    }
+    let hir = renumber_captures(&ast, &mut 1, &branch_resets);
+
+    let calls = rewritten
+        .calls
+        .into_iter()
+        .map(|(target, span)| {
+            let found = match &target {
+                CallTarget::Index(0) => return Ok(0),
+                CallTarget::Index(index) => find_capture(&hir, |c| c.index == *index),
+                CallTarget::Name(name) => find_capture(&hir, |c| c.name.as_deref() == Some(name)),
+            };
+            found.map(|c| c.index).ok_or_else(|| {
+                let target = match target {
+                    CallTarget::Index(index) => index.to_string(),
+                    CallTarget::Name(name) => name,
+                };
+                let message = format!("reference to non-existent subpattern {}", target);
+                Error::Parse { message, span }
+            })
+        })
+        .collect::<Result<Vec<u32>, Error>>()?;
+
+    Ok(Parsed {
+        hir,
+        calls,
+        named: rewritten.named,
+        named_spans: rewritten.named_spans,
+    })
+}
+
+// A regex-syntax error, its span moved back from the rewritten pattern to
+// the original one.
+fn syntax_error(err: regex_syntax::Error, origins: &[usize]) -> Error {
+    let (message, span) = match &err {
+        regex_syntax::Error::Parse(err) => (err.kind().to_string(), err.span()),
+        regex_syntax::Error::Translate(err) => (err.kind().to_string(), err.span()),
+        _ => {
+            let end = origins.last().copied().unwrap_or_default();
+            let message = err.to_string();
+            return Error::Parse {
+                message,
+                span: 0..end,
+            };
+        }
+    };
+    let origin = |offset: usize| origins[offset.min(origins.len() - 1)];
+    Error::Parse {
+        message,
+        span: origin(span.start.offset)..origin(span.end.offset),
+    }
+}
+
+// A HIR built without parsing, such as by `Pattern`, with its captures
+// numbered in order.
+pub fn from_hir(hir: &Hir) -> Parsed {
+    Parsed {
+        hir: renumber_captures(hir, &mut 1, &[]),
+        calls: vec![],
+        named: vec![],
+        named_spans: vec![],
+    }
+}
+
+// The indices of the capture groups of `ast` opening at one of `offsets`.
+fn captures_at(ast: &Ast, offsets: &[usize], indices: &mut Vec<u32>) {
+    match ast {
+        Ast::Group(group) => {
+            if let GroupKind::CaptureIndex(index) = group.kind
+                && offsets.contains(&group.span.start.offset)
+            {
+                indices.push(index);
+            }
+            captures_at(&group.ast, offsets, indices);
+        }
+        Ast::Repetition(repetition) => captures_at(&repetition.ast, offsets, indices),
+        Ast::Concat(concat) => concat
+            .asts
+            .iter()
+            .for_each(|ast| captures_at(ast, offsets, indices)),
+        Ast::Alternation(alternation) => alternation
+            .asts
+            .iter()
+            .for_each(|ast| captures_at(ast, offsets, indices)),
+        _ => (),
+    }
+}
+
+pub fn find_capture(hir: &Hir, pred: impl Fn(&Capture) -> bool + Copy) -> Option<&Capture> {
+    match hir.kind() {
+        HirKind::Capture(capture) if pred(capture) => Some(capture),
+        HirKind::Capture(capture) => find_capture(&capture.sub, pred),
+        HirKind::Repetition(repetition) => find_capture(&repetition.sub, pred),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => {
+            hirs.iter().find_map(|h| find_capture(h, pred))
+        }
+        _ => None,
+    }
+}
+
+fn parse_call(group: &str) -> Option<CallTarget> {
+    if group == "R" {
+        Some(CallTarget::Index(0))
+    } else if !group.is_empty() && group.chars().all(|c| c.is_ascii_digit()) {
+        group.parse().ok().map(CallTarget::Index)
+    } else {
+        group
+            .strip_prefix('&')
+            .or_else(|| group.strip_prefix("P>"))
+            .filter(|name| !name.is_empty())
+            .map(|name| CallTarget::Name(name.to_string()))
+    }
+}
+
+// The char a `\x`, `\u` or `\U` escape stands for, `chars` being just
+// past the letter; `None` for other escapes and malformed ones, which are
+// left to regex-syntax.
+fn escaped_char(
+    escape: char,
+    chars: &std::iter::Peekable<std::str::CharIndices>,
+) -> Option<(char, usize)> {
+    let width = match escape {
+        'x' => 2,
+        'u' => 4,
+        'U' => 8,
+        _ => return None,
+    };
+    let mut chars = chars.clone();
+    let digits: String = match chars.peek() {
+        Some((_, '{')) => {
+            chars.next();
+            let digits: String = chars
+                .by_ref()
+                .map(|(_, c)| c)
+                .take_while(|&c| c != '}')
+                .collect();
+            digits.trim().to_string()
+        }
+        _ => chars.by_ref().take(width).map(|(_, c)| c).collect(),
+    };
+    let c = u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(char::from_u32)?;
+    let end = chars.peek().map(|(end, _)| *end);
+    Some((c, end.unwrap_or(usize::MAX)))
+}
+
+fn rewrite_extensions(pattern: &str, allow_duplicate_names: bool) -> Result<Rewritten, Error> {
+    let mut rewritten = String::with_capacity(pattern.len());
+    let mut origins = Vec::with_capacity(pattern.len() + 1);
+    let mut chars = pattern.char_indices().peekable();
+    let mut in_class = false;
+    let mut branch_resets = vec![];
+    let mut calls = vec![];
+    let mut named: Vec<String> = vec![];
+    let mut named_spans = vec![];
+    let mut names: Vec<String> = vec![];
+    let mut duplicates = 0;
+    let next_is = |chars: &mut std::iter::Peekable<std::str::CharIndices>, c: char| {
+        chars.peek().is_some_and(|&(_, next)| next == c)
+    };
+    let reserved = |c: char, span: Range<usize>| Error::Parse {
+        message: format!("U+{:04X} is reserved for the pattern extensions", c as u32),
+        span,
+    };
+
+    while let Some((start, c)) = chars.next() {
+        // what the previous char was rewritten into came from it
+        let from = origins.last().copied().unwrap_or_default();
+        origins.resize(rewritten.len(), from);
+        origins.push(start);
+        if c == '[' && !in_class {
+            in_class = true;
+            rewritten.push(c);
+            // a leading `]` (optionally after `^`) is a literal
+            if next_is(&mut chars, '^') {
+                rewritten.push(chars.next().unwrap().1);
+            }
+            if next_is(&mut chars, ']') {
+                rewritten.push(chars.next().unwrap().1);
+            }
+            continue;
+        }
+        if c == ']' && in_class {
+            in_class = false;
+        }
+        if c == '(' && !in_class && next_is(&mut chars, '?') {
+            let group: String = chars
+                .clone()
+                .skip(1)
+                .map(|(_, c)| c)
+                .take_while(|&c| c != ')')
+                .collect();
+
+            if group.starts_with('|') {
+                chars.nth(1);
+                branch_resets.push(rewritten.len());
+                rewritten.push('(');
+                continue;
+            }
+            let name = group
+                .strip_prefix("P<")
+                .or_else(|| group.strip_prefix('<'))
+                .and_then(|rest| rest.split_once('>'))
+                .map(|(name, _)| name.to_string());
+            if let Some(name) = name.filter(|name| !name.is_empty()) {
+                if allow_duplicate_names && names.contains(&name) {
+                    // `?`, the opening and the name
+                    chars.nth(group.find(&name).unwrap() + name.len());
+                    rewritten.push_str(&format!(
+                        "(?P<{}{}{}",
+                        name, DUPLICATE_NAME_SUFFIX, duplicates
+                    ));
+                    duplicates += 1;
+                    continue;
+                }
+                names.push(name);
+            }
+            if let Some(target) = parse_call(&group) {
+                // `?`, the group body and `)`
+                chars.nth(group.chars().count() + 1);
+                let sentinel = CALL_SENTINEL_BASE as u32 + calls.len() as u32;
+                rewritten.push_str(&format!("\\x{{{:X}}}", sentinel));
+                // `(?`, the group body and `)`
+                calls.push((target, start..start + group.len() + 3));
+                continue;
+            }
+        }
+        if is_sentinel(c) {
+            return Err(reserved(c, start..start + c.len_utf8()));
+        }
+        if c != '\\' {
+            rewritten.push(c);
+            continue;
+        }
+
+        let escape = chars.next().map(|(_, c)| c);
+        if let Some((escaped, end)) = escape.and_then(|escape| escaped_char(escape, &chars))
+            && is_sentinel(escaped)
+        {
+            return Err(reserved(escaped, start..end.min(pattern.len())));
+        }
+        match escape {
+            Some('G') => rewritten.push_str(&format!("\\x{{{:X}}}", CONTINUATION_SENTINEL as u32)),
+            // `\m{name}`, left to regex-syntax to reject inside classes
+            Some('m') if !in_class && next_is(&mut chars, '{') => {
+                let name: String = chars
+                    .clone()
+                    .skip(1)
+                    .map(|(_, c)| c)
+                    .take_while(|&c| c != '}')
+                    .collect();
+                // `{`, the name and `}`
+                chars.nth(name.chars().count() + 1);
+                let index = match named.iter().position(|known| *known == name) {
+                    Some(index) => index,
+                    None => {
+                        // `\m{`, the name and `}`
+                        named_spans.push(start..start + name.len() + 4);
+                        named.push(name);
+                        named.len() - 1
+                    }
+                };
+                let sentinel = NAMED_SENTINEL_BASE as u32 + index as u32;
+                rewritten.push_str(&format!("\\x{{{:X}}}", sentinel));
+            }
+            Some(escaped) => {
+                rewritten.push(c);
+                rewritten.push(escaped);
+            }
+            None => rewritten.push(c),
+        }
+    }
+
+    let from = origins.last().copied().unwrap_or_default();
+    origins.resize(rewritten.len(), from);
+    origins.push(pattern.len());
+    Ok(Rewritten {
+        pattern: rewritten,
+        origins,
+        calls,
+        named,
+        named_spans,
+        branch_resets,
+    })
+}
+
+// Assigns capture indices in pattern order, letting every branch of a branch
+// reset group start from the same index. `branch_resets` holds the indices
+// of those groups before renumbering, as `parse_by_regex_syntax` found them.
+fn renumber_captures(hir: &Hir, next: &mut u32, branch_resets: &[u32]) -> Hir {
+    match hir.kind() {
+        HirKind::Capture(capture) => {
+            if branch_resets.contains(&capture.index) {
+                return reset_branches(&capture.sub, next, branch_resets);
+            }
+
+            let index = *next;
+            *next += 1;
+            Hir::capture(Capture {
+                index,
+                name: capture
+                    .name
+                    .as_ref()
+                    .map(|name| match name.find(DUPLICATE_NAME_SUFFIX) {
+                        Some(end) => name[..end].into(),
+                        None => name.clone(),
+                    }),
+                sub: Box::new(renumber_captures(&capture.sub, next, branch_resets)),
+            })
+        }
+        HirKind::Repetition(repetition) => Hir::repetition(Repetition {
+            sub: Box::new(renumber_captures(&repetition.sub, next, branch_resets)),
+            ..repetition.clone()
+        }),
+        HirKind::Concat(hirs) => Hir::concat(
+            hirs.iter()
+                .map(|h| renumber_captures(h, next, branch_resets))
+                .collect(),
+        ),
+        HirKind::Alternation(hirs) => Hir::alternation(
+            hirs.iter()
+                .map(|h| renumber_captures(h, next, branch_resets))
+                .collect(),
+        ),
+        _ => hir.clone(),
+    }
+}
+
+fn reset_branches(hir: &Hir, next: &mut u32, branch_resets: &[u32]) -> Hir {
+    match hir.kind() {
+        HirKind::Alternation(hirs) => {
+            let first = *next;
+            let mut last = first;
+            let branches = hirs
+                .iter()
+                .map(|h| {
+                    *next = first;
+                    let branch = renumber_captures(h, next, branch_resets);
+                    last = last.max(*next);
+                    branch
+                })
+                .collect();
+            *next = last;
+
+            Hir::alternation(branches)
+        }
+        // regex-syntax may lift a common prefix out of the alternation
+        HirKind::Concat(hirs) => Hir::concat(
+            hirs.iter()
+                .map(|h| reset_branches(h, next, branch_resets))
+                .collect(),
+        ),
+        _ => renumber_captures(hir, next, branch_resets),
+    }
 }
 
 #[cfg(test)]
@@ -13,6 +461,169 @@ mod test {
 
     #[test]
     fn test_parse_by_regex_syntax() {
-        println!("{:?}", parse_by_regex_syntax("1{2,}"));
+        println!(
+            "{:?}",
+            parse_by_regex_syntax("1{2,}", &ParserBuilder::new(), &Default::default(), true)
+        );
+    }
+
+    #[test]
+    fn test_branch_reset_numbering() {
+        let hir = parse_by_regex_syntax(
+            "(?|(a)|(b)(c))(d)",
+            &ParserBuilder::new(),
+            &Default::default(),
+            true,
+        )
+        .unwrap()
+        .hir;
+
+        assert_eq!(hir.properties().explicit_captures_len(), 4);
+        assert_eq!(format!("{:?}", hir).matches("index: 1").count(), 2);
+        assert_eq!(format!("{:?}", hir).matches("index: 3").count(), 1);
+
+        let parse = |pattern| {
+            parse_by_regex_syntax(pattern, &ParserBuilder::new(), &Default::default(), true)
+                .unwrap()
+                .hir
+        };
+        let hir = parse("(a)(?|(b)|(?<n>c)(d))(e)");
+        let name = |index| find_capture(&hir, |c| c.index == index).map(|c| c.name.clone());
+        assert_eq!(name(2), Some(None));
+        assert_eq!(name(3), Some(None));
+        assert_eq!(name(4), Some(None));
+        assert!(find_capture(&hir, |c| c.name.as_deref() == Some("n") && c.index == 2).is_some());
+        assert!(name(5).is_none());
+        // escaped and class parens open no group
+        let hir = parse("\\(([(])(?|(x)|(y))");
+        assert!(find_capture(&hir, |c| c.index == 2).is_some());
+        assert!(find_capture(&hir, |c| c.index == 3).is_none());
+        // nor do the parens of a POSIX class or of a comment
+        for pattern in ["[[:digit:](](?|(a)|(b))(c)", "(?x)a#(\n(?|(a)|(b))(c)"] {
+            let hir = parse(pattern);
+            assert!(
+                find_capture(&hir, |c| c.index == 2).is_some(),
+                "{}",
+                pattern
+            );
+            assert!(
+                find_capture(&hir, |c| c.index == 3).is_none(),
+                "{}",
+                pattern
+            );
+        }
+
+        // a name that looks like the ones of the rewriter is just a name
+        let hir = parse("(?<__rsgex_branch_reset_0>a)|(b)");
+        let named = find_capture(&hir, |c| c.index == 1).unwrap();
+        assert_eq!(named.name.as_deref(), Some("__rsgex_branch_reset_0"));
+        assert!(find_capture(&hir, |c| c.index == 2).is_some());
+    }
+
+    #[test]
+    fn test_call_resolution() {
+        let parsed = parse_by_regex_syntax(
+            "(a)(?<b>b)(?R)(?&b)(?P>b)(?1)",
+            &ParserBuilder::new(),
+            &Default::default(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(parsed.calls, vec![0, 2, 2, 1]);
+
+        assert!(
+            parse_by_regex_syntax("(a)(?2)", &ParserBuilder::new(), &Default::default(), true)
+                .is_err()
+        );
+        assert!(
+            parse_by_regex_syntax("(a)(?&b)", &ParserBuilder::new(), &Default::default(), true)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let parsed = parse_by_regex_syntax(
+            "(?<y>a)|(?P<y>b)",
+            &ParserBuilder::new(),
+            &Default::default(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(format!("{:?}", parsed.hir).matches("\"y\"").count(), 2);
+
+        assert!(
+            parse_by_regex_syntax(
+                "(?<y>a)|(?P<y>b)",
+                &ParserBuilder::new(),
+                &Default::default(),
+                false
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_rewrite_extensions() {
+        assert_eq!(
+            rewrite_extensions("\\Ga\\\\G", true).unwrap().pattern,
+            "\\x{FDD0}a\\\\G"
+        );
+        assert_eq!(
+            rewrite_extensions("(a(?1))", true).unwrap().pattern,
+            "(a\\x{F0000})"
+        );
+    }
+
+    #[test]
+    fn test_reserved_chars() {
+        let parse = |pattern| {
+            parse_by_regex_syntax(pattern, &ParserBuilder::new(), &Default::default(), true)
+        };
+        for pattern in ["\u{F0000}", "\\x{F0000}", "\\U000F0001", "[\\x{ FFFFD }]"] {
+            assert!(parse(pattern).is_err(), "{:?}", pattern);
+        }
+        let err = parse("a\\x{F0000}b").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "U+F0000 is reserved for the pattern extensions"
+        );
+        assert_eq!(err.span(), Some(1..10));
+        // would be taken for `\G`
+        let err = parse("\u{FDD0}|\\x{FDD0}").unwrap_err();
+        assert_eq!(err.span(), Some(0..3));
+        assert_eq!(parse("a|\\x{FDD0}").unwrap_err().span(), Some(2..10));
+        // would be taken for `\m{…}` once a matcher is defined
+        assert!(parse("\u{100000}").is_err());
+        assert!(parse("\\U{100001}").is_err());
+        // an escaped backslash, then plain text
+        assert!(parse("\\\\U000F0000").is_ok());
+        assert!(parse("\\x{EFFFF}").is_ok());
+
+        let hir = Hir::literal("\u{F0000}".as_bytes());
+        assert!(contains_reserved(&Hir::concat(vec![
+            Hir::dot(regex_syntax::hir::Dot::AnyChar),
+            hir
+        ])));
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let parse = |pattern| {
+            parse_by_regex_syntax(pattern, &ParserBuilder::new(), &Default::default(), true)
+        };
+        let err = parse("a(b").unwrap_err();
+        assert_eq!(err.to_string(), "unclosed group");
+        assert_eq!(err.span(), Some(1..2));
+        // offsets after a rewritten extension refer to the original pattern
+        let err = parse("\\G(?1)é{2,1}").unwrap_err();
+        assert_eq!(err.span(), Some(8..13));
+        assert_eq!(
+            err.diagnostic("\\G(?1)é{2,1}"),
+            format!("{}\n\\G(?1)é{{2,1}}\n       ^^^^^", err)
+        );
+        let err = parse("(a)(?&b)").unwrap_err();
+        assert_eq!(err.to_string(), "reference to non-existent subpattern b");
+        assert_eq!(err.span(), Some(3..8));
     }
 }
@@ -0,0 +1,173 @@
+use std::ops::RangeInclusive;
+
+use regex_syntax::hir::{
+    Capture, Class, ClassUnicode, ClassUnicodeRange, Dot, Hir, HirKind, Look, Repetition,
+};
+
+use super::{builder::EngineBuilder, engine::Engine, error::Error};
+
+// A pattern put together from typed parts instead of pattern text, so
+// literals taken from user data never need escaping:
+// `Pattern::literal("ab").then(Pattern::class('0'..='9').plus()).capture("num")`.
+// It compiles straight from the HIR the parser would have produced; see
+// `EngineBuilder::build_pattern`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    hir: Hir,
+}
+
+impl Pattern {
+    // Matches `text` exactly, whatever chars it holds.
+    pub fn literal(text: &str) -> Pattern {
+        Pattern {
+            hir: Hir::literal(text.as_bytes()),
+        }
+    }
+
+    pub fn class(range: RangeInclusive<char>) -> Pattern {
+        Pattern::classes([range])
+    }
+
+    // Matches a char in any of `ranges`.
+    pub fn classes(ranges: impl IntoIterator<Item = RangeInclusive<char>>) -> Pattern {
+        let ranges = ranges
+            .into_iter()
+            .map(|range| ClassUnicodeRange::new(*range.start(), *range.end()));
+        Pattern {
+            hir: Hir::class(Class::Unicode(ClassUnicode::new(ranges))),
+        }
+    }
+
+    // Matches any char, line feeds included.
+    pub fn any() -> Pattern {
+        Pattern {
+            hir: Hir::dot(Dot::AnyChar),
+        }
+    }
+
+    // `^`, the start of the input
+    pub fn start() -> Pattern {
+        Pattern {
+            hir: Hir::look(Look::Start),
+        }
+    }
+
+    // `$`, the end of the input
+    pub fn end() -> Pattern {
+        Pattern {
+            hir: Hir::look(Look::End),
+        }
+    }
+
+    pub fn then(self, next: Pattern) -> Pattern {
+        Pattern {
+            hir: Hir::concat(vec![self.hir, next.hir]),
+        }
+    }
+
+    // Tries `self` first, like `self|other`.
+    pub fn or(self, other: Pattern) -> Pattern {
+        Pattern {
+            hir: Hir::alternation(vec![self.hir, other.hir]),
+        }
+    }
+
+    // `{min,max}`, unbounded without `max`
+    pub fn repeat(self, min: u32, max: Option<u32>) -> Pattern {
+        Pattern {
+            hir: Hir::repetition(Repetition {
+                min,
+                max,
+                greedy: true,
+                sub: Box::new(self.hir),
+            }),
+        }
+    }
+
+    pub fn star(self) -> Pattern {
+        self.repeat(0, None)
+    }
+
+    pub fn plus(self) -> Pattern {
+        self.repeat(1, None)
+    }
+
+    pub fn optional(self) -> Pattern {
+        self.repeat(0, Some(1))
+    }
+
+    // Makes the repetition just built lazy, like `*?`; other patterns are
+    // left as they are.
+    pub fn lazy(self) -> Pattern {
+        match self.hir.kind() {
+            HirKind::Repetition(repetition) => Pattern {
+                hir: Hir::repetition(Repetition {
+                    greedy: false,
+                    ..repetition.clone()
+                }),
+            },
+            _ => self,
+        }
+    }
+
+    // A group named `name`; groups are numbered in order when compiled.
+    pub fn capture(self, name: &str) -> Pattern {
+        Pattern {
+            hir: Hir::capture(Capture {
+                index: 0,
+                name: Some(name.into()),
+                sub: Box::new(self.hir),
+            }),
+        }
+    }
+
+    pub(crate) fn hir(&self) -> &Hir {
+        &self.hir
+    }
+}
+
+impl TryFrom<&Pattern> for Engine {
+    type Error = Error;
+
+    fn try_from(pattern: &Pattern) -> Result<Engine, Self::Error> {
+        EngineBuilder::new().build_pattern(pattern)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Pattern;
+    use crate::rsgex::engine::Engine;
+
+    #[test]
+    fn test_pattern() {
+        let number = Pattern::class('0'..='9').plus().capture("num");
+        let pattern = Pattern::literal("a.b(").then(number);
+        let e = Engine::try_from(&pattern).unwrap();
+
+        let m = e.find_at("x a.b(42", 0).unwrap();
+        assert_eq!((m.start, m.end), (2, 8));
+        assert_eq!(m.groups["num"], "42");
+        // the literal is not a pattern
+        assert!(!e.test("axb(1"));
+
+        let word = Pattern::classes(['a'..='z', 'A'..='Z']).plus();
+        let pattern = Pattern::start()
+            .then(word.clone().capture("key"))
+            .then(Pattern::literal("=").or(Pattern::literal(": ")))
+            .then(Pattern::any().star().lazy().capture("value"))
+            .then(Pattern::end());
+        let m = Engine::try_from(&pattern)
+            .unwrap()
+            .find_at("k: v\n", 0)
+            .unwrap();
+        assert_eq!(
+            (m.groups["key"].as_str(), m.groups["value"].as_str()),
+            ("k", "v\n")
+        );
+        assert_eq!(
+            word.clone().optional().lazy(),
+            word.repeat(0, Some(1)).lazy()
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use super::{
+    error::Error,
+    haystack::Haystack,
+    nfa::{Budget, CaptureGroupRange, NFAutomata, captured_groups},
+    search::Match,
+};
+
+// A thread waiting on one consuming transition, or on acceptance when
+// `transition` is `None`. Threads are kept in priority order, which is the
+// order the backtracker would try them in, so both report the same match.
+struct Thread {
+    state: usize,
+    transition: Option<usize>,
+    start: usize,
+    groups: Vec<Option<CaptureGroupRange>>,
+}
+
+enum Work {
+    Visit(usize, Vec<Option<CaptureGroupRange>>),
+    Emit(usize, usize, Vec<Option<CaptureGroupRange>>),
+}
+
+// Simulates all threads in lockstep, one input char per step. Every state is
+// entered at most once per step, so a search takes O(chars × states) time.
+// Subroutine calls, counter registers and multi-char transitions are not
+// supported, `EngineBuilder` rejects or unrolls them for this backend.
+pub struct PikeVm<'a> {
+    nfa: &'a NFAutomata,
+    haystack: Haystack<'a>,
+    search_start: usize,
+    // position at which each state was last entered
+    seen: Vec<usize>,
+}
+
+impl<'a> PikeVm<'a> {
+    pub fn new(nfa: &'a NFAutomata, haystack: Haystack<'a>, search_start: usize) -> Self {
+        Self {
+            nfa,
+            haystack,
+            search_start,
+            seen: vec![usize::MAX; nfa.states.len()],
+        }
+    }
+
+    // Returns the leftmost-first match starting at `at`, or at any later position when not `anchored`. `budget` is
+    // ticked once per char.
+    pub fn search(
+        &mut self,
+        at: usize,
+        anchored: bool,
+        budget: &mut Budget,
+    ) -> Result<Option<Match>, Error> {
+        let mut current: Vec<Thread> = vec![];
+        let mut next: Vec<Thread> = vec![];
+        let mut matched = None;
+
+        let mut i = at;
+        while i <= self.haystack.len() {
+            budget.tick()?;
+            let next_i = self.haystack.next(i);
+            // a thread started here has the lowest priority
+            if matched.is_none() && (i == at || !anchored) {
+                self.add_thread(&mut current, self.nfa.initial, i, i, vec![]);
+            }
+            // an unanchored search seeds a thread at every later position
+            if current.is_empty() && (anchored || matched.is_some()) {
+                break;
+            }
+
+            for thread in current.drain(..) {
+                let Some(transition) = thread.transition else {
+                    // threads behind an accepting one are cut off
+                    matched = Some((thread.start, i, thread.groups));
+                    break;
+                };
+                let (matcher, to) = &self.nfa.states[thread.state].matchers[transition];
+                if i < self.haystack.len()
+                    && matcher.matches_at(&self.haystack, i, self.search_start)
+                {
+                    self.add_thread(&mut next, *to, next_i, thread.start, thread.groups);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            i = next_i;
+        }
+
+        Ok(matched.map(|(start, end, groups)| Match {
+            start,
+            end,
+            groups: captured_groups(&self.haystack, &groups),
+        }))
+    }
+
+    // Follows the epsilon closure of `state` at `i` depth first, queueing a
+    // thread for every consuming transition and accepting state reached.
+    fn add_thread(
+        &mut self,
+        list: &mut Vec<Thread>,
+        state: usize,
+        i: usize,
+        start: usize,
+        groups: Vec<Option<CaptureGroupRange>>,
+    ) {
+        let mut stack = vec![Work::Visit(state, groups)];
+
+        while let Some(work) = stack.pop() {
+            let (state_index, mut groups) = match work {
+                Work::Emit(state, transition, groups) => {
+                    list.push(Thread {
+                        state,
+                        transition: Some(transition),
+                        start,
+                        groups,
+                    });
+                    continue;
+                }
+                Work::Visit(state, groups) => (state, groups),
+            };
+            if self.seen[state_index] == i {
+                continue;
+            }
+            self.seen[state_index] = i;
+
+            let current_state = &self.nfa.states[state_index];
+            current_state.mark_groups(&mut groups, i);
+
+            if current_state.is_ending {
+                list.push(Thread {
+                    state: state_index,
+                    transition: None,
+                    start,
+                    groups,
+                });
+                continue;
+            }
+
+            for (transition, (matcher, to)) in current_state.matchers.iter().enumerate().rev() {
+                if !matcher.is_epsilon() {
+                    stack.push(Work::Emit(state_index, transition, groups.clone()));
+                } else if matcher.matches_at(&self.haystack, i, self.search_start) {
+                    stack.push(Work::Visit(*to, groups.clone()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PikeVm;
+    use crate::rsgex::{
+        builder::EngineBuilder,
+        engine::{Backend, Engine},
+        haystack::Haystack,
+        nfa::Budget,
+    };
+
+    fn search(pattern: &str, input: &str) -> Option<(usize, usize)> {
+        let e = EngineBuilder::new()
+            .backend(Backend::PikeVm)
+            .build(pattern)
+            .unwrap();
+        PikeVm::new(&e.nfa, Haystack::Str(input), 0)
+            .search(0, false, &mut Budget::default())
+            .unwrap()
+            .map(|m| (m.start, m.end))
+    }
+
+    #[test]
+    fn test_search() {
+        assert_eq!(search("b+", "abbbc"), Some((1, 4)));
+        assert_eq!(search("b+", "ébbc"), Some((2, 4)));
+        assert_eq!(search("b+?", "abbbc"), Some((1, 2)));
+        assert_eq!(search("a|ab", "xab"), Some((1, 2)));
+        assert_eq!(search("x*", "abc"), Some((0, 0)));
+        assert_eq!(search("c$", "abc"), Some((2, 3)));
+        assert_eq!(search("d", "abc"), None);
+    }
+
+    #[test]
+    fn test_unanchored_look() {
+        let cases = [
+            ("\\bb", " ab b"),
+            ("$x?", "c"),
+            ("(?m)^b", "ab\nb"),
+            ("\\Bb+", "b abb"),
+            ("a\\b", "aa a"),
+        ];
+        for (pattern, input) in cases {
+            let expected = Engine::try_from(pattern).unwrap().find_at(input, 0);
+            assert!(expected.is_some());
+            for backend in [Backend::PikeVm, Backend::BoundedBacktrack] {
+                let e = EngineBuilder::new()
+                    .backend(backend)
+                    .build(pattern)
+                    .unwrap();
+                assert_eq!(e.find_at(input, 0), expected, "{} on {:?}", pattern, input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lazy_counted_repetition() {
+        // unrolled for the Pike VM, each optional copy is skipped first
+        assert_eq!(search("(?:[^a]){2,4}?", "bbcc"), Some((0, 2)));
+        assert_eq!(search("(?:..|b){0,2}?", "cbc"), Some((0, 0)));
+        assert_eq!(search("x(?:..|b){1,3}?c", "xbbc"), Some((0, 4)));
+        for (pattern, input) in [("(?:[^a]){2,4}?", "bbcc"), ("(b|c){1,3}?c", "bcbc")] {
+            let expected = Engine::try_from(pattern).unwrap().find_at(input, 0);
+            let e = EngineBuilder::new()
+                .backend(Backend::PikeVm)
+                .build(pattern)
+                .unwrap();
+            assert_eq!(e.find_at(input, 0), expected, "{} on {:?}", pattern, input);
+        }
+    }
+
+    #[test]
+    fn test_linear_time() {
+        // exponential for the backtracker
+        let input = "a".repeat(64);
+        assert_eq!(search("(a*)*b", &input), None);
+        assert_eq!(search("(a|aa)+$", &input), Some((0, 64)));
+    }
+}
@@ -0,0 +1,323 @@
+use regex_syntax::hir::{
+    Hir, HirKind,
+    literal::{ExtractKind, Extractor, Seq},
+};
+
+use super::{aho_corasick::AhoCorasick, byte_set::ByteSet, haystack::Haystack, parser};
+
+// literals extracted at most, enough for alternations of a few hundred
+// keywords
+const MAX_LITERALS: usize = 1000;
+
+// chars of a class enumerated for its first bytes
+const MAX_START_CLASS: usize = 256;
+
+// start bytes that still rule out enough positions to be worth scanning for
+const MAX_START_BYTES: usize = 128;
+
+// A literal every match contains. `text` is set when it is UTF-8 and can be
+// searched for in a `&str`.
+pub struct Literal {
+    bytes: Vec<u8>,
+    text: Option<String>,
+}
+
+impl Literal {
+    fn new(bytes: &[u8]) -> Option<Literal> {
+        (!bytes.is_empty()).then(|| Literal {
+            bytes: bytes.to_vec(),
+            text: String::from_utf8(bytes.to_vec()).ok(),
+        })
+    }
+
+    // `None` when the literal does not occur at or after `at`, `Some(at)`
+    // when it cannot be searched for.
+    fn find(&self, haystack: &Haystack, at: usize) -> Option<usize> {
+        match (haystack, &self.text) {
+            (Haystack::Str(s), Some(text)) => s.get(at..)?.find(text.as_str()).map(|i| at + i),
+            // a Latin-1 literal never occurs in UTF-8 as is
+            (Haystack::Str(_), None) => Some(at),
+            (Haystack::Bytes(b), _) => b
+                .get(at..)?
+                .windows(self.bytes.len())
+                .position(|window| window == self.bytes)
+                .map(|i| at + i),
+            // the literal may be split between pieces
+            (Haystack::Segments(_), _) => Some(at),
+        }
+    }
+}
+
+// Skips to the positions a match can start at without running the
+// automaton.
+pub enum Prefilter {
+    // every match starts with the literal
+    Prefix(Literal),
+    // every match starts with one of several literals; `utf8` is set when
+    // they can all be searched for in a `&str`
+    Literals {
+        automaton: Box<AhoCorasick>,
+        utf8: bool,
+    },
+    // every match contains the literal, at most `max_offset` bytes after its
+    // start when that is bounded
+    Inner {
+        literal: Literal,
+        max_offset: Option<usize>,
+    },
+    // every match starts with one of the bytes; `utf8` is set when they
+    // start UTF-8 chars and can be searched for in a `&str`
+    StartBytes {
+        set: ByteSet,
+        utf8: bool,
+    },
+}
+
+impl Prefilter {
+    // `exact_lengths` is false when the HIR's lengths are not those of the
+    // matched text, i.e. with subroutine calls or in grapheme mode.
+    pub fn new(hir: &Hir, exact_lengths: bool) -> Option<Prefilter> {
+        if let Some(prefix) = required_prefix(hir) {
+            return Some(Prefilter::Prefix(prefix));
+        }
+        if let Some(literals) = prefixes(hir).literals()
+            && literals.len() > 1
+        {
+            let literals: Vec<&[u8]> = literals
+                .iter()
+                .map(|literal| without_sentinels(literal.as_bytes()))
+                .collect();
+            // an empty literal matches anywhere
+            if literals.iter().all(|literal| !literal.is_empty()) {
+                return Some(Prefilter::Literals {
+                    automaton: Box::new(AhoCorasick::new(&literals)),
+                    utf8: literals.iter().all(|l| std::str::from_utf8(l).is_ok()),
+                });
+            }
+        }
+
+        // the longest literal starting one of the top-level concatenation's
+        // suffixes
+        let mut hir = hir;
+        while let HirKind::Capture(capture) = hir.kind() {
+            hir = &capture.sub;
+        }
+        let children = match hir.kind() {
+            HirKind::Concat(children) => children.as_slice(),
+            _ => &[],
+        };
+        (1..children.len())
+            .filter_map(|i| {
+                let literal = required_prefix(&Hir::concat(children[i..].to_vec()))?;
+                let before = Hir::concat(children[..i].to_vec());
+                let max_offset = before.properties().maximum_len().filter(|_| exact_lengths);
+                Some(Prefilter::Inner {
+                    literal,
+                    max_offset,
+                })
+            })
+            .max_by_key(|prefilter| match prefilter {
+                Prefilter::Inner { literal, .. } => literal.bytes.len(),
+                _ => unreachable!(),
+            })
+            .or_else(|| start_bytes(hir))
+    }
+
+    // The first position at or after `at` where a match can start, `None`
+    // when there is none.
+    pub fn candidate(&self, haystack: &Haystack, at: usize) -> Option<usize> {
+        match self {
+            Prefilter::Prefix(prefix) => prefix.find(haystack, at),
+            Prefilter::Literals { automaton, utf8 } => match haystack {
+                Haystack::Str(s) if *utf8 => automaton.find(s.as_bytes(), at),
+                Haystack::Str(_) | Haystack::Segments(_) => Some(at),
+                Haystack::Bytes(b) => automaton.find(b, at),
+            },
+            Prefilter::Inner {
+                literal,
+                max_offset,
+            } => {
+                let found = literal.find(haystack, at)?;
+                let Some(max_offset) = max_offset else {
+                    return Some(at);
+                };
+                // the start of a match reaching the literal, moved onto a
+                // char boundary
+                let mut start = found.saturating_sub(*max_offset).max(at);
+                if let Haystack::Str(s) = haystack {
+                    while !s.is_char_boundary(start) {
+                        start += 1;
+                    }
+                }
+                Some(start)
+            }
+            Prefilter::StartBytes { set, utf8 } => match haystack {
+                Haystack::Str(s) if *utf8 => set.find(s.as_bytes(), at),
+                Haystack::Str(_) => Some(at),
+                Haystack::Bytes(b) => set.find(b, at),
+                Haystack::Segments(segments) if *utf8 => {
+                    segments.find_byte(at, |b, at| set.find(b, at))
+                }
+                Haystack::Segments(_) => Some(at),
+            },
+        }
+    }
+}
+
+fn prefixes(hir: &Hir) -> Seq {
+    Extractor::new()
+        .kind(ExtractKind::Prefix)
+        .limit_total(MAX_LITERALS)
+        .extract(hir)
+}
+
+// The first bytes of the matches, unless there are too many of them to
+// skip much.
+fn start_bytes(hir: &Hir) -> Option<Prefilter> {
+    // a char is at most 4 bytes, so the first one stays whole
+    let seq = Extractor::new()
+        .kind(ExtractKind::Prefix)
+        .limit_class(MAX_START_CLASS)
+        .limit_literal_len(4)
+        .limit_total(MAX_LITERALS)
+        .extract(hir);
+    let literals: Vec<&[u8]> = seq
+        .literals()?
+        .iter()
+        .map(|literal| without_sentinels(literal.as_bytes()))
+        .collect();
+
+    let set = ByteSet::new(
+        literals
+            .iter()
+            .map(|literal| literal.first().copied())
+            .collect::<Option<Vec<u8>>>()?,
+    );
+    (set.len() < MAX_START_BYTES).then(|| Prefilter::StartBytes {
+        set,
+        utf8: literals.iter().all(|literal| {
+            literal
+                .utf8_chunks()
+                .next()
+                .is_some_and(|chunk| !chunk.valid().is_empty())
+        }),
+    })
+}
+
+fn required_prefix(hir: &Hir) -> Option<Literal> {
+    Literal::new(without_sentinels(prefixes(hir).longest_common_prefix()?))
+}
+
+// `\G` and subroutine calls reach the HIR as literal sentinels, which the
+// haystack never contains.
+fn without_sentinels(prefix: &[u8]) -> &[u8] {
+    let mut end = 0;
+    for chunk in prefix.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if parser::is_sentinel(c) {
+                return &prefix[..end];
+            }
+            end += c.len_utf8();
+        }
+        end += chunk.invalid().len();
+    }
+    prefix
+}
+
+#[cfg(test)]
+mod test {
+    use super::Prefilter;
+    use crate::rsgex::{haystack::Haystack, parser};
+
+    fn prefilter(pattern: &str) -> Option<Prefilter> {
+        let hir =
+            parser::parse_by_regex_syntax(pattern, &Default::default(), &Default::default(), false)
+                .unwrap()
+                .hir;
+        Prefilter::new(&hir, true)
+    }
+
+    fn prefix(pattern: &str) -> Option<Vec<u8>> {
+        match prefilter(pattern)? {
+            Prefilter::Prefix(prefix) => Some(prefix.bytes),
+            _ => None,
+        }
+    }
+
+    fn inner(pattern: &str) -> Option<(Vec<u8>, Option<usize>)> {
+        match prefilter(pattern)? {
+            Prefilter::Inner {
+                literal,
+                max_offset,
+            } => Some((literal.bytes, max_offset)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(prefix("ERROR: \\d+"), Some(b"ERROR: ".to_vec()));
+        assert_eq!(prefix("foo(bar|baz)"), Some(b"fooba".to_vec()));
+        assert_eq!(prefix("^ab*"), Some(b"a".to_vec()));
+        assert_eq!(prefix("\\Gfoo"), None);
+        assert_eq!(prefix("a|b"), None);
+        assert_eq!(prefix("a*b"), None);
+    }
+
+    #[test]
+    fn test_inner() {
+        assert_eq!(
+            inner("\\w+@example\\.com"),
+            Some((b"@example.com".to_vec(), None))
+        );
+        assert_eq!(
+            inner("(?-u:\\w{2})-(ab|ac)"),
+            Some((b"-a".to_vec(), Some(2)))
+        );
+        assert_eq!(inner("[a-z]+"), None);
+    }
+
+    #[test]
+    fn test_literals() {
+        let filter = prefilter("(foo|bar|baz)\\d").unwrap();
+        assert!(matches!(filter, Prefilter::Literals { utf8: true, .. }));
+        assert_eq!(filter.candidate(&Haystack::Str("xxbaz1 foo"), 0), Some(2));
+        assert_eq!(filter.candidate(&Haystack::Str("xxbaz1 foo"), 3), Some(7));
+        assert_eq!(filter.candidate(&Haystack::Str("ba fo"), 0), None);
+
+        assert!(prefilter("foo|b*").is_none());
+    }
+
+    #[test]
+    fn test_start_bytes() {
+        let filter = prefilter("[a-m]+\\d|x\\s").unwrap();
+        assert!(matches!(filter, Prefilter::StartBytes { utf8: true, .. }));
+        let haystack = Haystack::Str("12 中 b4");
+        assert_eq!(filter.candidate(&haystack, 0), Some(7));
+        assert_eq!(filter.candidate(&haystack, 8), None);
+
+        // starts with the lead byte of `à` to `ï`
+        let filter = prefilter("[à-ï]\\d").unwrap();
+        assert_eq!(filter.candidate(&Haystack::Str("e è9"), 0), Some(2));
+
+        assert!(prefilter("\\w+").is_none());
+        assert!(prefilter("a*").is_none());
+    }
+
+    #[test]
+    fn test_candidate() {
+        let filter = prefilter("é\\d").unwrap();
+        let haystack = Haystack::Str("aé é1");
+        assert_eq!(filter.candidate(&haystack, 0), Some(1));
+        assert_eq!(filter.candidate(&haystack, 3), Some(4));
+        assert_eq!(filter.candidate(&haystack, 6), None);
+
+        let filter = prefilter("(?-u:\\w)é").unwrap();
+        let haystack = Haystack::Str("ab éxé");
+        // one byte before the `é` at 3
+        assert_eq!(filter.candidate(&haystack, 0), Some(2));
+        assert_eq!(filter.candidate(&haystack, 5), Some(5));
+        assert_eq!(filter.candidate(&haystack, 8), None);
+        assert_eq!(prefilter("\\w+é").unwrap().candidate(&haystack, 0), Some(0));
+    }
+}
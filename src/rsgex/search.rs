@@ -0,0 +1,58 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use super::{engine::Engine, haystack::Haystack};
+
+// start/end are byte offsets into the haystack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub groups: HashMap<String, String>,
+}
+
+impl Match {
+    pub fn as_str(&self) -> &str {
+        self.groups
+            .get(&0.to_string())
+            .map(|s| s.as_str())
+            .unwrap_or_default()
+    }
+}
+
+pub struct Matches<'e, 's> {
+    engine: &'e Engine,
+    haystack: Cow<'s, str>,
+    next: usize,
+}
+
+impl<'e, 's> Matches<'e, 's> {
+    pub(crate) fn new(engine: &'e Engine, haystack: Cow<'s, str>) -> Self {
+        Self {
+            engine,
+            haystack,
+            next: 0,
+        }
+    }
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        if self.next > self.haystack.len() {
+            return None;
+        }
+
+        // the previous match end is handed down as the search start so that
+        // `\G` can anchor on it
+        let haystack = Haystack::Str(&self.haystack);
+        let m = self.engine.find_in(&haystack, self.next)?;
+        self.next = if m.end == m.start {
+            haystack.next(m.end)
+        } else {
+            m.end
+        };
+
+        Some(m)
+    }
+}
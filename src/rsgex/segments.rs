@@ -0,0 +1,172 @@
+use super::{engine::Engine, error::Error, haystack::Haystack, search::Match};
+
+// A haystack made of pieces that are not contiguous in memory, like the
+// chunks of a rope or of a network buffer, searched without joining them.
+// Positions are byte offsets into the pieces laid end to end.
+#[derive(Clone, Debug, Default)]
+pub struct Segments<'h> {
+    // the non-empty pieces, with their offsets
+    pieces: Vec<(usize, &'h str)>,
+    len: usize,
+}
+
+impl<'h> Segments<'h> {
+    pub fn new(pieces: impl IntoIterator<Item = &'h str>) -> Self {
+        let mut segments = Segments::default();
+        for piece in pieces.into_iter().filter(|piece| !piece.is_empty()) {
+            segments.pieces.push((segments.len, piece));
+            segments.len += piece.len();
+        }
+        segments
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // the piece holding the byte at `i < len`, and its offset
+    fn piece(&self, i: usize) -> (usize, &'h str) {
+        let index = self.pieces.partition_point(|&(start, _)| start <= i) - 1;
+        self.pieces[index]
+    }
+
+    pub(crate) fn is_char_boundary(&self, i: usize) -> bool {
+        if i >= self.len {
+            return i == self.len;
+        }
+        let (start, piece) = self.piece(i);
+        piece.is_char_boundary(i - start)
+    }
+
+    pub(crate) fn char_at(&self, i: usize) -> Option<char> {
+        if i >= self.len {
+            return None;
+        }
+        let (start, piece) = self.piece(i);
+        piece.get(i - start..)?.chars().next()
+    }
+
+    pub(crate) fn char_before(&self, i: usize) -> Option<char> {
+        if i == 0 || i > self.len {
+            return None;
+        }
+        let (start, piece) = self.piece(i - 1);
+        piece.get(..i - start)?.chars().next_back()
+    }
+
+    // the chars from `at` on, with their positions
+    pub(crate) fn chars_from(&'h self, at: usize) -> impl Iterator<Item = (usize, char)> + 'h {
+        let index = self.pieces.partition_point(|&(start, _)| start <= at);
+        let (first, rest) = match index.checked_sub(1) {
+            Some(index) => (self.pieces[index], &self.pieces[index + 1..]),
+            None => ((at, ""), &self.pieces[..]),
+        };
+        let (start, piece) = first;
+        let head = piece.get(at - start..).unwrap_or_default();
+
+        head.char_indices().map(move |(i, c)| (at + i, c)).chain(
+            rest.iter()
+                .flat_map(|&(start, piece)| piece.char_indices().map(move |(i, c)| (start + i, c))),
+        )
+    }
+
+    // The first position at or after `at` that `find` reports in a piece,
+    // for searches that look at a byte at a time.
+    pub(crate) fn find_byte(
+        &self,
+        at: usize,
+        find: impl Fn(&[u8], usize) -> Option<usize>,
+    ) -> Option<usize> {
+        if at >= self.len {
+            return None;
+        }
+        let index = self.pieces.partition_point(|&(start, _)| start <= at) - 1;
+        self.pieces[index..].iter().find_map(|&(start, piece)| {
+            find(piece.as_bytes(), at.saturating_sub(start)).map(|i| start + i)
+        })
+    }
+
+    pub(crate) fn text(&self, start: usize, end: usize) -> String {
+        let mut text = String::with_capacity(end - start);
+        let first = self.pieces.partition_point(|&(offset, _)| offset <= start);
+        for &(offset, piece) in self.pieces[first.saturating_sub(1)..].iter() {
+            if offset >= end {
+                break;
+            }
+            let from = start.clamp(offset, offset + piece.len()) - offset;
+            let to = end.clamp(offset, offset + piece.len()) - offset;
+            text.push_str(&piece[from..to]);
+        }
+        text
+    }
+}
+
+impl Engine {
+    // Like `find_at` over the pieces of `segments` laid end to end. NFC
+    // normalization and grapheme mode need the whole text, so with either
+    // enabled the pieces are joined first.
+    pub fn find_segments(&self, segments: &Segments, start: usize) -> Option<Match> {
+        self.try_find_segments(segments, start).ok().flatten()
+    }
+
+    pub fn try_find_segments(
+        &self,
+        segments: &Segments,
+        start: usize,
+    ) -> Result<Option<Match>, Error> {
+        if self.flags.nfc || self.flags.graphemes {
+            return self.try_find_at(&segments.text(0, segments.len()), start);
+        }
+
+        self.run(&Haystack::Segments(segments), start, false, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Segments;
+    use crate::rsgex::{builder::EngineBuilder, engine::Backend};
+
+    #[test]
+    fn test_find_segments() {
+        let text = "foo=1 中文bar=22\n\u{e9}t\u{e9}=333";
+        // every split on char boundaries into three pieces
+        let bounds: Vec<usize> = (0..=text.len())
+            .filter(|&i| text.is_char_boundary(i))
+            .collect();
+
+        for backend in [Backend::Backtrack, Backend::PikeVm] {
+            for pattern in ["(?<k>\\w+)=(\\d+)$", "\\b\\w+\\b", "中文|文b", "=\\d{2}"] {
+                let e = EngineBuilder::new()
+                    .backend(backend)
+                    .build(pattern)
+                    .unwrap();
+                let expected: Vec<_> = e.find_iter(text).collect();
+                for &i in bounds.iter() {
+                    for &j in bounds.iter().filter(|&&j| j >= i) {
+                        let segments = Segments::new([&text[..i], &text[i..j], &text[j..]]);
+                        let mut found = vec![];
+                        let mut at = 0;
+                        while let Some(m) = e.find_segments(&segments, at) {
+                            at = if m.end == m.start { m.end + 1 } else { m.end };
+                            while at < text.len() && !text.is_char_boundary(at) {
+                                at += 1;
+                            }
+                            found.push(m);
+                        }
+                        assert_eq!(found, expected, "{} split at {} {}", pattern, i, j);
+                    }
+                }
+            }
+        }
+
+        let segments = Segments::new(["ab", "", "c中"]);
+        assert_eq!(segments.text(1, 6), "bc中");
+        assert_eq!(segments.char_before(2), Some('b'));
+        assert!(!segments.is_char_boundary(4));
+    }
+}
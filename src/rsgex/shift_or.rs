@@ -0,0 +1,254 @@
+use std::borrow::Cow;
+
+use super::{
+    alphabet::Alphabet,
+    dfa::{self, Edge},
+    engine::Engine,
+    error::{Error, SizeLimitExceeded},
+    matcher::Transition,
+    normalize,
+};
+
+// bits one machine word can track
+pub const MAX_SHIFT_OR_POSITIONS: usize = 64;
+
+// Simulates a small automaton with one bit per position, so a char costs a
+// few table lookups and word operations however many positions are active.
+// Set bits are active positions (the Shift-And form of Shift-Or). A
+// position is a state entered by a given char test, plus one for the
+// initial state, so the positions a char enters are the successors of the
+// active ones masked with the positions whose test accepts it. Successors
+// are looked up a byte of the word at a time. Like `Dfa` it only reports
+// match positions, and rejects anchors and everything else `Dfa` rejects.
+// `{n,m}` is unrolled before the positions are collected, each copy taking
+// positions of its own.
+pub struct ShiftOr {
+    alphabet: Alphabet,
+    // byte of the state word -> value of the byte -> successors
+    follow: Vec<[u64; 256]>,
+    // char class -> states it can enter
+    enters: Vec<u64>,
+    initial: u64,
+    accepting: u64,
+    nfc: bool,
+    // the initial position is only active at the input start
+    anchored: bool,
+}
+
+impl ShiftOr {
+    pub fn is_match(&self, s: &str) -> bool {
+        self.shortest_match(s).is_some()
+    }
+
+    // The byte offset where the earliest ending match ends.
+    pub fn shortest_match(&self, s: &str) -> Option<usize> {
+        let s = self.prepare(s);
+        if self.initial & self.accepting != 0 {
+            return Some(0);
+        }
+
+        let restart = if self.anchored { 0 } else { self.initial };
+        let mut active = self.initial;
+        for (i, c) in s.char_indices() {
+            active = self.step(active, c) | restart;
+            if active & self.accepting != 0 {
+                return Some(i + c.len_utf8());
+            }
+            if active == 0 {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    fn step(&self, active: u64, c: char) -> u64 {
+        let successors = self
+            .follow
+            .iter()
+            .enumerate()
+            .fold(0, |successors, (byte, table)| {
+                successors | table[(active >> (byte * 8)) as usize & 0xFF]
+            });
+
+        successors & self.enters[self.alphabet.class(c)]
+    }
+
+    fn prepare<'s>(&self, s: &'s str) -> Cow<'s, str> {
+        if self.nfc {
+            normalize::nfc(s)
+        } else {
+            Cow::Borrowed(s)
+        }
+    }
+}
+
+impl TryFrom<&Engine> for ShiftOr {
+    type Error = Error;
+
+    fn try_from(engine: &Engine) -> Result<ShiftOr, Error> {
+        let nfa = engine.unrolled_nfa()?;
+        let (edges, alphabet) = dfa::edges(nfa)?;
+        for (edge, _) in edges.iter().flatten() {
+            if !matches!(edge, Edge::Epsilon | Edge::Chars(_)) {
+                let message = "only char tests are supported by Shift-Or";
+                return Err(Error::Unsupported(message.to_string()));
+            }
+        }
+
+        // position -> (state entered, char test), the initial state first
+        let mut positions: Vec<(usize, Option<&Transition>)> = vec![(nfa.initial, None)];
+        for (edge, to) in edges.iter().flatten() {
+            if let Edge::Chars(transition) = edge
+                && !positions.contains(&(*to, Some(transition)))
+            {
+                positions.push((*to, Some(transition)));
+            }
+        }
+        if positions.len() > MAX_SHIFT_OR_POSITIONS {
+            let limit = MAX_SHIFT_OR_POSITIONS;
+            return Err(SizeLimitExceeded::States { limit }.into());
+        }
+
+        let mut successors = vec![0u64; positions.len()];
+        let mut accepting = 0;
+        for (position, &(state, _)) in positions.iter().enumerate() {
+            // states reachable over epsilon transitions
+            let mut closure = vec![state];
+            let mut i = 0;
+            while i < closure.len() {
+                for (edge, to) in edges[closure[i]].iter() {
+                    match edge {
+                        Edge::Epsilon if !closure.contains(to) => closure.push(*to),
+                        Edge::Chars(transition) => {
+                            let next = positions
+                                .iter()
+                                .position(|p| *p == (*to, Some(transition)))
+                                .unwrap();
+                            successors[position] |= 1 << next;
+                        }
+                        _ => (),
+                    }
+                }
+                if nfa.states[closure[i]].is_ending {
+                    accepting |= 1 << position;
+                }
+                i += 1;
+            }
+        }
+
+        let enters = (0..alphabet.classes())
+            .map(|class| {
+                let c = alphabet.representative(class);
+                positions
+                    .iter()
+                    .enumerate()
+                    .fold(0, |enters, (position, (_, transition))| {
+                        let entered = transition.is_some_and(|t| t.matches_char(c) == Some(true));
+                        enters | (u64::from(entered) << position)
+                    })
+            })
+            .collect();
+
+        let follow = successors
+            .chunks(8)
+            .map(|chunk| {
+                let mut table = [0; 256];
+                for (value, successors) in table.iter_mut().enumerate() {
+                    for (bit, state_successors) in chunk.iter().enumerate() {
+                        if value & (1 << bit) != 0 {
+                            *successors |= state_successors;
+                        }
+                    }
+                }
+                table
+            })
+            .collect();
+
+        Ok(ShiftOr {
+            alphabet,
+            follow,
+            enters,
+            initial: 1,
+            accepting,
+            nfc: engine.flags.nfc,
+            anchored: engine.flags.anchored,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShiftOr;
+    use crate::rsgex::{
+        builder::EngineBuilder,
+        engine::{Backend, Engine},
+        error::{Error, SizeLimitExceeded},
+    };
+
+    #[test]
+    fn test_agrees_with_engine() {
+        let patterns = [
+            "abc",
+            "a|ab",
+            "b+?",
+            "[^a-c]+",
+            "\\d+-\\w*",
+            "x*",
+            "get|getenv|gets",
+            "ab{2}c",
+            "(?:x|ab){1,2}",
+        ];
+        let inputs = [
+            "", "abc", "xab", "b", "123", "4-x_", "aaaab", "-", "getenv", "ge", "cab", "abbc",
+            "xabbcc", "abbbc",
+        ];
+
+        for pattern in patterns {
+            let e = Engine::try_from(pattern).unwrap();
+            let shift_or = ShiftOr::try_from(&e).unwrap();
+            for input in inputs {
+                let expected = e.find_at(input, 0).is_some();
+                assert_eq!(
+                    shift_or.is_match(input),
+                    expected,
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+
+        let shift_or = ShiftOr::try_from(&Engine::try_from("b+|abc").unwrap()).unwrap();
+        assert_eq!(shift_or.shortest_match("xabc"), Some(3));
+        assert_eq!(shift_or.shortest_match("中abc"), Some(5));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let literal = "x".repeat(64);
+        assert!(matches!(
+            ShiftOr::try_from(&Engine::try_from(literal.as_str()).unwrap()),
+            Err(Error::SizeLimitExceeded(SizeLimitExceeded::States { .. }))
+        ));
+        assert!(matches!(
+            ShiftOr::try_from(&Engine::try_from("^a").unwrap()),
+            Err(Error::Unsupported(_))
+        ));
+        // unrolled into more positions than a word has
+        assert!(matches!(
+            ShiftOr::try_from(&Engine::try_from("a{70}").unwrap()),
+            Err(Error::SizeLimitExceeded(SizeLimitExceeded::States { .. }))
+        ));
+
+        // unrolled the same whichever backend the engine runs
+        for backend in [Backend::Backtrack, Backend::PikeVm] {
+            let e = EngineBuilder::new()
+                .backend(backend)
+                .build("a{2,3}")
+                .unwrap();
+            let shift_or = ShiftOr::try_from(&e).unwrap();
+            assert!(shift_or.is_match("xaa") && !shift_or.is_match("xa"));
+        }
+    }
+}
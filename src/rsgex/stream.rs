@@ -0,0 +1,202 @@
+use std::io::{self, ErrorKind, Read};
+
+use super::{engine::Engine, haystack::Haystack, matcher::Transition, search::Match};
+
+// bytes read at once
+const CHUNK_LEN: usize = 1 << 16;
+
+// how far past its start a match of unbounded length is assumed to be
+// decided, see `StreamMatches::window`
+const DEFAULT_WINDOW: usize = 1 << 16;
+
+// a char on either side of a position, which look-arounds may inspect
+const CONTEXT: usize = 4;
+
+// The matches of a stream, found as it is read. Only the text a match can
+// still start in is kept in memory, along with a char before it.
+pub struct StreamMatches<'e, R> {
+    engine: &'e Engine,
+    reader: R,
+    // what was read last
+    chunk: Vec<u8>,
+    buf: Vec<u8>,
+    // stream offset of `buf[0]`
+    offset: usize,
+    // length of the UTF-8 prefix of `buf`, the rest being a split char
+    valid: usize,
+    // where the next search starts, in `buf`
+    next: usize,
+    eof: bool,
+    failed: bool,
+    window: usize,
+    // `\G` anchors on the search start, so searches never skip ahead
+    continuation: bool,
+}
+
+impl Engine {
+    // Searches `reader` as it is read, with offsets counted from the start
+    // of the stream, which has to be UTF-8. A match is reported once the
+    // text it and its look-arounds could cover has been read, so matches of
+    // patterns with a bounded length are exact. NFC normalization is not
+    // applied.
+    pub fn stream<R: Read>(&self, reader: R) -> StreamMatches<'_, R> {
+        let continuation = self.nfa.states.iter().any(|state| {
+            state
+                .matchers
+                .iter()
+                .any(|(t, _)| matches!(t, Transition::Continuation))
+        });
+
+        StreamMatches {
+            engine: self,
+            reader,
+            chunk: vec![0; CHUNK_LEN],
+            buf: vec![],
+            offset: 0,
+            valid: 0,
+            next: 0,
+            eof: false,
+            failed: false,
+            window: DEFAULT_WINDOW,
+            continuation,
+        }
+    }
+}
+
+impl<R: Read> StreamMatches<'_, R> {
+    // How many bytes past its start a match of a pattern without a bounded
+    // length is assumed to be decided in, 64 KiB by default. Longer matches
+    // may be cut short.
+    pub fn window(mut self, bytes: usize) -> Self {
+        self.window = bytes;
+        self
+    }
+
+    // bytes past a position the search from it may look at
+    fn horizon(&self) -> usize {
+        self.engine.max_len.unwrap_or(self.window) + CONTEXT
+    }
+
+    // Drops the text before the next search and reads another chunk.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut drop = self.next.saturating_sub(CONTEXT);
+        // continuation bytes are -0x80..-0x40 as `i8`
+        while drop > 0 && (self.buf[drop] as i8) < -0x40 {
+            drop -= 1;
+        }
+        self.buf.drain(..drop);
+        self.offset += drop;
+        self.valid -= drop;
+        self.next -= drop;
+
+        let read = loop {
+            match self.reader.read(&mut self.chunk) {
+                Ok(read) => break read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        };
+        self.buf.extend_from_slice(&self.chunk[..read]);
+        self.eof = read == 0;
+
+        match std::str::from_utf8(&self.buf[self.valid..]) {
+            Ok(s) => self.valid += s.len(),
+            Err(err) if err.error_len().is_none() && !self.eof => self.valid += err.valid_up_to(),
+            Err(err) => return Err(io::Error::new(ErrorKind::InvalidData, err)),
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for StreamMatches<'_, R> {
+    type Item = io::Result<Match>;
+
+    fn next(&mut self) -> Option<io::Result<Match>> {
+        while !self.failed {
+            // SAFETY: `valid` bytes were checked in `fill`
+            let text = unsafe { std::str::from_utf8_unchecked(&self.buf[..self.valid]) };
+            let haystack = Haystack::Str(text);
+            let horizon = self.horizon();
+
+            if self.next <= text.len() {
+                match self.engine.find_in(&haystack, self.next) {
+                    Some(m) if self.eof || m.start + horizon <= text.len() => {
+                        self.next = if m.end == m.start {
+                            haystack.next(m.end)
+                        } else {
+                            m.end
+                        };
+                        return Some(Ok(Match {
+                            start: self.offset + m.start,
+                            end: self.offset + m.end,
+                            groups: m.groups,
+                        }));
+                    }
+                    None if self.eof => return None,
+                    // no match starts where the search has seen far enough
+                    None if !self.continuation => {
+                        let mut skip = text.len().saturating_sub(horizon).max(self.next);
+                        while !text.is_char_boundary(skip) {
+                            skip += 1;
+                        }
+                        self.next = skip;
+                    }
+                    _ => (),
+                }
+            } else if self.eof {
+                return None;
+            }
+
+            if let Err(err) = self.fill() {
+                self.failed = true;
+                return Some(Err(err));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read};
+
+    use crate::rsgex::{engine::Engine, search::Match};
+
+    // hands out a few bytes per read, splitting chars
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_stream() {
+        let haystack = "id=7 中文 id=42\nx=1 id=300 $ ".repeat(20);
+        for pattern in ["id=\\d+", "\\b\\w=\\d\\b", "\\d+ \\S", "\\s*$|中", "\\w+"] {
+            let e = Engine::try_from(pattern).unwrap();
+            let expected: Vec<Match> = e.find_iter(&haystack).collect();
+            let found: Vec<Match> = e
+                .stream(Trickle(haystack.as_bytes()))
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(found, expected, "{}", pattern);
+        }
+
+        // only the text since the last match is kept
+        let e = Engine::try_from("a{3}").unwrap();
+        let mut matches = e.stream(io::repeat(b'a').take(1 << 20));
+        assert_eq!(matches.nth(1000).unwrap().unwrap().start, 3000);
+        assert!(matches.buf.len() < 1 << 17);
+
+        let e = Engine::try_from("x").unwrap();
+        let mut matches = e.stream(&b"x\xFFx"[..]);
+        assert!(matches.next().unwrap().is_err());
+        assert!(matches.next().is_none());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use regex_syntax::hir::{Class, HirKind};
+
+use super::nfa::NFAutomata;
+
+// The automata of subtrees occurring more than once in a pattern, like the
+// two `(?:\d+\.){3}` of `(?:\d+\.){3}\d+|(?:\d+\.){3}x`, so each is lowered
+// once per compilation and copied where it occurs again. The automaton is
+// still copied, as states stand for positions in the pattern, but
+// `NFAutomata::dedup` merges the copies' common tails afterwards.
+#[derive(Default)]
+pub(crate) struct SharedSubtrees {
+    // address of a subtree occurring more than once -> its structural hash
+    repeated: HashMap<usize, u64>,
+    // structural hash -> lowered subtrees
+    lowered: HashMap<u64, Vec<(HirKind, NFAutomata)>>,
+}
+
+impl SharedSubtrees {
+    pub(crate) fn new(root: &HirKind) -> Self {
+        let mut hashes = vec![];
+        hash_subtrees(root, &mut hashes);
+
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        for (_, hash) in hashes.iter() {
+            *counts.entry(*hash).or_default() += 1;
+        }
+        let repeated = hashes
+            .into_iter()
+            .filter(|(_, hash)| counts[hash] > 1)
+            .collect();
+
+        SharedSubtrees {
+            repeated,
+            lowered: HashMap::new(),
+        }
+    }
+
+    // The automaton of a subtree equal to `ast` lowered before.
+    pub(crate) fn get(&self, ast: &HirKind) -> Option<&NFAutomata> {
+        let hash = self.repeated.get(&address(ast))?;
+        self.lowered
+            .get(hash)?
+            .iter()
+            .find(|(lowered, _)| lowered == ast)
+            .map(|(_, nfa)| nfa)
+    }
+
+    // Keeps the automaton of `ast` when the subtree occurs again.
+    pub(crate) fn insert(&mut self, ast: &HirKind, nfa: &NFAutomata) {
+        if let Some(&hash) = self.repeated.get(&address(ast)) {
+            let lowered = self.lowered.entry(hash).or_default();
+            if !lowered.iter().any(|(lowered, _)| lowered == ast) {
+                lowered.push((ast.clone(), nfa.clone()));
+            }
+        }
+    }
+}
+
+// Subtrees lowered from rewritten copies of the pattern, see
+// `Engine::factor_suffix`, have addresses of their own and are not shared.
+fn address(ast: &HirKind) -> usize {
+    ast as *const HirKind as usize
+}
+
+// Pushes the address and structural hash of every subtree of `ast` but the
+// empty ones, children first, and returns the hash of `ast`.
+fn hash_subtrees(ast: &HirKind, hashes: &mut Vec<(usize, u64)>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    std::mem::discriminant(ast).hash(&mut hasher);
+    match ast {
+        HirKind::Empty => return hasher.finish(),
+        HirKind::Literal(literal) => literal.0.hash(&mut hasher),
+        HirKind::Class(Class::Unicode(class)) => {
+            class
+                .iter()
+                .for_each(|r| (r.start(), r.end()).hash(&mut hasher));
+        }
+        HirKind::Class(Class::Bytes(class)) => {
+            class
+                .iter()
+                .for_each(|r| (r.start(), r.end()).hash(&mut hasher));
+        }
+        HirKind::Look(look) => look.as_repr().hash(&mut hasher),
+        HirKind::Repetition(repetition) => {
+            (repetition.min, repetition.max, repetition.greedy).hash(&mut hasher);
+            hash_subtrees(repetition.sub.kind(), hashes).hash(&mut hasher);
+        }
+        HirKind::Capture(capture) => {
+            (capture.index, &capture.name).hash(&mut hasher);
+            hash_subtrees(capture.sub.kind(), hashes).hash(&mut hasher);
+        }
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => {
+            for hir in hirs {
+                hash_subtrees(hir.kind(), hashes).hash(&mut hasher);
+            }
+        }
+    }
+    let hash = hasher.finish();
+    hashes.push((address(ast), hash));
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use regex_syntax::{
+        hir::{Hir, HirKind},
+        parse,
+    };
+
+    use super::SharedSubtrees;
+    use crate::rsgex::{engine::Engine, nfa::NFAutomata};
+
+    fn branch(hir: &Hir, index: usize) -> &[Hir] {
+        match hir.kind() {
+            HirKind::Alternation(branches) => match branches[index].kind() {
+                HirKind::Concat(hirs) => hirs,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_shared_subtrees() {
+        let hir = parse("(?:a+b){2}x|(?:a+b){2}y|a+").unwrap();
+        let (first, second) = (branch(&hir, 0), branch(&hir, 1));
+        let mut shared = SharedSubtrees::new(hir.kind());
+
+        shared.insert(first[0].kind(), &NFAutomata::new());
+        shared.insert(first[1].kind(), &NFAutomata::new());
+        assert!(shared.get(second[0].kind()).is_some());
+        // `x` occurs once
+        assert!(shared.get(first[1].kind()).is_none());
+        assert!(shared.get(second[1].kind()).is_none());
+
+        let e = Engine::try_from("(?:a+b){2}x|(?:a+b){2}y|a+").unwrap();
+        assert_eq!(e.exec_test("aabaaby"), "aabaaby");
+        assert_eq!(e.exec_test("aabx"), "aa");
+    }
+}
@@ -0,0 +1,487 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use super::{
+    alphabet::Alphabet,
+    dfa::{self, DEAD, Edge, Edges, MAX_DFA_STATES},
+    engine::Engine,
+    error::{Error, SizeLimitExceeded},
+    haystack::Haystack,
+    nfa::{CaptureGroupRange, NFAutomata, State, captured_groups},
+    normalize,
+    search::Match,
+};
+
+// nfa state, consuming transition or `None` for an accepting state
+type Config = (usize, Option<usize>);
+
+// How a thread of the state a transition enters is made: copied from a
+// thread of the state left, or started afresh, then passed through the
+// capture markers of the states on its way.
+#[derive(Clone)]
+struct Tag {
+    from: Option<usize>,
+    // indices into `TaggedDfa::markers`
+    marks: Vec<usize>,
+}
+
+struct TaggedState {
+    // the thread accepting wherever the state is reached
+    accept: Option<usize>,
+    // the thread accepting at the end of input, with the markers behind its
+    // `$`
+    eof_accept: Option<(usize, Vec<usize>)>,
+}
+
+// (match start, capture slots) of one thread
+type Thread = (usize, Vec<Option<CaptureGroupRange>>);
+
+// A DFA whose transitions are tagged with what happens to the capture
+// slots of every NFA thread they advance, so a single forward scan reports
+// the same match and groups as `Engine::find_at` without simulating the
+// automaton. Threads are kept in the backtracker's priority order, like the
+// states of `Dfa`. Supports the same patterns as `Dfa`; `{n,m}` is
+// unrolled, as the threads have no counter registers.
+pub struct TaggedDfa {
+    alphabet: Alphabet,
+    // a row of next states per state, one column per char class
+    table: Vec<usize>,
+    // the tags of the threads each entry of `table` enters
+    tags: Vec<Vec<Tag>>,
+    states: Vec<TaggedState>,
+    // [anchored, unanchored] x [at the input start, elsewhere]
+    starts: [[usize; 2]; 2],
+    // [at the input start, elsewhere]
+    start_tags: [Vec<Tag>; 2],
+    // the NFA states with capture markers
+    markers: Vec<State>,
+    nfc: bool,
+    anchored: bool,
+}
+
+impl TaggedDfa {
+    pub fn find(&self, s: &str) -> Option<Match> {
+        self.find_at(s, 0)
+    }
+
+    // The leftmost-first match starting at or after `start`, with its
+    // groups.
+    pub fn find_at(&self, s: &str, start: usize) -> Option<Match> {
+        let s = self.prepare(s);
+        let haystack = Haystack::Str(&s);
+        let elsewhere = usize::from(start > 0);
+        let mut state = self.starts[usize::from(!self.anchored)][elsewhere];
+        let mut threads = self.follow(&self.start_tags[elsewhere], &[], start);
+        let mut matched = None;
+
+        let mut i = start;
+        loop {
+            let tagged = &self.states[state];
+            if i == s.len() {
+                if let Some((thread, marks)) = &tagged.eof_accept {
+                    let (start, mut groups) = threads[*thread].clone();
+                    self.mark(marks, &mut groups, i);
+                    matched = Some((start, i, groups));
+                }
+                break;
+            }
+            if let Some(thread) = tagged.accept {
+                let (start, groups) = &threads[thread];
+                matched = Some((*start, i, groups.clone()));
+            }
+
+            let c = s[i..].chars().next().unwrap();
+            let index = state * self.alphabet.classes() + self.alphabet.class(c);
+            state = self.table[index];
+            if state == DEAD {
+                break;
+            }
+            i += c.len_utf8();
+            threads = self.follow(&self.tags[index], &threads, i);
+        }
+
+        matched.map(|(start, end, groups)| Match {
+            start,
+            end,
+            groups: captured_groups(&haystack, &groups),
+        })
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    // the threads `tags` make out of `threads` at `i`
+    fn follow(&self, tags: &[Tag], threads: &[Thread], i: usize) -> Vec<Thread> {
+        tags.iter()
+            .map(|tag| {
+                let (start, mut groups) = match tag.from {
+                    Some(from) => threads[from].clone(),
+                    None => (i, vec![]),
+                };
+                self.mark(&tag.marks, &mut groups, i);
+                (start, groups)
+            })
+            .collect()
+    }
+
+    fn mark(&self, marks: &[usize], groups: &mut Vec<Option<CaptureGroupRange>>, i: usize) {
+        for &marker in marks {
+            self.markers[marker].mark_groups(groups, i);
+        }
+    }
+
+    fn prepare<'s>(&self, s: &'s str) -> Cow<'s, str> {
+        if self.nfc {
+            normalize::nfc(s)
+        } else {
+            Cow::Borrowed(s)
+        }
+    }
+}
+
+impl TryFrom<&Engine> for TaggedDfa {
+    type Error = Error;
+
+    fn try_from(engine: &Engine) -> Result<TaggedDfa, Error> {
+        let nfa = engine.unrolled_nfa()?;
+        let (edges, alphabet) = dfa::edges(nfa)?;
+        // only left in automata combined by `Engine::union` and the like
+        if edges
+            .iter()
+            .flatten()
+            .any(|(edge, _)| matches!(edge, Edge::Counter(_)))
+        {
+            let message = "counted repetitions are not supported by the tagged DFA";
+            return Err(Error::Unsupported(message.to_string()));
+        }
+
+        let mut markers = vec![];
+        let marker_ids = nfa
+            .states
+            .iter()
+            .map(|state| {
+                if state.start_group.is_empty() && state.end_group.is_empty() {
+                    return None;
+                }
+                markers.push(state.clone());
+                Some(markers.len() - 1)
+            })
+            .collect();
+
+        let mut tagger = Tagger {
+            nfa,
+            edges,
+            alphabet,
+            marker_ids,
+            ids: HashMap::new(),
+            queue: vec![],
+            states: vec![],
+            memory: 0,
+        };
+        let (starts, start_tags) = tagger.starts();
+
+        let mut table = vec![];
+        let mut tags = vec![];
+        let mut processed = 0;
+        while processed < tagger.queue.len() {
+            for class in 0..tagger.alphabet.classes() {
+                let (next, next_tags) = tagger.transition(processed, class);
+                table.push(next);
+                tags.push(next_tags);
+            }
+            processed += 1;
+            if tagger.queue.len() > MAX_DFA_STATES {
+                let limit = MAX_DFA_STATES;
+                return Err(SizeLimitExceeded::States { limit }.into());
+            }
+            if let Some(limit) = engine.dfa_size_limit
+                && tagger.memory > limit
+            {
+                return Err(SizeLimitExceeded::Dfa { limit }.into());
+            }
+        }
+
+        Ok(TaggedDfa {
+            alphabet: tagger.alphabet,
+            table,
+            tags,
+            states: tagger.states,
+            starts,
+            start_tags,
+            markers,
+            nfc: engine.flags.nfc,
+            anchored: engine.flags.anchored,
+        })
+    }
+}
+
+// Subset construction like `dfa::Determinizer`, also recording where every
+// thread of a state comes from.
+struct Tagger<'a> {
+    nfa: &'a NFAutomata,
+    edges: Vec<Edges>,
+    alphabet: Alphabet,
+    // nfa state -> index into `TaggedDfa::markers`
+    marker_ids: Vec<Option<usize>>,
+    // (configs, restart) -> state
+    ids: HashMap<(Vec<Config>, bool), usize>,
+    queue: Vec<(Vec<Config>, bool)>,
+    states: Vec<TaggedState>,
+    // approximate bytes held by the states and tags added so far
+    memory: usize,
+}
+
+impl Tagger<'_> {
+    fn starts(&mut self) -> ([[usize; 2]; 2], [Vec<Tag>; 2]) {
+        self.id((vec![], false));
+
+        let mut starts = [[DEAD; 2]; 2];
+        let mut start_tags: [Vec<Tag>; 2] = Default::default();
+        for (elsewhere, tags) in start_tags.iter_mut().enumerate() {
+            let mut tagged = vec![];
+            self.closure(
+                &mut tagged,
+                &mut HashSet::new(),
+                self.nfa.initial,
+                None,
+                elsewhere == 0,
+            );
+            let (configs, start) = cut(tagged);
+            starts[0][elsewhere] = self.id((configs.clone(), false));
+            starts[1][elsewhere] = self.id((configs, true));
+            *tags = start;
+        }
+        (starts, start_tags)
+    }
+
+    // The state reached from `state` on a char of `class`, added if new,
+    // and the tags of its threads. Unanchored states stop restarting once
+    // they match, as later threads cannot win.
+    fn transition(&mut self, state: usize, class: usize) -> (usize, Vec<Tag>) {
+        let (configs, restart) = &self.queue[state];
+        let restart = *restart && self.states[state].accept.is_none();
+        let c = self.alphabet.representative(class);
+
+        let mut tagged = vec![];
+        let mut seen = HashSet::new();
+        for (thread, (state, transition)) in configs.iter().enumerate() {
+            let Some(transition) = transition else {
+                continue;
+            };
+            if let (Edge::Chars(matcher), to) = &self.edges[*state][*transition]
+                && matcher.matches_char(c) == Some(true)
+            {
+                self.closure(&mut tagged, &mut seen, *to, Some(thread), false);
+            }
+        }
+        if restart {
+            self.closure(&mut tagged, &mut seen, self.nfa.initial, None, false);
+        }
+
+        let (configs, tags) = cut(tagged);
+        self.memory += tags
+            .iter()
+            .map(|tag| size_of::<Tag>() + tag.marks.len() * size_of::<usize>())
+            .sum::<usize>();
+        (self.id((configs, restart)), tags)
+    }
+
+    fn id(&mut self, key: (Vec<Config>, bool)) -> usize {
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        let (configs, _) = &key;
+        let accept = configs
+            .iter()
+            .position(|(_, transition)| transition.is_none());
+        let eof_accept = self.eof_accept(configs);
+        self.states.push(TaggedState { accept, eof_accept });
+        // the key is stored twice, plus a row of transitions
+        self.memory += 2 * configs.len() * size_of::<Config>()
+            + self.alphabet.classes() * (size_of::<usize>() + size_of::<Vec<Tag>>());
+
+        let id = self.queue.len();
+        self.ids.insert(key.clone(), id);
+        self.queue.push(key);
+        id
+    }
+
+    // Appends the configs reachable from `state` without consuming, depth
+    // first so they keep the backtracker's priority order, each tagged with
+    // the markers passed on the way.
+    fn closure(
+        &self,
+        tagged: &mut Vec<(Config, Tag)>,
+        seen: &mut HashSet<usize>,
+        state: usize,
+        from: Option<usize>,
+        at_start: bool,
+    ) {
+        let mut stack = vec![(state, None, vec![])];
+
+        while let Some((state, emit, mut marks)) = stack.pop() {
+            if emit.is_some() {
+                tagged.push(((state, emit), Tag { from, marks }));
+                continue;
+            }
+            if !seen.insert(state) {
+                continue;
+            }
+            marks.extend(self.marker_ids[state]);
+            if self.nfa.states[state].is_ending {
+                tagged.push(((state, None), Tag { from, marks }));
+                continue;
+            }
+
+            for (transition, (edge, to)) in self.edges[state].iter().enumerate().rev() {
+                match edge {
+                    Edge::Epsilon => stack.push((*to, None, marks.clone())),
+                    Edge::Start if at_start => stack.push((*to, None, marks.clone())),
+                    Edge::Start | Edge::Counter(_) => (),
+                    Edge::End | Edge::Chars(_) => {
+                        stack.push((state, Some(transition), marks.clone()))
+                    }
+                }
+            }
+        }
+    }
+
+    // The first config accepting once the input ends, either right away or
+    // behind a waiting `$`.
+    fn eof_accept(&self, configs: &[Config]) -> Option<(usize, Vec<usize>)> {
+        let mut seen = HashSet::new();
+
+        for (thread, (state, transition)) in configs.iter().enumerate() {
+            let Some(transition) = transition else {
+                return Some((thread, vec![]));
+            };
+            let (Edge::End, to) = &self.edges[*state][*transition] else {
+                continue;
+            };
+
+            let mut stack = vec![(*to, vec![])];
+            while let Some((state, mut marks)) = stack.pop() {
+                if !seen.insert(state) {
+                    continue;
+                }
+                marks.extend(self.marker_ids[state]);
+                if self.nfa.states[state].is_ending {
+                    return Some((thread, marks));
+                }
+                for (edge, to) in self.edges[state].iter().rev() {
+                    if matches!(edge, Edge::Epsilon | Edge::End) {
+                        stack.push((*to, marks.clone()));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// Splits the configs from their tags, dropping the configs behind an
+// accepting one, which can never win.
+fn cut(mut tagged: Vec<(Config, Tag)>) -> (Vec<Config>, Vec<Tag>) {
+    if let Some(accepting) = tagged.iter().position(|((_, t), _)| t.is_none()) {
+        tagged.truncate(accepting + 1);
+    }
+    tagged.into_iter().unzip()
+}
+
+#[cfg(test)]
+mod test {
+    use super::TaggedDfa;
+    use crate::rsgex::{
+        builder::EngineBuilder,
+        engine::{Backend, Engine},
+    };
+
+    #[test]
+    fn test_agrees_with_engine() {
+        let patterns = [
+            "(\\w+)@(\\w+)\\.com",
+            "^(a)?(b)?$",
+            "(?<x>x*)y?",
+            "(a|ab)(c|bcd)(d*)",
+            "(a+)(a+)",
+            "((a)|b)+",
+            "(\\d+)-(\\d+)?$",
+            "(?<w>é+)|(中)",
+            "(a+?)(a*)b?",
+            "(a|b){2,3}(b)?",
+            "(a){1,2}?(a*)",
+        ];
+        let inputs = [
+            "",
+            "ab",
+            "abcd",
+            "a_1@b.com x@y.com",
+            "aaa",
+            "xxxy",
+            "abab",
+            "12-",
+            "1-23",
+            "xéé中",
+        ];
+
+        // the backtracking backend counts `{n,m}`, which is unrolled here
+        for (pattern, backend) in patterns
+            .into_iter()
+            .flat_map(|pattern| [(pattern, Backend::Backtrack), (pattern, Backend::PikeVm)])
+        {
+            let e = EngineBuilder::new()
+                .backend(backend)
+                .build(pattern)
+                .unwrap();
+            let tagged = TaggedDfa::try_from(&e).unwrap();
+            for input in inputs {
+                for (at, _) in input.char_indices().chain([(input.len(), ' ')]) {
+                    assert_eq!(
+                        tagged.find_at(input, at),
+                        e.find_at(input, at),
+                        "{} {:?} on {:?} at {}",
+                        pattern,
+                        backend,
+                        input,
+                        at
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(TaggedDfa::try_from(&Engine::try_from("\\b(x)").unwrap()).is_err());
+
+        // counted by the engine, unrolled by the tagged DFA
+        let e = Engine::try_from("(a){2,3}$").unwrap();
+        let tagged = TaggedDfa::try_from(&e).unwrap();
+        assert_eq!(tagged.find("xaaa"), e.find_at("xaaa", 0));
+        assert_eq!(tagged.find("xaaa").map(|m| (m.start, m.end)), Some((1, 4)));
+
+        // the groups `captures` leaves out stay out
+        let e = EngineBuilder::new()
+            .captures(&["2"])
+            .build("(a){2}(b)")
+            .unwrap();
+        let tagged = TaggedDfa::try_from(&e).unwrap();
+        assert_eq!(tagged.find("aab"), e.find_at("aab", 0));
+    }
+
+    #[test]
+    fn test_combined_engines() {
+        let a = Engine::try_from("(a){2,3}").unwrap();
+        let b = Engine::try_from("(b){2}").unwrap();
+        for e in [a.union(&b), a.then(&b), a.or_pattern("(b){2}").unwrap()] {
+            let tagged = TaggedDfa::try_from(&e).unwrap();
+            for input in ["aa", "xaaab", "bb", "aabb", "ab"] {
+                assert_eq!(tagged.find(input), e.find_at(input, 0), "{}", input);
+            }
+        }
+    }
+}
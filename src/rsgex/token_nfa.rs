@@ -0,0 +1,284 @@
+use std::{fmt, sync::Arc};
+
+// A transition of a `TokenNfa`: taken without consuming, or on a token the
+// predicate accepts.
+pub enum TokenTransition<T> {
+    Epsilon,
+    Test(Arc<dyn Fn(&T) -> bool + Send + Sync>, Arc<str>),
+}
+
+impl<T> Clone for TokenTransition<T> {
+    fn clone(&self) -> Self {
+        match self {
+            TokenTransition::Epsilon => TokenTransition::Epsilon,
+            TokenTransition::Test(test, label) => {
+                TokenTransition::Test(test.clone(), label.clone())
+            }
+        }
+    }
+}
+
+// An automaton over any kind of token rather than chars, such as lexer
+// tokens or events, built by hand like `NFAutomata` and composed with
+// `union`, `concat` and `star`. All states are tracked at once, so
+// matching takes linear time in the number of tokens.
+pub struct TokenNfa<T> {
+    // transitions per state
+    states: Vec<Vec<(TokenTransition<T>, usize)>>,
+    initial: usize,
+    ending: Vec<bool>,
+}
+
+impl<T> Clone for TokenNfa<T> {
+    fn clone(&self) -> Self {
+        Self {
+            states: self.states.clone(),
+            initial: self.initial,
+            ending: self.ending.clone(),
+        }
+    }
+}
+
+impl<T> Default for TokenNfa<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> TokenNfa<T> {
+    pub fn new() -> Self {
+        Self {
+            states: vec![],
+            initial: 0,
+            ending: vec![],
+        }
+    }
+
+    // The automaton accepting the single token `test` accepts.
+    pub fn token(label: &str, test: impl Fn(&T) -> bool + Send + Sync + 'static) -> Self {
+        let mut nfa = Self::new();
+        nfa.fill_state(2);
+        nfa.add_transition(0, 1, label, test);
+        nfa.add_ending(1);
+        nfa
+    }
+
+    pub fn fill_state(&mut self, number: usize) {
+        for _ in 0..number {
+            self.states.push(vec![]);
+            self.ending.push(false);
+        }
+    }
+
+    pub fn set_initial(&mut self, initial: usize) {
+        self.initial = initial;
+    }
+
+    pub fn add_ending(&mut self, ending: usize) {
+        self.ending[ending] = true;
+    }
+
+    pub fn add_epsilon_transition(&mut self, from: usize, to: usize) {
+        self.states[from].push((TokenTransition::Epsilon, to));
+    }
+
+    // `label` names the test in `Debug` output.
+    pub fn add_transition(
+        &mut self,
+        from: usize,
+        to: usize,
+        label: &str,
+        test: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) {
+        let transition = TokenTransition::Test(Arc::new(test), label.into());
+        self.states[from].push((transition, to));
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    // Accepts what either automaton accepts.
+    pub fn union(&self, other: &TokenNfa<T>) -> TokenNfa<T> {
+        let mut nfa = Self::new();
+        nfa.fill_state(1);
+        for part in [self, other] {
+            let (initial, _) = nfa.embed(part, true);
+            nfa.add_epsilon_transition(0, initial);
+        }
+        nfa
+    }
+
+    // Accepts what `self` accepts followed by what `other` accepts.
+    pub fn concat(&self, other: &TokenNfa<T>) -> TokenNfa<T> {
+        let mut nfa = Self::new();
+        let (initial, endings) = nfa.embed(self, false);
+        let (next, _) = nfa.embed(other, true);
+        nfa.set_initial(initial);
+        for ending in endings {
+            nfa.add_epsilon_transition(ending, next);
+        }
+        nfa
+    }
+
+    // Accepts any number of repetitions of what `self` accepts, none
+    // included.
+    pub fn star(&self) -> TokenNfa<T> {
+        let mut nfa = Self::new();
+        nfa.fill_state(1);
+        nfa.add_ending(0);
+        let (initial, endings) = nfa.embed(self, false);
+        nfa.add_epsilon_transition(0, initial);
+        for ending in endings {
+            nfa.add_epsilon_transition(ending, 0);
+        }
+        nfa
+    }
+
+    // The length of the longest run of tokens from the start that the
+    // automaton accepts, like `NFAutomata::compute` does on text.
+    pub fn compute(&self, tokens: &[T]) -> Option<usize> {
+        if self.states.is_empty() {
+            return None;
+        }
+        let mut current = vec![false; self.state_count()];
+        let mut found = None;
+        self.closure(&mut current, self.initial);
+
+        for (i, token) in tokens.iter().enumerate() {
+            if self.accepts(&current) {
+                found = Some(i);
+            }
+            let mut next = vec![false; self.state_count()];
+            let mut alive = false;
+            for (state, _) in current.iter().enumerate().filter(|(_, on)| **on) {
+                for (transition, to) in self.states[state].iter() {
+                    if let TokenTransition::Test(test, _) = transition
+                        && test(token)
+                    {
+                        self.closure(&mut next, *to);
+                        alive = true;
+                    }
+                }
+            }
+            if !alive {
+                return found;
+            }
+            current = next;
+        }
+
+        if self.accepts(&current) {
+            found = Some(tokens.len());
+        }
+        found
+    }
+
+    // Whether the automaton accepts all of `tokens`.
+    pub fn is_full_match(&self, tokens: &[T]) -> bool {
+        self.compute(tokens) == Some(tokens.len())
+    }
+
+    // Copies `other` in, returning its initial and ending states, which
+    // stay endings when `keep_endings`.
+    fn embed(&mut self, other: &TokenNfa<T>, keep_endings: bool) -> (usize, Vec<usize>) {
+        let offset = self.state_count();
+        for transitions in other.states.iter() {
+            let transitions = transitions
+                .iter()
+                .map(|(transition, to)| (transition.clone(), to + offset))
+                .collect();
+            self.states.push(transitions);
+            self.ending.push(false);
+        }
+
+        let endings: Vec<usize> = (0..other.state_count())
+            .filter(|&state| other.ending[state])
+            .map(|state| state + offset)
+            .collect();
+        if keep_endings {
+            endings.iter().for_each(|&ending| self.add_ending(ending));
+        }
+        (other.initial + offset, endings)
+    }
+
+    fn closure(&self, set: &mut [bool], state: usize) {
+        let mut stack = vec![state];
+        while let Some(state) = stack.pop() {
+            if set[state] {
+                continue;
+            }
+            set[state] = true;
+            for (transition, to) in self.states[state].iter() {
+                if let TokenTransition::Epsilon = transition {
+                    stack.push(*to);
+                }
+            }
+        }
+    }
+
+    fn accepts(&self, set: &[bool]) -> bool {
+        set.iter()
+            .zip(self.ending.iter())
+            .any(|(on, ending)| *on && *ending)
+    }
+}
+
+impl<T: PartialEq + Send + Sync + 'static> TokenNfa<T> {
+    // The automaton accepting the single token equal to `expected`.
+    pub fn exactly(label: &str, expected: T) -> Self {
+        Self::token(label, move |token| *token == expected)
+    }
+}
+
+impl<T> fmt::Debug for TokenNfa<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, transitions) in self.states.iter().enumerate() {
+            write!(f, "State({})", index)?;
+            if index == self.initial {
+                write!(f, " [INITIAL]")?;
+            }
+            if self.ending[index] {
+                write!(f, " [ENDING]")?;
+            }
+            write!(f, ":")?;
+            for (transition, to) in transitions.iter() {
+                match transition {
+                    TokenTransition::Epsilon => write!(f, " --ε-> {}", to)?,
+                    TokenTransition::Test(_, label) => write!(f, " --{}-> {}", label, to)?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TokenNfa;
+
+    #[derive(PartialEq, Debug)]
+    enum Token {
+        Ident(&'static str),
+        Number(i64),
+        Comma,
+    }
+
+    #[test]
+    fn test_token_nfa() {
+        let ident = TokenNfa::token("ident", |t| matches!(t, Token::Ident(_)));
+        let number = TokenNfa::token("number", |t| matches!(t, Token::Number(n) if *n >= 0));
+        let item = ident.union(&number);
+        let comma = TokenNfa::exactly("comma", Token::Comma);
+        // item (, item)*
+        let list = item.concat(&comma.concat(&item).star());
+
+        use Token::*;
+        assert!(list.is_full_match(&[Ident("a"), Comma, Number(1), Comma, Ident("b")]));
+        assert!(!list.is_full_match(&[Ident("a"), Comma]));
+        assert_eq!(list.compute(&[Number(1), Comma, Number(-1)]), Some(1));
+        assert_eq!(list.compute(&[Comma]), None);
+        assert_eq!(list.star().compute(&[]), Some(0));
+        assert!(format!("{:?}", list).contains("--comma->"));
+    }
+}
@@ -0,0 +1,367 @@
+use std::{collections::HashMap, fmt::Write};
+
+use super::{
+    cache::Cache,
+    engine::Engine,
+    error::Error,
+    haystack::Haystack,
+    json::{self, Value, invalid},
+    nfa::{Budget, captured_groups},
+    search::Match,
+};
+
+// One step of the backtracking search, see `ExecObserver`. States and
+// transitions are numbered as in `Engine::states`, positions are byte
+// offsets into the haystack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepEvent {
+    // a match is tried from `at`
+    Start {
+        at: usize,
+    },
+    // the search reached `state` with `at` left to match
+    Enter {
+        state: usize,
+        at: usize,
+    },
+    // the `transition`th transition of `state`, going to `to`, was tested;
+    // the tested transitions that `passed` are then followed in order
+    Attempt {
+        state: usize,
+        transition: usize,
+        to: usize,
+        at: usize,
+        passed: bool,
+    },
+    GroupOpen {
+        group: u32,
+        at: usize,
+    },
+    GroupClose {
+        group: u32,
+        at: usize,
+    },
+    // `state` led nowhere, the search goes back to the latest choice left
+    Backtrack {
+        state: usize,
+        at: usize,
+    },
+    Match {
+        at: usize,
+    },
+}
+
+// Sees every step of a search, for debuggers and teaching tools that show
+// how the automaton runs; see `Engine::find_at_observed`. Closures taking a
+// `StepEvent` are observers.
+pub trait ExecObserver {
+    fn on_step(&mut self, event: StepEvent);
+}
+
+impl<F: FnMut(StepEvent)> ExecObserver for F {
+    fn on_step(&mut self, event: StepEvent) {
+        self(event)
+    }
+}
+
+impl Engine {
+    // Like `find_at`, telling `observer` of each step. Runs the plain
+    // backtracker whatever the backend, trying every start position in
+    // turn, so that the steps are those of the automaton alone.
+    pub fn find_at_observed(
+        &self,
+        s: &str,
+        start: usize,
+        observer: &mut impl ExecObserver,
+    ) -> Option<Match> {
+        self.try_find_at_observed(s, start, observer).ok().flatten()
+    }
+
+    pub fn try_find_at_observed(
+        &self,
+        s: &str,
+        start: usize,
+        observer: &mut impl ExecObserver,
+    ) -> Result<Option<Match>, Error> {
+        let s = self.prepare(s);
+        if start > s.len() || !s.is_char_boundary(start) {
+            return Ok(None);
+        }
+        let haystack = Haystack::Str(&s);
+        let mut budget = Budget::new(self.nfa.step_limit, self.nfa.timeout, None);
+        let mut cache = Cache::new();
+        let mut at = start;
+        loop {
+            observer.on_step(StepEvent::Start { at });
+            let found = self.nfa.try_search_observed(
+                &haystack,
+                at,
+                start,
+                &mut cache,
+                &mut budget,
+                observer,
+            )?;
+            if let Some(end) = found {
+                return Ok(Some(Match {
+                    start: at,
+                    end,
+                    groups: captured_groups(&haystack, &cache.groups),
+                }));
+            }
+            if self.flags.anchored || at == haystack.len() {
+                return Ok(None);
+            }
+            at = haystack.next(at);
+        }
+    }
+}
+
+// A recorded search, see `Engine::trace`. Written as JSON, a search that
+// goes wrong can be attached to a bug report, then read back and replayed
+// on a fixed engine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trace {
+    pub haystack: String,
+    pub start: usize,
+    pub steps: Vec<StepEvent>,
+    pub found: Option<Match>,
+    // set when the search was abandoned, `steps` then end there
+    pub error: Option<Error>,
+}
+
+impl Engine {
+    // Records every step of `find_at_observed`.
+    pub fn trace(&self, s: &str, start: usize) -> Trace {
+        let mut steps = vec![];
+        let found = self.try_find_at_observed(s, start, &mut |event| steps.push(event));
+        Trace {
+            haystack: s.to_string(),
+            start,
+            steps,
+            found: found.clone().ok().flatten(),
+            error: found.err(),
+        }
+    }
+}
+
+impl Trace {
+    // The same search run on `engine`, for comparing with this one.
+    pub fn replay(&self, engine: &Engine) -> Trace {
+        engine.trace(&self.haystack, self.start)
+    }
+
+    // One step per line, so that traces diff well.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{{");
+        let _ = writeln!(out, "  \"haystack\": {},", json::string(&self.haystack));
+        let _ = writeln!(out, "  \"start\": {},", self.start);
+        let found = match &self.found {
+            Some(m) => {
+                let mut groups: Vec<_> = m.groups.iter().collect();
+                groups.sort();
+                let groups: Vec<String> = groups
+                    .iter()
+                    .map(|(name, text)| format!("{}: {}", json::string(name), json::string(text)))
+                    .collect();
+                format!(
+                    "{{\"start\": {}, \"end\": {}, \"groups\": {{{}}}}}",
+                    m.start,
+                    m.end,
+                    groups.join(", ")
+                )
+            }
+            None => "null".to_string(),
+        };
+        let _ = writeln!(out, "  \"found\": {},", found);
+        let error = match &self.error {
+            Some(error) => json::string(&error.to_string()),
+            None => "null".to_string(),
+        };
+        let _ = writeln!(out, "  \"error\": {},", error);
+        let steps: Vec<String> = self.steps.iter().map(step_json).collect();
+        let _ = writeln!(out, "  \"steps\": [\n    {}\n  ]", steps.join(",\n    "));
+        out.push('}');
+        out
+    }
+
+    pub fn from_json(text: &str) -> Result<Trace, Error> {
+        let value = json::parse(text)?;
+        let found = match value.get("found")? {
+            Value::Null => None,
+            found => {
+                let Value::Object(members) = found.get("groups")? else {
+                    return Err(invalid("expected the groups as an object"));
+                };
+                let groups = members
+                    .iter()
+                    .map(|(name, text)| Ok((name.clone(), text.as_str()?.to_string())))
+                    .collect::<Result<HashMap<_, _>, Error>>()?;
+                Some(Match {
+                    start: found.get("start")?.as_usize()?,
+                    end: found.get("end")?.as_usize()?,
+                    groups,
+                })
+            }
+        };
+        let error = match value.get("error")? {
+            Value::Null => None,
+            error => {
+                let errors = [Error::StepLimitExceeded, Error::Timeout, Error::Cancelled];
+                let message = error.as_str()?;
+                let error = errors.into_iter().find(|e| e.to_string() == message);
+                match error {
+                    Some(error) => Some(error),
+                    None => return Err(invalid(&format!("unknown error {:?}", message))),
+                }
+            }
+        };
+        let steps = value.get("steps")?.as_array()?;
+        Ok(Trace {
+            haystack: value.get("haystack")?.as_str()?.to_string(),
+            start: value.get("start")?.as_usize()?,
+            steps: steps.iter().map(parse_step).collect::<Result<_, _>>()?,
+            found,
+            error,
+        })
+    }
+}
+
+fn step_json(event: &StepEvent) -> String {
+    match *event {
+        StepEvent::Start { at } => format!("{{\"event\": \"start\", \"at\": {}}}", at),
+        StepEvent::Enter { state, at } => format!(
+            "{{\"event\": \"enter\", \"state\": {}, \"at\": {}}}",
+            state, at
+        ),
+        StepEvent::Attempt {
+            state,
+            transition,
+            to,
+            at,
+            passed,
+        } => format!(
+            "{{\"event\": \"attempt\", \"state\": {}, \"transition\": {}, \"to\": {}, \"at\": {}, \"passed\": {}}}",
+            state, transition, to, at, passed
+        ),
+        StepEvent::GroupOpen { group, at } => format!(
+            "{{\"event\": \"group_open\", \"group\": {}, \"at\": {}}}",
+            group, at
+        ),
+        StepEvent::GroupClose { group, at } => format!(
+            "{{\"event\": \"group_close\", \"group\": {}, \"at\": {}}}",
+            group, at
+        ),
+        StepEvent::Backtrack { state, at } => format!(
+            "{{\"event\": \"backtrack\", \"state\": {}, \"at\": {}}}",
+            state, at
+        ),
+        StepEvent::Match { at } => format!("{{\"event\": \"match\", \"at\": {}}}", at),
+    }
+}
+
+fn parse_step(value: &Value) -> Result<StepEvent, Error> {
+    let field = |name| value.get(name)?.as_usize();
+    let group = || {
+        let group = field("group")?;
+        u32::try_from(group).map_err(|_| invalid(&format!("group {} out of range", group)))
+    };
+    let event = match value.get("event")?.as_str()? {
+        "start" => StepEvent::Start { at: field("at")? },
+        "enter" => StepEvent::Enter {
+            state: field("state")?,
+            at: field("at")?,
+        },
+        "attempt" => StepEvent::Attempt {
+            state: field("state")?,
+            transition: field("transition")?,
+            to: field("to")?,
+            at: field("at")?,
+            passed: value.get("passed")?.as_bool()?,
+        },
+        "group_open" => StepEvent::GroupOpen {
+            group: group()?,
+            at: field("at")?,
+        },
+        "group_close" => StepEvent::GroupClose {
+            group: group()?,
+            at: field("at")?,
+        },
+        "backtrack" => StepEvent::Backtrack {
+            state: field("state")?,
+            at: field("at")?,
+        },
+        "match" => StepEvent::Match { at: field("at")? },
+        event => return Err(invalid(&format!("unknown step {:?}", event))),
+    };
+    Ok(event)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StepEvent, Trace};
+    use crate::rsgex::{builder::EngineBuilder, engine::Engine, error::Error};
+
+    #[test]
+    fn test_find_at_observed() {
+        let e = Engine::try_from("x?(a|ab)c").unwrap();
+        let mut events = vec![];
+        let m = e
+            .find_at_observed("_abc", 0, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!((m.start, m.end), (1, 4));
+        assert_eq!(m.groups["1"], "ab");
+        assert_eq!(e.find_at("_abc", 0), Some(m));
+
+        assert_eq!(events[0], StepEvent::Start { at: 0 });
+        assert!(events.contains(&StepEvent::Start { at: 1 }));
+        assert!(events.contains(&StepEvent::GroupOpen { group: 1, at: 1 }));
+        assert!(events.contains(&StepEvent::GroupClose { group: 1, at: 3 }));
+        assert_eq!(events.last(), Some(&StepEvent::Match { at: 4 }));
+        // the `a` branch fails on the `b`, and the search backtracks into
+        // the `ab` one
+        let failed = events.iter().position(|event| {
+            matches!(
+                event,
+                StepEvent::Attempt {
+                    at: 2,
+                    passed: false,
+                    ..
+                }
+            )
+        });
+        let backtrack = events
+            .iter()
+            .position(|event| matches!(event, StepEvent::Backtrack { at: 2, .. }));
+        assert!(failed.unwrap() < backtrack.unwrap());
+        let closed = events
+            .iter()
+            .position(|event| *event == StepEvent::GroupClose { group: 1, at: 3 });
+        assert!(backtrack < closed);
+
+        let mut steps = 0;
+        assert!(e.find_at_observed("xyz", 0, &mut |_| steps += 1).is_none());
+        assert!(steps > 3);
+    }
+
+    #[test]
+    fn test_trace_json() {
+        let e = Engine::try_from("(?<word>\\w+)\"").unwrap();
+        let trace = e.trace("say \"hi\"", 0);
+        assert_eq!(trace.found.as_ref().unwrap().as_str(), "hi\"");
+        let json = trace.to_json();
+        assert!(json.contains("\"event\": \"backtrack\""));
+        assert_eq!(Trace::from_json(&json).unwrap(), trace);
+        assert_eq!(trace.replay(&e), trace);
+
+        let e = EngineBuilder::new().step_limit(10).build("(a*)*b").unwrap();
+        let trace = e.trace("aaaa", 0);
+        assert_eq!(trace.error, Some(Error::StepLimitExceeded));
+        assert_eq!(trace.found, None);
+        assert_eq!(Trace::from_json(&trace.to_json()).unwrap(), trace);
+        assert!(matches!(
+            Trace::from_json("{\"haystack\": \"a\"}"),
+            Err(Error::Import(_))
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+use std::{collections::VecDeque, fmt::Write};
+
+use super::{engine::Engine, json, trace::Trace};
+
+// columns and rows of the layout, in pixels
+const COLUMN: usize = 130;
+const ROW: usize = 80;
+const MARGIN: usize = 60;
+
+impl Engine {
+    // A self-contained HTML page drawing the automaton, with a slider that
+    // steps through `trace`: the state the search is in, the transition it
+    // tests and where it is in the haystack. Needs no network access, for
+    // showing how an NFA runs in class.
+    pub fn to_html_visualizer(&self, trace: &Trace) -> String {
+        let (positions, width, height) = self.layout();
+        let mut states = vec![];
+        let mut edges = vec![];
+        for view in self.states() {
+            let (x, y) = positions[view.index()];
+            states.push(format!(
+                "{{\"x\": {}, \"y\": {}, \"initial\": {}, \"ending\": {}}}",
+                x,
+                y,
+                view.is_initial(),
+                view.is_ending()
+            ));
+            for (transition, (label, to, _)) in view.transitions().enumerate() {
+                edges.push(format!(
+                    "{{\"from\": {}, \"transition\": {}, \"to\": {}, \"label\": {}}}",
+                    view.index(),
+                    transition,
+                    to,
+                    json::string(&label)
+                ));
+            }
+        }
+
+        let mut data = String::new();
+        let _ = write!(
+            data,
+            "{{\"width\": {}, \"height\": {}, \"states\": [{}], \"edges\": [{}], \"trace\": {}}}",
+            width,
+            height,
+            states.join(", "),
+            edges.join(", "),
+            trace.to_json()
+        );
+        PAGE.replace("{data}", &data)
+    }
+
+    // Places each state in the column of its distance from the initial
+    // state, unreachable ones last. Returns the positions and the size of
+    // the drawing.
+    fn layout(&self) -> (Vec<(usize, usize)>, usize, usize) {
+        let count = self.nfa.states.len();
+        let mut depths = vec![None; count];
+        let mut queue = VecDeque::new();
+        if count > 0 {
+            depths[self.nfa.initial] = Some(0);
+            queue.push_back(self.nfa.initial);
+        }
+        while let Some(index) = queue.pop_front() {
+            let depth = depths[index].unwrap_or_default();
+            for (_, to) in self.nfa.states[index].matchers.iter() {
+                if depths[*to].is_none() {
+                    depths[*to] = Some(depth + 1);
+                    queue.push_back(*to);
+                }
+            }
+        }
+        let last = depths.iter().flatten().max().map_or(0, |depth| depth + 1);
+
+        let mut rows = vec![0; last + 1];
+        let mut positions = vec![];
+        for depth in depths {
+            let column = depth.unwrap_or(last);
+            positions.push((MARGIN + column * COLUMN, MARGIN + rows[column] * ROW));
+            rows[column] += 1;
+        }
+        let columns = positions.iter().map(|(x, _)| x + MARGIN).max();
+        let rows = positions.iter().map(|(_, y)| y + MARGIN).max();
+        (positions, columns.unwrap_or(MARGIN), rows.unwrap_or(MARGIN))
+    }
+}
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>NFA trace</title>
+<style>
+body { font-family: sans-serif; margin: 1em; }
+svg { border: 1px solid #ccc; }
+.state circle { fill: #fff; stroke: #333; stroke-width: 1.5; }
+.state.current circle { fill: #ffe08a; }
+.state.failed circle { fill: #f4a3a3; }
+.edge path { fill: none; stroke: #999; stroke-width: 1.2; }
+.edge text { font-size: 12px; fill: #333; }
+.edge.passed path { stroke: #2a9d3f; stroke-width: 3; }
+.edge.rejected path { stroke: #d33; stroke-width: 3; }
+#haystack { font-family: monospace; font-size: 20px; white-space: pre; }
+#haystack .at { background: #ffe08a; border-left: 2px solid #d80; }
+#haystack .matched { text-decoration: underline; }
+#controls { margin: 1em 0; }
+#slider { width: 60%; vertical-align: middle; }
+</style>
+</head>
+<body>
+<div id="haystack"></div>
+<div id="controls">
+<button id="prev">&larr;</button>
+<input id="slider" type="range" min="0" value="0">
+<button id="next">&rarr;</button>
+<span id="step"></span>
+</div>
+<div id="event"></div>
+<svg id="graph" xmlns="http://www.w3.org/2000/svg">
+<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="7" markerHeight="7" orient="auto"><path d="M0,0 L10,5 L0,10 z" fill="#666"/></marker></defs>
+</svg>
+<script id="data" type="application/json">{data}</script>
+<script>
+const data = JSON.parse(document.getElementById("data").textContent);
+const steps = data.trace.steps;
+const svg = document.getElementById("graph");
+const ns = "http://www.w3.org/2000/svg";
+svg.setAttribute("width", data.width);
+svg.setAttribute("height", data.height);
+
+function element(name, attributes, parent) {
+  const e = document.createElementNS(ns, name);
+  for (const key in attributes) e.setAttribute(key, attributes[key]);
+  parent.appendChild(e);
+  return e;
+}
+
+const edges = {};
+for (const edge of data.edges) {
+  const from = data.states[edge.from], to = data.states[edge.to];
+  const g = element("g", { class: "edge" }, svg);
+  let d, lx, ly;
+  if (edge.from === edge.to) {
+    d = `M${from.x - 10},${from.y - 18} C${from.x - 30},${from.y - 60} ${from.x + 30},${from.y - 60} ${from.x + 10},${from.y - 18}`;
+    lx = from.x; ly = from.y - 50;
+  } else {
+    const dx = to.x - from.x, dy = to.y - from.y, length = Math.hypot(dx, dy);
+    const ux = dx / length, uy = dy / length;
+    const bend = edge.from < edge.to ? 20 : -20;
+    const mx = (from.x + to.x) / 2 - uy * bend, my = (from.y + to.y) / 2 + ux * bend;
+    d = `M${from.x + ux * 20},${from.y + uy * 20} Q${mx},${my} ${to.x - ux * 20},${to.y - uy * 20}`;
+    lx = mx; ly = my - 4;
+  }
+  element("path", { d: d, "marker-end": "url(#arrow)" }, g);
+  element("text", { x: lx, y: ly, "text-anchor": "middle" }, g).textContent = edge.label || "ε";
+  edges[edge.from + ":" + edge.transition] = g;
+}
+
+const states = data.states.map((state, index) => {
+  const g = element("g", { class: "state" }, svg);
+  element("circle", { cx: state.x, cy: state.y, r: 20 }, g);
+  if (state.ending) element("circle", { cx: state.x, cy: state.y, r: 15 }, g);
+  if (state.initial) element("path", { d: `M${state.x - 45},${state.y} L${state.x - 20},${state.y}`, stroke: "#666", "marker-end": "url(#arrow)" }, g);
+  element("text", { x: state.x, y: state.y + 5, "text-anchor": "middle" }, g).textContent = index;
+  return g;
+});
+
+// the state and match start in force at each step
+const context = [];
+let state = null, start = data.trace.start;
+for (const step of steps) {
+  if (step.event === "start") { start = step.at; state = null; }
+  if (step.event === "enter" || step.event === "backtrack") state = step.state;
+  context.push({ state: state, start: start });
+}
+
+function describe(step) {
+  switch (step.event) {
+    case "start": return `try a match from byte ${step.at}`;
+    case "enter": return `enter state ${step.state}`;
+    case "attempt": return `test transition ${step.transition} of state ${step.state} to state ${step.to}: ${step.passed ? "passed" : "failed"}`;
+    case "group_open": return `open group ${step.group}`;
+    case "group_close": return `close group ${step.group}`;
+    case "backtrack": return `state ${step.state} leads nowhere, backtrack`;
+    case "match": return `match ending at byte ${step.at}`;
+  }
+  return step.event;
+}
+
+function show(i) {
+  for (const g of states) g.setAttribute("class", "state");
+  for (const key in edges) edges[key].setAttribute("class", "edge");
+  const haystack = document.getElementById("haystack");
+  haystack.textContent = "";
+  const bytes = new TextEncoder().encode(data.trace.haystack);
+  const decode = (from, to) => new TextDecoder().decode(bytes.slice(from, to));
+  const step = steps[i];
+  if (!step) {
+    haystack.textContent = data.trace.haystack;
+    document.getElementById("event").textContent = "no steps recorded";
+    return;
+  }
+  const { state, start } = context[i];
+  if (state !== null) states[state].setAttribute("class", step.event === "backtrack" ? "state failed" : "state current");
+  if (step.event === "attempt") {
+    const edge = edges[step.state + ":" + step.transition];
+    if (edge) edge.setAttribute("class", step.passed ? "edge passed" : "edge rejected");
+  }
+  const at = step.at;
+  haystack.append(decode(0, start));
+  const matched = document.createElement("span");
+  matched.className = "matched";
+  matched.textContent = decode(start, at);
+  haystack.append(matched);
+  const next = document.createElement("span");
+  next.className = "at";
+  const rest = [...decode(at)];
+  next.textContent = rest.length ? rest[0] : " ";
+  haystack.append(next);
+  haystack.append(rest.slice(1).join(""));
+  document.getElementById("step").textContent = `step ${i + 1} of ${steps.length}`;
+  document.getElementById("event").textContent = describe(step);
+}
+
+const slider = document.getElementById("slider");
+slider.max = Math.max(steps.length - 1, 0);
+slider.oninput = () => show(Number(slider.value));
+const move = delta => { slider.value = Number(slider.value) + delta; show(Number(slider.value)); };
+document.getElementById("prev").onclick = () => move(-1);
+document.getElementById("next").onclick = () => move(1);
+document.onkeydown = event => {
+  if (event.key === "ArrowLeft") move(-1);
+  if (event.key === "ArrowRight") move(1);
+};
+show(0);
+</script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod test {
+    use crate::rsgex::engine::Engine;
+
+    #[test]
+    fn test_to_html_visualizer() {
+        let e = Engine::try_from("(a|ab)c").unwrap();
+        let html = e.to_html_visualizer(&e.trace("</script>abc", 0));
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("\"event\": \"backtrack\""));
+        assert!(html.contains("\"label\": \"a\""));
+        // the haystack cannot close the script element holding it
+        assert_eq!(html.matches("</script>").count(), 2);
+        assert!(!html.contains("{data}"));
+    }
+}